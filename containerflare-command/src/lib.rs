@@ -1,16 +1,23 @@
+use std::fmt;
 use std::str::FromStr;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock, Weak};
+use std::time::{Duration, SystemTime};
 
 #[cfg(unix)]
 use std::path::PathBuf;
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use serde_json::value::RawValue;
 use thiserror::Error;
-use tokio::io::{self, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
-use tokio::sync::Mutex;
-use tokio::time;
+use tokio::io::{
+    self, AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+    BufReader,
+};
+use tokio::sync::{Mutex, Semaphore, watch};
+use tokio::time::{self, Instant};
 
 #[cfg(unix)]
 use tokio::net::UnixStream;
@@ -23,6 +30,32 @@ use tokio::net::{
 };
 
 const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+/// Control command sent by the keepalive task; any response (even `ok: false`) counts as a pong.
+const PING_COMMAND: &str = "__ping";
+/// Handshake sent by [`CommandClient::connect_probed`] to confirm a host is listening.
+const HELLO_COMMAND: &str = "__hello";
+/// Clock comparison sent by [`CommandClient::sync_time`].
+const TIME_COMMAND: &str = "__time";
+/// Introspection request sent by [`CommandClient::list_commands`].
+const COMMANDS_COMMAND: &str = "__commands";
+/// Upper bound on a single request read by [`CommandServer`], guarding against corrupt length
+/// prefixes and runaway lines.
+const MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+/// Bytes of an undecodable response quoted in [`CommandError::MalformedResponse`].
+const MALFORMED_PREVIEW_LEN: usize = 128;
+/// Size of the chunks [`CommandClient::send_with_body`] streams a body in.
+const BODY_CHUNK_LEN: usize = 64 * 1024;
+/// Largest response a client accepts unless [`CommandOptions::max_response_bytes`] says otherwise.
+const DEFAULT_MAX_RESPONSE_BYTES: usize = 4 * 1024 * 1024;
+/// Per-attempt budget used while resolving [`CommandEndpoint::Auto`].
+const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_millis(250);
+/// Pause between the reconnect attempts [`CommandClient::wait_ready`] makes.
+const READY_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+/// Conventional location of the sidecar's Unix socket, tried first by [`CommandEndpoint::Auto`].
+#[cfg(unix)]
+pub const DEFAULT_UNIX_SOCKET_PATH: &str = "/run/containerflare/command.sock";
+/// Environment variable holding a `host:port` that [`CommandEndpoint::Auto`] tries after the socket.
+pub const AUTO_TCP_ADDR_ENV: &str = "CF_CMD_TCP_ADDR";
 
 /// Describes how the container establishes the host command channel transport.
 #[derive(Clone, Debug, PartialEq, Eq, Default)]
@@ -32,6 +65,10 @@ pub enum CommandEndpoint {
     #[cfg(unix)]
     UnixSocket(PathBuf),
     Tcp(String),
+    /// Resolved at connect time by trying each transport in [`CommandOptions::auto_probes`].
+    ///
+    /// [`CommandClient::endpoint`] reports the transport that was actually chosen.
+    Auto,
     /// Marker used when the runtime intentionally disables the command channel.
     Unavailable,
 }
@@ -45,6 +82,10 @@ impl FromStr for CommandEndpoint {
             return Ok(CommandEndpoint::Stdio);
         }
 
+        if value.eq_ignore_ascii_case("auto") {
+            return Ok(CommandEndpoint::Auto);
+        }
+
         if value.eq_ignore_ascii_case("disabled") || value.eq_ignore_ascii_case("unavailable") {
             return Ok(CommandEndpoint::Unavailable);
         }
@@ -58,12 +99,37 @@ impl FromStr for CommandEndpoint {
             return Ok(CommandEndpoint::Tcp(addr.to_owned()));
         }
 
+        if is_bare_host_port(value) {
+            return Ok(CommandEndpoint::Tcp(value.to_owned()));
+        }
+
         Err(CommandEndpointParseError::InvalidCommandEndpoint(
             value.to_owned(),
         ))
     }
 }
 
+/// Recognizes a TCP address written without the `tcp://` scheme: a socket address such as
+/// `127.0.0.1:9000` or `[::1]:9000`, or a hostname plus port such as `localhost:9000`.
+///
+/// Anything with a `/` is left alone, so paths containing a colon are never taken for TCP.
+fn is_bare_host_port(value: &str) -> bool {
+    if value.parse::<std::net::SocketAddr>().is_ok() {
+        return true;
+    }
+    let Some((host, port)) = value.rsplit_once(':') else {
+        return false;
+    };
+    !host.is_empty()
+        && port.parse::<u16>().is_ok()
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+}
+
 /// Errors encountered while parsing a [`CommandEndpoint`] from a string.
 #[derive(Debug, Error, Clone)]
 pub enum CommandEndpointParseError {
@@ -79,8 +145,10 @@ pub enum CommandEndpointParseError {
 ///
 /// # Transport Modes
 /// - `stdio`: bidirectional pipes that the Workers container sidecar keeps open.
-/// - `tcp://host:port`: an explicit TCP socket managed by the sidecar.
+/// - `tcp://host:port`: an explicit TCP socket managed by the sidecar (a bare `host:port` works
+///   too).
 /// - `unix://path` *(Unix only)*: a Unix domain socket exposed by the sidecar.
+/// - `auto`: probes the transports above in order and uses the first one that is available.
 ///
 /// # Errors
 /// All async constructors and [`CommandClient::send`] return [`CommandError`] when the transport
@@ -97,289 +165,3988 @@ pub enum CommandEndpointParseError {
 /// # Ok(())
 /// # }
 /// ```
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct CommandClient {
     inner: Arc<CommandClientInner>,
+    /// Per-handle, so [`CommandClient::with_timeout`] can vary it over one shared transport.
+    timeout: Duration,
+    observer: Option<Arc<dyn CommandObserver>>,
+}
+
+impl fmt::Debug for CommandClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CommandClient")
+            .field("inner", &self.inner)
+            .field("timeout", &self.timeout)
+            .field("observer", &self.observer.is_some())
+            .finish()
+    }
+}
+
+/// Hook notified after every command issued through [`CommandClient::send`].
+///
+/// Observers run inline on the calling task, so implementations should be cheap (increment a
+/// counter, record a histogram sample) and must not block.
+pub trait CommandObserver: Send + Sync {
+    /// Called once per command after the response arrives or the command fails.
+    fn on_command(&self, event: &CommandEvent<'_>);
 }
 
+/// Outcome of a single command, handed to [`CommandObserver::on_command`].
 #[derive(Debug)]
-struct CommandClientInner {
-    endpoint: CommandEndpoint,
-    writer: CommandWriter,
-    reader: CommandReader,
-    timeout: Duration,
+pub struct CommandEvent<'a> {
+    /// Command verb that was issued.
+    pub command: &'a str,
+    /// Time spent writing the request and waiting for the response.
+    pub elapsed: Duration,
+    /// The response, or the error the caller is about to receive.
+    pub outcome: Result<&'a CommandResponse, &'a CommandError>,
 }
 
-impl CommandClient {
-    /// Connects to the configured endpoint using the default timeout.
+/// Connection settings accepted by [`CommandClient::connect_with_options`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommandOptions {
+    /// Maximum duration to wait for each response before failing (defaults to 30s).
     ///
-    /// # Parameters
-    /// * `endpoint` - Transport descriptor (stdio, TCP, or Unix socket).
+    /// Responses carry no correlation id, so a reply that arrives after its command timed out
+    /// would be read as the answer to the next one. The client therefore counts replies it
+    /// stopped waiting for, after a timeout or when the sending future was dropped, and discards
+    /// that many before reading the next command's answer. TCP
+    /// and Unix socket clients take a shortcut and drop the connection instead, reconnecting
+    /// before their next command.
     ///
-    /// # Returns
-    /// A connected [`CommandClient`] ready to issue commands.
+    /// All of the client's timers run on Tokio's clock, so tests can drive them with
+    /// `tokio::time::pause` and `advance` instead of waiting.
+    pub timeout: Duration,
+    /// Budget for opening a TCP or Unix socket connection (unbounded by default, leaving it to
+    /// the operating system).
     ///
-    /// # Errors
-    /// Returns [`CommandError`] if the underlying transport cannot be opened or is closed
-    /// before the connection is established.
+    /// Only the connect step is bounded; responses are governed by `timeout`. An attempt that
+    /// runs out fails with an [`io::ErrorKind::TimedOut`] [`CommandError::Io`].
+    /// [`CommandEndpoint::Auto`] uses `probe_timeout` per attempt instead.
+    pub connect_timeout: Option<Duration>,
+    /// Staggers connection attempts with other clients sharing the same gate (ungated by default).
     ///
-    /// # Panics
-    /// Does not panic.
-    pub async fn connect(endpoint: CommandEndpoint) -> Result<Self, CommandError> {
-        Self::connect_with_timeout(endpoint, DEFAULT_COMMAND_TIMEOUT).await
-    }
-
-    /// Connects to the endpoint and enforces a custom read timeout.
+    /// Processes that recreate many clients after the host goes away (e.g. one per tenant) can
+    /// share a [`ConnectGate`] so the reconnects do not all hit the host the moment it recovers.
+    pub connect_gate: Option<ConnectGate>,
+    /// Probes an idle connection every interval (disabled by default).
     ///
-    /// # Parameters
-    /// * `endpoint` - Transport descriptor (stdio, TCP, or Unix socket).
-    /// * `timeout` - Maximum duration to wait for each response before failing.
+    /// TCP sockets additionally get `SO_KEEPALIVE` with the same idle time. When a `__ping` goes
+    /// unanswered within `timeout`, the transport is considered dead and every later command
+    /// fails fast with [`CommandError::TransportClosed`] instead of waiting for its own timeout.
+    pub keepalive: Option<Duration>,
+    /// Caps how many commands may be waiting on the channel at once (unlimited by default).
     ///
-    /// # Returns
-    /// A connected [`CommandClient`] that enforces the provided timeout for every command.
+    /// [`CommandClient::send`] waits for a free slot; [`CommandClient::try_send`] fails with
+    /// [`CommandError::Saturated`] instead.
+    pub max_in_flight: Option<usize>,
+    /// Transports tried, in order, when connecting to [`CommandEndpoint::Auto`].
     ///
-    /// # Errors
-    /// Returns [`CommandError`] if the underlying transport cannot be opened or the timeout
-    /// elapses while establishing the connection.
+    /// Defaults to [`AutoProbe::default_order`].
+    pub auto_probes: Vec<AutoProbe>,
+    /// Budget for each connection attempt while resolving [`CommandEndpoint::Auto`] (250ms).
+    pub probe_timeout: Duration,
+    /// Payload compression to offer the host during the `__hello` handshake (off by default).
     ///
-    /// # Panics
-    /// Does not panic.
-    pub async fn connect_with_timeout(
-        endpoint: CommandEndpoint,
-        timeout: Duration,
-    ) -> Result<Self, CommandError> {
-        let (writer, reader) = match &endpoint {
-            CommandEndpoint::Stdio => (
-                CommandWriter::Stdio(Mutex::new(tokio::io::stdout())),
-                CommandReader::Stdio(Mutex::new(BufReader::new(tokio::io::stdin()))),
-            ),
-            CommandEndpoint::Tcp(addr) => {
-                let stream = TcpStream::connect(addr).await?;
-                let (read_half, write_half) = stream.into_split();
-                (
-                    CommandWriter::Tcp(Mutex::new(write_half)),
-                    CommandReader::Tcp(Mutex::new(BufReader::new(read_half))),
-                )
-            }
-            #[cfg(unix)]
-            CommandEndpoint::UnixSocket(path) => {
-                let stream = UnixStream::connect(path).await?;
-                let (read_half, write_half) = stream.into_split();
-                (
-                    CommandWriter::Unix(Mutex::new(write_half)),
-                    CommandReader::Unix(Mutex::new(BufReader::new(read_half))),
-                )
-            }
-            CommandEndpoint::Unavailable => {
-                return Err(CommandError::Unavailable(
-                    "command endpoint marked unavailable".into(),
-                ));
-            }
-        };
-
-        Ok(Self {
-            inner: Arc::new(CommandClientInner {
-                endpoint,
-                writer,
-                reader,
-                timeout,
-            }),
-        })
-    }
-
-    /// Creates a [`CommandClient`] that always reports an unavailable channel.
+    /// When set, connecting costs one extra round trip. The channel stays uncompressed if this
+    /// build lacks the codec's feature or the host does not accept the offer.
+    pub compression: CommandCompression,
+    /// Largest response, in bytes after decompression, the client will buffer (4 MiB by default).
     ///
-    /// This is useful for runtimes (Google Cloud Run, local testing, etc.) that do not expose
-    /// a host-managed command bus but still want to share the API surface.
-    pub fn unavailable(reason: impl Into<String>) -> Self {
-        let reason = reason.into();
-        let shared = Arc::new(reason);
+    /// A bigger response fails with [`CommandError::ResponseTooLarge`]. The rest of it is skipped
+    /// before the next response, or the connection dropped like after a
+    /// [`timeout`](Self::timeout).
+    pub max_response_bytes: usize,
+    /// Connects [`CommandEndpoint::Stdio`] even when stdin is a terminal (off by default).
+    ///
+    /// A terminal on stdin usually means the binary was started by hand (e.g. `cargo run`)
+    /// rather than by the host, so typed input would be parsed as responses and commands would
+    /// hang. By default such a connection yields an [`unavailable`](CommandClient::unavailable)
+    /// client instead.
+    pub allow_terminal_stdin: bool,
+    /// Checks every response, as raw JSON, before it is accepted (none by default).
+    ///
+    /// A rejected response fails with [`CommandError::MalformedResponse`] carrying the
+    /// validator's message. The channel stays usable.
+    pub response_validator: Option<ResponseValidator>,
+    /// Highest command protocol version to offer the host during the `__hello` handshake (not
+    /// negotiated by default).
+    ///
+    /// The host answers with the version both sides speak, readable through
+    /// [`CommandClient::protocol_version`]. A host that does not answer within `probe_timeout`,
+    /// or answers without a version, is assumed to speak version 0.
+    pub protocol_version: Option<u32>,
+    /// Byte that ends each uncompressed response (`\n` by default).
+    ///
+    /// For hosts that frame responses with NUL bytes or similar; requests are still written as
+    /// `\n`-terminated lines. A `\r` right before the delimiter is dropped, so `\r\n` framing
+    /// works with the default.
+    pub line_delimiter: u8,
+}
+
+impl Default for CommandOptions {
+    fn default() -> Self {
         Self {
-            inner: Arc::new(CommandClientInner {
-                endpoint: CommandEndpoint::Unavailable,
-                writer: CommandWriter::Unavailable(shared.clone()),
-                reader: CommandReader::Unavailable(shared),
-                timeout: DEFAULT_COMMAND_TIMEOUT,
-            }),
+            timeout: DEFAULT_COMMAND_TIMEOUT,
+            connect_timeout: None,
+            connect_gate: None,
+            keepalive: None,
+            max_in_flight: None,
+            auto_probes: AutoProbe::default_order(),
+            probe_timeout: DEFAULT_PROBE_TIMEOUT,
+            compression: CommandCompression::None,
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+            allow_terminal_stdin: false,
+            response_validator: None,
+            protocol_version: None,
+            line_delimiter: b'\n',
         }
     }
+}
 
-    /// Returns the endpoint backing this client.
-    pub fn endpoint(&self) -> &CommandEndpoint {
-        &self.inner.endpoint
+impl CommandOptions {
+    /// Sets the per-command response timeout.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
     }
 
-    /// Sends a command request and waits for a response (or timeout).
-    ///
-    /// # Parameters
-    /// * `request` - Structured command for the Workers sidecar.
-    ///
-    /// # Returns
-    /// The [`CommandResponse`] emitted by the sidecar.
-    ///
-    /// # Errors
-    /// Returns [`CommandError`] if the channel closes, the response payload cannot be
-    /// deserialized, the command reports a failure, or the read timeout elapses.
-    ///
-    /// # Panics
-    /// Does not panic.
-    pub async fn send(&self, request: CommandRequest) -> Result<CommandResponse, CommandError> {
-        self.inner.writer.send(&request).await?;
+    /// Bounds how long opening the connection may take.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
 
-        let response = time::timeout(self.inner.timeout, self.inner.reader.read()).await;
-        let response = match response {
-            Ok(result) => result?,
-            Err(_) => return Err(CommandError::Timeout(self.inner.timeout)),
-        };
+    /// Routes connection attempts through `gate`, shared with other clients.
+    pub fn connect_gate(mut self, gate: ConnectGate) -> Self {
+        self.connect_gate = Some(gate);
+        self
+    }
 
-        if response.ok {
-            Ok(response)
-        } else {
-            let diagnostic = response
-                .diagnostic
-                .clone()
-                .unwrap_or_else(|| "host returned failure".to_owned());
-            Err(CommandError::CommandFailure {
-                diagnostic,
-                payload: response.payload.clone(),
-            })
-        }
+    /// Enables idle keepalive probes every `interval`.
+    pub fn keepalive(mut self, interval: Duration) -> Self {
+        self.keepalive = Some(interval);
+        self
+    }
+
+    /// Bounds the number of concurrent commands to `limit`.
+    pub fn max_in_flight(mut self, limit: usize) -> Self {
+        self.max_in_flight = Some(limit);
+        self
+    }
+
+    /// Replaces the transports tried for [`CommandEndpoint::Auto`].
+    pub fn auto_probes(mut self, probes: Vec<AutoProbe>) -> Self {
+        self.auto_probes = probes;
+        self
+    }
+
+    /// Sets the per-attempt budget used while resolving [`CommandEndpoint::Auto`].
+    pub fn probe_timeout(mut self, timeout: Duration) -> Self {
+        self.probe_timeout = timeout;
+        self
+    }
+
+    /// Requests payload compression for the channel.
+    pub fn compression(mut self, compression: CommandCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Caps the size of a single response at `limit` bytes.
+    pub fn max_response_bytes(mut self, limit: usize) -> Self {
+        self.max_response_bytes = limit;
+        self
+    }
+
+    /// Allows stdio connections while stdin is a terminal.
+    pub fn allow_terminal_stdin(mut self, allow: bool) -> Self {
+        self.allow_terminal_stdin = allow;
+        self
+    }
+
+    /// Negotiates a protocol version of at most `version` with the host.
+    pub fn protocol_version(mut self, version: u32) -> Self {
+        self.protocol_version = Some(version);
+        self
+    }
+
+    /// Reads responses terminated by `delimiter` instead of `\n`.
+    pub fn line_delimiter(mut self, delimiter: u8) -> Self {
+        self.line_delimiter = delimiter;
+        self
+    }
+
+    /// Validates each response with `validator`, e.g. against the host's JSON schema.
+    pub fn response_validator<F>(mut self, validator: F) -> Self
+    where
+        F: Fn(&Value) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.response_validator = Some(ResponseValidator(Arc::new(validator)));
+        self
     }
 }
 
-/// JSON payload describing a command issued to the host.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CommandRequest {
-    /// Command verb recognized by the Workers sidecar.
-    pub command: String,
-    /// Structured JSON payload to accompany the command (defaults to `null`).
-    #[serde(default)]
-    pub payload: serde_json::Value,
+/// Hook installed with [`CommandOptions::response_validator`].
+///
+/// Two validators compare equal only when they are the same instance.
+#[derive(Clone)]
+pub struct ResponseValidator(Arc<ValidateFn>);
+
+type ValidateFn = dyn Fn(&Value) -> Result<(), String> + Send + Sync;
+
+impl ResponseValidator {
+    fn check(&self, value: &Value) -> Result<(), CommandError> {
+        (self.0)(value).map_err(CommandError::MalformedResponse)
+    }
 }
 
-impl CommandRequest {
-    /// Creates a new request with the provided command name and payload.
-    pub fn new(command: impl Into<String>, payload: serde_json::Value) -> Self {
-        Self {
-            command: command.into(),
-            payload,
-        }
+impl fmt::Debug for ResponseValidator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ResponseValidator(..)")
     }
+}
 
-    /// Creates a request whose payload is `null`.
-    pub fn empty(command: impl Into<String>) -> Self {
-        Self::new(command, serde_json::Value::Null)
+impl PartialEq for ResponseValidator {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
     }
 }
 
-/// Response returned by the host for a previously issued command.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CommandResponse {
-    /// Indicates whether the host executed the command successfully.
-    pub ok: bool,
-    /// JSON payload returned by the host (defaults to `null`).
-    #[serde(default)]
-    pub payload: serde_json::Value,
-    /// Optional diagnostic string supplied by the host when `ok == false`.
-    #[serde(default)]
-    pub diagnostic: Option<String>,
+impl Eq for ResponseValidator {}
+
+/// Limits how many connection attempts run at once across every client configured with the same
+/// gate, optionally spacing out when they start.
+///
+/// Clones share their limits, so one gate (held in a `static` for a process-wide limit, or one per
+/// endpoint) can be passed to each [`CommandOptions::connect_gate`]. An attempt holds its slot
+/// while the transport opens and the `__hello` handshake, if any, completes. Two gates compare
+/// equal only when one is a clone of the other.
+#[derive(Clone)]
+pub struct ConnectGate(Arc<GateState>);
+
+struct GateState {
+    permits: Semaphore,
+    max_concurrent: usize,
+    spacing: Duration,
+    /// Earliest moment the next attempt may start.
+    next_start: std::sync::Mutex<Option<Instant>>,
 }
 
-impl CommandResponse {
-    /// Constructs a success response with an empty payload.
-    pub fn ok() -> Self {
-        Self {
-            ok: true,
-            payload: serde_json::Value::Null,
-            diagnostic: None,
+impl ConnectGate {
+    /// Allows at most `max_concurrent` connection attempts at once (at least one).
+    pub fn new(max_concurrent: usize) -> Self {
+        Self::with_spacing(max_concurrent, Duration::ZERO)
+    }
+
+    /// Like [`ConnectGate::new`], additionally starting attempts at least `spacing` apart.
+    pub fn with_spacing(max_concurrent: usize, spacing: Duration) -> Self {
+        let max_concurrent = max_concurrent.max(1);
+        Self(Arc::new(GateState {
+            permits: Semaphore::new(max_concurrent),
+            max_concurrent,
+            spacing,
+            next_start: std::sync::Mutex::new(None),
+        }))
+    }
+
+    /// Waits for a free slot and this attempt's start time.
+    async fn enter(&self) -> tokio::sync::SemaphorePermit<'_> {
+        let permit = self
+            .0
+            .permits
+            .acquire()
+            .await
+            .expect("connect gate semaphore is never closed");
+        if !self.0.spacing.is_zero() {
+            let start = {
+                let mut next_start = self
+                    .0
+                    .next_start
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner());
+                let now = Instant::now();
+                let start = next_start.map_or(now, |next| next.max(now));
+                *next_start = Some(start + self.0.spacing);
+                start
+            };
+            time::sleep_until(start).await;
         }
+        permit
     }
 }
-/// Errors emitted by [`CommandClient`] when transport or payload handling fails.
-#[derive(Debug, Error)]
-pub enum CommandError {
-    #[error("command failed: {diagnostic}")]
-    CommandFailure { diagnostic: String, payload: Value },
-    #[error("command transport closed")]
-    TransportClosed,
-    #[error("command timed out after {0:?}")]
-    Timeout(Duration),
-    #[error("io error: {0}")]
-    Io(#[from] io::Error),
-    #[error("invalid command payload: {0}")]
-    Serialization(#[from] serde_json::Error),
-    #[error("command channel unavailable: {0}")]
-    Unavailable(String),
+
+impl fmt::Debug for ConnectGate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectGate")
+            .field("max_concurrent", &self.0.max_concurrent)
+            .field("spacing", &self.0.spacing)
+            .finish()
+    }
 }
 
-#[derive(Debug)]
-enum CommandWriter {
-    Stdio(Mutex<tokio::io::Stdout>),
-    Tcp(Mutex<TcpOwnedWriteHalf>),
-    #[cfg(unix)]
-    Unix(Mutex<UnixOwnedWriteHalf>),
-    Unavailable(Arc<String>),
+impl PartialEq for ConnectGate {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
 }
 
-#[derive(Debug)]
-enum CommandReader {
-    Stdio(Mutex<BufReader<tokio::io::Stdin>>),
-    Tcp(Mutex<BufReader<TcpOwnedReadHalf>>),
-    #[cfg(unix)]
-    Unix(Mutex<BufReader<UnixOwnedReadHalf>>),
-    Unavailable(Arc<String>),
+impl Eq for ConnectGate {}
+
+/// Payload compression negotiated for the command channel.
+///
+/// Uncompressed channels speak JSON lines. Once a codec has been agreed in the `__hello`
+/// handshake, both directions switch to binary frames: a 1-byte codec flag (`0` plain JSON,
+/// `1` gzip, `2` zstd), a big-endian `u32` length, then the body. Writers fall back to flag `0`
+/// for frames that do not shrink, and readers decode whatever flag they receive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandCompression {
+    /// JSON lines, no compression.
+    #[default]
+    None,
+    /// gzip frames (requires the `gzip` feature).
+    Gzip,
+    /// zstd frames (requires the `zstd` feature).
+    Zstd,
 }
 
-impl CommandWriter {
-    async fn send(&self, request: &CommandRequest) -> Result<(), CommandError> {
-        let line = serde_json::to_string(request)?;
+impl CommandCompression {
+    /// Returns `true` if this build can encode and decode the codec.
+    pub fn is_supported(self) -> bool {
         match self {
-            CommandWriter::Stdio(writer) => Self::write_line(writer, &line).await,
-            CommandWriter::Tcp(writer) => Self::write_line(writer, &line).await,
-            #[cfg(unix)]
-            CommandWriter::Unix(writer) => Self::write_line(writer, &line).await,
-            CommandWriter::Unavailable(reason) => {
-                Err(CommandError::Unavailable(reason.as_ref().clone()))
-            }
+            CommandCompression::None => true,
+            CommandCompression::Gzip => cfg!(feature = "gzip"),
+            CommandCompression::Zstd => cfg!(feature = "zstd"),
         }
     }
 
-    async fn write_line<W>(writer: &Mutex<W>, line: &str) -> Result<(), CommandError>
-    where
-        W: AsyncWrite + Unpin + Send,
-    {
-        let mut guard = writer.lock().await;
-        guard.write_all(line.as_bytes()).await?;
-        guard.write_all(b"\n").await?;
-        guard.flush().await?;
-        Ok(())
+    fn flag(self) -> u8 {
+        match self {
+            CommandCompression::None => 0,
+            CommandCompression::Gzip => 1,
+            CommandCompression::Zstd => 2,
+        }
     }
-}
 
-impl CommandReader {
-    async fn read(&self) -> Result<CommandResponse, CommandError> {
+    fn from_flag(flag: u8) -> Result<Self, CommandError> {
+        match flag {
+            0 => Ok(CommandCompression::None),
+            1 => Ok(CommandCompression::Gzip),
+            2 => Ok(CommandCompression::Zstd),
+            other => Err(invalid_frame(format!("unknown frame flag {other}"))),
+        }
+    }
+
+    fn encode(self, json: &[u8]) -> Result<Vec<u8>, CommandError> {
         match self {
-            CommandReader::Stdio(reader) => Self::read_line(reader).await,
-            CommandReader::Tcp(reader) => Self::read_line(reader).await,
-            #[cfg(unix)]
-            CommandReader::Unix(reader) => Self::read_line(reader).await,
-            CommandReader::Unavailable(reason) => {
-                Err(CommandError::Unavailable(reason.as_ref().clone()))
+            CommandCompression::None => Ok(json.to_vec()),
+            #[cfg(feature = "gzip")]
+            CommandCompression::Gzip => {
+                use std::io::Write;
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(json)?;
+                Ok(encoder.finish()?)
             }
+            #[cfg(feature = "zstd")]
+            CommandCompression::Zstd => Ok(zstd::encode_all(json, 0)?),
+            #[allow(unreachable_patterns)]
+            codec => Err(invalid_frame(format!(
+                "{codec:?} support is not compiled in"
+            ))),
         }
     }
 
-    async fn read_line<R>(reader: &Mutex<BufReader<R>>) -> Result<CommandResponse, CommandError>
-    where
-        R: AsyncRead + Unpin + Send,
-    {
-        let mut guard = reader.lock().await;
-        let mut buf = String::new();
-        let read = guard.read_line(&mut buf).await?;
+    /// Decompresses `body`, failing with [`CommandError::ResponseTooLarge`] once the output
+    /// exceeds `limit` bytes.
+    #[cfg_attr(not(any(feature = "gzip", feature = "zstd")), allow(unused_variables))]
+    fn decode(self, body: Vec<u8>, limit: usize) -> Result<Vec<u8>, CommandError> {
+        match self {
+            CommandCompression::None => Ok(body),
+            #[cfg(feature = "gzip")]
+            CommandCompression::Gzip => {
+                read_bounded(flate2::read::GzDecoder::new(body.as_slice()), limit)
+            }
+            #[cfg(feature = "zstd")]
+            CommandCompression::Zstd => {
+                read_bounded(zstd::stream::read::Decoder::new(body.as_slice())?, limit)
+            }
+            #[allow(unreachable_patterns)]
+            codec => Err(invalid_frame(format!(
+                "{codec:?} support is not compiled in"
+            ))),
+        }
+    }
+}
+
+/// Payload of a `__hello` offer or acknowledgement.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+struct Hello {
+    #[serde(default)]
+    compression: CommandCompression,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    protocol_version: Option<u32>,
+}
+
+/// Drains a decompressor, stopping with [`CommandError::ResponseTooLarge`] past `limit` bytes.
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+fn read_bounded(decoder: impl std::io::Read, limit: usize) -> Result<Vec<u8>, CommandError> {
+    use std::io::Read;
+    let mut json = Vec::new();
+    decoder.take(limit as u64 + 1).read_to_end(&mut json)?;
+    if json.len() > limit {
+        return Err(CommandError::ResponseTooLarge { limit });
+    }
+    Ok(json)
+}
+
+/// Body streamed after a request header by [`CommandClient::send_with_body`].
+type UploadBody<'a> = &'a mut (dyn AsyncRead + Unpin + Send);
+
+/// Streams `body` as length-prefixed chunks (a big-endian `u32` length, then the bytes), ending
+/// with an empty chunk.
+async fn write_body<W>(writer: &mut W, body: UploadBody<'_>) -> Result<(), CommandError>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    let mut chunk = vec![0; BODY_CHUNK_LEN];
+    loop {
+        let read = body.read(&mut chunk).await?;
+        writer.write_u32(read as u32).await?;
         if read == 0 {
-            return Err(CommandError::TransportClosed);
+            break;
         }
-        let response = serde_json::from_str(&buf)?;
+        writer.write_all(&chunk[..read]).await?;
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Collects a body written by [`write_body`].
+async fn read_body<R>(reader: &mut R) -> Result<Vec<u8>, CommandError>
+where
+    R: AsyncBufRead + Unpin + Send,
+{
+    let mut body = Vec::new();
+    loop {
+        let len = reader.read_u32().await? as usize;
+        if len == 0 {
+            return Ok(body);
+        }
+        if len > MAX_FRAME_LEN {
+            return Err(invalid_frame(format!(
+                "body chunk of {len} bytes is too large"
+            )));
+        }
+        let start = body.len();
+        body.resize(start + len, 0);
+        reader.read_exact(&mut body[start..]).await?;
+    }
+}
+
+fn invalid_frame(message: String) -> CommandError {
+    CommandError::Io(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+/// Checks that a response message is UTF-8 before it reaches the JSON parser.
+///
+/// The message has already been read in full, so the stream stays in sync and the next
+/// response can still be read. The error carries a lossy preview of the offending bytes.
+fn decode_utf8(message: &[u8]) -> Result<&str, CommandError> {
+    std::str::from_utf8(message).map_err(|err| {
+        let preview = &message[..message.len().min(MALFORMED_PREVIEW_LEN)];
+        CommandError::MalformedResponse(format!(
+            "invalid UTF-8 at byte {}: {:?}",
+            err.valid_up_to(),
+            String::from_utf8_lossy(preview).trim_end()
+        ))
+    })
+}
+
+/// Writes one message in the channel's framing: a JSON line, or a flagged, length-prefixed
+/// frame once compression has been negotiated.
+async fn write_message<W>(
+    writer: &mut W,
+    json: &[u8],
+    compression: CommandCompression,
+) -> Result<(), CommandError>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    if compression == CommandCompression::None {
+        writer.write_all(json).await?;
+        writer.write_all(b"\n").await?;
+    } else {
+        let encoded = compression.encode(json)?;
+        let (flag, body) = if encoded.len() < json.len() {
+            (compression.flag(), encoded.as_slice())
+        } else {
+            (CommandCompression::None.flag(), json)
+        };
+        let len = u32::try_from(body.len())
+            .ok()
+            .filter(|len| *len as usize <= MAX_FRAME_LEN)
+            .ok_or_else(|| invalid_frame(format!("frame of {} bytes is too large", body.len())))?;
+        writer.write_u8(flag).await?;
+        writer.write_u32(len).await?;
+        writer.write_all(body).await?;
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Reads one message written by [`write_message`], returning `None` at end of stream.
+///
+/// Uncompressed messages end at `delimiter`, which is stripped along with a `\r` before it.
+/// Messages longer than `limit` bytes fail with [`CommandError::ResponseTooLarge`] without being
+/// buffered in full; the stream is left mid-message.
+///
+/// A JSON line cut short by end of stream fails with [`CommandError::TransportClosed`] rather
+/// than surfacing as a parse error.
+async fn read_message<R>(
+    reader: &mut R,
+    compression: CommandCompression,
+    limit: usize,
+    delimiter: u8,
+) -> Result<Option<Vec<u8>>, CommandError>
+where
+    R: AsyncBufRead + Unpin + Send,
+{
+    if compression == CommandCompression::None {
+        let mut line = Vec::new();
+        let read = (&mut *reader)
+            .take(limit as u64 + 1)
+            .read_until(delimiter, &mut line)
+            .await?;
+        // Reading at most `limit + 1` bytes means a complete line always fits within the limit.
+        return match line.last() {
+            Some(last) if *last == delimiter => {
+                line.pop();
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                Ok(Some(line))
+            }
+            _ if read == 0 => Ok(None),
+            _ if line.len() > limit => Err(CommandError::ResponseTooLarge { limit }),
+            // The peer hung up part-way through a line.
+            _ => Err(CommandError::TransportClosed),
+        };
+    }
+
+    let flag = match reader.read_u8().await {
+        Ok(flag) => flag,
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let codec = CommandCompression::from_flag(flag)?;
+    let len = reader.read_u32().await? as usize;
+    if len > limit {
+        return Err(CommandError::ResponseTooLarge { limit });
+    }
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).await?;
+    codec.decode(body, limit).map(Some)
+}
+
+/// Bytes before the body of a binary frame: the codec flag and the `u32` length.
+const FRAME_HEADER_LEN: usize = 5;
+
+/// Client side of [`read_message`] that keeps a partly read response across cancelled reads.
+///
+/// A read cut short by a timeout resumes where it stopped, and the rest of an oversized response
+/// is skipped before the next one, so frames stay aligned with the host's replies.
+#[derive(Debug)]
+struct ResponseReader<R> {
+    reader: BufReader<R>,
+    /// The response read so far; binary frames include their header.
+    partial: Vec<u8>,
+    /// What is left of a response rejected as too large.
+    skip: Skip,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Skip {
+    Nothing,
+    ToDelimiter,
+    Bytes(usize),
+}
+
+impl<R> ResponseReader<R>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    fn new(reader: R) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            partial: Vec::new(),
+            skip: Skip::Nothing,
+        }
+    }
+
+    /// Reads the next response, returning `None` at end of stream. Cancel safe.
+    async fn next(
+        &mut self,
+        compression: CommandCompression,
+        limit: usize,
+        delimiter: u8,
+    ) -> Result<Option<Vec<u8>>, CommandError> {
+        self.skip_rest(delimiter).await?;
+        if compression == CommandCompression::None {
+            self.next_line(limit, delimiter).await
+        } else {
+            self.next_frame(limit).await
+        }
+    }
+
+    async fn skip_rest(&mut self, delimiter: u8) -> Result<(), CommandError> {
+        loop {
+            let skip = self.skip;
+            if let Skip::Nothing = skip {
+                return Ok(());
+            }
+            let buf = self.reader.fill_buf().await?;
+            if buf.is_empty() {
+                return Err(CommandError::TransportClosed);
+            }
+            let (used, rest) = match skip {
+                Skip::ToDelimiter => match buf.iter().position(|&byte| byte == delimiter) {
+                    Some(end) => (end + 1, Skip::Nothing),
+                    None => (buf.len(), Skip::ToDelimiter),
+                },
+                Skip::Bytes(left) if left <= buf.len() => (left, Skip::Nothing),
+                Skip::Bytes(left) => (buf.len(), Skip::Bytes(left - buf.len())),
+                Skip::Nothing => (0, Skip::Nothing),
+            };
+            self.reader.consume(used);
+            self.skip = rest;
+        }
+    }
+
+    async fn next_line(
+        &mut self,
+        limit: usize,
+        delimiter: u8,
+    ) -> Result<Option<Vec<u8>>, CommandError> {
+        loop {
+            let buf = self.reader.fill_buf().await?;
+            if buf.is_empty() {
+                if self.partial.is_empty() {
+                    return Ok(None);
+                }
+                // The peer hung up part-way through a line.
+                self.partial.clear();
+                return Err(CommandError::TransportClosed);
+            }
+            let end = buf.iter().position(|&byte| byte == delimiter);
+            let chunk = &buf[..end.unwrap_or(buf.len())];
+            if self.partial.len() + chunk.len() > limit {
+                let used = end.map_or(buf.len(), |end| end + 1);
+                self.reader.consume(used);
+                self.partial.clear();
+                if end.is_none() {
+                    self.skip = Skip::ToDelimiter;
+                }
+                return Err(CommandError::ResponseTooLarge { limit });
+            }
+            self.partial.extend_from_slice(chunk);
+            let Some(end) = end else {
+                let used = buf.len();
+                self.reader.consume(used);
+                continue;
+            };
+            self.reader.consume(end + 1);
+            let mut line = std::mem::take(&mut self.partial);
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            return Ok(Some(line));
+        }
+    }
+
+    async fn next_frame(&mut self, limit: usize) -> Result<Option<Vec<u8>>, CommandError> {
+        loop {
+            let mut target = FRAME_HEADER_LEN;
+            if self.partial.len() >= FRAME_HEADER_LEN {
+                let mut len = [0; 4];
+                len.copy_from_slice(&self.partial[1..FRAME_HEADER_LEN]);
+                let len = u32::from_be_bytes(len) as usize;
+                if len > limit {
+                    self.partial.clear();
+                    self.skip = Skip::Bytes(len);
+                    return Err(CommandError::ResponseTooLarge { limit });
+                }
+                target += len;
+                if self.partial.len() == target {
+                    let codec = CommandCompression::from_flag(self.partial[0]);
+                    let body = self.partial.split_off(FRAME_HEADER_LEN);
+                    self.partial.clear();
+                    return codec?.decode(body, limit).map(Some);
+                }
+            }
+            let buf = self.reader.fill_buf().await?;
+            if buf.is_empty() {
+                if self.partial.is_empty() {
+                    return Ok(None);
+                }
+                self.partial.clear();
+                return Err(CommandError::TransportClosed);
+            }
+            let used = buf.len().min(target - self.partial.len());
+            self.partial.extend_from_slice(&buf[..used]);
+            self.reader.consume(used);
+        }
+    }
+}
+
+/// One candidate transport tried while resolving [`CommandEndpoint::Auto`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AutoProbe {
+    /// Connects to the socket at this path if the file exists.
+    #[cfg(unix)]
+    UnixSocket(PathBuf),
+    /// Connects to the `host:port` stored in this environment variable, if it is set.
+    TcpFromEnv(String),
+    /// Uses stdin/stdout when stdin is not a terminal (i.e. something is piped in).
+    Stdio,
+}
+
+impl AutoProbe {
+    /// The documented probe order: [`DEFAULT_UNIX_SOCKET_PATH`] (Unix only), then the address in
+    /// [`AUTO_TCP_ADDR_ENV`], then stdio.
+    pub fn default_order() -> Vec<AutoProbe> {
+        vec![
+            #[cfg(unix)]
+            AutoProbe::UnixSocket(PathBuf::from(DEFAULT_UNIX_SOCKET_PATH)),
+            AutoProbe::TcpFromEnv(AUTO_TCP_ADDR_ENV.to_owned()),
+            AutoProbe::Stdio,
+        ]
+    }
+
+    /// Returns the concrete endpoint for this probe, or `None` when it is not available here.
+    fn candidate(&self) -> Option<CommandEndpoint> {
+        match self {
+            #[cfg(unix)]
+            AutoProbe::UnixSocket(path) => path
+                .exists()
+                .then(|| CommandEndpoint::UnixSocket(path.clone())),
+            AutoProbe::TcpFromEnv(var) => std::env::var(var)
+                .ok()
+                .map(|addr| addr.trim().to_owned())
+                .filter(|addr| !addr.is_empty())
+                .map(CommandEndpoint::Tcp),
+            AutoProbe::Stdio => (!stdin_is_terminal()).then_some(CommandEndpoint::Stdio),
+        }
+    }
+}
+
+/// Whether a [`CommandClient`]'s transport is up, as published by
+/// [`CommandClient::connection_state`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// The transport is open and in step with the host.
+    Connected,
+    /// The transport failed; the next command, keepalive tick, or
+    /// [`wait_ready`](CommandClient::wait_ready) reconnects it.
+    Disconnected,
+    /// A new connection is being opened.
+    Reconnecting,
+    /// Closed, unavailable, or a failed stdio channel; it never recovers.
+    Closed,
+}
+
+#[derive(Debug)]
+struct CommandClientInner {
+    endpoint: CommandEndpoint,
+    /// Swapped for a fresh connection by [`CommandClientInner::reconnect`].
+    transport: RwLock<Arc<Transport>>,
+    /// Settings for reopening the transport; `None` for stdio, which cannot be reopened.
+    reconnect: Option<Reconnect>,
+    /// Set by [`CommandClient::unavailable`].
+    unavailable: Option<Arc<String>>,
+    closed: AtomicBool,
+    /// Set once the transport is broken, or, for TCP and Unix sockets, owes a reply nobody waits
+    /// for (see [`Transport::abandoned`]). The next exchange reconnects first when it can; a
+    /// failed stdio channel stays closed.
+    failed: AtomicBool,
+    /// Published alongside `closed` and `failed` for [`CommandClient::connection_state`].
+    state: watch::Sender<ConnectionState>,
+    /// Held for a whole request/response exchange: responses carry no correlation id, so only
+    /// one command may be in flight on the stream at a time.
+    exchange: Mutex<()>,
+    /// Read on Tokio's clock, like every timer here, so a paused test clock drives keepalives.
+    last_activity: std::sync::Mutex<Instant>,
+    /// Admission control configured through [`CommandOptions::max_in_flight`].
+    in_flight: Option<Semaphore>,
+    /// Framing agreed in the `__hello` handshake; fixed before the client is shared.
+    compression: CommandCompression,
+    /// Version agreed in the `__hello` handshake, when one was offered.
+    protocol_version: Option<u32>,
+    /// See [`CommandOptions::max_response_bytes`].
+    max_response_bytes: usize,
+    /// See [`CommandOptions::line_delimiter`].
+    line_delimiter: u8,
+    /// See [`CommandOptions::response_validator`].
+    response_validator: Option<ResponseValidator>,
+    /// Counters behind [`CommandClient::stats`], shared by every handle on this transport.
+    stats: ChannelStats,
+}
+
+/// One connection's halves, replaced wholesale when the client reconnects.
+#[derive(Debug)]
+struct Transport {
+    writer: CommandWriter,
+    reader: CommandReader,
+    /// Replies to commands nobody waits for any more, discarded before the next read.
+    abandoned: AtomicUsize,
+}
+
+impl Transport {
+    fn new(writer: CommandWriter, reader: CommandReader) -> Self {
+        Self {
+            writer,
+            reader,
+            abandoned: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// What [`CommandClientInner::reconnect`] needs to open a fresh transport to the same host.
+#[derive(Debug)]
+struct Reconnect {
+    connect_timeout: Option<Duration>,
+    connect_gate: Option<ConnectGate>,
+    keepalive: Option<Duration>,
+    /// The `__hello` offer the first connection made, repeated on every new one.
+    offer: Option<Hello>,
+    timeout: Duration,
+    probe_timeout: Duration,
+}
+
+/// Point-in-time counters for a command channel, returned by [`CommandClient::stats`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct CommandStats {
+    /// Commands issued with `send`, `send_with_timeout`, or `try_send` (notifications excluded).
+    pub sent: u64,
+    /// Commands that returned an error, including host-reported failures.
+    pub failed: u64,
+    /// Commands currently waiting for a response or an in-flight slot.
+    pub in_flight: u64,
+    /// When the most recent failure happened.
+    pub last_error_at: Option<SystemTime>,
+    /// The offset measured by the most recent successful [`CommandClient::sync_time`].
+    pub clock_offset: Option<ClockOffset>,
+}
+
+/// Estimated difference between the host's clock and the local one, measured by
+/// [`CommandClient::sync_time`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClockOffset {
+    /// The host's clock reads later than the local clock by this much.
+    HostAhead(Duration),
+    /// The host's clock reads earlier than the local clock by this much.
+    HostBehind(Duration),
+}
+
+impl ClockOffset {
+    /// Returns how far apart the clocks are, regardless of direction.
+    pub fn magnitude(&self) -> Duration {
+        match self {
+            ClockOffset::HostAhead(offset) | ClockOffset::HostBehind(offset) => *offset,
+        }
+    }
+}
+
+/// A command the host advertises through [`CommandClient::list_commands`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CommandDescriptor {
+    /// Command verb to put in [`CommandRequest::command`].
+    pub name: String,
+    /// Human-readable summary (empty when the host gives none).
+    #[serde(default)]
+    pub description: String,
+    /// JSON Schema the command's payload must satisfy, when the host publishes one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_schema: Option<Value>,
+}
+
+/// Reply to a `__commands` request.
+#[derive(Debug, Deserialize)]
+struct CommandList {
+    commands: Vec<CommandDescriptor>,
+}
+
+/// Payload of a `__time` request and its reply: a wall-clock reading in milliseconds since the
+/// Unix epoch.
+#[derive(Debug, Serialize, Deserialize)]
+struct TimeSync {
+    unix_ms: u64,
+}
+
+#[derive(Debug, Default)]
+struct ChannelStats {
+    sent: AtomicU64,
+    failed: AtomicU64,
+    in_flight: AtomicU64,
+    /// Milliseconds since the Unix epoch; zero until the first failure.
+    last_error_ms: AtomicU64,
+    clock_offset: std::sync::Mutex<Option<ClockOffset>>,
+}
+
+impl ChannelStats {
+    fn begin(&self) -> InFlight<'_> {
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(self)
+    }
+
+    fn record_failure(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        self.last_error_ms.store(now.max(1), Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> CommandStats {
+        let last_error_ms = self.last_error_ms.load(Ordering::Relaxed);
+        CommandStats {
+            sent: self.sent.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            last_error_at: (last_error_ms > 0)
+                .then(|| SystemTime::UNIX_EPOCH + Duration::from_millis(last_error_ms)),
+            clock_offset: *self
+                .clock_offset
+                .lock()
+                .unwrap_or_else(|err| err.into_inner()),
+        }
+    }
+}
+
+/// Counts a command as in flight until dropped, so cancelled sends are not leaked.
+struct InFlight<'a>(&'a ChannelStats);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A reply owed on `transport` from the moment its request is written until it is read.
+///
+/// Dropped unsettled, because the wait timed out or the caller's future was cancelled, it counts
+/// the reply as abandoned so the reader discards it, and fails a TCP or Unix socket transport so
+/// the next command reconnects instead.
+struct PendingReply<'a> {
+    inner: &'a CommandClientInner,
+    transport: &'a Transport,
+    settled: bool,
+}
+
+impl Drop for PendingReply<'_> {
+    fn drop(&mut self) {
+        if !self.settled {
+            self.transport.abandoned.fetch_add(1, Ordering::AcqRel);
+            self.inner.abandon(self.transport);
+        }
+    }
+}
+
+/// Publishes [`ConnectionState::Disconnected`] when a reconnect fails or is cancelled part-way.
+struct ReconnectAttempt<'a>(&'a CommandClientInner);
+
+impl Drop for ReconnectAttempt<'_> {
+    fn drop(&mut self) {
+        if self.0.failed.load(Ordering::Acquire) {
+            self.0.set_state(ConnectionState::Disconnected);
+        }
+    }
+}
+
+impl CommandClientInner {
+    fn new(endpoint: CommandEndpoint, writer: CommandWriter, reader: CommandReader) -> Self {
+        Self {
+            endpoint,
+            transport: RwLock::new(Arc::new(Transport::new(writer, reader))),
+            reconnect: None,
+            unavailable: None,
+            closed: AtomicBool::new(false),
+            failed: AtomicBool::new(false),
+            state: watch::Sender::new(ConnectionState::Connected),
+            exchange: Mutex::new(()),
+            last_activity: std::sync::Mutex::new(Instant::now()),
+            in_flight: None,
+            compression: CommandCompression::None,
+            protocol_version: None,
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+            line_delimiter: b'\n',
+            response_validator: None,
+            stats: ChannelStats::default(),
+        }
+    }
+
+    fn is_usable(&self) -> bool {
+        !self.closed.load(Ordering::Acquire) && !self.failed.load(Ordering::Acquire)
+    }
+
+    fn transport(&self) -> Arc<Transport> {
+        self.transport
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    /// Marks the channel failed and closes `transport`'s write side so the host sees end-of-file,
+    /// unless it has already been replaced by a newer connection.
+    async fn fail(&self, transport: &Arc<Transport>) {
+        if !Arc::ptr_eq(&self.transport(), transport) {
+            return;
+        }
+        self.failed.store(true, Ordering::Release);
+        self.set_state(if self.reconnect.is_some() {
+            ConnectionState::Disconnected
+        } else {
+            ConnectionState::Closed
+        });
+        let _ = transport.writer.close().await;
+    }
+
+    /// Publishes `next`, unless the channel is already [`ConnectionState::Closed`].
+    fn set_state(&self, next: ConnectionState) {
+        self.state.send_if_modified(|state| {
+            if *state == ConnectionState::Closed || *state == next {
+                return false;
+            }
+            *state = next;
+            true
+        });
+    }
+
+    /// Reconnects a failed channel; callers hold the `exchange` lock.
+    ///
+    /// # Errors
+    /// Returns [`CommandError::TransportClosed`] when the channel was closed, cannot be reopened
+    /// (stdio), or the new connection fails.
+    async fn ensure_connected(&self) -> Result<(), CommandError> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(CommandError::TransportClosed);
+        }
+        if !self.failed.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        self.set_state(ConnectionState::Reconnecting);
+        let _attempt = ReconnectAttempt(self);
+        self.reconnect()
+            .await
+            .map_err(|_| CommandError::TransportClosed)
+    }
+
+    /// Opens a new transport to the endpoint, repeats the original `__hello` offer, and swaps it
+    /// in. Fails when the host now agrees to different settings.
+    async fn reconnect(&self) -> Result<(), CommandError> {
+        let Some(reconnect) = &self.reconnect else {
+            return Err(CommandError::TransportClosed);
+        };
+        let _slot = match &reconnect.connect_gate {
+            Some(gate) => Some(gate.enter().await),
+            None => None,
+        };
+        let open = open_transport(&self.endpoint, reconnect.keepalive);
+        let (writer, reader) = match reconnect.connect_timeout {
+            Some(limit) => time::timeout(limit, open)
+                .await
+                .map_err(|_| connect_timed_out(&self.endpoint, limit))??,
+            None => open.await?,
+        };
+        let transport = Transport::new(writer, reader);
+        if let Some(offer) = reconnect.offer {
+            let agreed = negotiate(
+                self,
+                &transport,
+                offer,
+                reconnect.timeout,
+                reconnect.probe_timeout,
+            )
+            .await?;
+            if agreed != (self.compression, self.protocol_version) {
+                return Err(CommandError::Unavailable(
+                    "host agreed to different settings after reconnecting".into(),
+                ));
+            }
+        }
+        *self
+            .transport
+            .write()
+            .unwrap_or_else(|err| err.into_inner()) = Arc::new(transport);
+        self.failed.store(false, Ordering::Release);
+        self.set_state(ConnectionState::Connected);
+        self.touch();
+        Ok(())
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_activity
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .elapsed()
+    }
+
+    fn touch(&self) {
+        *self
+            .last_activity
+            .lock()
+            .unwrap_or_else(|err| err.into_inner()) = Instant::now();
+    }
+
+    /// Writes `request` and waits up to `timeout` for the matching response line.
+    async fn exchange(
+        &self,
+        request: &CommandRequest,
+        timeout: Duration,
+    ) -> Result<CommandResponse, CommandError> {
+        self.exchange_with_body(request, None, timeout).await
+    }
+
+    /// Like [`CommandClientInner::exchange`], streaming `body` after the request when given.
+    async fn exchange_with_body(
+        &self,
+        request: &CommandRequest,
+        body: Option<UploadBody<'_>>,
+        timeout: Duration,
+    ) -> Result<CommandResponse, CommandError> {
+        let _exchange = self.exchange.lock().await;
+        self.ensure_connected().await?;
+        let result = self.exchange_locked(request, body, timeout).await;
+        self.touch();
+        result
+    }
+
+    /// Exchanges over the current transport, failing the channel when the outcome leaves it out
+    /// of step with the host.
+    async fn exchange_locked(
+        &self,
+        request: &CommandRequest,
+        body: Option<UploadBody<'_>>,
+        timeout: Duration,
+    ) -> Result<CommandResponse, CommandError> {
+        let transport = self.transport();
+        let result = self
+            .exchange_on(&transport, self.compression, request, body, timeout)
+            .await;
+        let broken = match &result {
+            Err(CommandError::TransportClosed | CommandError::Io(_)) => true,
+            // The reader discards a late reply or the rest of an oversized one, but a TCP or
+            // Unix socket channel can start afresh instead of waiting for them.
+            Err(CommandError::Timeout(_) | CommandError::ResponseTooLarge { .. }) => {
+                self.reconnect.is_some()
+            }
+            _ => false,
+        };
+        if broken {
+            self.fail(&transport).await;
+        }
+        result
+    }
+
+    /// Writes `request` on `transport` and reads one response within `timeout`.
+    async fn exchange_on(
+        &self,
+        transport: &Transport,
+        compression: CommandCompression,
+        request: &CommandRequest,
+        body: Option<UploadBody<'_>>,
+        timeout: Duration,
+    ) -> Result<CommandResponse, CommandError> {
+        transport.writer.send(request, compression, body).await?;
+        let mut pending = PendingReply {
+            inner: self,
+            transport,
+            settled: false,
+        };
+        let read = async {
+            while transport.abandoned.load(Ordering::Acquire) > 0 {
+                transport
+                    .reader
+                    .discard(compression, self.max_response_bytes, self.line_delimiter)
+                    .await?;
+                transport.abandoned.fetch_sub(1, Ordering::AcqRel);
+            }
+            transport
+                .reader
+                .read(
+                    compression,
+                    self.max_response_bytes,
+                    self.line_delimiter,
+                    self.response_validator.as_ref(),
+                )
+                .await
+        };
+        let result = time::timeout(timeout, read).await;
+        // A timed-out reply is still owed, so `pending` abandons it when dropped.
+        pending.settled = result.is_ok();
+        result.unwrap_or(Err(CommandError::Timeout(timeout)))
+    }
+
+    /// Fails `transport` after a reply on it was abandoned, if it can be reconnected and is still
+    /// current. Unlike [`fail`](Self::fail) this cannot wait, so the old connection closes when
+    /// it is replaced.
+    fn abandon(&self, transport: &Transport) {
+        if self.reconnect.is_some() && std::ptr::eq(Arc::as_ptr(&self.transport()), transport) {
+            self.failed.store(true, Ordering::Release);
+            self.set_state(ConnectionState::Disconnected);
+        }
+    }
+}
+
+/// Sends the `__hello` offer uncompressed on `transport` and returns the compression and
+/// protocol version the host acknowledged.
+///
+/// Hosts without compression support answer without a `compression` field (or with
+/// `ok: false`), which leaves the channel uncompressed; hosts without versioning answer without
+/// a `protocol_version` and are assumed to speak version 0. A compression offer must be answered
+/// within `timeout`, while a version-only offer falls back to version 0 after `probe_timeout`.
+async fn negotiate(
+    inner: &CommandClientInner,
+    transport: &Transport,
+    offer: Hello,
+    timeout: Duration,
+    probe_timeout: Duration,
+) -> Result<(CommandCompression, Option<u32>), CommandError> {
+    let requested = offer.compression;
+    let offered_version = offer.protocol_version;
+    let hello = CommandRequest::new(HELLO_COMMAND, serde_json::to_value(offer)?);
+    let window = if requested == CommandCompression::None {
+        probe_timeout
+    } else {
+        timeout
+    };
+    let exchange = inner.exchange_on(transport, CommandCompression::None, &hello, None, window);
+    let ack = match exchange.await {
+        Ok(response) if response.ok => {
+            serde_json::from_value::<Hello>(response.payload).unwrap_or_default()
+        }
+        Ok(_) => Hello::default(),
+        Err(CommandError::Timeout(_)) if requested == CommandCompression::None => Hello::default(),
+        Err(err) => return Err(err),
+    };
+    let compression = if ack.compression == requested {
+        requested
+    } else {
+        CommandCompression::None
+    };
+    let protocol_version =
+        offered_version.map(|offered| ack.protocol_version.map_or(0, |agreed| agreed.min(offered)));
+    Ok((compression, protocol_version))
+}
+
+const TERMINAL_STDIN_REASON: &str = "stdin is a terminal rather than a pipe from the host; \
+     set CommandOptions::allow_terminal_stdin to use it anyway";
+
+fn stdin_is_terminal() -> bool {
+    use std::io::IsTerminal;
+    std::io::stdin().is_terminal()
+}
+
+/// The error for a connection attempt that ran out its
+/// [`connect_timeout`](CommandOptions::connect_timeout). Unlike [`CommandError::Timeout`], no
+/// command has been sent.
+fn connect_timed_out(endpoint: &CommandEndpoint, limit: Duration) -> CommandError {
+    CommandError::Io(io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("connecting to {endpoint:?} timed out after {limit:?}"),
+    ))
+}
+
+/// Opens the reader/writer pair for a concrete endpoint.
+async fn open_transport(
+    endpoint: &CommandEndpoint,
+    keepalive: Option<Duration>,
+) -> Result<(CommandWriter, CommandReader), CommandError> {
+    let transport = match endpoint {
+        CommandEndpoint::Stdio => (
+            CommandWriter::Stdio(Mutex::new(tokio::io::stdout())),
+            CommandReader::Stdio(Mutex::new(ResponseReader::new(tokio::io::stdin()))),
+        ),
+        CommandEndpoint::Tcp(addr) => {
+            let stream = TcpStream::connect(addr).await?;
+            if let Some(idle) = keepalive {
+                // The kernel counts keepalive idle time in whole seconds and rejects zero.
+                let idle = idle.max(Duration::from_secs(1));
+                socket2::SockRef::from(&stream)
+                    .set_tcp_keepalive(&socket2::TcpKeepalive::new().with_time(idle))?;
+            }
+            let (read_half, write_half) = stream.into_split();
+            (
+                CommandWriter::Tcp(Mutex::new(write_half)),
+                CommandReader::Tcp(Mutex::new(ResponseReader::new(read_half))),
+            )
+        }
+        #[cfg(unix)]
+        CommandEndpoint::UnixSocket(path) => {
+            let stream = UnixStream::connect(path).await?;
+            let (read_half, write_half) = stream.into_split();
+            (
+                CommandWriter::Unix(Mutex::new(write_half)),
+                CommandReader::Unix(Mutex::new(ResponseReader::new(read_half))),
+            )
+        }
+        CommandEndpoint::Auto => {
+            return Err(CommandError::Unavailable(
+                "auto endpoint must be resolved before opening".into(),
+            ));
+        }
+        CommandEndpoint::Unavailable => {
+            return Err(CommandError::Unavailable(
+                "command endpoint marked unavailable".into(),
+            ));
+        }
+    };
+    Ok(transport)
+}
+
+/// Tries every probe in order and returns the first transport that opens within `per_attempt`.
+async fn resolve_auto(
+    probes: &[AutoProbe],
+    per_attempt: Duration,
+    keepalive: Option<Duration>,
+) -> Result<(CommandEndpoint, (CommandWriter, CommandReader)), CommandError> {
+    let mut tried = Vec::new();
+    for probe in probes {
+        let Some(endpoint) = probe.candidate() else {
+            continue;
+        };
+        match time::timeout(per_attempt, open_transport(&endpoint, keepalive)).await {
+            Ok(Ok(transport)) => return Ok((endpoint, transport)),
+            Ok(Err(err)) => tried.push(format!("{endpoint:?}: {err}")),
+            Err(_) => tried.push(format!("{endpoint:?}: timed out after {per_attempt:?}")),
+        }
+    }
+    let detail = if tried.is_empty() {
+        "no transport was present".to_owned()
+    } else {
+        tried.join("; ")
+    };
+    Err(CommandError::Unavailable(format!(
+        "auto endpoint found no usable transport ({detail})"
+    )))
+}
+
+/// Pings the host whenever the connection has been idle for `interval`, until the client is
+/// dropped or closed. A probe unanswered within `timeout` fails the channel, and later ticks try
+/// to reconnect it.
+async fn keepalive_task(inner: Weak<CommandClientInner>, interval: Duration, timeout: Duration) {
+    let mut ticker = time::interval(interval);
+    ticker.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+        if inner.closed.load(Ordering::Acquire) {
+            return;
+        }
+        // A command in flight proves the connection is being exercised already.
+        let Ok(_exchange) = inner.exchange.try_lock() else {
+            continue;
+        };
+        if inner.failed.load(Ordering::Acquire) {
+            if inner.reconnect.is_none() {
+                return;
+            }
+            let _ = inner.ensure_connected().await;
+            continue;
+        }
+        if inner.idle_for() < interval {
+            continue;
+        }
+
+        let ping = CommandRequest::empty(PING_COMMAND);
+        // A failed ping marks the channel failed.
+        let _ = inner.exchange_locked(&ping, None, timeout).await;
+        inner.touch();
+    }
+}
+
+impl CommandClient {
+    /// Connects to the configured endpoint using the default timeout.
+    ///
+    /// # Parameters
+    /// * `endpoint` - Transport descriptor (stdio, TCP, or Unix socket).
+    ///
+    /// # Returns
+    /// A connected [`CommandClient`] ready to issue commands.
+    ///
+    /// # Errors
+    /// Returns [`CommandError`] if the underlying transport cannot be opened or is closed
+    /// before the connection is established.
+    ///
+    /// # Panics
+    /// Does not panic.
+    pub async fn connect(endpoint: CommandEndpoint) -> Result<Self, CommandError> {
+        Self::connect_with_options(endpoint, CommandOptions::default()).await
+    }
+
+    /// Connects to the endpoint and enforces a custom read timeout.
+    ///
+    /// # Parameters
+    /// * `endpoint` - Transport descriptor (stdio, TCP, or Unix socket).
+    /// * `timeout` - Maximum duration to wait for the connection to open, and then for each
+    ///   response, before failing. Use [`CommandOptions::connect_timeout`] and
+    ///   [`CommandOptions::timeout`] with [`CommandClient::connect_with_options`] to set them
+    ///   separately.
+    ///
+    /// # Returns
+    /// A connected [`CommandClient`] that enforces the provided timeout for every command.
+    ///
+    /// # Errors
+    /// Returns [`CommandError`] if the underlying transport cannot be opened or the timeout
+    /// elapses while establishing the connection.
+    ///
+    /// # Panics
+    /// Does not panic.
+    pub async fn connect_with_timeout(
+        endpoint: CommandEndpoint,
+        timeout: Duration,
+    ) -> Result<Self, CommandError> {
+        let options = CommandOptions::default()
+            .timeout(timeout)
+            .connect_timeout(timeout);
+        Self::connect_with_options(endpoint, options).await
+    }
+
+    /// Connects to the endpoint with explicit [`CommandOptions`].
+    ///
+    /// For [`CommandEndpoint::Auto`], each entry of `options.auto_probes` is attempted in order
+    /// with `options.probe_timeout` per attempt; the first transport that opens wins.
+    ///
+    /// # Errors
+    /// Returns [`CommandError`] if the underlying transport cannot be opened or configured, or
+    /// [`CommandError::Unavailable`] when no auto probe succeeds.
+    ///
+    /// # Panics
+    /// Panics if `options.keepalive` is set and this is called outside a Tokio runtime.
+    pub async fn connect_with_options(
+        endpoint: CommandEndpoint,
+        options: CommandOptions,
+    ) -> Result<Self, CommandError> {
+        let CommandOptions {
+            timeout,
+            connect_timeout,
+            connect_gate,
+            keepalive,
+            max_in_flight,
+            auto_probes,
+            probe_timeout,
+            compression,
+            max_response_bytes,
+            allow_terminal_stdin,
+            response_validator,
+            protocol_version,
+            line_delimiter,
+        } = options;
+        if endpoint == CommandEndpoint::Stdio && !allow_terminal_stdin && stdin_is_terminal() {
+            return Ok(Self::unavailable(TERMINAL_STDIN_REASON));
+        }
+        let _slot = match &connect_gate {
+            Some(gate) => Some(gate.enter().await),
+            None => None,
+        };
+        let (endpoint, (writer, reader)) = match endpoint {
+            CommandEndpoint::Auto => resolve_auto(&auto_probes, probe_timeout, keepalive).await?,
+            endpoint => {
+                let open = open_transport(&endpoint, keepalive);
+                let transport = match connect_timeout {
+                    Some(limit) => time::timeout(limit, open)
+                        .await
+                        .map_err(|_| connect_timed_out(&endpoint, limit))??,
+                    None => open.await?,
+                };
+                (endpoint, transport)
+            }
+        };
+
+        let mut inner = CommandClientInner::new(endpoint, writer, reader);
+        inner.in_flight = max_in_flight.map(Semaphore::new);
+        inner.max_response_bytes = max_response_bytes;
+        inner.line_delimiter = line_delimiter;
+        inner.response_validator = response_validator;
+        let compression = if compression.is_supported() {
+            compression
+        } else {
+            CommandCompression::None
+        };
+        let offer = (compression != CommandCompression::None || protocol_version.is_some())
+            .then_some(Hello {
+                compression,
+                protocol_version,
+            });
+        if let Some(offer) = offer {
+            let transport = inner.transport();
+            (inner.compression, inner.protocol_version) =
+                negotiate(&inner, &transport, offer, timeout, probe_timeout).await?;
+        }
+        if inner.endpoint != CommandEndpoint::Stdio {
+            inner.reconnect = Some(Reconnect {
+                connect_timeout,
+                connect_gate: connect_gate.clone(),
+                keepalive,
+                offer,
+                timeout,
+                probe_timeout,
+            });
+        }
+        let inner = Arc::new(inner);
+        if let Some(interval) = keepalive {
+            tokio::spawn(keepalive_task(Arc::downgrade(&inner), interval, timeout));
+        }
+
+        Ok(Self {
+            inner,
+            timeout,
+            observer: None,
+        })
+    }
+
+    /// Connects like [`CommandClient::connect`], then sends a `__hello` handshake and waits up to
+    /// `probe_timeout` for the host to answer.
+    ///
+    /// Stdio "connects" even when nothing is attached to the other end, so without a probe the
+    /// first real command only fails once its full timeout elapses. Any response counts as an
+    /// acknowledgement, including `ok: false` from hosts that do not know the handshake.
+    ///
+    /// # Errors
+    /// Returns [`CommandError::Unavailable`] when the host does not answer in time or closes the
+    /// channel, and any error [`CommandClient::connect`] can return.
+    pub async fn connect_probed(
+        endpoint: CommandEndpoint,
+        probe_timeout: Duration,
+    ) -> Result<Self, CommandError> {
+        let client = Self::connect(endpoint).await?;
+        client.probe(probe_timeout).await?;
+        Ok(client)
+    }
+
+    /// Sends a `__hello` handshake and waits up to `timeout` for the host to answer, proving the
+    /// channel is genuinely reachable.
+    ///
+    /// # Errors
+    /// Same as [`CommandClient::connect_probed`]; an [`unavailable`](CommandClient::unavailable)
+    /// client reports its reason.
+    pub async fn probe(&self, timeout: Duration) -> Result<(), CommandError> {
+        let hello = CommandRequest::empty(HELLO_COMMAND);
+        match self.inner.exchange(&hello, timeout).await {
+            Ok(_) => Ok(()),
+            Err(CommandError::Timeout(_)) => Err(CommandError::Unavailable(format!(
+                "host did not answer {HELLO_COMMAND} within {timeout:?}"
+            ))),
+            Err(CommandError::TransportClosed) => Err(CommandError::Unavailable(
+                "host closed the command channel during the handshake".into(),
+            )),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Waits up to `timeout` for the channel to reach [`ConnectionState::Connected`], for
+    /// readiness probes that should hold traffic back until the command channel is live.
+    ///
+    /// Returns at once while connected, including stdio. A disconnected TCP or Unix socket channel
+    /// is reconnected, retrying until the host is back; closed channels, stdio channels that
+    /// failed, and [`unavailable`](CommandClient::unavailable) clients fail immediately, as they
+    /// never recover.
+    ///
+    /// # Errors
+    /// Returns [`CommandError::TransportClosed`] for a channel that cannot recover, the reason of an
+    /// unavailable client, and [`CommandError::Timeout`] when the channel is not connected within
+    /// `timeout`.
+    pub async fn wait_ready(&self, timeout: Duration) -> Result<(), CommandError> {
+        if let Some(reason) = self.unavailable_reason() {
+            return Err(CommandError::Unavailable(reason.to_owned()));
+        }
+        let mut state = self.connection_state();
+        let ready = async {
+            loop {
+                let current = *state.borrow_and_update();
+                match current {
+                    ConnectionState::Connected => return Ok(()),
+                    ConnectionState::Closed => return Err(CommandError::TransportClosed),
+                    ConnectionState::Reconnecting => {
+                        let _ = state.changed().await;
+                    }
+                    ConnectionState::Disconnected => {
+                        let exchange = self.inner.exchange.lock().await;
+                        let reconnected = self.inner.ensure_connected().await;
+                        drop(exchange);
+                        if reconnected.is_err() {
+                            time::sleep(READY_RETRY_INTERVAL).await;
+                        }
+                    }
+                }
+            }
+        };
+        time::timeout(timeout, ready)
+            .await
+            .map_err(|_| CommandError::Timeout(timeout))?
+    }
+
+    /// Subscribes to the channel's [`ConnectionState`], which changes as the transport fails,
+    /// reconnects, and closes.
+    ///
+    /// Every handle sharing the transport observes the same state.
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.inner.state.subscribe()
+    }
+
+    /// Creates a [`CommandClient`] that always reports an unavailable channel.
+    ///
+    /// This is useful for runtimes (Google Cloud Run, local testing, etc.) that do not expose
+    /// a host-managed command bus but still want to share the API surface.
+    pub fn unavailable(reason: impl Into<String>) -> Self {
+        let reason = reason.into();
+        let shared = Arc::new(reason);
+        let mut inner = CommandClientInner::new(
+            CommandEndpoint::Unavailable,
+            CommandWriter::Unavailable(shared.clone()),
+            CommandReader::Unavailable(shared.clone()),
+        );
+        inner.unavailable = Some(shared);
+        inner.state.send_replace(ConnectionState::Closed);
+        Self {
+            inner: Arc::new(inner),
+            timeout: DEFAULT_COMMAND_TIMEOUT,
+            observer: None,
+        }
+    }
+
+    /// Returns a handle that reports every command to `observer`.
+    ///
+    /// The transport is shared with `self`; only the returned handle (and its clones) notify the
+    /// observer.
+    pub fn with_observer(mut self, observer: Arc<dyn CommandObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Returns a handle that waits at most `timeout` for each response.
+    ///
+    /// The transport, observer, and in-flight limit are shared with `self`; only the returned
+    /// handle (and its clones) use the new timeout.
+    pub fn with_timeout(&self, timeout: Duration) -> Self {
+        Self {
+            timeout,
+            ..self.clone()
+        }
+    }
+
+    /// Returns the endpoint backing this client.
+    pub fn endpoint(&self) -> &CommandEndpoint {
+        &self.inner.endpoint
+    }
+
+    /// Returns why this client cannot send commands, if it was created unavailable.
+    pub fn unavailable_reason(&self) -> Option<&str> {
+        self.inner.unavailable.as_deref().map(String::as_str)
+    }
+
+    /// Returns the per-command response timeout.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Returns the payload compression agreed with the host.
+    pub fn compression(&self) -> CommandCompression {
+        self.inner.compression
+    }
+
+    /// Returns the protocol version agreed with the host, or `None` when
+    /// [`CommandOptions::protocol_version`] was not set.
+    ///
+    /// Gate newer commands on it, e.g. `client.protocol_version() >= Some(2)`.
+    pub fn protocol_version(&self) -> Option<u32> {
+        self.inner.protocol_version
+    }
+
+    /// Sends a command request and waits for a response (or timeout).
+    ///
+    /// # Parameters
+    /// * `request` - Structured command for the Workers sidecar.
+    ///
+    /// # Returns
+    /// The [`CommandResponse`] emitted by the sidecar.
+    ///
+    /// # Errors
+    /// Returns [`CommandError`] if the channel closes, the response payload cannot be
+    /// deserialized, the command reports a failure, or the read timeout elapses.
+    ///
+    /// # Panics
+    /// Does not panic.
+    pub async fn send(&self, request: CommandRequest) -> Result<CommandResponse, CommandError> {
+        self.dispatch(request, None, Admission::Wait, self.timeout)
+            .await
+    }
+
+    /// Like [`CommandClient::send`], but streams `body` to the host after the request so a large
+    /// upload (e.g. a file) never has to sit in memory as a [`serde_json::Value`].
+    ///
+    /// The request goes out with `"_body": true`, followed by the body in length-prefixed chunks
+    /// (a big-endian `u32` length, then that many bytes) and an empty chunk marking the end. The
+    /// chunks are never compressed. The host replies once it has consumed the whole body;
+    /// [`CommandServer`] hands it to the handler as [`CommandRequest::body`]. The timeout starts
+    /// once the upload has been written.
+    ///
+    /// # Errors
+    /// Same as [`CommandClient::send`]. Failing to read `body` part-way through leaves the host
+    /// mid-upload, so the connection is dropped like after a
+    /// [`timeout`](CommandOptions::timeout).
+    pub async fn send_with_body<B>(
+        &self,
+        mut request: CommandRequest,
+        mut body: B,
+    ) -> Result<CommandResponse, CommandError>
+    where
+        B: AsyncRead + Unpin + Send,
+    {
+        request.streams_body = true;
+        self.dispatch(request, Some(&mut body), Admission::Wait, self.timeout)
+            .await
+    }
+
+    /// Like [`CommandClient::send`], but waits at most `timeout` for this response instead of the
+    /// client's configured timeout.
+    ///
+    /// # Errors
+    /// Same as [`CommandClient::send`]; the timeout variant reports `timeout`.
+    pub async fn send_with_timeout(
+        &self,
+        request: CommandRequest,
+        timeout: Duration,
+    ) -> Result<CommandResponse, CommandError> {
+        self.dispatch(request, None, Admission::Wait, timeout).await
+    }
+
+    /// Runs [`CommandClient::send`] to completion from synchronous code (requires the `blocking`
+    /// feature).
+    ///
+    /// Outside a Tokio runtime this spins up a throwaway current-thread runtime for the call.
+    /// Inside a multi-threaded runtime it parks the calling worker with
+    /// [`tokio::task::block_in_place`], which stalls every other task scheduled on that worker
+    /// until the command completes; prefer `send(..).await` whenever you can. The client's
+    /// transport is still driven by the runtime it was connected on, so that runtime must be
+    /// running.
+    ///
+    /// # Errors
+    /// Same as [`CommandClient::send`]. Calling it from a current-thread runtime, where blocking
+    /// would deadlock, fails with an [`io::ErrorKind::WouldBlock`] [`CommandError::Io`].
+    #[cfg(feature = "blocking")]
+    pub fn send_blocking(&self, request: CommandRequest) -> Result<CommandResponse, CommandError> {
+        use tokio::runtime::{Builder, Handle, RuntimeFlavor};
+
+        match Handle::try_current() {
+            Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(|| handle.block_on(self.send(request)))
+            }
+            Ok(_) => Err(CommandError::Io(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "send_blocking cannot block a current-thread runtime; use send().await",
+            ))),
+            Err(_) => Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                .block_on(self.send(request)),
+        }
+    }
+
+    /// Like [`CommandClient::send`], but fails immediately with [`CommandError::Saturated`] when
+    /// [`CommandOptions::max_in_flight`] commands are already in flight instead of waiting.
+    ///
+    /// # Errors
+    /// Returns [`CommandError::Saturated`] when no slot is free, otherwise the same errors as
+    /// [`CommandClient::send`].
+    pub async fn try_send(&self, request: CommandRequest) -> Result<CommandResponse, CommandError> {
+        self.dispatch(request, None, Admission::FailFast, self.timeout)
+            .await
+    }
+
+    async fn dispatch(
+        &self,
+        request: CommandRequest,
+        body: Option<UploadBody<'_>>,
+        admission: Admission,
+        timeout: Duration,
+    ) -> Result<CommandResponse, CommandError> {
+        let started = Instant::now();
+        let in_flight = self.inner.stats.begin();
+        let result = self.roundtrip(&request, body, admission, timeout).await;
+        drop(in_flight);
+        if result.is_err() {
+            self.inner.stats.record_failure();
+        }
+
+        if let Some(observer) = &self.observer {
+            observer.on_command(&CommandEvent {
+                command: &request.command,
+                elapsed: started.elapsed(),
+                outcome: result.as_ref(),
+            });
+        }
+        result
+    }
+
+    /// Writes `request` as a notification and returns once it has been flushed.
+    ///
+    /// The request goes out with `notify: true`, telling the host not to reply, so nothing is
+    /// read back and no in-flight slot (see [`CommandOptions::max_in_flight`]) is taken. Use it
+    /// for commands such as log emission or metric flushes whose result the caller ignores;
+    /// host-side failures are not reported.
+    ///
+    /// # Errors
+    /// Returns [`CommandError`] if the channel is closed or unavailable, or the write fails.
+    pub async fn notify(&self, mut request: CommandRequest) -> Result<(), CommandError> {
+        if !self.inner.is_usable() {
+            let _exchange = self.inner.exchange.lock().await;
+            self.inner.ensure_connected().await?;
+        }
+        request.notify = true;
+        let transport = self.inner.transport();
+        if let Err(err) = transport
+            .writer
+            .send(&request, self.inner.compression, None)
+            .await
+        {
+            if matches!(err, CommandError::Io(_)) {
+                self.inner.fail(&transport).await;
+            }
+            return Err(err);
+        }
+        self.inner.touch();
+        Ok(())
+    }
+
+    /// Flushes pending writes and shuts down the write side of the transport.
+    ///
+    /// The host observes end-of-file, and every later [`CommandClient::send`] on this client (or
+    /// its clones) fails with [`CommandError::TransportClosed`]. Closing more than once is a no-op.
+    ///
+    /// # Errors
+    /// Returns [`CommandError::Io`] if the final flush or shutdown fails.
+    pub async fn close(&self) -> Result<(), CommandError> {
+        if self.inner.closed.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+        self.inner.state.send_replace(ConnectionState::Closed);
+        self.inner.transport().writer.close().await
+    }
+
+    /// Returns a snapshot of this channel's command counters.
+    ///
+    /// The counters belong to the transport, so every clone and
+    /// [`with_timeout`](CommandClient::with_timeout) handle reports the same totals.
+    pub fn stats(&self) -> CommandStats {
+        self.inner.stats.snapshot()
+    }
+
+    /// Estimates how far the host's clock is from the local one, e.g. to flag audit timestamps
+    /// taken while the container clock drifts.
+    ///
+    /// Sends a `__time` command whose payload is `{"unix_ms": <local time>}`; the host answers with
+    /// its own time in the same shape. Assuming the reply was produced halfway through the round
+    /// trip, the offset is the host time minus the local time at that midpoint, so its accuracy is
+    /// bounded by half the round-trip time. The result is also kept as
+    /// [`CommandStats::clock_offset`].
+    ///
+    /// # Errors
+    /// Same as [`CommandClient::send`], plus [`CommandError::MalformedResponse`] when the reply
+    /// carries no `unix_ms`.
+    pub async fn sync_time(&self) -> Result<ClockOffset, CommandError> {
+        let unix_ms = |time: SystemTime| {
+            time.duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_millis() as u64)
+        };
+        let sent_at = SystemTime::now();
+        let started = Instant::now();
+        let request = CommandRequest::new(
+            TIME_COMMAND,
+            serde_json::to_value(TimeSync {
+                unix_ms: unix_ms(sent_at),
+            })?,
+        );
+        let response = self.send(request).await?;
+        let midpoint = sent_at + started.elapsed() / 2;
+        let host = serde_json::from_value::<TimeSync>(response.payload).map_err(|err| {
+            CommandError::MalformedResponse(format!("invalid __time reply: {err}"))
+        })?;
+        let host = SystemTime::UNIX_EPOCH + Duration::from_millis(host.unix_ms);
+        let offset = match host.duration_since(midpoint) {
+            Ok(ahead) => ClockOffset::HostAhead(ahead),
+            Err(behind) => ClockOffset::HostBehind(behind.duration()),
+        };
+        *self
+            .inner
+            .stats
+            .clock_offset
+            .lock()
+            .unwrap_or_else(|err| err.into_inner()) = Some(offset);
+        Ok(offset)
+    }
+
+    /// Asks the host which commands it supports.
+    ///
+    /// Sends a `__commands` request with a `null` payload; the host answers with
+    /// `{"commands": [{"name": "...", "description": "...", "payload_schema": {...}}]}`, where
+    /// `description` and `payload_schema` may be omitted.
+    ///
+    /// # Errors
+    /// Same as [`CommandClient::send`]. Hosts without introspection answer `ok: false`, which
+    /// surfaces as [`CommandError::CommandFailure`]; a reply in any other shape fails with
+    /// [`CommandError::MalformedResponse`].
+    pub async fn list_commands(&self) -> Result<Vec<CommandDescriptor>, CommandError> {
+        let response = self.send(CommandRequest::empty(COMMANDS_COMMAND)).await?;
+        serde_json::from_value::<CommandList>(response.payload)
+            .map(|list| list.commands)
+            .map_err(|err| {
+                CommandError::MalformedResponse(format!("invalid {COMMANDS_COMMAND} reply: {err}"))
+            })
+    }
+
+    /// Indicates whether [`CommandClient::close`] has been called.
+    pub fn is_closed(&self) -> bool {
+        self.inner.closed.load(Ordering::Acquire)
+    }
+
+    async fn roundtrip(
+        &self,
+        request: &CommandRequest,
+        body: Option<UploadBody<'_>>,
+        admission: Admission,
+        timeout: Duration,
+    ) -> Result<CommandResponse, CommandError> {
+        if self.is_closed() {
+            return Err(CommandError::TransportClosed);
+        }
+        // Held until the response (or timeout) so the slot covers the whole exchange.
+        let _permit = match (&self.inner.in_flight, admission) {
+            (None, _) => None,
+            (Some(slots), Admission::Wait) => Some(
+                slots
+                    .acquire()
+                    .await
+                    .map_err(|_| CommandError::TransportClosed)?,
+            ),
+            (Some(slots), Admission::FailFast) => {
+                Some(slots.try_acquire().map_err(|_| CommandError::Saturated)?)
+            }
+        };
+        let response = self
+            .inner
+            .exchange_with_body(request, body, timeout)
+            .await?;
+
+        if response.ok {
+            Ok(response)
+        } else {
+            let diagnostic = response
+                .diagnostic
+                .clone()
+                .unwrap_or_else(|| "host returned failure".to_owned());
+            Err(CommandError::CommandFailure {
+                diagnostic,
+                payload: response.payload.clone(),
+            })
+        }
+    }
+}
+
+/// How [`CommandClient`] behaves when every in-flight slot is taken.
+#[derive(Clone, Copy, Debug)]
+enum Admission {
+    Wait,
+    FailFast,
+}
+
+/// A fixed set of independent connections to one host, handed out per caller.
+///
+/// A single [`CommandClient`] sends every command over one transport. Under heavy concurrency a
+/// pool spreads commands across `size` connections instead: [`CommandClientPool::acquire`] checks
+/// out the connection with the fewest outstanding checkouts (ties go round-robin), and the
+/// returned [`PooledClient`] gives it back when dropped. Clones share the same connections.
+///
+/// Stdio is a single stream, so a stdio pool (including [`CommandEndpoint::Auto`] resolving to
+/// stdio) is limited to one connection.
+#[derive(Clone, Debug)]
+pub struct CommandClientPool(Arc<PoolState>);
+
+#[derive(Debug)]
+struct PoolState {
+    clients: Vec<CommandClient>,
+    /// Live checkouts per connection.
+    checkouts: Vec<AtomicUsize>,
+    /// Where the next tie-breaking scan starts.
+    cursor: AtomicUsize,
+}
+
+impl CommandClientPool {
+    /// Opens `size` connections to `endpoint` with default options.
+    ///
+    /// # Errors
+    /// See [`CommandClientPool::connect_with_options`].
+    pub async fn connect(endpoint: CommandEndpoint, size: usize) -> Result<Self, CommandError> {
+        Self::connect_with_options(endpoint, size, CommandOptions::default()).await
+    }
+
+    /// Opens `size` connections to `endpoint`, each configured like
+    /// [`CommandClient::connect_with_options`].
+    ///
+    /// [`CommandEndpoint::Auto`] is resolved once; the remaining connections go to the transport
+    /// it picked. When the first connection comes back [`unavailable`](CommandClient::unavailable)
+    /// the pool holds just that client.
+    ///
+    /// # Errors
+    /// Fails with an [`io::ErrorKind::InvalidInput`] [`CommandError::Io`] when `size` is zero, or
+    /// above one for stdio, and with the first error any connection reports.
+    pub async fn connect_with_options(
+        endpoint: CommandEndpoint,
+        size: usize,
+        options: CommandOptions,
+    ) -> Result<Self, CommandError> {
+        if size == 0 {
+            return Err(invalid_pool_size(
+                "a command client pool needs at least one connection",
+            ));
+        }
+        let first = CommandClient::connect_with_options(endpoint, options.clone()).await?;
+        let endpoint = first.endpoint().clone();
+        let mut clients = vec![first];
+        if size > 1 {
+            match endpoint {
+                CommandEndpoint::Stdio => {
+                    return Err(invalid_pool_size(
+                        "stdio supports a single command connection",
+                    ));
+                }
+                CommandEndpoint::Unavailable => {}
+                endpoint => {
+                    for _ in 1..size {
+                        clients.push(
+                            CommandClient::connect_with_options(endpoint.clone(), options.clone())
+                                .await?,
+                        );
+                    }
+                }
+            }
+        }
+        Ok(Self::from_clients(clients))
+    }
+
+    fn from_clients(clients: Vec<CommandClient>) -> Self {
+        let checkouts = clients.iter().map(|_| AtomicUsize::new(0)).collect();
+        Self(Arc::new(PoolState {
+            clients,
+            checkouts,
+            cursor: AtomicUsize::new(0),
+        }))
+    }
+
+    /// Checks out the least-busy connection. Never waits: connections may be shared by several
+    /// checkouts once every one is taken.
+    pub fn acquire(&self) -> PooledClient {
+        let state = &self.0;
+        let len = state.clients.len();
+        let start = state.cursor.fetch_add(1, Ordering::Relaxed) % len;
+        let index = (0..len)
+            .map(|offset| (start + offset) % len)
+            .min_by_key(|&index| state.checkouts[index].load(Ordering::Relaxed))
+            .unwrap_or(start);
+        state.checkouts[index].fetch_add(1, Ordering::Relaxed);
+        PooledClient {
+            pool: state.clone(),
+            index,
+        }
+    }
+
+    /// Returns the number of connections in the pool.
+    pub fn size(&self) -> usize {
+        self.0.clients.len()
+    }
+
+    /// Returns every connection in the pool, e.g. to probe or inspect their stats.
+    pub fn clients(&self) -> &[CommandClient] {
+        &self.0.clients
+    }
+}
+
+fn invalid_pool_size(message: &str) -> CommandError {
+    CommandError::Io(io::Error::new(io::ErrorKind::InvalidInput, message))
+}
+
+/// A connection checked out of a [`CommandClientPool`]; derefs to its [`CommandClient`] and
+/// returns it to the pool when dropped.
+#[derive(Debug)]
+pub struct PooledClient {
+    pool: Arc<PoolState>,
+    index: usize,
+}
+
+impl std::ops::Deref for PooledClient {
+    type Target = CommandClient;
+
+    fn deref(&self) -> &CommandClient {
+        &self.pool.clients[self.index]
+    }
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        self.pool.checkouts[self.index].fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Host side of the command channel, answering [`CommandRequest`]s with a handler.
+///
+/// Speaks the same JSON-lines framing as [`CommandClient`], which makes it useful for local
+/// development and for exercising clients in tests over any byte stream (a TCP connection,
+/// a Unix socket, or an in-memory duplex pipe).
+///
+/// # Examples
+/// ```ignore
+/// use containerflare_command::{CommandResponse, CommandServer};
+///
+/// # async fn demo(stream: tokio::net::TcpStream) -> Result<(), Box<dyn std::error::Error>> {
+/// let server = CommandServer::new(|_request| CommandResponse::ok());
+/// let (reader, writer) = stream.into_split();
+/// server.serve(reader, writer).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct CommandServer {
+    handler: Arc<dyn Fn(CommandRequest) -> CommandResponse + Send + Sync>,
+    protocol_version: Option<u32>,
+}
+
+impl fmt::Debug for CommandServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CommandServer").finish_non_exhaustive()
+    }
+}
+
+impl CommandServer {
+    /// Creates a server that answers every request with `handler`.
+    pub fn new<H>(handler: H) -> Self
+    where
+        H: Fn(CommandRequest) -> CommandResponse + Send + Sync + 'static,
+    {
+        Self {
+            handler: Arc::new(handler),
+            protocol_version: None,
+        }
+    }
+
+    /// Answers version offers in `__hello` with the lower of the client's version and `version`.
+    pub fn protocol_version(mut self, version: u32) -> Self {
+        self.protocol_version = Some(version);
+        self
+    }
+
+    /// Answers requests read from `reader` until the client closes its side of the stream.
+    ///
+    /// A `__hello` offering a [`CommandCompression`] this build supports, or a protocol version
+    /// when [`CommandServer::protocol_version`] is set, is acknowledged by the server itself, and
+    /// the stream switches to compressed framing after the reply; every other request (including
+    /// plain `__hello`s) goes to the handler. Notifications (see
+    /// [`CommandClient::notify`]) are handled but never answered. Bodies streamed with
+    /// [`CommandClient::send_with_body`] are collected in memory before the handler runs.
+    ///
+    /// # Errors
+    /// Returns [`CommandError`] if the stream fails or a request is not valid JSON.
+    pub async fn serve<R, W>(&self, reader: R, mut writer: W) -> Result<(), CommandError>
+    where
+        R: AsyncRead + Unpin + Send,
+        W: AsyncWrite + Unpin + Send,
+    {
+        let mut reader = BufReader::new(reader);
+        let mut compression = CommandCompression::None;
+        while let Some(message) = read_message(&mut reader, compression, MAX_FRAME_LEN, b'\n')
+            .await
+            .map_err(|err| match err {
+                CommandError::ResponseTooLarge { limit } => {
+                    invalid_frame(format!("request larger than {limit} bytes"))
+                }
+                err => err,
+            })?
+        {
+            if message.trim_ascii().is_empty() {
+                continue;
+            }
+            let mut request: CommandRequest = serde_json::from_slice(&message)?;
+            if request.streams_body {
+                request.body = Some(read_body(&mut reader).await?);
+            }
+            let hello = (request.command == HELLO_COMMAND)
+                .then(|| serde_json::from_value::<Hello>(request.payload.clone()).ok())
+                .flatten();
+            let offered = hello
+                .as_ref()
+                .map(|hello| hello.compression)
+                .filter(|codec| *codec != CommandCompression::None && codec.is_supported());
+            let version = hello
+                .and_then(|hello| hello.protocol_version)
+                .zip(self.protocol_version)
+                .map(|(offered, supported)| offered.min(supported));
+            if request.notify {
+                (self.handler)(request);
+                continue;
+            }
+            let response = if offered.is_some() || version.is_some() {
+                let mut ack = CommandResponse::ok();
+                ack.payload = serde_json::to_value(Hello {
+                    compression: offered.unwrap_or_default(),
+                    protocol_version: version,
+                })?;
+                ack
+            } else {
+                (self.handler)(request)
+            };
+            let encoded = serde_json::to_vec(&response)?;
+            write_message(&mut writer, &encoded, compression).await?;
+            if let Some(codec) = offered {
+                compression = codec;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// JSON payload describing a command issued to the host.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CommandRequest {
+    /// Command verb recognized by the Workers sidecar.
+    pub command: String,
+    /// Structured JSON payload to accompany the command (defaults to `null`).
+    ///
+    /// Ignored on the wire when the request was built with [`CommandRequest::raw`].
+    #[serde(default)]
+    pub payload: serde_json::Value,
+    /// Trace identifiers of the request that issued the command, serialized as `_trace` so the
+    /// host can correlate its work with the originating HTTP request.
+    #[serde(default, rename = "_trace")]
+    pub trace: Option<CommandTrace>,
+    /// Marks a notification: the host must not reply. Set by [`CommandClient::notify`].
+    #[serde(default)]
+    pub notify: bool,
+    /// Marks a dry run: the host validates the command and answers as usual, but must not cause
+    /// any side effects. Set by [`CommandRequest::dry_run`].
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(skip)]
+    raw_payload: Option<Box<RawValue>>,
+    /// Set by [`CommandClient::send_with_body`]; a streamed body follows the request.
+    #[serde(default, rename = "_body")]
+    streams_body: bool,
+    #[serde(skip)]
+    body: Option<Vec<u8>>,
+}
+
+impl CommandRequest {
+    /// Creates a new request with the provided command name and payload.
+    pub fn new(command: impl Into<String>, payload: serde_json::Value) -> Self {
+        Self {
+            command: command.into(),
+            payload,
+            trace: None,
+            notify: false,
+            dry_run: false,
+            raw_payload: None,
+            streams_body: false,
+            body: None,
+        }
+    }
+
+    /// Creates a request whose payload is `null`.
+    pub fn empty(command: impl Into<String>) -> Self {
+        Self::new(command, serde_json::Value::Null)
+    }
+
+    /// Creates a request around already-serialized JSON, which is written to the host verbatim
+    /// instead of being parsed into a [`serde_json::Value`] and re-serialized.
+    ///
+    /// ```
+    /// use containerflare_command::CommandRequest;
+    /// use serde_json::value::RawValue;
+    ///
+    /// let blob = RawValue::from_string(r#"{"cached": true}"#.to_owned()).unwrap();
+    /// let request = CommandRequest::raw("kv_put_json", blob);
+    /// assert_eq!(
+    ///     serde_json::to_string(&request).unwrap(),
+    ///     r#"{"command":"kv_put_json","payload":{"cached": true}}"#
+    /// );
+    /// ```
+    pub fn raw(command: impl Into<String>, payload: Box<RawValue>) -> Self {
+        Self {
+            command: command.into(),
+            payload: serde_json::Value::Null,
+            trace: None,
+            notify: false,
+            dry_run: false,
+            raw_payload: Some(payload),
+            streams_body: false,
+            body: None,
+        }
+    }
+
+    /// Returns the pre-serialized payload supplied to [`CommandRequest::raw`], if any.
+    pub fn raw_payload(&self) -> Option<&RawValue> {
+        self.raw_payload.as_deref()
+    }
+
+    /// Returns the body streamed with [`CommandClient::send_with_body`], as collected by
+    /// [`CommandServer`].
+    pub fn body(&self) -> Option<&[u8]> {
+        self.body.as_deref()
+    }
+
+    /// Attaches trace identifiers to the request.
+    pub fn with_trace(mut self, trace: CommandTrace) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+
+    /// Marks the request as a dry run, serialized as `dry_run: true`, e.g. to check a deploy
+    /// against the host without touching any data.
+    ///
+    /// The host must treat the command as a no-op, validating it and replying as it would
+    /// otherwise without performing any side effects. Hosts that predate the flag ignore it and
+    /// run the command for real, so only rely on it with hosts known to honor it.
+    pub fn dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+}
+
+impl Serialize for CommandRequest {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        #[derive(Serialize)]
+        struct Wire<'a, P: Serialize + ?Sized> {
+            command: &'a str,
+            payload: &'a P,
+            #[serde(rename = "_trace", skip_serializing_if = "Option::is_none")]
+            trace: Option<&'a CommandTrace>,
+            #[serde(skip_serializing_if = "std::ops::Not::not")]
+            notify: bool,
+            #[serde(skip_serializing_if = "std::ops::Not::not")]
+            dry_run: bool,
+            #[serde(rename = "_body", skip_serializing_if = "std::ops::Not::not")]
+            body: bool,
+        }
+
+        match &self.raw_payload {
+            Some(raw) => Wire {
+                command: &self.command,
+                payload: raw.as_ref(),
+                trace: self.trace.as_ref(),
+                notify: self.notify,
+                dry_run: self.dry_run,
+                body: self.streams_body,
+            }
+            .serialize(serializer),
+            None => Wire {
+                command: &self.command,
+                payload: &self.payload,
+                trace: self.trace.as_ref(),
+                notify: self.notify,
+                dry_run: self.dry_run,
+                body: self.streams_body,
+            }
+            .serialize(serializer),
+        }
+    }
+}
+
+/// Trace identifiers forwarded alongside a [`CommandRequest`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandTrace {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub span_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sampled: Option<bool>,
+}
+
+/// Response returned by the host for a previously issued command.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandResponse {
+    /// Indicates whether the host executed the command successfully.
+    pub ok: bool,
+    /// JSON payload returned by the host (defaults to `null`).
+    #[serde(default)]
+    pub payload: serde_json::Value,
+    /// Optional diagnostic string supplied by the host when `ok == false`.
+    #[serde(default)]
+    pub diagnostic: Option<String>,
+}
+
+impl CommandResponse {
+    /// Constructs a success response with an empty payload.
+    pub fn ok() -> Self {
+        Self {
+            ok: true,
+            payload: serde_json::Value::Null,
+            diagnostic: None,
+        }
+    }
+}
+/// Errors emitted by [`CommandClient`] when transport or payload handling fails.
+#[derive(Debug, Error)]
+pub enum CommandError {
+    #[error("command failed: {diagnostic}")]
+    CommandFailure { diagnostic: String, payload: Value },
+    #[error("command transport closed")]
+    TransportClosed,
+    #[error("command timed out after {0:?}")]
+    Timeout(Duration),
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid command payload: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("command channel unavailable: {0}")]
+    Unavailable(String),
+    #[error("command channel saturated: too many commands in flight")]
+    Saturated,
+    #[error("command response exceeded {limit} bytes")]
+    ResponseTooLarge { limit: usize },
+    #[error("malformed command response: {0}")]
+    MalformedResponse(String),
+}
+
+impl CommandError {
+    /// Returns a short, stable label for the error variant (useful as a metrics label).
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CommandError::CommandFailure { .. } => "command_failure",
+            CommandError::TransportClosed => "transport_closed",
+            CommandError::Timeout(_) => "timeout",
+            CommandError::Io(_) => "io",
+            CommandError::Serialization(_) => "serialization",
+            CommandError::Unavailable(_) => "unavailable",
+            CommandError::Saturated => "saturated",
+            CommandError::ResponseTooLarge { .. } => "response_too_large",
+            CommandError::MalformedResponse(_) => "malformed_response",
+        }
+    }
+
+    /// Deserializes the host's error body for [`CommandError::CommandFailure`] into `T`.
+    ///
+    /// Returns `None` for every other variant, and `Some(Err(_))` when the payload does not match
+    /// `T`.
+    pub fn failure_details<T: DeserializeOwned>(&self) -> Option<Result<T, serde_json::Error>> {
+        match self {
+            CommandError::CommandFailure { payload, .. } => Some(T::deserialize(payload)),
+            _ => None,
+        }
+    }
+
+    /// Suggests how long a caller should wait before retrying, for errors that are expected to
+    /// clear on their own (the channel reconnecting or the host being slow to answer).
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            CommandError::TransportClosed | CommandError::Unavailable(_) => {
+                Some(RECONNECT_RETRY_AFTER)
+            }
+            CommandError::Timeout(_) | CommandError::Saturated => Some(TIMEOUT_RETRY_AFTER),
+            _ => None,
+        }
+    }
+}
+
+/// Retry hint returned while the command channel is down or reconnecting.
+const RECONNECT_RETRY_AFTER: Duration = Duration::from_secs(1);
+/// Retry hint returned when the host did not answer in time or is saturated.
+const TIMEOUT_RETRY_AFTER: Duration = Duration::from_secs(2);
+
+/// Maps command failures onto HTTP responses so handlers can propagate them with `?`.
+///
+/// An unavailable, closed, or saturated channel becomes `503 Service Unavailable`, a timeout
+/// `504 Gateway Timeout`, and both carry a `Retry-After` header from
+/// [`CommandError::retry_after`]. Host-side failures, I/O errors, and oversized or rejected
+/// responses become `502 Bad Gateway`; serialization errors `500 Internal Server Error`.
+#[cfg(feature = "axum")]
+impl axum_core::response::IntoResponse for CommandError {
+    fn into_response(self) -> axum_core::response::Response {
+        use http::{HeaderValue, StatusCode, header};
+
+        let status = match &self {
+            CommandError::TransportClosed
+            | CommandError::Unavailable(_)
+            | CommandError::Saturated => StatusCode::SERVICE_UNAVAILABLE,
+            CommandError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            CommandError::CommandFailure { .. }
+            | CommandError::Io(_)
+            | CommandError::ResponseTooLarge { .. }
+            | CommandError::MalformedResponse(_) => StatusCode::BAD_GATEWAY,
+            CommandError::Serialization(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let retry_after = self.retry_after();
+
+        let mut response = (status, self.to_string()).into_response();
+        if let Some(delay) = retry_after {
+            // Retry-After only carries whole seconds; round up so clients never retry early.
+            let secs = delay.as_secs() + u64::from(delay.subsec_nanos() > 0);
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs.max(1)));
+        }
+        response
+    }
+}
+
+#[derive(Debug)]
+enum CommandWriter {
+    Stdio(Mutex<tokio::io::Stdout>),
+    Tcp(Mutex<TcpOwnedWriteHalf>),
+    #[cfg(unix)]
+    Unix(Mutex<UnixOwnedWriteHalf>),
+    /// In-memory stand-in for stdio in tests.
+    #[cfg(test)]
+    Pipe(Mutex<io::WriteHalf<io::DuplexStream>>),
+    Unavailable(Arc<String>),
+}
+
+#[derive(Debug)]
+enum CommandReader {
+    Stdio(Mutex<ResponseReader<tokio::io::Stdin>>),
+    Tcp(Mutex<ResponseReader<TcpOwnedReadHalf>>),
+    #[cfg(unix)]
+    Unix(Mutex<ResponseReader<UnixOwnedReadHalf>>),
+    #[cfg(test)]
+    Pipe(Mutex<ResponseReader<io::ReadHalf<io::DuplexStream>>>),
+    Unavailable(Arc<String>),
+}
+
+impl CommandWriter {
+    async fn send(
+        &self,
+        request: &CommandRequest,
+        compression: CommandCompression,
+        body: Option<UploadBody<'_>>,
+    ) -> Result<(), CommandError> {
+        let json = serde_json::to_vec(request)?;
+        match self {
+            CommandWriter::Stdio(writer) => Self::write(writer, &json, compression, body).await,
+            CommandWriter::Tcp(writer) => Self::write(writer, &json, compression, body).await,
+            #[cfg(unix)]
+            CommandWriter::Unix(writer) => Self::write(writer, &json, compression, body).await,
+            #[cfg(test)]
+            CommandWriter::Pipe(writer) => Self::write(writer, &json, compression, body).await,
+            CommandWriter::Unavailable(reason) => {
+                Err(CommandError::Unavailable(reason.as_ref().clone()))
+            }
+        }
+    }
+
+    async fn close(&self) -> Result<(), CommandError> {
+        match self {
+            CommandWriter::Stdio(writer) => Self::shutdown(writer).await,
+            CommandWriter::Tcp(writer) => Self::shutdown(writer).await,
+            #[cfg(unix)]
+            CommandWriter::Unix(writer) => Self::shutdown(writer).await,
+            #[cfg(test)]
+            CommandWriter::Pipe(writer) => Self::shutdown(writer).await,
+            CommandWriter::Unavailable(_) => Ok(()),
+        }
+    }
+
+    async fn shutdown<W>(writer: &Mutex<W>) -> Result<(), CommandError>
+    where
+        W: AsyncWrite + Unpin + Send,
+    {
+        let mut guard = writer.lock().await;
+        guard.flush().await?;
+        guard.shutdown().await?;
+        Ok(())
+    }
+
+    async fn write<W>(
+        writer: &Mutex<W>,
+        json: &[u8],
+        compression: CommandCompression,
+        body: Option<UploadBody<'_>>,
+    ) -> Result<(), CommandError>
+    where
+        W: AsyncWrite + Unpin + Send,
+    {
+        // Held across the body so a concurrent notification cannot land between its chunks.
+        let mut guard = writer.lock().await;
+        write_message(&mut *guard, json, compression).await?;
+        match body {
+            Some(body) => write_body(&mut *guard, body).await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl CommandReader {
+    /// Reads the next raw response, returning `None` at end of stream.
+    async fn next(
+        &self,
+        compression: CommandCompression,
+        limit: usize,
+        delimiter: u8,
+    ) -> Result<Option<Vec<u8>>, CommandError> {
+        match self {
+            CommandReader::Stdio(reader) => {
+                reader
+                    .lock()
+                    .await
+                    .next(compression, limit, delimiter)
+                    .await
+            }
+            CommandReader::Tcp(reader) => {
+                reader
+                    .lock()
+                    .await
+                    .next(compression, limit, delimiter)
+                    .await
+            }
+            #[cfg(unix)]
+            CommandReader::Unix(reader) => {
+                reader
+                    .lock()
+                    .await
+                    .next(compression, limit, delimiter)
+                    .await
+            }
+            #[cfg(test)]
+            CommandReader::Pipe(reader) => {
+                reader
+                    .lock()
+                    .await
+                    .next(compression, limit, delimiter)
+                    .await
+            }
+            CommandReader::Unavailable(reason) => {
+                Err(CommandError::Unavailable(reason.as_ref().clone()))
+            }
+        }
+    }
+
+    /// Reads and drops one response, including one too large to keep.
+    async fn discard(
+        &self,
+        compression: CommandCompression,
+        limit: usize,
+        delimiter: u8,
+    ) -> Result<(), CommandError> {
+        match self.next(compression, limit, delimiter).await {
+            Ok(Some(_)) | Err(CommandError::ResponseTooLarge { .. }) => Ok(()),
+            Ok(None) => Err(CommandError::TransportClosed),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn read(
+        &self,
+        compression: CommandCompression,
+        limit: usize,
+        delimiter: u8,
+        validator: Option<&ResponseValidator>,
+    ) -> Result<CommandResponse, CommandError> {
+        let Some(message) = self.next(compression, limit, delimiter).await? else {
+            return Err(CommandError::TransportClosed);
+        };
+        let message = decode_utf8(&message)?;
+        let response = match validator {
+            Some(validator) => {
+                let value: Value = serde_json::from_str(message)?;
+                validator.check(&value)?;
+                serde_json::from_value(value)?
+            }
+            None => serde_json::from_str(message)?,
+        };
         Ok(response)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn stats_count_successes_and_failures() {
+        let server = CommandServer::new(|request| CommandResponse {
+            ok: request.command != "fail",
+            ..CommandResponse::ok()
+        });
+        let addr = host(server).await;
+        let client = CommandClient::connect(CommandEndpoint::Tcp(addr))
+            .await
+            .unwrap();
+        assert_eq!(client.stats(), CommandStats::default());
+
+        for _ in 0..3 {
+            client.send(CommandRequest::empty("ok")).await.unwrap();
+        }
+        let before = SystemTime::now();
+        client
+            .with_timeout(Duration::from_secs(1))
+            .send(CommandRequest::empty("fail"))
+            .await
+            .unwrap_err();
+
+        let stats = client.stats();
+        assert_eq!(stats.sent, 4);
+        assert_eq!(stats.failed, 1);
+        assert_eq!(stats.in_flight, 0);
+        let last_error_at = stats.last_error_at.unwrap();
+        assert!(last_error_at + Duration::from_millis(1) >= before);
+        assert_eq!(serde_json::to_value(&stats).unwrap()["sent"], 4);
+    }
+
+    #[tokio::test]
+    async fn validator_rejects_unexpected_responses() {
+        let server = CommandServer::new(|request| CommandResponse {
+            payload: match request.command.as_str() {
+                "versioned" => serde_json::json!({ "version": 2 }),
+                _ => serde_json::json!({ "other": true }),
+            },
+            ..CommandResponse::ok()
+        });
+        let addr = host(server).await;
+
+        let options = CommandOptions::default().response_validator(|value| {
+            match value.pointer("/payload/version") {
+                Some(_) => Ok(()),
+                None => Err("payload.version is required".to_owned()),
+            }
+        });
+        let client = CommandClient::connect_with_options(CommandEndpoint::Tcp(addr), options)
+            .await
+            .unwrap();
+
+        let err = client
+            .send(CommandRequest::empty("unversioned"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, CommandError::MalformedResponse(reason) if reason == "payload.version is required")
+        );
+        assert_eq!(err.kind(), "malformed_response");
+
+        let response = client
+            .send(CommandRequest::empty("versioned"))
+            .await
+            .unwrap();
+        assert_eq!(response.payload["version"], 2);
+    }
+
+    #[tokio::test]
+    async fn oversized_responses_reset_the_connection() {
+        let server = CommandServer::new(|request| CommandResponse {
+            ok: true,
+            payload: Value::String(if request.command == "big" {
+                "x".repeat(1024)
+            } else {
+                request.command
+            }),
+            diagnostic: None,
+        });
+        let (addr, accepted) = host_every_connection(server).await;
+
+        let options = CommandOptions::default().max_response_bytes(256);
+        let client = CommandClient::connect_with_options(CommandEndpoint::Tcp(addr), options)
+            .await
+            .unwrap();
+
+        let err = client.send(CommandRequest::empty("big")).await.unwrap_err();
+        assert!(matches!(err, CommandError::ResponseTooLarge { limit: 256 }));
+        assert_eq!(err.kind(), "response_too_large");
+
+        // The rest of the oversized response is abandoned with its connection.
+        let response = client.send(CommandRequest::empty("small")).await.unwrap();
+        assert_eq!(response.payload, "small");
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn wait_ready_follows_the_connection_state() {
+        async fn serve_once(listener: TcpListener) {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, writer) = stream.into_split();
+            let server = CommandServer::new(|_| CommandResponse::ok());
+            let _ = server.serve(reader, writer).await;
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let host = tokio::spawn(serve_once(listener));
+        let client = CommandClient::connect(CommandEndpoint::Tcp(addr.to_string()))
+            .await
+            .unwrap();
+        let mut state = client.connection_state();
+        assert_eq!(*state.borrow(), ConnectionState::Connected);
+        client.wait_ready(Duration::from_secs(5)).await.unwrap();
+
+        // The host goes away; the next command notices and marks the channel disconnected.
+        host.abort();
+        let _ = host.await;
+        assert!(client.send(CommandRequest::empty("ping")).await.is_err());
+        assert_eq!(*state.borrow_and_update(), ConnectionState::Disconnected);
+        let err = client
+            .wait_ready(Duration::from_millis(200))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CommandError::Timeout(_)), "{err:?}");
+
+        let waiter = tokio::spawn({
+            let client = client.clone();
+            async move { client.wait_ready(Duration::from_secs(5)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiter.is_finished());
+        tokio::spawn(serve_once(TcpListener::bind(addr).await.unwrap()));
+        waiter.await.unwrap().unwrap();
+        assert_eq!(*state.borrow(), ConnectionState::Connected);
+        client.send(CommandRequest::empty("ping")).await.unwrap();
+
+        client.close().await.unwrap();
+        assert_eq!(*state.borrow(), ConnectionState::Closed);
+        let err = client.wait_ready(Duration::from_secs(5)).await.unwrap_err();
+        assert!(matches!(err, CommandError::TransportClosed), "{err:?}");
+
+        let err = CommandClient::unavailable("cloud run")
+            .wait_ready(Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert!(matches!(&err, CommandError::Unavailable(reason) if reason == "cloud run"));
+    }
+
+    #[tokio::test]
+    async fn invalid_utf8_response_is_malformed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.split();
+            let mut requests = BufReader::new(reader);
+            requests.read_until(b'\n', &mut Vec::new()).await.unwrap();
+            writer
+                .write_all(b"{\"ok\":false,\"diagnostic\":\"bad \xff\xfe\"}\n")
+                .await
+                .unwrap();
+            requests.read_until(b'\n', &mut Vec::new()).await.unwrap();
+            writer.write_all(b"{\"ok\":true}\n").await.unwrap();
+        });
+
+        let client = CommandClient::connect(CommandEndpoint::Tcp(addr))
+            .await
+            .unwrap();
+        let err = client
+            .send(CommandRequest::empty("ping"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, CommandError::MalformedResponse(reason)
+                if reason.starts_with("invalid UTF-8 at byte 30") && reason.contains("bad \u{fffd}\u{fffd}")),
+            "{err:?}"
+        );
+        // The whole line was consumed, so the channel stays usable.
+        let response = client.send(CommandRequest::empty("ping")).await.unwrap();
+        assert!(response.ok);
+    }
+
+    #[tokio::test]
+    async fn reads_crlf_terminated_responses() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.split();
+            let mut requests = BufReader::new(reader);
+            for payload in ["first", "second"] {
+                requests.read_until(b'\n', &mut Vec::new()).await.unwrap();
+                let line = format!("{{\"ok\":true,\"payload\":\"{payload}\"}}\r\n");
+                writer.write_all(line.as_bytes()).await.unwrap();
+            }
+        });
+
+        let client = CommandClient::connect(CommandEndpoint::Tcp(addr))
+            .await
+            .unwrap();
+        for expected in ["first", "second"] {
+            let response = client.send(CommandRequest::empty("ping")).await.unwrap();
+            assert_eq!(response.payload, serde_json::json!(expected));
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_responses_with_a_custom_delimiter() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.split();
+            let mut requests = BufReader::new(reader);
+            requests.read_until(b'\n', &mut Vec::new()).await.unwrap();
+            writer
+                .write_all(b"{\"ok\":true,\n\"payload\":1}\r\0")
+                .await
+                .unwrap();
+            requests.read_until(b'\n', &mut Vec::new()).await.unwrap();
+            writer
+                .write_all(b"{\"ok\":true,\"payload\":2}\0")
+                .await
+                .unwrap();
+        });
+
+        let options = CommandOptions::default().line_delimiter(0);
+        let client = CommandClient::connect_with_options(CommandEndpoint::Tcp(addr), options)
+            .await
+            .unwrap();
+        // Newlines inside a NUL-delimited response are just whitespace.
+        let response = client.send(CommandRequest::empty("ping")).await.unwrap();
+        assert_eq!(response.payload, serde_json::json!(1));
+        let response = client.send(CommandRequest::empty("ping")).await.unwrap();
+        assert_eq!(response.payload, serde_json::json!(2));
+    }
+
+    #[tokio::test]
+    async fn connect_gate_staggers_reconnects() {
+        // Reserve a port, then bring the host up only once the clients are already retrying.
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let accepted = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let host_accepted = accepted.clone();
+        tokio::spawn(async move {
+            time::sleep(Duration::from_millis(100)).await;
+            let listener = TcpListener::bind(addr).await.unwrap();
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                host_accepted.lock().unwrap().push((Instant::now(), stream));
+            }
+        });
+
+        let spacing = Duration::from_millis(50);
+        let options = CommandOptions::default().connect_gate(ConnectGate::with_spacing(1, spacing));
+        let clients = (0..4).map(|_| {
+            let options = options.clone();
+            tokio::spawn(async move {
+                loop {
+                    let endpoint = CommandEndpoint::Tcp(addr.to_string());
+                    match CommandClient::connect_with_options(endpoint, options.clone()).await {
+                        Ok(client) => return client,
+                        Err(_) => time::sleep(Duration::from_millis(5)).await,
+                    }
+                }
+            })
+        });
+        for client in clients.collect::<Vec<_>>() {
+            client.await.unwrap();
+        }
+
+        let accepted = accepted.lock().unwrap();
+        assert_eq!(accepted.len(), 4);
+        for pair in accepted.windows(2) {
+            let gap = pair[1].0 - pair[0].0;
+            assert!(gap >= spacing / 2, "attempts only {gap:?} apart");
+        }
+    }
+
+    #[tokio::test]
+    async fn pool_spreads_concurrent_sends_across_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            for connection in 0.. {
+                let (stream, _) = listener.accept().await.unwrap();
+                let server = CommandServer::new(move |_request| CommandResponse {
+                    payload: serde_json::json!(connection),
+                    ..CommandResponse::ok()
+                });
+                tokio::spawn(async move {
+                    let (reader, writer) = stream.into_split();
+                    let _ = server.serve(reader, writer).await;
+                });
+            }
+        });
+
+        let pool = CommandClientPool::connect(CommandEndpoint::Tcp(addr), 3)
+            .await
+            .unwrap();
+        assert_eq!(pool.size(), 3);
+        let [first, second, third] = [pool.acquire(), pool.acquire(), pool.acquire()];
+        let responses = tokio::join!(
+            first.send(CommandRequest::empty("whoami")),
+            second.send(CommandRequest::empty("whoami")),
+            third.send(CommandRequest::empty("whoami")),
+        );
+        let mut connections = [responses.0, responses.1, responses.2]
+            .into_iter()
+            .map(|response| response.unwrap().payload.as_u64().unwrap())
+            .collect::<Vec<_>>();
+        connections.sort_unstable();
+        assert_eq!(connections, [0, 1, 2]);
+
+        // A released connection is preferred over busy ones.
+        let released = first
+            .send(CommandRequest::empty("whoami"))
+            .await
+            .unwrap()
+            .payload;
+        drop(first);
+        let next = pool.acquire();
+        let response = next.send(CommandRequest::empty("whoami")).await.unwrap();
+        assert_eq!(response.payload, released);
+        drop((second, third));
+    }
+
+    #[tokio::test]
+    async fn stdio_pools_hold_a_single_connection() {
+        let options = CommandOptions::default().allow_terminal_stdin(true);
+        let pool =
+            CommandClientPool::connect_with_options(CommandEndpoint::Stdio, 1, options.clone())
+                .await
+                .unwrap();
+        assert_eq!(pool.size(), 1);
+        let err = CommandClientPool::connect_with_options(CommandEndpoint::Stdio, 2, options)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, CommandError::Io(err) if err.kind() == io::ErrorKind::InvalidInput),
+            "{err:?}"
+        );
+        let err = CommandClientPool::connect(CommandEndpoint::Stdio, 0)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CommandError::Io(_)));
+    }
+
+    #[tokio::test]
+    async fn truncated_response_reports_transport_closed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = BufReader::new(&mut stream);
+            request.read_until(b'\n', &mut Vec::new()).await.unwrap();
+            stream.write_all(br#"{"ok":true,"payl"#).await.unwrap();
+        });
+
+        let client = CommandClient::connect(CommandEndpoint::Tcp(addr))
+            .await
+            .unwrap();
+        let err = client
+            .send(CommandRequest::empty("ping"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CommandError::TransportClosed), "{err:?}");
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn send_blocking_outside_and_inside_runtimes() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let client = runtime.block_on(async {
+            let addr = host(CommandServer::new(|_| CommandResponse::ok())).await;
+            CommandClient::connect(CommandEndpoint::Tcp(addr))
+                .await
+                .unwrap()
+        });
+
+        assert!(
+            client
+                .send_blocking(CommandRequest::empty("sync"))
+                .unwrap()
+                .ok
+        );
+
+        let from_worker = client.clone();
+        let response = runtime
+            .block_on(async move {
+                tokio::spawn(async move { from_worker.send_blocking(CommandRequest::empty("w")) })
+                    .await
+            })
+            .unwrap()
+            .unwrap();
+        assert!(response.ok);
+
+        let current_thread = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let err = current_thread
+            .block_on(async { client.send_blocking(CommandRequest::empty("nested")) })
+            .unwrap_err();
+        assert!(matches!(err, CommandError::Io(ref io) if io.kind() == io::ErrorKind::WouldBlock));
+    }
+
+    #[tokio::test]
+    async fn failure_details_deserializes_error_payloads() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Violation {
+            code: String,
+            fields: Vec<String>,
+        }
+
+        let server = CommandServer::new(|_| CommandResponse {
+            ok: false,
+            payload: serde_json::json!({ "code": "invalid", "fields": ["key"] }),
+            diagnostic: Some("validation failed".into()),
+        });
+        let client = CommandClient::connect(CommandEndpoint::Tcp(host(server).await))
+            .await
+            .unwrap();
+
+        let err = client
+            .send(CommandRequest::empty("kv_put"))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.failure_details::<Violation>().unwrap().unwrap(),
+            Violation {
+                code: "invalid".into(),
+                fields: vec!["key".into()],
+            }
+        );
+        assert!(err.failure_details::<Vec<u8>>().unwrap().is_err());
+        assert!(
+            CommandError::Saturated
+                .failure_details::<Violation>()
+                .is_none()
+        );
+    }
+
+    async fn host(server: CommandServer) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, writer) = stream.into_split();
+            let _ = server.serve(reader, writer).await;
+        });
+        addr
+    }
+
+    /// Like [`host`], but serves every connection and counts them, so clients can reconnect.
+    async fn host_every_connection(server: CommandServer) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        tokio::spawn({
+            let accepted = accepted.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    accepted.fetch_add(1, Ordering::SeqCst);
+                    let server = server.clone();
+                    tokio::spawn(async move {
+                        let (reader, writer) = stream.into_split();
+                        let _ = server.serve(reader, writer).await;
+                    });
+                }
+            }
+        });
+        (addr, accepted)
+    }
+
+    #[tokio::test]
+    async fn keepalive_pings_idle_connections() {
+        let pings = Arc::new(AtomicUsize::new(0));
+        let server = CommandServer::new({
+            let pings = pings.clone();
+            move |request| {
+                if request.command == PING_COMMAND {
+                    pings.fetch_add(1, Ordering::SeqCst);
+                }
+                CommandResponse::ok()
+            }
+        });
+        let addr = host(server).await;
+
+        let options = CommandOptions::default().keepalive(Duration::from_millis(20));
+        let client = CommandClient::connect_with_options(CommandEndpoint::Tcp(addr), options)
+            .await
+            .unwrap();
+        time::sleep(Duration::from_millis(150)).await;
+
+        assert!(pings.load(Ordering::SeqCst) > 0);
+        assert!(client.send(CommandRequest::empty("noop")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn raw_payloads_reach_the_host_verbatim() {
+        let server = CommandServer::new(|request| CommandResponse {
+            ok: true,
+            payload: request.payload,
+            diagnostic: None,
+        });
+        let addr = host(server).await;
+        let client = CommandClient::connect(CommandEndpoint::Tcp(addr))
+            .await
+            .unwrap();
+
+        let raw = RawValue::from_string(r#"{"id": 7, "tags": ["a"]}"#.to_owned()).unwrap();
+        let request = CommandRequest::raw("echo", raw).with_trace(CommandTrace {
+            trace_id: Some("abc".into()),
+            ..CommandTrace::default()
+        });
+        assert_eq!(
+            serde_json::to_string(&request).unwrap(),
+            r#"{"command":"echo","payload":{"id": 7, "tags": ["a"]},"_trace":{"trace_id":"abc"}}"#
+        );
+
+        let response = client.send(request).await.unwrap();
+        assert_eq!(
+            response.payload,
+            serde_json::json!({"id": 7, "tags": ["a"]})
+        );
+    }
+
+    #[tokio::test]
+    async fn with_timeout_shares_the_transport() {
+        use std::sync::atomic::AtomicUsize;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        // Accept and hold every connection without ever answering.
+        tokio::spawn({
+            let accepted = accepted.clone();
+            async move {
+                let mut streams = Vec::new();
+                while let Ok((stream, _)) = listener.accept().await {
+                    accepted.fetch_add(1, Ordering::SeqCst);
+                    streams.push(stream);
+                }
+            }
+        });
+
+        let client =
+            CommandClient::connect_with_timeout(CommandEndpoint::Tcp(addr), Duration::from_secs(1))
+                .await
+                .unwrap();
+        let tight = client.with_timeout(Duration::from_millis(30));
+        assert_eq!(tight.timeout(), Duration::from_millis(30));
+        assert_eq!(client.timeout(), Duration::from_secs(1));
+
+        let err = tight.send(CommandRequest::empty("slow")).await.unwrap_err();
+        assert!(matches!(err, CommandError::Timeout(t) if t == Duration::from_millis(30)));
+        let err = client
+            .send_with_timeout(CommandRequest::empty("slow"), Duration::from_millis(60))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CommandError::Timeout(t) if t == Duration::from_millis(60)));
+        // The first timeout failed the shared transport, so the second send reconnected it.
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+
+        tight.close().await.unwrap();
+        assert!(client.is_closed());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn default_timeout_fires_at_exactly_thirty_seconds() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        // Answers the first request just inside the deadline, then goes silent.
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.split();
+            let mut requests = BufReader::new(reader);
+            requests.read_until(b'\n', &mut Vec::new()).await.unwrap();
+            time::sleep(Duration::from_secs(29)).await;
+            writer.write_all(b"{\"ok\":true}\n").await.unwrap();
+            requests.read_until(b'\n', &mut Vec::new()).await.unwrap();
+            time::sleep(Duration::from_secs(3600)).await;
+        });
+
+        let client = CommandClient::connect(CommandEndpoint::Tcp(addr))
+            .await
+            .unwrap();
+        assert_eq!(client.timeout(), Duration::from_secs(30));
+
+        // The paused clock jumps straight to each timer, so nothing waits in real time.
+        let started = Instant::now();
+        client.send(CommandRequest::empty("slow")).await.unwrap();
+        assert_eq!(started.elapsed(), Duration::from_secs(29));
+
+        let started = Instant::now();
+        let err = client
+            .send(CommandRequest::empty("silent"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CommandError::Timeout(t) if t == Duration::from_secs(30)));
+        assert_eq!(started.elapsed(), Duration::from_secs(30));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn timeout_does_not_fire_before_the_deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (received_tx, received_rx) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut requests = BufReader::new(&mut stream);
+            requests.read_until(b'\n', &mut Vec::new()).await.unwrap();
+            let _ = received_tx.send(());
+            time::sleep(Duration::from_secs(3600)).await;
+        });
+
+        let client = CommandClient::connect(CommandEndpoint::Tcp(addr))
+            .await
+            .unwrap();
+        let timeout = Duration::from_millis(500);
+        let pending = tokio::spawn({
+            let client = client.clone();
+            async move {
+                client
+                    .send_with_timeout(CommandRequest::empty("slow"), timeout)
+                    .await
+            }
+        });
+        received_rx.await.unwrap();
+
+        time::advance(timeout - Duration::from_millis(1)).await;
+        assert!(!pending.is_finished());
+        time::advance(Duration::from_millis(1)).await;
+        let err = pending.await.unwrap().unwrap_err();
+        assert!(matches!(err, CommandError::Timeout(t) if t == timeout));
+    }
+
+    #[tokio::test]
+    async fn try_send_reports_saturation() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        // Accept and hold the connection without ever answering.
+        tokio::spawn(async move {
+            let (_stream, _) = listener.accept().await.unwrap();
+            time::sleep(Duration::from_secs(5)).await;
+        });
+
+        let options = CommandOptions::default()
+            .timeout(Duration::from_millis(200))
+            .max_in_flight(1);
+        let client = CommandClient::connect_with_options(CommandEndpoint::Tcp(addr), options)
+            .await
+            .unwrap();
+
+        let pending = tokio::spawn({
+            let client = client.clone();
+            async move { client.send(CommandRequest::empty("slow")).await }
+        });
+        time::sleep(Duration::from_millis(50)).await;
+
+        let err = client
+            .try_send(CommandRequest::empty("fast"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CommandError::Saturated));
+        assert!(matches!(
+            pending.await.unwrap(),
+            Err(CommandError::Timeout(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn probed_connect_requires_an_answer() {
+        let addr = host(CommandServer::new(|_| CommandResponse::ok())).await;
+        let probe = Duration::from_millis(200);
+        assert!(
+            CommandClient::connect_probed(CommandEndpoint::Tcp(addr), probe)
+                .await
+                .is_ok()
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (_stream, _) = listener.accept().await.unwrap();
+            time::sleep(Duration::from_secs(5)).await;
+        });
+        let err = CommandClient::connect_probed(CommandEndpoint::Tcp(addr), probe)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CommandError::Unavailable(_)));
+    }
+
+    #[tokio::test]
+    async fn streams_request_bodies() {
+        let addr = host(CommandServer::new(|request| {
+            let body = request.body().unwrap_or_default();
+            let sum = body.iter().map(|byte| u64::from(*byte)).sum::<u64>();
+            let mut response = CommandResponse::ok();
+            response.payload = serde_json::json!({ "len": body.len(), "sum": sum });
+            response
+        }))
+        .await;
+        let client = CommandClient::connect(CommandEndpoint::Tcp(addr))
+            .await
+            .unwrap();
+
+        let upload = (0..10 * 1024 * 1024)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        let sum = upload.iter().map(|byte| u64::from(*byte)).sum::<u64>();
+        let response = client
+            .send_with_body(
+                CommandRequest::empty("upload"),
+                std::io::Cursor::new(upload),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            response.payload,
+            serde_json::json!({ "len": 10 * 1024 * 1024, "sum": sum })
+        );
+
+        // The stream is still in step for ordinary commands afterwards.
+        let response = client.send(CommandRequest::empty("plain")).await.unwrap();
+        assert_eq!(response.payload, serde_json::json!({ "len": 0, "sum": 0 }));
+    }
+
+    #[tokio::test]
+    async fn connect_timeout_bounds_only_the_connect() {
+        // Nothing accepts and the backlog is full, so further SYNs go unanswered.
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener = socket.listen(1).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let endpoint = CommandEndpoint::Tcp(addr.to_string());
+        let options = CommandOptions::default()
+            .connect_timeout(Duration::from_millis(50))
+            .timeout(Duration::from_secs(30));
+        // Connected while the backlog still has room.
+        let early = CommandClient::connect_with_options(endpoint.clone(), options.clone())
+            .await
+            .unwrap();
+        let mut queued = Vec::new();
+        while let Ok(Ok(stream)) =
+            time::timeout(Duration::from_millis(100), TcpStream::connect(addr)).await
+        {
+            queued.push(stream);
+        }
+        let started = Instant::now();
+        let err = CommandClient::connect_with_options(endpoint, options)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, CommandError::Io(err) if err.kind() == io::ErrorKind::TimedOut),
+            "{err:?}"
+        );
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        // Reconnecting runs into the same bound and reports it the same way.
+        let err = early.inner.reconnect().await.unwrap_err();
+        assert!(
+            matches!(&err, CommandError::Io(err) if err.kind() == io::ErrorKind::TimedOut),
+            "{err:?}"
+        );
+        drop(queued);
+
+        let addr = host(CommandServer::new(|_| CommandResponse::ok())).await;
+        let options = CommandOptions::default()
+            .connect_timeout(Duration::from_millis(50))
+            .timeout(Duration::from_secs(30));
+        let client = CommandClient::connect_with_options(CommandEndpoint::Tcp(addr), options)
+            .await
+            .unwrap();
+        assert_eq!(client.timeout(), Duration::from_secs(30));
+    }
+
+    #[tokio::test]
+    async fn sync_time_reports_host_clock_offset() {
+        let skewed = |skew: i64| {
+            CommandServer::new(move |request| {
+                let local = request.payload["unix_ms"].as_u64().unwrap() as i64;
+                let mut response = CommandResponse::ok();
+                response.payload = serde_json::json!({ "unix_ms": local + skew });
+                response
+            })
+        };
+
+        let client = CommandClient::connect(CommandEndpoint::Tcp(host(skewed(5_000)).await))
+            .await
+            .unwrap();
+        assert_eq!(client.stats().clock_offset, None);
+        let offset = client.sync_time().await.unwrap();
+        assert!(matches!(offset, ClockOffset::HostAhead(_)), "{offset:?}");
+        assert!(offset.magnitude() > Duration::from_secs(4));
+        assert!(offset.magnitude() <= Duration::from_secs(5));
+        assert_eq!(client.stats().clock_offset, Some(offset));
+
+        let client = CommandClient::connect(CommandEndpoint::Tcp(host(skewed(-5_000)).await))
+            .await
+            .unwrap();
+        let offset = client.sync_time().await.unwrap();
+        assert!(matches!(offset, ClockOffset::HostBehind(_)), "{offset:?}");
+        assert!(offset.magnitude() >= Duration::from_secs(4));
+    }
+
+    #[tokio::test]
+    async fn lists_advertised_commands() {
+        let addr = host(CommandServer::new(|request| {
+            if request.command != COMMANDS_COMMAND {
+                return CommandResponse {
+                    ok: false,
+                    diagnostic: Some("unknown command".into()),
+                    ..CommandResponse::ok()
+                };
+            }
+            let mut response = CommandResponse::ok();
+            response.payload = serde_json::json!({
+                "commands": [
+                    {
+                        "name": "kv_get",
+                        "description": "Reads a key",
+                        "payload_schema": {
+                            "type": "object",
+                            "required": ["key"],
+                        },
+                    },
+                    { "name": "flush" },
+                ],
+            });
+            response
+        }))
+        .await;
+        let client = CommandClient::connect(CommandEndpoint::Tcp(addr))
+            .await
+            .unwrap();
+
+        let commands = client.list_commands().await.unwrap();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].name, "kv_get");
+        assert_eq!(commands[0].description, "Reads a key");
+        assert_eq!(
+            commands[0].payload_schema.as_ref().unwrap()["required"],
+            serde_json::json!(["key"])
+        );
+        assert_eq!(
+            commands[1],
+            CommandDescriptor {
+                name: "flush".into(),
+                description: String::new(),
+                payload_schema: None,
+            }
+        );
+
+        let addr = host(CommandServer::new(|_| CommandResponse {
+            ok: false,
+            ..CommandResponse::ok()
+        }))
+        .await;
+        let client = CommandClient::connect(CommandEndpoint::Tcp(addr))
+            .await
+            .unwrap();
+        let err = client.list_commands().await.unwrap_err();
+        assert!(
+            matches!(err, CommandError::CommandFailure { .. }),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn protocol_version_is_negotiated() {
+        let addr = host(CommandServer::new(|_| CommandResponse::ok()).protocol_version(2)).await;
+        let options = CommandOptions::default().protocol_version(3);
+        let client = CommandClient::connect_with_options(CommandEndpoint::Tcp(addr), options)
+            .await
+            .unwrap();
+        assert_eq!(client.protocol_version(), Some(2));
+        assert!(client.send(CommandRequest::empty("ping")).await.unwrap().ok);
+
+        let addr = host(CommandServer::new(|_| CommandResponse::ok())).await;
+        let client = CommandClient::connect(CommandEndpoint::Tcp(addr))
+            .await
+            .unwrap();
+        assert_eq!(client.protocol_version(), None);
+
+        // A host that never answers the hello is assumed to predate versioning.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (_stream, _) = listener.accept().await.unwrap();
+            time::sleep(Duration::from_secs(5)).await;
+        });
+        let options = CommandOptions::default()
+            .protocol_version(2)
+            .probe_timeout(Duration::from_millis(50));
+        let client = CommandClient::connect_with_options(CommandEndpoint::Tcp(addr), options)
+            .await
+            .unwrap();
+        assert_eq!(client.protocol_version(), Some(0));
+    }
+
+    #[tokio::test]
+    async fn unanswered_ping_fails_and_replaces_the_transport() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        // Hold the first connection without ever answering; serve the ones after it.
+        tokio::spawn(async move {
+            let (_stalled, _) = listener.accept().await.unwrap();
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (reader, writer) = stream.into_split();
+                    let server = CommandServer::new(|_| CommandResponse::ok());
+                    let _ = server.serve(reader, writer).await;
+                });
+            }
+        });
+
+        let options = CommandOptions::default()
+            .timeout(Duration::from_millis(200))
+            .keepalive(Duration::from_millis(20));
+        let client = CommandClient::connect_with_options(CommandEndpoint::Tcp(addr), options)
+            .await
+            .unwrap();
+        time::sleep(Duration::from_millis(400)).await;
+
+        let response = client.send(CommandRequest::empty("noop")).await.unwrap();
+        assert!(response.ok);
+    }
+
+    #[tokio::test]
+    async fn late_replies_never_answer_the_next_command() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        // Each connection echoes the command name back, but the first answers too late.
+        tokio::spawn(async move {
+            let mut connection = 0;
+            while let Ok((stream, _)) = listener.accept().await {
+                connection += 1;
+                let delay = if connection == 1 { 200 } else { 0 };
+                tokio::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    let mut lines = BufReader::new(reader).lines();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let request: CommandRequest = serde_json::from_str(&line).unwrap();
+                        time::sleep(Duration::from_millis(delay)).await;
+                        let reply = serde_json::json!({"ok": true, "payload": request.command});
+                        let _ = writer.write_all(format!("{reply}\n").as_bytes()).await;
+                    }
+                });
+            }
+        });
+
+        let client = CommandClient::connect(CommandEndpoint::Tcp(addr))
+            .await
+            .unwrap();
+        let err = client
+            .send_with_timeout(CommandRequest::empty("first"), Duration::from_millis(50))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CommandError::Timeout(_)));
+
+        // Outlive the first reply, which must not be taken for the answer to "second".
+        time::sleep(Duration::from_millis(250)).await;
+        let response = client.send(CommandRequest::empty("second")).await.unwrap();
+        assert_eq!(response.payload, "second");
+    }
+
+    /// A client on an in-memory pipe standing in for stdio, and the host's end of the pipe.
+    fn pipe_client() -> (CommandClient, io::DuplexStream) {
+        let (client_end, host_end) = io::duplex(64 * 1024);
+        let (reader, writer) = io::split(client_end);
+        let inner = CommandClientInner::new(
+            CommandEndpoint::Stdio,
+            CommandWriter::Pipe(Mutex::new(writer)),
+            CommandReader::Pipe(Mutex::new(ResponseReader::new(reader))),
+        );
+        let client = CommandClient {
+            inner: Arc::new(inner),
+            timeout: DEFAULT_COMMAND_TIMEOUT,
+            observer: None,
+        };
+        (client, host_end)
+    }
+
+    #[tokio::test]
+    async fn stdio_channels_survive_timeouts() {
+        let (client, host_end) = pipe_client();
+        // Echoes the command name back; "slow" is answered late, half a line at a time.
+        tokio::spawn(async move {
+            let (reader, mut writer) = io::split(host_end);
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let request: CommandRequest = serde_json::from_str(&line).unwrap();
+                let reply = serde_json::json!({"ok": true, "payload": request.command});
+                let reply = format!("{reply}\n").into_bytes();
+                let (head, tail) = reply.split_at(reply.len() / 2);
+                if request.command == "slow" {
+                    time::sleep(Duration::from_millis(100)).await;
+                }
+                writer.write_all(head).await.unwrap();
+                if request.command == "slow" {
+                    time::sleep(Duration::from_millis(100)).await;
+                }
+                writer.write_all(tail).await.unwrap();
+            }
+        });
+
+        let err = client
+            .send_with_timeout(CommandRequest::empty("slow"), Duration::from_millis(150))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CommandError::Timeout(_)), "{err:?}");
+
+        // Sent while the rest of the late reply is still on its way.
+        let response = client.send(CommandRequest::empty("second")).await.unwrap();
+        assert_eq!(response.payload, "second");
+        let response = client.send(CommandRequest::empty("third")).await.unwrap();
+        assert_eq!(response.payload, "third");
+        assert_eq!(
+            *client.connection_state().borrow(),
+            ConnectionState::Connected
+        );
+    }
+
+    /// Echoes each command name back over `reader`/`writer`, answering "slow" after 100ms.
+    async fn echo_host<R, W>(reader: R, mut writer: W)
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let request: CommandRequest = serde_json::from_str(&line).unwrap();
+            if request.command == "slow" {
+                time::sleep(Duration::from_millis(100)).await;
+            }
+            let reply = serde_json::json!({"ok": true, "payload": request.command});
+            let _ = writer.write_all(format!("{reply}\n").as_bytes()).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn dropped_sends_never_answer_the_next_command() {
+        let (pipe, host_end) = pipe_client();
+        tokio::spawn(async move {
+            let (reader, writer) = io::split(host_end);
+            echo_host(reader, writer).await;
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (reader, writer) = stream.into_split();
+                    echo_host(reader, writer).await;
+                });
+            }
+        });
+        let tcp = CommandClient::connect(CommandEndpoint::Tcp(addr))
+            .await
+            .unwrap();
+
+        for client in [pipe, tcp] {
+            // The caller gives up (e.g. the HTTP client disconnected) while the reply is owed.
+            let send = client.send(CommandRequest::empty("slow"));
+            assert!(
+                time::timeout(Duration::from_millis(30), send)
+                    .await
+                    .is_err()
+            );
+
+            let response = client.send(CommandRequest::empty("second")).await.unwrap();
+            assert_eq!(response.payload, "second", "{:?}", client.endpoint());
+            let response = client.send(CommandRequest::empty("third")).await.unwrap();
+            assert_eq!(response.payload, "third", "{:?}", client.endpoint());
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn auto_uses_the_first_available_probe() {
+        let path = std::env::temp_dir().join(format!("cf-auto-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let _listener = tokio::net::UnixListener::bind(&path).unwrap();
+
+        let options = CommandOptions::default().auto_probes(vec![
+            AutoProbe::UnixSocket(path.with_extension("missing")),
+            AutoProbe::TcpFromEnv("CONTAINERFLARE_TEST_UNSET_ADDR".into()),
+            AutoProbe::UnixSocket(path.clone()),
+        ]);
+        let client = CommandClient::connect_with_options(CommandEndpoint::Auto, options)
+            .await
+            .unwrap();
+        assert_eq!(
+            client.endpoint(),
+            &CommandEndpoint::UnixSocket(path.clone())
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn auto_without_transports_is_unavailable() {
+        let options = CommandOptions::default().auto_probes(vec![AutoProbe::TcpFromEnv(
+            "CONTAINERFLARE_TEST_UNSET_ADDR".into(),
+        )]);
+        let err = CommandClient::connect_with_options(CommandEndpoint::Auto, options)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CommandError::Unavailable(_)));
+    }
+
+    #[tokio::test]
+    async fn terminal_stdin_yields_unavailable_client() {
+        use std::io::IsTerminal;
+
+        let client = CommandClient::connect(CommandEndpoint::Stdio)
+            .await
+            .unwrap();
+        if std::io::stdin().is_terminal() {
+            assert_eq!(client.unavailable_reason(), Some(TERMINAL_STDIN_REASON));
+            assert_eq!(client.endpoint(), &CommandEndpoint::Unavailable);
+        } else {
+            assert_eq!(client.unavailable_reason(), None);
+        }
+
+        let options = CommandOptions::default().allow_terminal_stdin(true);
+        let client = CommandClient::connect_with_options(CommandEndpoint::Stdio, options)
+            .await
+            .unwrap();
+        assert_eq!(client.unavailable_reason(), None);
+        assert_eq!(client.endpoint(), &CommandEndpoint::Stdio);
+    }
+
+    #[cfg(all(feature = "gzip", feature = "zstd"))]
+    #[tokio::test]
+    async fn compressed_payloads_roundtrip() {
+        // A cheap xorshift keeps the "incompressible" payload deterministic.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let noise: String = (0..64 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                char::from(b'!' + (state % 90) as u8)
+            })
+            .collect();
+        let repetitive = "containerflare ".repeat(16 * 1024);
+
+        for codec in [CommandCompression::Gzip, CommandCompression::Zstd] {
+            let addr = host(CommandServer::new(|request| {
+                let mut response = CommandResponse::ok();
+                response.payload = request.payload;
+                response
+            }))
+            .await;
+            let options = CommandOptions::default().compression(codec);
+            let client = CommandClient::connect_with_options(CommandEndpoint::Tcp(addr), options)
+                .await
+                .unwrap();
+            assert_eq!(client.compression(), codec);
+
+            for payload in [&noise, &repetitive, &String::from("tiny")] {
+                let response = client
+                    .send(CommandRequest::new("echo", Value::from(payload.as_str())))
+                    .await
+                    .unwrap();
+                assert_eq!(response.payload.as_str(), Some(payload.as_str()));
+            }
+        }
+    }
+
+    #[cfg(feature = "zstd")]
+    #[tokio::test]
+    async fn compression_falls_back_for_plain_hosts() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        // A host that predates compression: it answers every line, ignoring the offer.
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(_)) = lines.next_line().await {
+                writer.write_all(b"{\"ok\":true}\n").await.unwrap();
+            }
+        });
+
+        let options = CommandOptions::default().compression(CommandCompression::Zstd);
+        let client = CommandClient::connect_with_options(CommandEndpoint::Tcp(addr), options)
+            .await
+            .unwrap();
+        assert_eq!(client.compression(), CommandCompression::None);
+        assert!(client.send(CommandRequest::empty("noop")).await.unwrap().ok);
+    }
+
+    #[tokio::test]
+    async fn dry_run_flag_round_trips() {
+        let request = CommandRequest::empty("kv_put");
+        assert!(!request.dry_run);
+        assert!(!serde_json::to_string(&request).unwrap().contains("dry_run"));
+        let parsed: CommandRequest = serde_json::from_str(r#"{"command":"kv_put"}"#).unwrap();
+        assert!(!parsed.dry_run);
+
+        let request = CommandRequest::new("kv_put", serde_json::json!({"key": "a"})).dry_run();
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({"command": "kv_put", "payload": {"key": "a"}, "dry_run": true})
+        );
+
+        let addr = host(CommandServer::new(|request| CommandResponse {
+            ok: true,
+            payload: Value::from(request.dry_run),
+            diagnostic: None,
+        }))
+        .await;
+        let client = CommandClient::connect(CommandEndpoint::Tcp(addr))
+            .await
+            .unwrap();
+        let response = client.send(request).await.unwrap();
+        assert_eq!(response.payload, Value::from(true));
+        let response = client.send(CommandRequest::empty("kv_put")).await.unwrap();
+        assert_eq!(response.payload, Value::from(false));
+    }
+
+    #[tokio::test]
+    async fn notify_writes_without_awaiting_a_reply() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let addr = host(CommandServer::new({
+            let seen = seen.clone();
+            move |request| {
+                seen.lock()
+                    .unwrap()
+                    .push((request.command.clone(), request.notify));
+                let mut response = CommandResponse::ok();
+                response.payload = Value::from(request.command);
+                response
+            }
+        }))
+        .await;
+        let client = CommandClient::connect_with_options(
+            CommandEndpoint::Tcp(addr),
+            CommandOptions::default().max_in_flight(1),
+        )
+        .await
+        .unwrap();
+
+        client
+            .notify(CommandRequest::empty("log_emit"))
+            .await
+            .unwrap();
+        client
+            .notify(CommandRequest::empty("metrics_flush"))
+            .await
+            .unwrap();
+        // Had either notification been answered, this would read the stale reply.
+        let response = client.send(CommandRequest::empty("query")).await.unwrap();
+        assert_eq!(response.payload, Value::from("query"));
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                ("log_emit".to_owned(), true),
+                ("metrics_flush".to_owned(), true),
+                ("query".to_owned(), false),
+            ]
+        );
+    }
+}
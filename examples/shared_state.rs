@@ -0,0 +1,32 @@
+//! Shares the command client between handlers (through axum `State`) and a background task.
+
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::{Router, routing::get};
+use containerflare::{CommandClient, CommandRequest, ContainerflareState, RuntimeConfig};
+
+#[tokio::main]
+async fn main() -> containerflare::Result<()> {
+    let mut config = RuntimeConfig::from_env()?;
+    // Connects once; `serve` picks the same client up from `config`.
+    let state = ContainerflareState::connect(&mut config).await?;
+
+    let client = state.command_client().clone();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            ticker.tick().await;
+            if let Err(err) = client.notify(CommandRequest::empty("heartbeat")).await {
+                eprintln!("heartbeat failed: {err}");
+            }
+        }
+    });
+
+    let router = Router::new().route("/stats", get(stats)).with_state(state);
+    containerflare::serve(router, config).await
+}
+
+async fn stats(State(client): State<CommandClient>) -> String {
+    format!("{:?}", client.stats())
+}
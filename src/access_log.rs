@@ -0,0 +1,212 @@
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use axum::body::HttpBody;
+use axum::extract::{Request, State};
+use axum::http::header::REFERER;
+use axum::middleware::Next;
+use axum::response::Response;
+use serde::Serialize;
+
+use crate::context::RequestMetadata;
+use crate::platform::RuntimePlatform;
+use crate::request_id::RequestId;
+
+/// `tracing` target access log lines are emitted under.
+const ACCESS_LOG_TARGET: &str = "containerflare::access";
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Line formats supported by [`RuntimeConfigBuilder::access_log`](crate::RuntimeConfigBuilder::access_log).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// NCSA Common Log Format: `client - - [time] "METHOD path VERSION" status bytes`.
+    Common,
+    /// Common Log Format followed by the quoted `Referer` and `User-Agent`.
+    Combined,
+    /// One JSON object per request with `request_id`, `client_ip`, `method`, `path`, `status`,
+    /// `bytes`, `duration_ms`, and `colo`.
+    Json,
+}
+
+/// State consumed by [`log_access`].
+#[derive(Clone, Debug)]
+pub(crate) struct AccessLog {
+    pub(crate) format: AccessLogFormat,
+    pub(crate) platform: RuntimePlatform,
+}
+
+/// Middleware that emits one access log line per request at `INFO` under the
+/// `containerflare::access` target.
+pub(crate) async fn log_access(
+    State(log): State<AccessLog>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let started = Instant::now();
+    let (parts, body) = request.into_parts();
+    let mut metadata = RequestMetadata::from_parts(&parts, &log.platform);
+    if metadata.request_id.is_none() {
+        metadata.request_id = parts.extensions.get::<RequestId>().map(|id| id.0.clone());
+    }
+    let referer = parts
+        .headers
+        .get(REFERER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+    let version = format!("{:?}", parts.version);
+
+    let response = next.run(Request::from_parts(parts, body)).await;
+
+    let entry = AccessLogEntry {
+        request_id: metadata.request_id,
+        client_ip: metadata.client_ip,
+        method: metadata.method,
+        path: metadata.path,
+        version,
+        status: response.status().as_u16(),
+        bytes: response.body().size_hint().exact(),
+        duration_ms: started.elapsed().as_secs_f64() * 1000.0,
+        colo: metadata.colo,
+        referer,
+        user_agent: metadata.user_agent,
+        time: SystemTime::now(),
+    };
+    tracing::info!(target: ACCESS_LOG_TARGET, "{}", entry.render(log.format));
+
+    response
+}
+
+/// Everything a single access log line can mention.
+#[derive(Debug, Serialize)]
+struct AccessLogEntry {
+    request_id: Option<String>,
+    client_ip: Option<String>,
+    method: String,
+    path: String,
+    #[serde(skip)]
+    version: String,
+    status: u16,
+    bytes: Option<u64>,
+    duration_ms: f64,
+    colo: Option<String>,
+    #[serde(skip)]
+    referer: Option<String>,
+    #[serde(skip)]
+    user_agent: Option<String>,
+    #[serde(skip)]
+    time: SystemTime,
+}
+
+impl AccessLogEntry {
+    fn render(&self, format: AccessLogFormat) -> String {
+        match format {
+            AccessLogFormat::Common => self.common(),
+            AccessLogFormat::Combined => format!(
+                "{} \"{}\" \"{}\"",
+                self.common(),
+                self.referer.as_deref().unwrap_or("-"),
+                self.user_agent.as_deref().unwrap_or("-"),
+            ),
+            AccessLogFormat::Json => serde_json::to_string(self).unwrap_or_default(),
+        }
+    }
+
+    fn common(&self) -> String {
+        format!(
+            "{} - - [{}] \"{} {} {}\" {} {}",
+            self.client_ip.as_deref().unwrap_or("-"),
+            clf_timestamp(self.time),
+            self.method,
+            self.path,
+            self.version,
+            self.status,
+            self.bytes
+                .map_or_else(|| "-".to_owned(), |bytes| bytes.to_string()),
+        )
+    }
+}
+
+/// Formats `time` as `10/Oct/2000:13:55:36 +0000` (always UTC).
+fn clf_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let rem = secs % 86_400;
+    format!(
+        "{day:02}/{}/{year:04}:{:02}:{:02}:{:02} +0000",
+        MONTHS[(month - 1) as usize],
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+    )
+}
+
+/// Converts days since the Unix epoch into a proleptic Gregorian `(year, month, day)`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn entry() -> AccessLogEntry {
+        AccessLogEntry {
+            request_id: Some("ray123".into()),
+            client_ip: Some("203.0.113.1".into()),
+            method: "GET".into(),
+            path: "/hello?x=1".into(),
+            version: "HTTP/1.1".into(),
+            status: 200,
+            bytes: Some(5),
+            duration_ms: 1.5,
+            colo: Some("IAD".into()),
+            referer: None,
+            user_agent: Some("curl/8.0".into()),
+            time: UNIX_EPOCH + Duration::from_secs(971_186_136),
+        }
+    }
+
+    #[test]
+    fn renders_common_and_combined() {
+        assert_eq!(
+            entry().render(AccessLogFormat::Common),
+            r#"203.0.113.1 - - [10/Oct/2000:13:55:36 +0000] "GET /hello?x=1 HTTP/1.1" 200 5"#
+        );
+        assert_eq!(
+            entry().render(AccessLogFormat::Combined),
+            r#"203.0.113.1 - - [10/Oct/2000:13:55:36 +0000] "GET /hello?x=1 HTTP/1.1" 200 5 "-" "curl/8.0""#
+        );
+    }
+
+    #[test]
+    fn renders_json() {
+        let line: serde_json::Value =
+            serde_json::from_str(&entry().render(AccessLogFormat::Json)).unwrap();
+        assert_eq!(
+            line,
+            serde_json::json!({
+                "request_id": "ray123",
+                "client_ip": "203.0.113.1",
+                "method": "GET",
+                "path": "/hello?x=1",
+                "status": 200,
+                "bytes": 5,
+                "duration_ms": 1.5,
+                "colo": "IAD",
+            })
+        );
+    }
+}
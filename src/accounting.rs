@@ -0,0 +1,129 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+
+use axum::body::{Body, Bytes};
+use axum::extract::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use hyper::body::{Body as HttpBody, Frame, SizeHint};
+
+/// Running totals of body bytes read from the request and written in the response.
+///
+/// `serve` stores one per request in the extensions; read it through
+/// [`ContainerContext::bytes_in`](crate::ContainerContext::bytes_in) and
+/// [`ContainerContext::bytes_out`](crate::ContainerContext::bytes_out) or extract it directly with
+/// `Extension<ByteCounts>`.
+#[derive(Clone, Debug, Default)]
+pub struct ByteCounts {
+    read: Arc<AtomicU64>,
+    written: Arc<AtomicU64>,
+}
+
+impl ByteCounts {
+    /// Request body bytes consumed so far.
+    pub fn bytes_in(&self) -> u64 {
+        self.read.load(Ordering::Relaxed)
+    }
+
+    /// Response body bytes handed to the connection so far.
+    ///
+    /// The response streams after the handler returns, so this only reaches its final value once
+    /// the body has been fully sent (e.g. when read from a later middleware or a background task).
+    pub fn bytes_out(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+}
+
+/// Middleware that installs [`ByteCounts`] and wraps both bodies to tally data frames.
+pub(crate) async fn count_bytes(request: Request, next: Next) -> Response {
+    let counts = ByteCounts::default();
+    let (mut parts, body) = request.into_parts();
+    parts.extensions.insert(counts.clone());
+    let body = Body::new(CountingBody::new(body, counts.read.clone()));
+
+    let response = next.run(Request::from_parts(parts, body)).await;
+    response.map(|body| Body::new(CountingBody::new(body, counts.written)))
+}
+
+/// Body wrapper adding the size of every data frame to `counter`.
+struct CountingBody {
+    inner: Body,
+    counter: Arc<AtomicU64>,
+}
+
+impl CountingBody {
+    fn new(inner: Body, counter: Arc<AtomicU64>) -> Self {
+        Self { inner, counter }
+    }
+}
+
+impl HttpBody for CountingBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let polled = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &polled
+            && let Some(data) = frame.data_ref()
+        {
+            self.counter.fetch_add(data.len() as u64, Ordering::Relaxed);
+        }
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::extract::Extension;
+    use axum::middleware;
+    use axum::routing::post;
+    use http_body_util::BodyExt;
+    use std::sync::Mutex;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn tallies_request_and_response_bodies() {
+        let seen = Arc::new(Mutex::new(None::<ByteCounts>));
+        let router = Router::new()
+            .route(
+                "/",
+                post({
+                    let seen = seen.clone();
+                    move |Extension(counts): Extension<ByteCounts>, body: String| async move {
+                        assert_eq!(counts.bytes_in(), body.len() as u64);
+                        *seen.lock().unwrap() = Some(counts);
+                        "x".repeat(42)
+                    }
+                }),
+            )
+            .layer(middleware::from_fn(count_bytes));
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/")
+            .body(Body::from("hello world"))
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        let counts = seen.lock().unwrap().clone().unwrap();
+        assert_eq!(counts.bytes_out(), 0);
+
+        response.into_body().collect().await.unwrap();
+        assert_eq!(counts.bytes_in(), 11);
+        assert_eq!(counts.bytes_out(), 42);
+    }
+}
@@ -0,0 +1,176 @@
+use async_trait::async_trait;
+use axum::body::Bytes;
+use axum::extract::rejection::BytesRejection;
+use axum::extract::{FromRequest, FromRequestParts, Request};
+use axum::response::{IntoResponse, IntoResponseParts, Response, ResponseParts};
+use mime::Mime;
+use thiserror::Error;
+
+use crate::context::{ContainerContext, ContainerContextRejection};
+
+/// Response marker for bodies that must reach the client chunk by chunk (progress updates, log
+/// tails, long-polling).
+///
+/// `serve` never buffers response bodies, but response compression (the `compression` feature)
+/// holds small chunks back until the encoder has a full block to emit. Responses carrying this
+/// marker are sent uncompressed. `text/event-stream` responses are never compressed and do not
+/// need it.
+///
+/// ```no_run
+/// use axum::body::Body;
+/// use axum::response::IntoResponse;
+/// use containerflare::Streaming;
+///
+/// async fn tail(body: Body) -> impl IntoResponse {
+///     (Streaming, body)
+/// }
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Streaming;
+
+impl IntoResponseParts for Streaming {
+    type Error = std::convert::Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        res.extensions_mut().insert(self);
+        Ok(res)
+    }
+}
+
+/// Extractor bundling the request's [`ContainerContext`] with its fully buffered body.
+///
+/// A request body can be read only once, so `ContainerBody` consumes it: it must be the last
+/// extractor of a handler and cannot be combined with `Json`, `String`, or other body
+/// extractors. Buffering honours the runtime's body limit
+/// ([`RuntimeConfig::max_body_bytes`](crate::RuntimeConfig::max_body_bytes)).
+///
+/// # Examples
+/// ```no_run
+/// use axum::body::Bytes;
+/// use containerflare::ContainerBody;
+///
+/// async fn upload(body: ContainerBody) -> String {
+///     let kind = body
+///         .content_type()
+///         .map_or_else(|| "unknown".to_owned(), |mime| mime.essence_str().to_owned());
+///     let bytes: Bytes = body.body_bytes();
+///     format!("{} bytes of {kind}", bytes.len())
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ContainerBody {
+    context: ContainerContext,
+    bytes: Bytes,
+}
+
+impl ContainerBody {
+    /// Returns the request context.
+    pub fn context(&self) -> &ContainerContext {
+        &self.context
+    }
+
+    /// Returns the buffered body.
+    pub fn bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+
+    /// Returns the body's `Content-Type`, parsed.
+    pub fn content_type(&self) -> Option<Mime> {
+        self.context.metadata().mime()
+    }
+
+    /// Consumes the extractor and returns the buffered body.
+    pub fn body_bytes(self) -> Bytes {
+        self.bytes
+    }
+
+    /// Consumes the extractor and returns the context and body separately.
+    pub fn into_parts(self) -> (ContainerContext, Bytes) {
+        (self.context, self.bytes)
+    }
+}
+
+/// Errors emitted when [`ContainerBody`] cannot be extracted.
+#[derive(Debug, Error)]
+pub enum ContainerBodyRejection {
+    #[error(transparent)]
+    Context(#[from] ContainerContextRejection),
+    /// The body failed to read or exceeded the body limit.
+    #[error(transparent)]
+    Body(#[from] BytesRejection),
+}
+
+impl IntoResponse for ContainerBodyRejection {
+    fn into_response(self) -> Response {
+        match self {
+            ContainerBodyRejection::Context(rejection) => rejection.into_response(),
+            ContainerBodyRejection::Body(rejection) => rejection.into_response(),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequest<S> for ContainerBody
+where
+    S: Send + Sync,
+{
+    type Rejection = ContainerBodyRejection;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let (mut parts, body) = request.into_parts();
+        let context = ContainerContext::from_request_parts(&mut parts, state).await?;
+        let bytes = Bytes::from_request(Request::from_parts(parts, body), state).await?;
+        Ok(Self { context, bytes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RuntimePlatform;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::extract::{DefaultBodyLimit, Extension};
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use containerflare_command::CommandClient;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn buffers_body_with_content_type() {
+        let app = Router::new()
+            .route(
+                "/",
+                post(|body: ContainerBody| async move {
+                    let mime = body.content_type().unwrap();
+                    assert_eq!(mime.essence_str(), "application/json");
+                    assert_eq!(mime.get_param("charset").unwrap(), "utf-8");
+                    assert_eq!(body.context().metadata().method, "POST");
+                    body.body_bytes()
+                }),
+            )
+            .layer(DefaultBodyLimit::max(16))
+            .layer(Extension(CommandClient::unavailable("tests")))
+            .layer(Extension(RuntimePlatform::Generic));
+
+        let request = |body: &'static str| {
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("content-type", "application/json; charset=utf-8")
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let response = app.clone().oneshot(request(r#"{"a":1}"#)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let echoed = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(echoed, r#"{"a":1}"#);
+
+        let response = app
+            .oneshot(request(r#"{"much":"too long"}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,258 @@
+//! Embedded lookup from Cloudflare colo codes to their locations (the `colo-db` feature).
+//!
+//! Cloudflare names each data center after the IATA code of its nearest airport. The table covers
+//! the major metros from Cloudflare's published network map; coordinates are the airport's.
+
+use serde::Serialize;
+
+use Continent::*;
+
+/// Continent of a Cloudflare data center, for grouping requests without a full geo database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Continent {
+    Africa,
+    Asia,
+    Europe,
+    NorthAmerica,
+    Oceania,
+    SouthAmerica,
+}
+
+impl Continent {
+    /// Two-letter code in the style of the Workers `request.cf.continent` field, e.g. `EU`.
+    pub fn code(self) -> &'static str {
+        match self {
+            Africa => "AF",
+            Asia => "AS",
+            Europe => "EU",
+            NorthAmerica => "NA",
+            Oceania => "OC",
+            SouthAmerica => "SA",
+        }
+    }
+}
+
+/// Location of a Cloudflare data center.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct ColoInfo {
+    /// Upper-case IATA code, e.g. `IAD`.
+    pub code: &'static str,
+    pub city: &'static str,
+    /// ISO 3166-1 alpha-2 country code.
+    pub country: &'static str,
+    /// Continent the data center is on (Istanbul counts as Europe).
+    pub continent: Continent,
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// Looks up a colo code (case-insensitive), such as the value of `cf-colo` or a ray suffix.
+pub fn colo_to_location(code: &str) -> Option<ColoInfo> {
+    let code = code.trim().to_ascii_uppercase();
+    COLOS
+        .binary_search_by(|colo| colo.code.cmp(code.as_str()))
+        .ok()
+        .map(|index| COLOS[index])
+}
+
+/// Looks up the continent of a colo code (case-insensitive); `None` for codes not in the table.
+pub fn continent_for_colo(code: &str) -> Option<Continent> {
+    colo_to_location(code).map(|colo| colo.continent)
+}
+
+const fn colo(
+    code: &'static str,
+    city: &'static str,
+    country: &'static str,
+    continent: Continent,
+    latitude: f64,
+    longitude: f64,
+) -> ColoInfo {
+    ColoInfo {
+        code,
+        city,
+        country,
+        continent,
+        latitude,
+        longitude,
+    }
+}
+
+/// Sorted by code for binary search.
+static COLOS: &[ColoInfo] = &[
+    colo("ADL", "Adelaide", "AU", Oceania, -34.9450, 138.5306),
+    colo("AKL", "Auckland", "NZ", Oceania, -37.0082, 174.7850),
+    colo("AMS", "Amsterdam", "NL", Europe, 52.3105, 4.7683),
+    colo("ARN", "Stockholm", "SE", Europe, 59.6498, 17.9238),
+    colo("ATH", "Athens", "GR", Europe, 37.9364, 23.9445),
+    colo("ATL", "Atlanta", "US", NorthAmerica, 33.6407, -84.4277),
+    colo("BAH", "Manama", "BH", Asia, 26.2708, 50.6336),
+    colo("BCN", "Barcelona", "ES", Europe, 41.2974, 2.0833),
+    colo("BKK", "Bangkok", "TH", Asia, 13.6900, 100.7501),
+    colo("BLR", "Bangalore", "IN", Asia, 13.1986, 77.7066),
+    colo("BNE", "Brisbane", "AU", Oceania, -27.3842, 153.1175),
+    colo("BOG", "Bogotá", "CO", SouthAmerica, 4.7016, -74.1469),
+    colo("BOM", "Mumbai", "IN", Asia, 19.0896, 72.8656),
+    colo("BOS", "Boston", "US", NorthAmerica, 42.3656, -71.0096),
+    colo("BRU", "Brussels", "BE", Europe, 50.9010, 4.4856),
+    colo("BUD", "Budapest", "HU", Europe, 47.4298, 19.2611),
+    colo("CAI", "Cairo", "EG", Africa, 30.1219, 31.4056),
+    colo("CCU", "Kolkata", "IN", Asia, 22.6547, 88.4467),
+    colo("CDG", "Paris", "FR", Europe, 49.0097, 2.5479),
+    colo("CGK", "Jakarta", "ID", Asia, -6.1256, 106.6559),
+    colo("CMB", "Colombo", "LK", Asia, 7.1808, 79.8841),
+    colo("CPH", "Copenhagen", "DK", Europe, 55.6180, 12.6508),
+    colo("CPT", "Cape Town", "ZA", Africa, -33.9715, 18.6021),
+    colo("DAC", "Dhaka", "BD", Asia, 23.8433, 90.3978),
+    colo("DEL", "New Delhi", "IN", Asia, 28.5562, 77.1000),
+    colo("DEN", "Denver", "US", NorthAmerica, 39.8561, -104.6737),
+    colo("DFW", "Dallas", "US", NorthAmerica, 32.8998, -97.0403),
+    colo("DOH", "Doha", "QA", Asia, 25.2731, 51.6081),
+    colo("DUB", "Dublin", "IE", Europe, 53.4264, -6.2499),
+    colo("DUS", "Düsseldorf", "DE", Europe, 51.2895, 6.7668),
+    colo("DXB", "Dubai", "AE", Asia, 25.2532, 55.3657),
+    colo("EWR", "Newark", "US", NorthAmerica, 40.6895, -74.1745),
+    colo(
+        "EZE",
+        "Buenos Aires",
+        "AR",
+        SouthAmerica,
+        -34.8222,
+        -58.5358,
+    ),
+    colo("FCO", "Rome", "IT", Europe, 41.8003, 12.2389),
+    colo("FRA", "Frankfurt", "DE", Europe, 50.0379, 8.5622),
+    colo(
+        "GIG",
+        "Rio de Janeiro",
+        "BR",
+        SouthAmerica,
+        -22.8090,
+        -43.2506,
+    ),
+    colo("GRU", "São Paulo", "BR", SouthAmerica, -23.4356, -46.4731),
+    colo("GVA", "Geneva", "CH", Europe, 46.2370, 6.1092),
+    colo("HAM", "Hamburg", "DE", Europe, 53.6304, 9.9882),
+    colo("HAN", "Hanoi", "VN", Asia, 21.2212, 105.8072),
+    colo("HEL", "Helsinki", "FI", Europe, 60.3172, 24.9633),
+    colo("HKG", "Hong Kong", "HK", Asia, 22.3080, 113.9185),
+    colo("HNL", "Honolulu", "US", NorthAmerica, 21.3187, -157.9225),
+    colo("HYD", "Hyderabad", "IN", Asia, 17.2403, 78.4294),
+    colo("IAD", "Ashburn", "US", NorthAmerica, 38.9531, -77.4565),
+    colo("IAH", "Houston", "US", NorthAmerica, 29.9902, -95.3368),
+    colo("ICN", "Seoul", "KR", Asia, 37.4602, 126.4407),
+    colo("IST", "Istanbul", "TR", Europe, 41.2753, 28.7519),
+    colo("JNB", "Johannesburg", "ZA", Africa, -26.1392, 28.2460),
+    colo("KBP", "Kyiv", "UA", Europe, 50.3450, 30.8947),
+    colo("KHI", "Karachi", "PK", Asia, 24.9065, 67.1608),
+    colo("KIX", "Osaka", "JP", Asia, 34.4320, 135.2304),
+    colo("KUL", "Kuala Lumpur", "MY", Asia, 2.7456, 101.7099),
+    colo("LAS", "Las Vegas", "US", NorthAmerica, 36.0840, -115.1537),
+    colo("LAX", "Los Angeles", "US", NorthAmerica, 33.9416, -118.4085),
+    colo("LHR", "London", "GB", Europe, 51.4700, -0.4543),
+    colo("LIM", "Lima", "PE", SouthAmerica, -12.0219, -77.1143),
+    colo("LIS", "Lisbon", "PT", Europe, 38.7756, -9.1354),
+    colo("LOS", "Lagos", "NG", Africa, 6.5774, 3.3210),
+    colo("MAA", "Chennai", "IN", Asia, 12.9941, 80.1709),
+    colo("MAD", "Madrid", "ES", Europe, 40.4983, -3.5676),
+    colo("MAN", "Manchester", "GB", Europe, 53.3650, -2.2725),
+    colo("MEL", "Melbourne", "AU", Oceania, -37.6690, 144.8410),
+    colo("MEX", "Mexico City", "MX", NorthAmerica, 19.4361, -99.0719),
+    colo("MIA", "Miami", "US", NorthAmerica, 25.7959, -80.2870),
+    colo("MNL", "Manila", "PH", Asia, 14.5086, 121.0194),
+    colo("MRS", "Marseille", "FR", Europe, 43.4393, 5.2214),
+    colo("MSP", "Minneapolis", "US", NorthAmerica, 44.8848, -93.2223),
+    colo("MUC", "Munich", "DE", Europe, 48.3538, 11.7861),
+    colo("MXP", "Milan", "IT", Europe, 45.6306, 8.7281),
+    colo("NBO", "Nairobi", "KE", Africa, -1.3192, 36.9278),
+    colo("NRT", "Tokyo", "JP", Asia, 35.7720, 140.3929),
+    colo("ORD", "Chicago", "US", NorthAmerica, 41.9742, -87.9073),
+    colo("OSL", "Oslo", "NO", Europe, 60.1976, 11.1004),
+    colo("OTP", "Bucharest", "RO", Europe, 44.5711, 26.0850),
+    colo("PDX", "Portland", "US", NorthAmerica, 45.5898, -122.5951),
+    colo("PER", "Perth", "AU", Oceania, -31.9385, 115.9672),
+    colo("PHL", "Philadelphia", "US", NorthAmerica, 39.8744, -75.2424),
+    colo("PHX", "Phoenix", "US", NorthAmerica, 33.4342, -112.0116),
+    colo("PRG", "Prague", "CZ", Europe, 50.1008, 14.2600),
+    colo("PTY", "Panama City", "PA", NorthAmerica, 9.0714, -79.3835),
+    colo("RUH", "Riyadh", "SA", Asia, 24.9576, 46.6988),
+    colo("SCL", "Santiago", "CL", SouthAmerica, -33.3930, -70.7858),
+    colo("SEA", "Seattle", "US", NorthAmerica, 47.4502, -122.3088),
+    colo(
+        "SFO",
+        "San Francisco",
+        "US",
+        NorthAmerica,
+        37.6213,
+        -122.3790,
+    ),
+    colo("SGN", "Ho Chi Minh City", "VN", Asia, 10.8188, 106.6519),
+    colo("SIN", "Singapore", "SG", Asia, 1.3644, 103.9915),
+    colo("SJC", "San Jose", "US", NorthAmerica, 37.3639, -121.9289),
+    colo(
+        "SLC",
+        "Salt Lake City",
+        "US",
+        NorthAmerica,
+        40.7899,
+        -111.9791,
+    ),
+    colo("SOF", "Sofia", "BG", Europe, 42.6967, 23.4114),
+    colo("SYD", "Sydney", "AU", Oceania, -33.9399, 151.1753),
+    colo("TLV", "Tel Aviv", "IL", Asia, 32.0055, 34.8854),
+    colo("TPE", "Taipei", "TW", Asia, 25.0797, 121.2342),
+    colo("VIE", "Vienna", "AT", Europe, 48.1103, 16.5697),
+    colo("WAW", "Warsaw", "PL", Europe, 52.1657, 20.9671),
+    colo("YUL", "Montréal", "CA", NorthAmerica, 45.4706, -73.7408),
+    colo("YVR", "Vancouver", "CA", NorthAmerica, 49.1967, -123.1815),
+    colo("YYZ", "Toronto", "CA", NorthAmerica, 43.6777, -79.6248),
+    colo("ZRH", "Zurich", "CH", Europe, 47.4582, 8.5555),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_is_sorted() {
+        assert!(COLOS.windows(2).all(|pair| pair[0].code < pair[1].code));
+    }
+
+    #[test]
+    fn looks_up_well_known_colos() {
+        let iad = colo_to_location("IAD").unwrap();
+        assert_eq!((iad.city, iad.country), ("Ashburn", "US"));
+        let lhr = colo_to_location("lhr").unwrap();
+        assert_eq!((lhr.city, lhr.country), ("London", "GB"));
+        let nrt = colo_to_location(" NRT ").unwrap();
+        assert_eq!((nrt.city, nrt.country), ("Tokyo", "JP"));
+        assert!(nrt.latitude > 35.0 && nrt.longitude > 139.0);
+        assert_eq!(colo_to_location("XXX"), None);
+    }
+
+    #[test]
+    fn maps_colos_to_continents() {
+        let cases = [
+            ("IAD", NorthAmerica),
+            ("yyz", NorthAmerica),
+            ("GRU", SouthAmerica),
+            ("LHR", Europe),
+            ("IST", Europe),
+            ("NRT", Asia),
+            ("DXB", Asia),
+            ("JNB", Africa),
+            ("SYD", Oceania),
+        ];
+        for (code, continent) in cases {
+            assert_eq!(continent_for_colo(code), Some(continent), "{code}");
+        }
+        assert_eq!(continent_for_colo("XXX"), None);
+        assert_eq!(continent_for_colo(""), None);
+        assert_eq!(Oceania.code(), "OC");
+        assert_eq!(
+            serde_json::to_value(NorthAmerica).unwrap(),
+            serde_json::json!("north_america")
+        );
+    }
+}
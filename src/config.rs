@@ -1,55 +1,265 @@
+use std::collections::HashMap;
 use std::env;
+use std::fs;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 
-use containerflare_command::CommandEndpoint;
+use axum::http::Method;
+use containerflare_command::{CommandClient, CommandEndpoint};
 use dotenvy::Error as DotenvError;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
+#[cfg(unix)]
+use tokio::signal::unix::SignalKind;
+use tokio::sync::mpsc;
 
-use crate::platform::RuntimePlatform;
+use crate::access_log::AccessLogFormat;
+use crate::context::RequestMetadata;
+use crate::context::{DEFAULT_DEADLINE_HEADER, DEFAULT_MAX_METADATA_HEADER_LEN};
+use crate::events::RuntimeEvent;
+use crate::not_found::NotFoundHandler;
+use crate::platform::{CloudflarePlatform, RuntimePlatform};
+use crate::raw_headers::DEFAULT_CAPTURE_DENYLIST;
 
 const DEFAULT_CLOUDFLARE_PORT: u16 = 8787;
 const DEFAULT_CLOUD_RUN_PORT: u16 = 8080;
 const CLOUD_RUN_COMMAND_REASON: &str = "host command channel is not available on Google Cloud Run";
 const PORT_ENV: &str = "PORT";
 const LEGACY_PORT_ENV: &str = "CF_CONTAINER_PORT";
+const COMMAND_TIMEOUT_ENV: &str = "CF_CMD_TIMEOUT";
+/// When set to anything but `0`/`false`, [`RuntimeConfig::from_env`] ignores `.env` files.
+pub const SKIP_DOTENV_ENV: &str = "CONTAINERFLARE_SKIP_DOTENV";
+/// Path of a JSON control file [`RuntimeConfig::from_env`] loads before applying env vars.
+pub const CONTROL_FILE_ENV: &str = "CF_CONTROL_FILE";
+/// Matches the backlog `tokio::net::TcpListener::bind` uses.
+const DEFAULT_LISTEN_BACKLOG: u32 = 1024;
 
 /// Configuration consumed by the runtime before spinning up Axum/hyper.
 #[derive(Clone, Debug)]
 pub struct RuntimeConfig {
     pub bind_addr: SocketAddr,
+    /// Further addresses served alongside `bind_addr` (e.g. `[::]:8787` next to `0.0.0.0:8787`
+    /// on platforms where an IPv6 socket does not dual-stack).
+    pub additional_bind_addrs: Vec<SocketAddr>,
     pub platform: RuntimePlatform,
     pub command_endpoint: Option<CommandEndpoint>,
     pub command_disabled_reason: Option<String>,
+    /// Pre-built client used by `serve` instead of dialing `command_endpoint`.
+    pub command_client: Option<CommandClient>,
+    /// Additional clients for other sidecars, looked up with
+    /// [`ContainerContext::command_named`](crate::ContainerContext::command_named).
+    pub named_command_clients: HashMap<String, CommandClient>,
+    /// Overrides the command client's per-command timeout (defaults to the client's 30s).
+    pub command_timeout: Option<Duration>,
+    /// Makes `serve` fail at startup unless the command channel answers a handshake (off by
+    /// default, so a missing channel only surfaces as `Unavailable` errors at request time).
+    pub require_command_channel: bool,
+    pub http_version: HttpVersion,
+    /// How long the listener keeps answering `503` to new requests after a shutdown signal
+    /// before it stops accepting and drains in-flight connections.
+    pub shutdown_grace_period: Duration,
+    /// Paths that keep being served normally while the runtime is shutting down.
+    pub health_check_paths: Vec<String>,
+    /// Forwards the request's trace identifiers with every [`ContainerContext::invoke`](crate::ContainerContext::invoke).
+    pub propagate_trace_context: bool,
+    /// Sets `TCP_NODELAY` on every accepted connection.
+    pub tcp_nodelay: bool,
+    /// Maximum number of pending connections queued by the kernel for the listener.
+    pub listen_backlog: u32,
+    /// Serves [`RuntimePlatform::detect_with_report`](crate::RuntimePlatform::detect_with_report)
+    /// as JSON at `/debug/platform` when enabled.
+    pub debug_platform_route: bool,
+    /// Path that serves Prometheus metrics from the main listener when set.
+    #[cfg(feature = "metrics")]
+    pub metrics_endpoint: Option<String>,
+    /// Installs a global `tracing` subscriber that streams log lines to the host as `log_emit`
+    /// commands once the command channel is live. See [`crate::log_sink`].
+    #[cfg(feature = "log-sink")]
+    pub stream_logs: bool,
+    /// Request header carrying the caller's remaining time budget, which caps the timeout of
+    /// commands issued through [`ContainerContext::invoke`](crate::ContainerContext::invoke).
+    /// `None` disables deadline propagation.
+    pub deadline_header: Option<String>,
+    /// Largest `x-containerflare-metadata` header (in bytes) the runtime will deserialize; larger
+    /// headers are ignored in favour of the plain request headers.
+    pub max_metadata_header_len: usize,
+    /// Largest request body extractors such as `Json` or `Bytes` will buffer. Defaults to the
+    /// platform's [`recommended_limits`](crate::RuntimePlatform::recommended_limits) (32 MiB on
+    /// Cloud Run, 100 MB on Cloudflare); `None` keeps axum's own 2 MB default.
+    pub max_body_bytes: Option<usize>,
+    /// Lets extractors buffer bodies of any size, ignoring `max_body_bytes`. Only set by
+    /// [`RuntimeConfigBuilder::unlimited_body_size`].
+    pub unlimited_body_size: bool,
+    /// Answers `504 Gateway Timeout` when a handler runs longer than this. Defaults to the
+    /// platform's [`recommended_limits`](crate::RuntimePlatform::recommended_limits), which sets
+    /// none on any platform. On Cloud Run, set it a little under the service's configured request
+    /// timeout so requests get a response before Cloud Run cuts them off.
+    pub request_timeout: Option<Duration>,
+    /// Answers `405 Method Not Allowed` for any method outside this list (all methods are allowed
+    /// by default).
+    ///
+    /// This is a global guard applied before routing, not a replacement for per-route methods.
+    pub allowed_methods: Option<Vec<Method>>,
+    /// Answers `400 Bad Request` when the percent-decoded path contains a control character
+    /// such as `%00` (off by default).
+    pub reject_malformed_paths: bool,
+    /// Answers `421 Misdirected Request` when the request's host is not in this list (all hosts
+    /// are allowed by default).
+    ///
+    /// Entries are exact host names (`api.example.com`) or a leading wildcard (`*.example.com`)
+    /// matching any subdomain, but not `example.com` itself. Comparison ignores case and the
+    /// port. The host is read like [`RequestMetadata::host`](crate::RequestMetadata::host):
+    /// `x-forwarded-host`, then `Host`, then the HTTP/2 `:authority`.
+    pub allowed_hosts: Option<Vec<String>>,
+    /// Emits one access log line per request in the given format.
+    pub access_log: Option<AccessLogFormat>,
+    /// Echoes the request id (`cf-ray`, or a generated UUID) in an `x-request-id` response header.
+    pub echo_request_id: bool,
+    /// Snapshots each request's headers for
+    /// [`ContainerContext::raw_headers`](crate::ContainerContext::raw_headers) (off by default).
+    pub capture_headers: bool,
+    /// Headers left out of the snapshot (`authorization`, `proxy-authorization`, and `cookie` by
+    /// default).
+    pub capture_headers_denylist: Vec<String>,
+    /// Compresses responses according to the client's `Accept-Encoding`.
+    #[cfg(feature = "compression")]
+    pub compression: bool,
+    /// Receives [`RuntimeEvent`]s as `serve` moves through its lifecycle.
+    pub events: Option<mpsc::Sender<RuntimeEvent>>,
+    /// Answers requests that match no route (axum's empty `404` when unset).
+    ///
+    /// `serve` installs it as the router's fallback, replacing any fallback set on the router.
+    pub not_found_handler: Option<NotFoundHandler>,
+    /// Signals that start a graceful shutdown (`SIGTERM` and `SIGINT` by default).
+    #[cfg(unix)]
+    pub shutdown_signals: Vec<SignalKind>,
+}
+
+/// HTTP protocol versions accepted by the embedded listener.
+///
+/// The default, [`HttpVersion::Auto`], sniffs the connection preface so HTTP/1.1 clients and
+/// HTTP/2 prior-knowledge (h2c) clients can share the same plaintext port. The listener does not
+/// terminate TLS itself; when TLS is terminated in front of the container the proxy negotiates
+/// `h2`/`http/1.1` via ALPN and forwards whichever version it speaks to us. Cloudflare's proxy
+/// may already downgrade traffic to HTTP/1.1 before it reaches the container.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HttpVersion {
+    /// Serve HTTP/1.1 and HTTP/2 (including h2c prior knowledge) on the same listener.
+    #[default]
+    Auto,
+    /// Serve HTTP/1.1 only.
+    Http1,
+    /// Serve HTTP/2 only (h2c prior knowledge for plaintext listeners).
+    Http2,
 }
 
 impl RuntimeConfig {
     /// Loads configuration from Cloudflare-supplied `CF_*` variables and Cloud Run's `PORT`.
     ///
-    /// Values from a local `.env` file (parsed via [`dotenvy::dotenv_override`]) override whatever is already set in
-    /// the process environment, which makes local development workflows predictable.
+    /// `CF_CONTAINER_ADDR` may list several comma-separated IPs (`0.0.0.0,::`); the runtime binds
+    /// each of them on the resolved port.
+    ///
+    /// `CF_CMD_TIMEOUT` accepts either whole seconds (`45`) or a humantime duration (`1500ms`,
+    /// `2m`) and overrides the command client's default timeout.
+    ///
+    /// Values from a local `.env` file (the first found in the working directory or one of its
+    /// parents) override whatever is already set in the process environment, which makes local
+    /// development workflows predictable. Set
+    /// `CONTAINERFLARE_SKIP_DOTENV=1` in the real environment (or call
+    /// [`RuntimeConfig::from_env_without_dotenv`]) so a stray `.env` in production cannot clobber
+    /// deployed variables.
+    ///
+    /// When `CF_CONTROL_FILE` names a JSON control file it is loaded first, exactly as
+    /// [`RuntimeConfig::from_control_file`] would.
     pub fn from_env() -> Result<Self, ConfigError> {
-        load_env_overrides()?;
+        match env::current_dir() {
+            Ok(dir) => Self::from_env_in(&dir),
+            // Without a working directory there is no `.env` to look for.
+            Err(_) => Self::from_env_without_dotenv(),
+        }
+    }
+
+    /// [`RuntimeConfig::from_env`], looking for the `.env` file from `dir` rather than the working
+    /// directory.
+    fn from_env_in(dir: &Path) -> Result<Self, ConfigError> {
+        if !skip_dotenv() {
+            load_env_overrides(dir)?;
+        }
+        Self::from_process_env(control_file_from_env()?)
+    }
+
+    /// Like [`RuntimeConfig::from_env`], but reads only the process environment and never looks
+    /// for a `.env` file.
+    pub fn from_env_without_dotenv() -> Result<Self, ConfigError> {
+        Self::from_process_env(control_file_from_env()?)
+    }
+
+    /// Builds the configuration from a sidecar's JSON control file, such as
+    /// `/var/run/containerflare.json`:
+    ///
+    /// ```json
+    /// {"bind_addr": "0.0.0.0:8787", "command_endpoint": "unix:///run/cmd.sock", "worker_name": "api"}
+    /// ```
+    ///
+    /// Every key is optional. Environment variables (`PORT`, `CF_CONTAINER_ADDR`,
+    /// `CF_CMD_ENDPOINT`, `CONTAINERFLARE_WORKER`, ...) still take precedence over the file.
+    pub fn from_control_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        Self::from_process_env(Some(ControlFile::load(path.as_ref())?))
+    }
 
-        let platform = RuntimePlatform::detect();
+    fn from_process_env(control: Option<ControlFile>) -> Result<Self, ConfigError> {
+        let control = control.unwrap_or_default();
+        let mut platform = RuntimePlatform::detect();
+        if let Some(worker_name) = control.worker_name {
+            match &mut platform {
+                RuntimePlatform::Cloudflare(cloudflare) => {
+                    cloudflare.worker_name.get_or_insert(worker_name);
+                }
+                RuntimePlatform::Generic => {
+                    platform = RuntimePlatform::Cloudflare(CloudflarePlatform {
+                        worker_name: Some(worker_name),
+                    });
+                }
+                RuntimePlatform::CloudRun(_) => {}
+            }
+        }
 
-        let port = resolve_port(&platform);
+        let port = env_port()
+            .or(control.bind_addr.map(|addr| addr.port()))
+            .unwrap_or_else(|| default_port(&platform));
+        let default_ip = control
+            .bind_addr
+            .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |addr| addr.ip());
 
-        let addr = env::var("CF_CONTAINER_ADDR")
+        let mut addrs = env::var("CF_CONTAINER_ADDR")
             .ok()
-            .and_then(|value| value.parse::<IpAddr>().ok())
-            .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+            .map(|value| parse_bind_ips(&value))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, port));
 
-        let bind_addr = SocketAddr::new(addr, port);
+        let bind_addr = addrs
+            .next()
+            .unwrap_or_else(|| SocketAddr::new(default_ip, port));
+        let additional_bind_addrs = addrs.collect();
 
         let command_endpoint = env::var("CF_CMD_ENDPOINT")
             .ok()
+            .or(control.command_endpoint)
             .map(|value| {
                 CommandEndpoint::from_str(&value)
                     .map_err(|_| ConfigError::InvalidCommandEndpoint(value))
             })
             .transpose()?; // convert Option<Result> -> Result<Option>
 
+        let command_timeout = env::var(COMMAND_TIMEOUT_ENV)
+            .ok()
+            .map(|value| parse_timeout(&value))
+            .transpose()?;
+
+        let limits = platform.recommended_limits();
         let (command_endpoint, command_disabled_reason) = match command_endpoint {
             Some(endpoint) => (Some(endpoint), None),
             None => match platform {
@@ -60,31 +270,214 @@ impl RuntimeConfig {
 
         Ok(Self {
             bind_addr,
+            additional_bind_addrs,
             platform,
             command_endpoint,
             command_disabled_reason,
+            command_client: None,
+            named_command_clients: HashMap::new(),
+            command_timeout,
+            require_command_channel: false,
+            http_version: HttpVersion::default(),
+            shutdown_grace_period: Duration::ZERO,
+            health_check_paths: Vec::new(),
+            propagate_trace_context: true,
+            tcp_nodelay: true,
+            listen_backlog: DEFAULT_LISTEN_BACKLOG,
+            debug_platform_route: false,
+            #[cfg(feature = "metrics")]
+            metrics_endpoint: None,
+            #[cfg(feature = "log-sink")]
+            stream_logs: false,
+            deadline_header: Some(DEFAULT_DEADLINE_HEADER.to_owned()),
+            max_metadata_header_len: DEFAULT_MAX_METADATA_HEADER_LEN,
+            max_body_bytes: limits.max_body_bytes,
+            unlimited_body_size: false,
+            request_timeout: limits.request_timeout,
+            allowed_methods: None,
+            reject_malformed_paths: false,
+            allowed_hosts: None,
+            access_log: None,
+            echo_request_id: true,
+            capture_headers: false,
+            capture_headers_denylist: default_capture_denylist(),
+            #[cfg(feature = "compression")]
+            compression: false,
+            events: None,
+            not_found_handler: None,
+            #[cfg(unix)]
+            shutdown_signals: default_shutdown_signals(),
         })
     }
 
+    /// Returns every address the runtime listens on, starting with `bind_addr`.
+    pub fn bind_addrs(&self) -> Vec<SocketAddr> {
+        std::iter::once(self.bind_addr)
+            .chain(self.additional_bind_addrs.iter().copied())
+            .collect()
+    }
+
     /// Returns a builder for programmatic overrides.
     pub fn builder() -> RuntimeConfigBuilder {
         RuntimeConfigBuilder::default()
     }
+
+    /// Returns a redacted snapshot of the settings, as logged by `serve` at startup.
+    pub fn summary(&self) -> ConfigSummary {
+        let millis = |duration: Duration| duration.as_millis() as u64;
+        let command_endpoint = match (&self.command_client, &self.command_endpoint) {
+            (Some(_), _) => Some("injected"),
+            (None, Some(CommandEndpoint::Stdio)) => Some("stdio"),
+            #[cfg(unix)]
+            (None, Some(CommandEndpoint::UnixSocket(_))) => Some("unix_socket"),
+            (None, Some(CommandEndpoint::Tcp(_))) => Some("tcp"),
+            (None, Some(CommandEndpoint::Auto)) => Some("auto"),
+            (None, Some(CommandEndpoint::Unavailable)) => Some("unavailable"),
+            (None, None) => None,
+        };
+        let mut named_command_clients = self
+            .named_command_clients
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        named_command_clients.sort();
+        ConfigSummary {
+            bind_addrs: self.bind_addrs(),
+            platform: self.platform.name(),
+            command_endpoint,
+            command_disabled_reason: self.command_disabled_reason.clone(),
+            command_timeout_ms: self.command_timeout.map(millis),
+            require_command_channel: self.require_command_channel,
+            named_command_clients,
+            http_version: match self.http_version {
+                HttpVersion::Auto => "auto",
+                HttpVersion::Http1 => "http1",
+                HttpVersion::Http2 => "http2",
+            },
+            shutdown_grace_period_ms: millis(self.shutdown_grace_period),
+            request_timeout_ms: self.request_timeout.map(millis),
+            max_body_bytes: self.max_body_bytes,
+            unlimited_body_size: self.unlimited_body_size,
+            listen_backlog: self.listen_backlog,
+            tcp_nodelay: self.tcp_nodelay,
+            propagate_trace_context: self.propagate_trace_context,
+            deadline_header: self.deadline_header.clone(),
+            debug_platform_route: self.debug_platform_route,
+            allowed_methods: self
+                .allowed_methods
+                .as_ref()
+                .map(|methods| methods.iter().map(ToString::to_string).collect()),
+            reject_malformed_paths: self.reject_malformed_paths,
+            allowed_hosts: self.allowed_hosts.clone(),
+            access_log: self.access_log.map(|format| match format {
+                AccessLogFormat::Common => "common",
+                AccessLogFormat::Combined => "combined",
+                AccessLogFormat::Json => "json",
+            }),
+            echo_request_id: self.echo_request_id,
+            capture_headers: self.capture_headers,
+            #[cfg(feature = "compression")]
+            compression: self.compression,
+            #[cfg(feature = "metrics")]
+            metrics_endpoint: self.metrics_endpoint.clone(),
+            #[cfg(feature = "log-sink")]
+            stream_logs: self.stream_logs,
+        }
+    }
+}
+
+/// Redacted snapshot of a [`RuntimeConfig`], returned by [`RuntimeConfig::summary`] and logged
+/// once by `serve` at startup so support requests can include the configuration that was
+/// actually loaded.
+///
+/// The command endpoint is reduced to its kind, without addresses or socket paths, and
+/// pre-built clients, the header denylist, the event sink, and the not-found handler are left
+/// out. Durations are in milliseconds.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ConfigSummary {
+    pub bind_addrs: Vec<SocketAddr>,
+    /// [`RuntimePlatform::name`].
+    pub platform: &'static str,
+    /// `stdio`, `tcp`, `unix_socket`, `auto`, `unavailable`, or `injected` for a pre-built client;
+    /// `None` when the channel is disabled.
+    pub command_endpoint: Option<&'static str>,
+    pub command_disabled_reason: Option<String>,
+    pub command_timeout_ms: Option<u64>,
+    pub require_command_channel: bool,
+    /// Names of the [`RuntimeConfig::named_command_clients`], sorted.
+    pub named_command_clients: Vec<String>,
+    pub http_version: &'static str,
+    pub shutdown_grace_period_ms: u64,
+    pub request_timeout_ms: Option<u64>,
+    pub max_body_bytes: Option<usize>,
+    pub unlimited_body_size: bool,
+    pub listen_backlog: u32,
+    pub tcp_nodelay: bool,
+    pub propagate_trace_context: bool,
+    pub deadline_header: Option<String>,
+    pub debug_platform_route: bool,
+    pub allowed_methods: Option<Vec<String>>,
+    pub reject_malformed_paths: bool,
+    pub allowed_hosts: Option<Vec<String>>,
+    pub access_log: Option<&'static str>,
+    pub echo_request_id: bool,
+    pub capture_headers: bool,
+    #[cfg(feature = "compression")]
+    pub compression: bool,
+    #[cfg(feature = "metrics")]
+    pub metrics_endpoint: Option<String>,
+    #[cfg(feature = "log-sink")]
+    pub stream_logs: bool,
 }
 
 impl Default for RuntimeConfig {
     /// Binds to `0.0.0.0` with the resolved port (prefers `PORT`, then `CF_CONTAINER_PORT`, otherwise `8787`)
     /// and talks to the host over stdio.
     fn default() -> Self {
+        let limits = RuntimePlatform::default().recommended_limits();
         // Default matches the local Cloudflare containers sidecar contract.
         Self {
             bind_addr: SocketAddr::new(
                 IpAddr::V4(Ipv4Addr::UNSPECIFIED),
                 resolve_port(&RuntimePlatform::default()),
             ),
+            additional_bind_addrs: Vec::new(),
             platform: RuntimePlatform::default(),
             command_endpoint: Some(CommandEndpoint::Stdio),
             command_disabled_reason: None,
+            command_client: None,
+            named_command_clients: HashMap::new(),
+            command_timeout: None,
+            require_command_channel: false,
+            http_version: HttpVersion::default(),
+            shutdown_grace_period: Duration::ZERO,
+            health_check_paths: Vec::new(),
+            propagate_trace_context: true,
+            tcp_nodelay: true,
+            listen_backlog: DEFAULT_LISTEN_BACKLOG,
+            debug_platform_route: false,
+            #[cfg(feature = "metrics")]
+            metrics_endpoint: None,
+            #[cfg(feature = "log-sink")]
+            stream_logs: false,
+            deadline_header: Some(DEFAULT_DEADLINE_HEADER.to_owned()),
+            max_metadata_header_len: DEFAULT_MAX_METADATA_HEADER_LEN,
+            max_body_bytes: limits.max_body_bytes,
+            unlimited_body_size: false,
+            request_timeout: limits.request_timeout,
+            allowed_methods: None,
+            reject_malformed_paths: false,
+            allowed_hosts: None,
+            access_log: None,
+            echo_request_id: true,
+            capture_headers: false,
+            capture_headers_denylist: default_capture_denylist(),
+            #[cfg(feature = "compression")]
+            compression: false,
+            events: None,
+            not_found_handler: None,
+            #[cfg(unix)]
+            shutdown_signals: default_shutdown_signals(),
         }
     }
 }
@@ -93,15 +486,59 @@ impl Default for RuntimeConfig {
 #[derive(Default, Clone, Debug)]
 pub struct RuntimeConfigBuilder {
     bind_addr: Option<SocketAddr>,
+    additional_bind_addrs: Vec<SocketAddr>,
     platform: Option<RuntimePlatform>,
     command_endpoint: Option<CommandEndpoint>,
     command_disabled_reason: Option<String>,
+    command_client: Option<CommandClient>,
+    named_command_clients: HashMap<String, CommandClient>,
+    command_timeout: Option<Duration>,
+    require_command_channel: bool,
+    http_version: Option<HttpVersion>,
+    shutdown_grace_period: Option<Duration>,
+    health_check_paths: Vec<String>,
+    propagate_trace_context: Option<bool>,
+    tcp_nodelay: Option<bool>,
+    listen_backlog: Option<u32>,
+    debug_platform_route: bool,
+    #[cfg(feature = "metrics")]
+    metrics_endpoint: Option<String>,
+    #[cfg(feature = "log-sink")]
+    stream_logs: bool,
+    deadline_header: Option<Option<String>>,
+    max_metadata_header_len: Option<usize>,
+    max_body_bytes: Option<Option<usize>>,
+    request_timeout: Option<Option<Duration>>,
+    allowed_methods: Option<Vec<Method>>,
+    reject_malformed_paths: bool,
+    allowed_hosts: Option<Vec<String>>,
+    access_log: Option<AccessLogFormat>,
+    echo_request_id: Option<bool>,
+    capture_headers: bool,
+    capture_headers_denylist: Option<Vec<String>>,
+    #[cfg(feature = "compression")]
+    compression: bool,
+    events: Option<mpsc::Sender<RuntimeEvent>>,
+    not_found_handler: Option<NotFoundHandler>,
+    #[cfg(unix)]
+    shutdown_signals: Option<Vec<SignalKind>>,
 }
 
 impl RuntimeConfigBuilder {
     /// Sets the address for the embedded Axum listener.
     pub fn bind_addr(mut self, addr: SocketAddr) -> Self {
         self.bind_addr = Some(addr);
+        self.additional_bind_addrs.clear();
+        self
+    }
+
+    /// Listens on every address in `addrs` (for example `127.0.0.1:8787` and `[::1]:8787`).
+    ///
+    /// An empty list keeps the default address.
+    pub fn bind_addrs(mut self, addrs: Vec<SocketAddr>) -> Self {
+        let mut addrs = addrs.into_iter();
+        self.bind_addr = addrs.next().or(self.bind_addr);
+        self.additional_bind_addrs = addrs.collect();
         self
     }
 
@@ -125,10 +562,296 @@ impl RuntimeConfigBuilder {
         self
     }
 
+    /// Hands `serve` an already-connected client (custom options, observer, or a test host)
+    /// instead of dialing [`RuntimeConfigBuilder::command_endpoint`].
+    ///
+    /// The client takes precedence over any endpoint or disabled reason; `build` logs a warning
+    /// when an endpoint was also set. `command_timeout` does not apply to an injected client.
+    pub fn command_client(mut self, client: CommandClient) -> Self {
+        self.command_client = Some(client);
+        self
+    }
+
+    /// Registers an extra client under `name` for handlers that talk to more than one sidecar.
+    ///
+    /// Handlers fetch it with
+    /// [`ContainerContext::command_named`](crate::ContainerContext::command_named); registering the
+    /// same name twice keeps the last client. `serve` closes named clients after draining, just
+    /// like the default one.
+    pub fn command_client_named(mut self, name: impl Into<String>, client: CommandClient) -> Self {
+        self.named_command_clients.insert(name.into(), client);
+        self
+    }
+
+    /// Overrides the timeout applied to each host command.
+    pub fn command_timeout(mut self, timeout: Duration) -> Self {
+        self.command_timeout = Some(timeout);
+        self
+    }
+
+    /// Fails `serve` during startup when the command channel is disabled, unavailable, or does
+    /// not answer a `__hello` handshake within the command timeout.
+    ///
+    /// Use this on platforms where the channel is mandatory, so a misconfigured container crashes
+    /// instead of failing every command at request time.
+    pub fn require_command_channel(mut self, required: bool) -> Self {
+        self.require_command_channel = required;
+        self
+    }
+
+    /// Selects which HTTP versions the listener accepts (defaults to [`HttpVersion::Auto`]).
+    pub fn http_version(mut self, version: HttpVersion) -> Self {
+        self.http_version = Some(version);
+        self
+    }
+
+    /// Enables or disables HTTP/2 (including h2c prior knowledge) alongside HTTP/1.1.
+    ///
+    /// `true` maps to [`HttpVersion::Auto`] and `false` to [`HttpVersion::Http1`].
+    pub fn http2(self, enabled: bool) -> Self {
+        self.http_version(if enabled {
+            HttpVersion::Auto
+        } else {
+            HttpVersion::Http1
+        })
+    }
+
+    /// Keeps accepting connections for `period` after a shutdown signal, answering new requests
+    /// with `503 Service Unavailable` so load balancers can take the instance out of rotation
+    /// before in-flight requests are drained.
+    pub fn shutdown_grace_period(mut self, period: Duration) -> Self {
+        self.shutdown_grace_period = Some(period);
+        self
+    }
+
+    /// Registers a path (e.g. a liveness probe) that keeps being served while shutting down.
+    pub fn health_check_path(mut self, path: impl Into<String>) -> Self {
+        self.health_check_paths.push(path.into());
+        self
+    }
+
+    /// Controls whether [`ContainerContext::invoke`](crate::ContainerContext::invoke) attaches the
+    /// request's trace identifiers to outgoing commands (enabled by default).
+    pub fn propagate_trace_context(mut self, enabled: bool) -> Self {
+        self.propagate_trace_context = Some(enabled);
+        self
+    }
+
+    /// Controls `TCP_NODELAY` on accepted connections (enabled by default, since request/response
+    /// workloads suffer from Nagle's algorithm delaying small writes).
+    pub fn tcp_nodelay(mut self, enabled: bool) -> Self {
+        self.tcp_nodelay = Some(enabled);
+        self
+    }
+
+    /// Sets the listener's accept backlog (defaults to 1024).
+    ///
+    /// The kernel may silently clamp the value: Linux caps it at `net.core.somaxconn` and macOS at
+    /// `kern.ipc.somaxconn`, so raise those sysctls as well when asking for a larger queue.
+    pub fn listen_backlog(mut self, backlog: u32) -> Self {
+        self.listen_backlog = Some(backlog);
+        self
+    }
+
+    /// Exposes a `/debug/platform` route that returns the platform detection report as JSON.
+    ///
+    /// The report includes the values of platform-related environment variables, so only enable
+    /// this where those are safe to reveal.
+    pub fn debug_platform_route(mut self, enabled: bool) -> Self {
+        self.debug_platform_route = enabled;
+        self
+    }
+
+    /// Serves Prometheus metrics at `path` (e.g. `/metrics`) on the main listener and records
+    /// HTTP and command metrics.
+    #[cfg(feature = "metrics")]
+    pub fn metrics_endpoint(mut self, path: impl Into<String>) -> Self {
+        self.metrics_endpoint = Some(path.into());
+        self
+    }
+
+    /// Streams `tracing` output to the host as `log_emit` commands once the command channel
+    /// is live, by installing a global `fmt` subscriber that writes to a
+    /// [`LogSink`](crate::log_sink::LogSink). Logs only warn if a subscriber is already installed.
+    #[cfg(feature = "log-sink")]
+    pub fn stream_logs(mut self, enabled: bool) -> Self {
+        self.stream_logs = enabled;
+        self
+    }
+
+    /// Reads the request deadline from `header` instead of `grpc-timeout`.
+    ///
+    /// The header holds a relative budget in gRPC syntax (`250m`, `2S`) or as a humantime duration
+    /// (`1500ms`); commands issued from the request wait at most for whatever budget remains.
+    pub fn deadline_header(mut self, header: impl Into<String>) -> Self {
+        self.deadline_header = Some(Some(header.into()));
+        self
+    }
+
+    /// Ignores request deadlines; commands always use the client's own timeout.
+    pub fn disable_deadline_propagation(mut self) -> Self {
+        self.deadline_header = Some(None);
+        self
+    }
+
+    /// Caps the size of the Worker shim's `x-containerflare-metadata` header (64 KiB by default).
+    ///
+    /// Oversized headers are skipped with a warning and metadata is read from the regular
+    /// request headers instead.
+    pub fn max_metadata_header_len(mut self, len: usize) -> Self {
+        self.max_metadata_header_len = Some(len);
+        self
+    }
+
+    /// Caps request bodies buffered by extractors at `limit` bytes instead of the platform's
+    /// recommendation.
+    pub fn max_body_bytes(mut self, limit: usize) -> Self {
+        self.max_body_bytes = Some(Some(limit));
+        self
+    }
+
+    /// Lets extractors buffer request bodies of any size.
+    ///
+    /// Any client can then make a handler buffer an arbitrarily large body, so only use this
+    /// behind a proxy that caps request sizes.
+    pub fn unlimited_body_size(mut self) -> Self {
+        self.max_body_bytes = Some(None);
+        self
+    }
+
+    /// Answers `504 Gateway Timeout` once a handler has run for `timeout`, instead of the
+    /// platform's recommendation.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(Some(timeout));
+        self
+    }
+
+    /// Lets handlers run for as long as they need.
+    pub fn disable_request_timeout(mut self) -> Self {
+        self.request_timeout = Some(None);
+        self
+    }
+
+    /// Rejects every request whose method is not in `methods` with `405 Method Not Allowed`.
+    ///
+    /// Useful to turn away `TRACE`, `CONNECT`, and other verbs no handler expects before they
+    /// reach the router.
+    pub fn allowed_methods(mut self, methods: Vec<Method>) -> Self {
+        self.allowed_methods = Some(methods);
+        self
+    }
+
+    /// Rejects requests whose path decodes to a control character with `400 Bad Request`.
+    pub fn reject_malformed_paths(mut self, enabled: bool) -> Self {
+        self.reject_malformed_paths = enabled;
+        self
+    }
+
+    /// Rejects requests for any host outside `hosts` with `421 Misdirected Request`, guarding
+    /// against host-header attacks.
+    ///
+    /// Accepts exact names and `*.example.com` wildcards; see [`RuntimeConfig::allowed_hosts`].
+    /// Any other wildcard fails `serve` with a validation error.
+    pub fn allowed_hosts(mut self, hosts: Vec<String>) -> Self {
+        self.allowed_hosts = Some(hosts);
+        self
+    }
+
+    /// Logs every request in `format` (disabled by default).
+    ///
+    /// Lines are emitted through `tracing` at `INFO` under the `containerflare::access` target,
+    /// so they can be routed or filtered independently of other runtime logs.
+    pub fn access_log(mut self, format: AccessLogFormat) -> Self {
+        self.access_log = Some(format);
+        self
+    }
+
+    /// Controls the `x-request-id` response header (enabled by default).
+    ///
+    /// When enabled, every response carries the request's `cf-ray`/shim request id, or a generated
+    /// UUID when the request has none; handlers can read the value through
+    /// [`RequestId`](crate::RequestId) or [`RequestMetadata::request_id`](crate::RequestMetadata::request_id).
+    /// Responses that already set the header are left untouched.
+    pub fn echo_request_id(mut self, enabled: bool) -> Self {
+        self.echo_request_id = Some(enabled);
+        self
+    }
+
+    /// Keeps a copy of every request's incoming headers, readable through
+    /// [`ContainerContext::raw_headers`](crate::ContainerContext::raw_headers).
+    ///
+    /// Meant for debugging metadata parsing: it clones each header map, so leave it off in
+    /// production. Credentials listed in the denylist are never captured.
+    pub fn capture_headers(mut self, enabled: bool) -> Self {
+        self.capture_headers = enabled;
+        self
+    }
+
+    /// Replaces the headers stripped from captured snapshots.
+    pub fn capture_headers_denylist<I, S>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.capture_headers_denylist = Some(headers.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Compresses response bodies with gzip (plus brotli and zstd when the `compression-br` and
+    /// `compression-zstd` features are enabled), negotiated from the client's `Accept-Encoding`.
+    ///
+    /// Disabled by default to avoid spending CPU unexpectedly. Small bodies, images, gRPC,
+    /// server-sent events, and already-compressed archives are passed through untouched; streamed
+    /// responses are compressed chunk by chunk.
+    #[cfg(feature = "compression")]
+    pub fn compression(mut self, enabled: bool) -> Self {
+        self.compression = enabled;
+        self
+    }
+
+    /// Replaces the signals that trigger a graceful shutdown (`SIGTERM` and `SIGINT` by default),
+    /// e.g. to also stop on `SIGQUIT` or `SIGHUP` from supervisors that send those.
+    #[cfg(unix)]
+    pub fn shutdown_signals(mut self, signals: Vec<SignalKind>) -> Self {
+        self.shutdown_signals = Some(signals);
+        self
+    }
+
+    /// Reports lifecycle milestones (bound, command channel connected, shutdown signal received,
+    /// drained) to `sender`.
+    ///
+    /// Events are delivered with `try_send`: when the channel is full or closed the event is
+    /// dropped rather than delaying the server.
+    pub fn events(mut self, sender: mpsc::Sender<RuntimeEvent>) -> Self {
+        self.events = Some(sender);
+        self
+    }
+
+    /// Answers requests that match no route with `handler`, which receives the request's
+    /// [`RequestMetadata`].
+    ///
+    /// [`not_found::negotiated`](crate::not_found::negotiated) picks JSON, HTML, or plain text
+    /// from the `Accept` header. Unset, unmatched requests get axum's empty `404`.
+    pub fn not_found_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&RequestMetadata) -> axum::response::Response + Send + Sync + 'static,
+    {
+        self.not_found_handler = Some(NotFoundHandler::new(handler));
+        self
+    }
+
     /// Builds the final configuration.
     pub fn build(self) -> RuntimeConfig {
+        if let (Some(client), Some(endpoint)) = (&self.command_client, &self.command_endpoint) {
+            tracing::warn!(
+                configured = ?endpoint,
+                injected = ?client.endpoint(),
+                "both a command endpoint and a command client were configured; using the client"
+            );
+        }
         let command_disabled_reason = self.command_disabled_reason;
         let platform = self.platform.unwrap_or_default();
+        let limits = platform.recommended_limits();
         let command_endpoint = if command_disabled_reason.is_some() {
             None
         } else {
@@ -139,9 +862,51 @@ impl RuntimeConfigBuilder {
             bind_addr: self.bind_addr.unwrap_or_else(|| {
                 SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), resolve_port(&platform))
             }),
+            additional_bind_addrs: self.additional_bind_addrs,
             platform,
             command_endpoint,
             command_disabled_reason,
+            command_client: self.command_client,
+            named_command_clients: self.named_command_clients,
+            command_timeout: self.command_timeout,
+            require_command_channel: self.require_command_channel,
+            http_version: self.http_version.unwrap_or_default(),
+            shutdown_grace_period: self.shutdown_grace_period.unwrap_or_default(),
+            health_check_paths: self.health_check_paths,
+            propagate_trace_context: self.propagate_trace_context.unwrap_or(true),
+            tcp_nodelay: self.tcp_nodelay.unwrap_or(true),
+            listen_backlog: self.listen_backlog.unwrap_or(DEFAULT_LISTEN_BACKLOG),
+            debug_platform_route: self.debug_platform_route,
+            #[cfg(feature = "metrics")]
+            metrics_endpoint: self.metrics_endpoint,
+            #[cfg(feature = "log-sink")]
+            stream_logs: self.stream_logs,
+            deadline_header: self
+                .deadline_header
+                .unwrap_or_else(|| Some(DEFAULT_DEADLINE_HEADER.to_owned())),
+            max_metadata_header_len: self
+                .max_metadata_header_len
+                .unwrap_or(DEFAULT_MAX_METADATA_HEADER_LEN),
+            max_body_bytes: self.max_body_bytes.unwrap_or(limits.max_body_bytes),
+            unlimited_body_size: self.max_body_bytes == Some(None),
+            request_timeout: self.request_timeout.unwrap_or(limits.request_timeout),
+            allowed_methods: self.allowed_methods,
+            reject_malformed_paths: self.reject_malformed_paths,
+            allowed_hosts: self.allowed_hosts,
+            access_log: self.access_log,
+            echo_request_id: self.echo_request_id.unwrap_or(true),
+            capture_headers: self.capture_headers,
+            capture_headers_denylist: self
+                .capture_headers_denylist
+                .unwrap_or_else(default_capture_denylist),
+            #[cfg(feature = "compression")]
+            compression: self.compression,
+            events: self.events,
+            not_found_handler: self.not_found_handler,
+            #[cfg(unix)]
+            shutdown_signals: self
+                .shutdown_signals
+                .unwrap_or_else(default_shutdown_signals),
         }
     }
 }
@@ -153,17 +918,100 @@ pub enum ConfigError {
     InvalidCommandEndpoint(String),
     #[error("failed to load .env overrides: {0}")]
     Dotenv(#[from] DotenvError),
+    #[error("invalid configuration: {0}")]
+    Validation(String),
+    #[error("failed to load control file {}: {reason}", path.display())]
+    ControlFile { path: PathBuf, reason: String },
+}
+
+/// Transport details a sidecar launcher writes for [`RuntimeConfig::from_control_file`].
+#[derive(Debug, Default, Deserialize)]
+struct ControlFile {
+    bind_addr: Option<SocketAddr>,
+    command_endpoint: Option<String>,
+    worker_name: Option<String>,
+}
+
+impl ControlFile {
+    fn load(path: &Path) -> Result<Self, ConfigError> {
+        let error = |reason: String| ConfigError::ControlFile {
+            path: path.to_owned(),
+            reason,
+        };
+        let raw = fs::read_to_string(path).map_err(|err| error(err.to_string()))?;
+        serde_json::from_str(&raw).map_err(|err| error(err.to_string()))
+    }
+}
+
+fn control_file_from_env() -> Result<Option<ControlFile>, ConfigError> {
+    env::var_os(CONTROL_FILE_ENV)
+        .filter(|path| !path.is_empty())
+        .map(|path| ControlFile::load(Path::new(&path)))
+        .transpose()
+}
+
+/// Parses a comma-separated IP list, skipping entries that are not valid IPs.
+fn parse_bind_ips(value: &str) -> Vec<IpAddr> {
+    value
+        .split(',')
+        .filter_map(|ip| ip.trim().parse().ok())
+        .collect()
+}
+
+fn default_capture_denylist() -> Vec<String> {
+    DEFAULT_CAPTURE_DENYLIST
+        .iter()
+        .map(|name| (*name).to_owned())
+        .collect()
+}
+
+#[cfg(unix)]
+fn default_shutdown_signals() -> Vec<SignalKind> {
+    vec![SignalKind::terminate(), SignalKind::interrupt()]
+}
+
+fn skip_dotenv() -> bool {
+    env::var(SKIP_DOTENV_ENV).is_ok_and(|value| {
+        let value = value.trim();
+        !(value.is_empty() || value == "0" || value.eq_ignore_ascii_case("false"))
+    })
 }
 
-fn load_env_overrides() -> Result<(), ConfigError> {
-    match dotenvy::dotenv_override() {
-        Ok(_) => Ok(()),
-        Err(err) if err.not_found() => Ok(()),
-        Err(err) => Err(ConfigError::Dotenv(err)),
+/// Loads the first `.env` in `dir` or its ancestors, overriding the process environment.
+fn load_env_overrides(dir: &Path) -> Result<(), ConfigError> {
+    let Some(path) = dir
+        .ancestors()
+        .map(|dir| dir.join(".env"))
+        .find(|path| path.is_file())
+    else {
+        return Ok(());
+    };
+    dotenvy::from_path_override(path).map_err(ConfigError::Dotenv)
+}
+
+fn parse_timeout(value: &str) -> Result<Duration, ConfigError> {
+    let value = value.trim();
+    let timeout = match value.parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => humantime::parse_duration(value).map_err(|err| {
+            ConfigError::Validation(format!("{COMMAND_TIMEOUT_ENV}={value:?}: {err}"))
+        })?,
+    };
+
+    if timeout.is_zero() {
+        return Err(ConfigError::Validation(format!(
+            "{COMMAND_TIMEOUT_ENV} must be greater than zero"
+        )));
     }
+
+    Ok(timeout)
 }
 
 fn resolve_port(platform: &RuntimePlatform) -> u16 {
+    env_port().unwrap_or_else(|| default_port(platform))
+}
+
+fn env_port() -> Option<u16> {
     env::var(PORT_ENV)
         .ok()
         .and_then(|value| value.parse::<u16>().ok())
@@ -172,23 +1020,71 @@ fn resolve_port(platform: &RuntimePlatform) -> u16 {
                 .ok()
                 .and_then(|value| value.parse::<u16>().ok())
         })
-        .unwrap_or(match platform {
-            RuntimePlatform::CloudRun(_) => DEFAULT_CLOUD_RUN_PORT,
-            _ => DEFAULT_CLOUDFLARE_PORT,
-        })
+}
+
+fn default_port(platform: &RuntimePlatform) -> u16 {
+    match platform {
+        RuntimePlatform::CloudRun(_) => DEFAULT_CLOUD_RUN_PORT,
+        _ => DEFAULT_CLOUDFLARE_PORT,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::env_lock;
     use containerflare_command::CommandEndpoint;
-    #[cfg(unix)]
-    use std::path::PathBuf;
-    use std::sync::{Mutex, OnceLock};
 
-    fn env_lock() -> &'static Mutex<()> {
-        static ENV_LOCK: OnceLock<Mutex<()>> = OnceLock::new();
-        ENV_LOCK.get_or_init(|| Mutex::new(()))
+    #[test]
+    fn summarizes_without_transport_details() {
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 8)), 9999);
+        let config = RuntimeConfig::builder()
+            .bind_addr(addr)
+            .platform(RuntimePlatform::Generic)
+            .command_endpoint(CommandEndpoint::Tcp("10.1.2.3:9998".into()))
+            .command_client_named("gpu", CommandClient::unavailable("tests"))
+            .command_timeout(Duration::from_millis(1500))
+            .http_version(HttpVersion::Http1)
+            .max_body_bytes(1024)
+            .request_timeout(Duration::from_secs(10))
+            .allowed_methods(vec![Method::GET])
+            .access_log(AccessLogFormat::Json)
+            .build();
+
+        let mut summary = serde_json::to_value(config.summary()).unwrap();
+        let fields = summary.as_object_mut().unwrap();
+        fields.remove("compression");
+        fields.remove("metrics_endpoint");
+        fields.remove("stream_logs");
+        assert_eq!(
+            summary,
+            serde_json::json!({
+                "bind_addrs": ["10.0.0.8:9999"],
+                "platform": "generic",
+                "command_endpoint": "tcp",
+                "command_disabled_reason": null,
+                "command_timeout_ms": 1500,
+                "require_command_channel": false,
+                "named_command_clients": ["gpu"],
+                "http_version": "http1",
+                "shutdown_grace_period_ms": 0,
+                "request_timeout_ms": 10000,
+                "max_body_bytes": 1024,
+                "unlimited_body_size": false,
+                "listen_backlog": 1024,
+                "tcp_nodelay": true,
+                "propagate_trace_context": true,
+                "deadline_header": DEFAULT_DEADLINE_HEADER,
+                "debug_platform_route": false,
+                "allowed_methods": ["GET"],
+                "reject_malformed_paths": false,
+                "allowed_hosts": null,
+                "access_log": "json",
+                "echo_request_id": true,
+                "capture_headers": false,
+            })
+        );
+        assert!(!summary.to_string().contains("10.1.2.3"));
     }
 
     #[test]
@@ -205,6 +1101,22 @@ mod tests {
             Some(CommandEndpoint::Tcp(_))
         ));
         assert!(config.command_disabled_reason.is_none());
+        assert_eq!(config.http_version, HttpVersion::Auto);
+        assert_eq!(config.shutdown_grace_period, Duration::ZERO);
+        assert!(config.health_check_paths.is_empty());
+        assert!(config.tcp_nodelay);
+        assert_eq!(config.listen_backlog, DEFAULT_LISTEN_BACKLOG);
+    }
+
+    #[test]
+    fn builder_selects_http_version() {
+        let config = RuntimeConfig::builder().http2(false).build();
+        assert_eq!(config.http_version, HttpVersion::Http1);
+
+        let config = RuntimeConfig::builder()
+            .http_version(HttpVersion::Http2)
+            .build();
+        assert_eq!(config.http_version, HttpVersion::Http2);
     }
 
     #[test]
@@ -230,6 +1142,30 @@ mod tests {
             "tcp://127.0.0.1:1111".parse::<CommandEndpoint>(),
             Ok(CommandEndpoint::Tcp(addr)) if addr == "127.0.0.1:1111"
         ));
+        for bare in [
+            "127.0.0.1:9000",
+            "[::1]:9000",
+            "localhost:9000",
+            "cmd.internal:9000",
+        ] {
+            assert_eq!(
+                bare.parse::<CommandEndpoint>().unwrap(),
+                CommandEndpoint::Tcp(bare.to_owned())
+            );
+        }
+        for invalid in [
+            "/tmp/cmd:9000",
+            "./sock:1",
+            "localhost",
+            "localhost:http",
+            ":9000",
+        ] {
+            assert!(invalid.parse::<CommandEndpoint>().is_err(), "{invalid}");
+        }
+        assert!(matches!(
+            "auto".parse::<CommandEndpoint>(),
+            Ok(CommandEndpoint::Auto)
+        ));
         assert!(matches!(
             "disabled".parse::<CommandEndpoint>(),
             Ok(CommandEndpoint::Unavailable)
@@ -239,11 +1175,44 @@ mod tests {
         {
             let endpoint = "unix:///tmp/socket".parse::<CommandEndpoint>();
             assert!(
-                matches!(endpoint, Ok(CommandEndpoint::UnixSocket(path)) if path == PathBuf::from("/tmp/socket"))
+                matches!(endpoint, Ok(CommandEndpoint::UnixSocket(path)) if path.as_path() == std::path::Path::new("/tmp/socket"))
             );
         }
     }
 
+    #[test]
+    fn limits_follow_the_platform_unless_overridden() {
+        let cloud_run = RuntimePlatform::CloudRun(Default::default());
+        let config = RuntimeConfig::builder().platform(cloud_run.clone()).build();
+        assert_eq!(config.max_body_bytes, Some(32 * 1024 * 1024));
+        // Cloud Run's timeout is per service, so it is left to the application.
+        assert_eq!(config.request_timeout, None);
+
+        let config = RuntimeConfig::builder()
+            .platform(cloud_run)
+            .max_body_bytes(1024)
+            .request_timeout(Duration::from_secs(3590))
+            .build();
+        assert_eq!(config.max_body_bytes, Some(1024));
+        assert_eq!(config.request_timeout, Some(Duration::from_secs(3590)));
+
+        let config = RuntimeConfig::builder()
+            .request_timeout(Duration::from_secs(10))
+            .disable_request_timeout()
+            .build();
+        assert_eq!(config.request_timeout, None);
+
+        let config = RuntimeConfig::builder()
+            .platform(RuntimePlatform::Generic)
+            .build();
+        assert_eq!(config.max_body_bytes, None);
+        assert!(!config.unlimited_body_size);
+        assert_eq!(config.request_timeout, None);
+
+        let config = RuntimeConfig::builder().unlimited_body_size().build();
+        assert!(config.unlimited_body_size);
+    }
+
     #[test]
     fn reads_env_configuration() {
         let _guard = env_lock().lock().unwrap();
@@ -271,6 +1240,156 @@ mod tests {
         }
     }
 
+    #[test]
+    fn reads_multiple_bind_addresses() {
+        let _guard = env_lock().lock().unwrap();
+        unsafe {
+            std::env::set_var("CF_CONTAINER_PORT", "9000");
+            std::env::set_var("CF_CONTAINER_ADDR", "127.0.0.1, ::1");
+        }
+
+        let config = RuntimeConfig::from_env().expect("config");
+        assert_eq!(
+            config.bind_addrs(),
+            vec![
+                "127.0.0.1:9000".parse::<SocketAddr>().unwrap(),
+                "[::1]:9000".parse().unwrap(),
+            ]
+        );
+
+        unsafe {
+            std::env::remove_var("CF_CONTAINER_PORT");
+            std::env::remove_var("CF_CONTAINER_ADDR");
+        }
+    }
+
+    #[test]
+    fn parses_command_timeout() {
+        assert_eq!(parse_timeout("45").unwrap(), Duration::from_secs(45));
+        assert_eq!(
+            parse_timeout("1500ms").unwrap(),
+            Duration::from_millis(1500)
+        );
+        assert_eq!(parse_timeout(" 2m ").unwrap(), Duration::from_secs(120));
+        assert!(matches!(
+            parse_timeout("soon"),
+            Err(ConfigError::Validation(_))
+        ));
+        assert!(matches!(
+            parse_timeout("0"),
+            Err(ConfigError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn reads_command_timeout_from_env() {
+        let _guard = env_lock().lock().unwrap();
+        unsafe {
+            std::env::set_var("CF_CMD_TIMEOUT", "5s");
+        }
+        let config = RuntimeConfig::from_env().expect("config");
+        assert_eq!(config.command_timeout, Some(Duration::from_secs(5)));
+
+        unsafe {
+            std::env::set_var("CF_CMD_TIMEOUT", "never");
+        }
+        assert!(matches!(
+            RuntimeConfig::from_env(),
+            Err(ConfigError::Validation(_))
+        ));
+
+        unsafe {
+            std::env::remove_var("CF_CMD_TIMEOUT");
+        }
+    }
+
+    #[test]
+    fn skipping_dotenv_ignores_env_files() {
+        let _guard = env_lock().lock().unwrap();
+        let dir =
+            std::env::temp_dir().join(format!("containerflare-dotenv-{}", std::process::id()));
+        let nested = dir.join("nested");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(dir.join(".env"), "CF_CMD_TIMEOUT=99\n").unwrap();
+        unsafe {
+            std::env::set_var("CF_CMD_TIMEOUT", "5");
+        }
+
+        let config = RuntimeConfig::from_env_without_dotenv().expect("config");
+        assert_eq!(config.command_timeout, Some(Duration::from_secs(5)));
+
+        unsafe {
+            std::env::set_var(SKIP_DOTENV_ENV, "1");
+        }
+        let config = RuntimeConfig::from_env_in(&nested).expect("config");
+        assert_eq!(config.command_timeout, Some(Duration::from_secs(5)));
+
+        unsafe {
+            std::env::remove_var(SKIP_DOTENV_ENV);
+        }
+        let config = RuntimeConfig::from_env_in(&nested).expect("config");
+        assert_eq!(config.command_timeout, Some(Duration::from_secs(99)));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        unsafe {
+            std::env::remove_var("CF_CMD_TIMEOUT");
+        }
+    }
+
+    #[test]
+    fn reads_control_file_with_env_overrides() {
+        let _guard = env_lock().lock().unwrap();
+        let path = std::env::temp_dir().join(format!(
+            "containerflare-control-{}.json",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            r#"{"bind_addr":"127.0.0.3:7000","command_endpoint":"tcp://127.0.0.1:7979","worker_name":"api"}"#,
+        )
+        .unwrap();
+
+        let config = RuntimeConfig::from_control_file(&path).expect("config");
+        assert_eq!(config.bind_addr, "127.0.0.3:7000".parse().unwrap());
+        assert!(matches!(
+            config.command_endpoint,
+            Some(CommandEndpoint::Tcp(ref addr)) if addr == "127.0.0.1:7979"
+        ));
+        assert_eq!(
+            config
+                .platform
+                .as_cloudflare()
+                .unwrap()
+                .worker_name
+                .as_deref(),
+            Some("api")
+        );
+
+        unsafe {
+            std::env::set_var(CONTROL_FILE_ENV, &path);
+            std::env::set_var("CF_CONTAINER_PORT", "9001");
+            std::env::set_var("CF_CMD_ENDPOINT", "stdio");
+        }
+        let config = RuntimeConfig::from_env_without_dotenv().expect("config");
+        assert_eq!(config.bind_addr, "127.0.0.3:9001".parse().unwrap());
+        assert!(matches!(
+            config.command_endpoint,
+            Some(CommandEndpoint::Stdio)
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            RuntimeConfig::from_env_without_dotenv(),
+            Err(ConfigError::ControlFile { .. })
+        ));
+
+        unsafe {
+            std::env::remove_var(CONTROL_FILE_ENV);
+            std::env::remove_var("CF_CONTAINER_PORT");
+            std::env::remove_var("CF_CMD_ENDPOINT");
+        }
+    }
+
     #[test]
     fn infers_cloud_run_defaults() {
         let _guard = env_lock().lock().unwrap();
@@ -1,18 +1,35 @@
 use async_trait::async_trait;
-use axum::extract::FromRequestParts;
+use axum::extract::{ConnectInfo, FromRequestParts, Request};
 use axum::http::StatusCode;
 use axum::http::header::{
-    ACCEPT, ACCEPT_ENCODING, ACCEPT_LANGUAGE, FORWARDED, HOST, HeaderName, USER_AGENT,
+    ACCEPT, ACCEPT_ENCODING, ACCEPT_LANGUAGE, CONNECTION, CONTENT_TYPE, FORWARDED, HOST,
+    HeaderName, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, RANGE, UPGRADE, USER_AGENT,
 };
 use axum::http::request::Parts;
+use axum::http::{Extensions, HeaderMap, HeaderValue, Method};
+use axum::middleware::Next;
 use axum::response::{IntoResponse, Response};
 use serde::{Deserialize, Serialize};
-use std::net::IpAddr;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use thiserror::Error;
 
-use containerflare_command::{CommandClient, CommandError, CommandRequest, CommandResponse};
+use containerflare_command::{
+    CommandClient, CommandError, CommandRequest, CommandResponse, CommandTrace,
+};
 
+use crate::accounting::ByteCounts;
+use crate::early_hints::EarlyHints;
+use crate::kv::KvClient;
 use crate::platform::{CloudRunPlatform, CloudflarePlatform, RuntimePlatform};
+use crate::queue::QueueClient;
+use crate::raw_headers::CapturedHeaders;
+use crate::request_id::RequestId;
+use crate::secrets::SecretClient;
+use crate::shutdown::ShutdownState;
 
 /// Header set by the Worker shim that carries Cloudflare-specific request metadata.
 const METADATA_HEADER: &str = "x-containerflare-metadata";
@@ -25,7 +42,22 @@ const HEADER_X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-
 const HEADER_X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
 const HEADER_X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
 const HEADER_X_CLOUD_TRACE_CONTEXT: HeaderName = HeaderName::from_static("x-cloud-trace-context");
+const HEADER_TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
 const HEADER_SEC_GPC: HeaderName = HeaderName::from_static("sec-gpc");
+const HEADER_CF_VISITOR: HeaderName = HeaderName::from_static("cf-visitor");
+const HEADER_CF_TLS_VERSION: HeaderName = HeaderName::from_static("cf-tls-version");
+const HEADER_CF_TLS_CIPHER: HeaderName = HeaderName::from_static("cf-tls-cipher");
+const HEADER_CF_WORKER: HeaderName = HeaderName::from_static("cf-worker");
+const HEADER_CF_EW_VIA: HeaderName = HeaderName::from_static("cf-ew-via");
+/// Header read for the request deadline unless configured otherwise.
+pub(crate) const DEFAULT_DEADLINE_HEADER: &str = "grpc-timeout";
+/// Largest metadata header deserialized unless configured otherwise.
+pub(crate) const DEFAULT_MAX_METADATA_HEADER_LEN: usize = 64 * 1024;
+/// Pause before the first retry issued by [`ContainerContext::invoke_with`].
+const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+/// Reason reported by the fallback command client the `dev` feature installs outside `serve`.
+const DEV_COMMAND_REASON: &str =
+    "command channel not configured (router is not running under serve)";
 
 /// Request-scoped handle that exposes platform-specific request metadata plus the host command
 /// client.
@@ -34,6 +66,94 @@ pub struct ContainerContext {
     metadata: RequestMetadata,
     command_client: CommandClient,
     platform: RuntimePlatform,
+    shutdown: ShutdownState,
+    options: ContextOptions,
+    deadline: Option<Instant>,
+    byte_counts: ByteCounts,
+    named_clients: NamedCommandClients,
+    raw_headers: Option<CapturedHeaders>,
+    store: RequestStore,
+    peer_addr: Option<SocketAddr>,
+    early_hints: Option<EarlyHints>,
+}
+
+/// Typed values shared by every [`ContainerContext`] extracted from one request.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RequestStore(Arc<std::sync::Mutex<Extensions>>);
+
+/// Middleware that gives each request its own [`RequestStore`].
+pub(crate) async fn install_request_store(mut request: Request, next: Next) -> Response {
+    request.extensions_mut().insert(RequestStore::default());
+    next.run(request).await
+}
+
+/// Clients registered with `RuntimeConfigBuilder::command_client_named`, installed by `serve`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct NamedCommandClients(pub(crate) Arc<HashMap<String, CommandClient>>);
+
+/// Per-call policy for [`ContainerContext::invoke_with`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvokeOptions {
+    /// Timeout for each attempt, replacing the client's own (still capped by the request
+    /// deadline).
+    pub timeout: Option<Duration>,
+    /// How many times a transient failure is retried (none by default).
+    ///
+    /// Only errors with a [`CommandError::retry_after`] hint are retried; host-side failures are
+    /// returned immediately.
+    pub retries: u32,
+    /// Pause before the first retry, doubled after each further attempt (100ms by default).
+    pub retry_backoff: Duration,
+}
+
+impl Default for InvokeOptions {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            retries: 0,
+            retry_backoff: DEFAULT_RETRY_BACKOFF,
+        }
+    }
+}
+
+impl InvokeOptions {
+    /// Waits at most `timeout` for each attempt.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Retries transient failures up to `retries` times.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Sets the pause before the first retry.
+    pub fn retry_backoff(mut self, backoff: Duration) -> Self {
+        self.retry_backoff = backoff;
+        self
+    }
+}
+
+/// Runtime settings that influence how [`ContainerContext`] behaves, installed by `serve`.
+#[derive(Clone, Debug)]
+pub(crate) struct ContextOptions {
+    pub(crate) propagate_trace_context: bool,
+    /// Header carrying the caller's remaining time budget (`grpc-timeout` by default).
+    pub(crate) deadline_header: Option<HeaderName>,
+    /// Metadata headers longer than this are ignored without being parsed.
+    pub(crate) max_metadata_header_len: usize,
+}
+
+impl Default for ContextOptions {
+    fn default() -> Self {
+        Self {
+            propagate_trace_context: true,
+            deadline_header: Some(HeaderName::from_static(DEFAULT_DEADLINE_HEADER)),
+            max_metadata_header_len: DEFAULT_MAX_METADATA_HEADER_LEN,
+        }
+    }
 }
 
 impl ContainerContext {
@@ -47,15 +167,294 @@ impl ContainerContext {
         &self.command_client
     }
 
+    /// Returns the client registered under `name` with
+    /// `RuntimeConfigBuilder::command_client_named`, if any.
+    pub fn command_named(&self, name: &str) -> Option<&CommandClient> {
+        self.named_clients.0.get(name)
+    }
+
     /// Returns the runtime platform detected from the environment.
     pub fn platform(&self) -> &RuntimePlatform {
         &self.platform
     }
 
+    /// Indicates whether the runtime has received a shutdown signal and is draining.
+    ///
+    /// Readiness handlers can use this to report `503` while the instance leaves rotation.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown.is_shutting_down()
+    }
+
+    /// Returns a key/value client backed by the host command channel.
+    pub fn kv(&self) -> KvClient {
+        KvClient::new(self.command_client.clone())
+    }
+
+    /// Returns a Cloudflare Queues publisher backed by the host command channel.
+    pub fn queue(&self) -> QueueClient {
+        QueueClient::new(self.command_client.clone())
+    }
+
+    /// Returns a client for secrets exposed by the host command channel.
+    pub fn secrets(&self) -> SecretClient {
+        SecretClient::new(self.command_client.clone())
+    }
+
+    /// Request body bytes read so far (zero when the router is not running under `serve`).
+    pub fn bytes_in(&self) -> u64 {
+        self.byte_counts.bytes_in()
+    }
+
+    /// Response body bytes written so far.
+    ///
+    /// The response body streams after the handler returns, so from inside a handler this is
+    /// normally `0`; keep [`ContainerContext::byte_counts`] around (e.g. in a spawned task or an
+    /// outer middleware) to read the final total once the response has been sent.
+    pub fn bytes_out(&self) -> u64 {
+        self.byte_counts.bytes_out()
+    }
+
+    /// Returns the shared byte counters for this request.
+    pub fn byte_counts(&self) -> &ByteCounts {
+        &self.byte_counts
+    }
+
+    /// Returns the address of the TCP peer this request arrived from.
+    ///
+    /// On Cloudflare Containers and Cloud Run this is the platform's proxy, not the end user; use
+    /// [`RequestMetadata::client_ip`] for the latter. `None` outside `serve`.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Sends a `103 Early Hints` response with one `Link` header per entry (e.g.
+    /// `</app.css>; rel=preload; as=style`) so the client can start fetching while the handler
+    /// is still working.
+    ///
+    /// Returns `Ok(false)` when hints cannot be sent: outside `serve`, over HTTP/2 or HTTP/1.0,
+    /// or once the response is underway. See [`EarlyHints`] for how proxies treat them.
+    ///
+    /// # Errors
+    /// See [`EarlyHints::send`].
+    pub fn send_early_hints(&self, links: Vec<String>) -> std::io::Result<bool> {
+        match &self.early_hints {
+            Some(hints) => hints.send(links),
+            None => Ok(false),
+        }
+    }
+
+    /// Returns `traceparent` and `x-cloud-trace-context` headers continuing this request's trace,
+    /// for forwarding on outbound HTTP calls.
+    ///
+    /// Empty when [`RequestMetadata::trace_context`] is unset. See
+    /// [`TraceContext::inject_into_headers`].
+    pub fn trace_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(trace) = &self.metadata.trace_context {
+            trace.inject_into_headers(&mut headers);
+        }
+        headers
+    }
+
+    /// Returns the headers the request arrived with, minus the capture denylist.
+    ///
+    /// Only available when `RuntimeConfigBuilder::capture_headers(true)` is set; meant for
+    /// debugging unexpected [`RequestMetadata`] values.
+    pub fn raw_headers(&self) -> Option<&HeaderMap> {
+        self.raw_headers
+            .as_ref()
+            .map(|CapturedHeaders(headers)| &**headers)
+    }
+
+    /// Stores `value` for the rest of the request, replacing any earlier value of the same type.
+    ///
+    /// The store belongs to the request, not to this context: every `ContainerContext` extracted
+    /// from the same request (in a middleware and later in the handler) and all of their clones
+    /// share it, so values inserted upstream are visible downstream. It is guarded by a mutex and
+    /// safe to use from spawned tasks. Outside `serve` each extraction gets a private store.
+    pub fn insert<T>(&self, value: T)
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        self.store
+            .0
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(value);
+    }
+
+    /// Returns a clone of the value of type `T` stored with [`ContainerContext::insert`].
+    pub fn get<T>(&self) -> Option<T>
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        self.store
+            .0
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .get::<T>()
+            .cloned()
+    }
+
+    /// Returns the instant by which the caller expects a response, derived from the deadline
+    /// header (`grpc-timeout` by default) when the request carried one.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
     /// Issues an IPC command over the host-managed channel.
+    ///
+    /// Unless disabled via `RuntimeConfigBuilder::propagate_trace_context(false)`, the request's
+    /// trace identifiers are attached as `_trace` when the caller has not set them already.
+    ///
+    /// When the request has a [deadline](Self::deadline), the command waits at most for the
+    /// remaining budget (capped by the client's own timeout); once the deadline has passed it fails
+    /// with [`CommandError::Timeout`] without touching the transport.
+    ///
+    /// With the `otel` feature each call runs in a `command` span, a child of the request span.
     pub async fn invoke(&self, request: CommandRequest) -> Result<CommandResponse, CommandError> {
-        self.command_client.send(request).await
+        self.invoke_with(request, InvokeOptions::default()).await
+    }
+
+    /// Issues `request` as a [dry run](CommandRequest::dry_run): the host answers as usual but
+    /// must not cause any side effects.
+    pub async fn invoke_dry_run(
+        &self,
+        request: CommandRequest,
+    ) -> Result<CommandResponse, CommandError> {
+        self.invoke(request.dry_run()).await
     }
+
+    /// Like [`ContainerContext::invoke`], but with a per-call timeout and retry policy instead of
+    /// the shared client's settings.
+    ///
+    /// Each attempt waits for the smaller of the configured timeout and whatever remains of the
+    /// request [deadline](Self::deadline); retries stop once the deadline has passed. A timed-out
+    /// attempt's late reply is discarded, or its connection dropped (see
+    /// [`CommandOptions::timeout`](containerflare_command::CommandOptions::timeout)), so its
+    /// retry cannot read that reply. Only retry commands that are safe to repeat: a timed-out
+    /// attempt may still have run on the host.
+    ///
+    /// Dropping the returned future (e.g. because the client disconnected and Axum cancelled the
+    /// handler) abandons the in-flight attempt and skips any remaining retries.
+    pub async fn invoke_with(
+        &self,
+        request: CommandRequest,
+        options: InvokeOptions,
+    ) -> Result<CommandResponse, CommandError> {
+        #[cfg(feature = "otel")]
+        {
+            use tracing::Instrument;
+            let span = crate::otel::command_span(&request.command);
+            self.invoke_inner(request, options).instrument(span).await
+        }
+        #[cfg(not(feature = "otel"))]
+        self.invoke_inner(request, options).await
+    }
+
+    async fn invoke_inner(
+        &self,
+        request: CommandRequest,
+        options: InvokeOptions,
+    ) -> Result<CommandResponse, CommandError> {
+        let request = self.prepare_request(request);
+        let mut backoff = options.retry_backoff;
+        let mut attempt = 0;
+        loop {
+            let result = self.attempt(request.clone(), options.timeout).await;
+            match result {
+                Err(err)
+                    if attempt < options.retries
+                        && err.retry_after().is_some()
+                        && self
+                            .deadline
+                            .is_none_or(|deadline| Instant::now() < deadline) =>
+                {
+                    attempt += 1;
+                    tracing::debug!(
+                        command = %request.command,
+                        attempt,
+                        error = %err,
+                        "retrying command"
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = backoff.saturating_mul(2);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn attempt(
+        &self,
+        request: CommandRequest,
+        timeout: Option<Duration>,
+    ) -> Result<CommandResponse, CommandError> {
+        let timeout = timeout.unwrap_or_else(|| self.command_client.timeout());
+        let Some(deadline) = self.deadline else {
+            return self
+                .command_client
+                .send_with_timeout(request, timeout)
+                .await;
+        };
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(CommandError::Timeout(Duration::ZERO));
+        }
+        // Running out the deadline is handled like any other timeout, so a reply the host sends
+        // afterwards never reaches the next command on the shared channel.
+        self.command_client
+            .send_with_timeout(request, remaining.min(timeout))
+            .await
+    }
+
+    fn prepare_request(&self, mut request: CommandRequest) -> CommandRequest {
+        if self.options.propagate_trace_context
+            && request.trace.is_none()
+            && let Some(trace) = &self.metadata.trace_context
+        {
+            request.trace = Some(CommandTrace {
+                trace_id: trace.trace_id.clone(),
+                span_id: trace.span_id.clone(),
+                sampled: trace.sampled,
+            });
+        }
+        request
+    }
+}
+
+/// Where a request's [`RequestMetadata`] was read from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MetadataSource {
+    /// The Worker shim's `x-containerflare-metadata` header, as sent.
+    ///
+    /// Also reported for metadata deserialized or built by hand.
+    #[default]
+    ShimHeader,
+    /// Reconstructed from the plain request headers because the shim header was missing or
+    /// rejected.
+    Headers,
+    /// The shim header, with fields filled in from the request headers (e.g. Cloud Run's
+    /// `x-cloud-trace-context`).
+    Merged,
+}
+
+/// Privacy-sensitive [`RequestMetadata`] fields that [`RequestMetadata::redacted`] can mask.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MetadataField {
+    /// `client_ip`, truncated to its /24 (IPv4) or /48 (IPv6) network.
+    ClientIp,
+    /// `forwarded_for`, with every hop truncated like [`MetadataField::ClientIp`].
+    ForwardedFor,
+    /// The raw `forwarded` header, which embeds addresses in free-form syntax; cleared.
+    Forwarded,
+    /// `user_agent`, replaced by the hex SHA-256 of its value so requests can still be grouped.
+    UserAgent,
+    /// `client_hints`, which fingerprint the device much like the user agent; cleared.
+    ClientHints,
+    /// `accept_language`; cleared.
+    AcceptLanguage,
 }
 
 /// Cloudflare metadata forwarded by the Worker shim plus additional Cloud Run details inferred
@@ -63,8 +462,9 @@ impl ContainerContext {
 ///
 /// For Cloudflare Containers this mirrors the fields documented in Cloudflare's `cf` object:
 /// <https://developers.cloudflare.com/workers/runtime-apis/request/#incomingrequestcfproperties>.
-/// When running on Google Cloud Run the `cloud_run_*`, `project_id`, and `trace_context` fields
-/// are populated automatically from the platform metadata and `x-cloud-trace-context` header.
+/// When running on Google Cloud Run the `cloud_run_*` and `project_id` fields are populated
+/// automatically from the platform metadata. On every platform `trace_context` is read from the
+/// `x-cloud-trace-context` or `traceparent` header when the shim did not send one.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "platform", rename_all = "snake_case")]
 pub enum RequestMetadataPlatform {
@@ -92,6 +492,11 @@ pub struct RequestMetadata {
     pub scheme: Option<String>,
     pub platform: Option<RequestMetadataPlatform>,
     pub worker_name: Option<String>,
+    /// Zone of the Worker that invoked this one through a subrequest (`cf-worker`), as opposed to
+    /// our own [`worker_name`](RequestMetadata::worker_name).
+    pub invoking_worker: Option<String>,
+    /// Raw `cf-ew-via` header Cloudflare adds to Worker subrequests.
+    pub edge_via: Option<String>,
     pub project_id: Option<String>,
     pub cloud_run_service: Option<String>,
     pub cloud_run_revision: Option<String>,
@@ -107,9 +512,26 @@ pub struct RequestMetadata {
     pub accept_encoding: Option<String>,
     pub sec_gpc: Option<String>,
     pub client_hints: Option<ClientHints>,
+    /// TLS parameters negotiated between the client and Cloudflare's edge.
+    pub tls: Option<TlsInfo>,
+    /// Protocol requested by an upgrade (`Connection: upgrade` + `Upgrade`, or an HTTP/2
+    /// extended CONNECT), e.g. `websocket`.
+    pub upgrade: Option<String>,
+    /// Raw `If-None-Match` header; see [`RequestMetadata::etag_matches`].
+    pub if_none_match: Option<String>,
+    /// Raw `If-Modified-Since` header; see [`RequestMetadata::not_modified_since`].
+    pub if_modified_since: Option<String>,
+    /// Raw `If-Match` header; see [`RequestMetadata::if_match_allows`].
+    pub if_match: Option<String>,
+    /// Raw `Range` header.
+    pub range: Option<String>,
+    /// Raw `Content-Type` header; see [`RequestMetadata::mime`].
+    pub content_type: Option<String>,
     pub method: String,
     pub path: String,
     pub raw_url: Option<String>,
+    #[serde(skip)]
+    source: MetadataSource,
 }
 
 impl Default for RequestMetadata {
@@ -124,6 +546,8 @@ impl Default for RequestMetadata {
             scheme: None,
             platform: None,
             worker_name: None,
+            invoking_worker: None,
+            edge_via: None,
             project_id: None,
             cloud_run_service: None,
             cloud_run_revision: None,
@@ -139,16 +563,278 @@ impl Default for RequestMetadata {
             accept_encoding: None,
             sec_gpc: None,
             client_hints: None,
+            tls: None,
+            upgrade: None,
+            if_none_match: None,
+            if_modified_since: None,
+            if_match: None,
+            range: None,
+            content_type: None,
             method: "GET".to_owned(),
             path: "/".to_owned(),
             raw_url: None,
+            source: MetadataSource::default(),
         }
     }
 }
 
 impl RequestMetadata {
+    /// Returns a builder for constructing metadata by hand, e.g. in handler unit tests.
+    ///
+    /// Unset fields keep their [`Default`] values (`GET /`, everything else empty).
+    ///
+    /// # Examples
+    /// ```
+    /// use containerflare::RequestMetadata;
+    ///
+    /// fn greeting(metadata: &RequestMetadata) -> String {
+    ///     match metadata.country.as_deref() {
+    ///         Some("FR") => "bonjour".to_owned(),
+    ///         _ => "hello".to_owned(),
+    ///     }
+    /// }
+    ///
+    /// let metadata = RequestMetadata::builder()
+    ///     .request_id("ray-123")
+    ///     .client_ip("203.0.113.7")
+    ///     .country("FR")
+    ///     .method("POST")
+    ///     .path("/greet")
+    ///     .build();
+    ///
+    /// assert_eq!(greeting(&metadata), "bonjour");
+    /// assert_eq!(metadata.path, "/greet");
+    /// assert!(metadata.colo.is_none());
+    /// ```
+    pub fn builder() -> RequestMetadataBuilder {
+        RequestMetadataBuilder::default()
+    }
+
+    /// Resolves [`RequestMetadata::colo`] (or the ray's colo suffix) to the data center's city,
+    /// country, and coordinates.
+    #[cfg(feature = "colo-db")]
+    pub fn colo_location(&self) -> Option<crate::colo::ColoInfo> {
+        let colo = self
+            .colo
+            .clone()
+            .or_else(|| self.ray().and_then(|ray| ray.colo))?;
+        crate::colo::colo_to_location(&colo)
+    }
+
+    /// Resolves the continent of [`RequestMetadata::colo`] (or the ray's colo suffix), for
+    /// routing by continent. `None` when there is no colo or it is not in the embedded table.
+    #[cfg(feature = "colo-db")]
+    pub fn continent(&self) -> Option<crate::colo::Continent> {
+        self.colo_location().map(|colo| colo.continent)
+    }
+
+    /// Reports whether this metadata came from the shim header, the request headers, or both.
+    ///
+    /// Handy when the shim and the fallback disagree about a request.
+    pub fn source(&self) -> MetadataSource {
+        self.source
+    }
+
+    /// Parses [`RequestMetadata::request_id`] as a Cloudflare Ray ID (`<hex>-<colo>`, or 16 bare
+    /// hex characters).
+    ///
+    /// Returns `None` when the request id is missing or is not a ray, such as the Cloud Trace id
+    /// Cloud Run requests use as their request id.
+    pub fn ray(&self) -> Option<RayId> {
+        RayId::parse(self.request_id.as_deref()?)
+    }
+
+    /// Indicates whether the client asked to switch protocols (e.g. a WebSocket handshake).
+    pub fn is_upgrade(&self) -> bool {
+        self.upgrade.is_some()
+    }
+
+    /// Returns the protocol the client asked to upgrade to, such as `websocket`.
+    pub fn upgrade_protocol(&self) -> Option<String> {
+        self.upgrade.clone()
+    }
+
+    /// Parses [`RequestMetadata::content_type`], returning `None` when it is absent or invalid.
+    pub fn mime(&self) -> Option<mime::Mime> {
+        self.content_type.as_deref()?.parse().ok()
+    }
+
+    /// Picks the media type in `offered` that the `Accept` header ranks highest, for content
+    /// negotiation.
+    ///
+    /// Each offer is weighed by the `q` of the most specific range matching it (`text/html` over
+    /// `text/*` over `*/*`); ties go to the earlier offer. Without an `Accept` header the first
+    /// offer wins. `None` when the client accepts none of them.
+    pub fn preferred_media_type<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        let Some(accept) = self
+            .accept
+            .as_deref()
+            .filter(|value| !value.trim().is_empty())
+        else {
+            return offered.first().copied();
+        };
+        let ranges = accept
+            .split(',')
+            .filter_map(|range| {
+                let range = range.to_ascii_lowercase();
+                let mut params = range.split(';');
+                let (kind, subtype) = params.next()?.trim().split_once('/')?;
+                let q = params
+                    .find_map(|param| param.trim().strip_prefix("q=")?.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                Some((kind.to_owned(), subtype.to_owned(), q))
+            })
+            .collect::<Vec<_>>();
+
+        let mut best: Option<(&'a str, f32)> = None;
+        for &offer in offered {
+            let offer_lower = offer.to_ascii_lowercase();
+            let Some((kind, subtype)) = offer_lower.split_once('/') else {
+                continue;
+            };
+            let q = ranges
+                .iter()
+                .filter_map(|(range_kind, range_subtype, q)| {
+                    let specificity = match (range_kind.as_str(), range_subtype.as_str()) {
+                        ("*", "*") => 0,
+                        (range_kind, "*") if range_kind == kind => 1,
+                        (range_kind, range_subtype)
+                            if range_kind == kind && range_subtype == subtype =>
+                        {
+                            2
+                        }
+                        _ => return None,
+                    };
+                    Some((specificity, *q))
+                })
+                .max_by_key(|(specificity, _)| *specificity)
+                .map(|(_, q)| q);
+            if let Some(q) = q
+                && q > 0.0
+                && best.is_none_or(|(_, best_q)| q > best_q)
+            {
+                best = Some((offer, q));
+            }
+        }
+        best.map(|(offer, _)| offer)
+    }
+
+    /// Indicates whether `If-None-Match` lists `etag` (or is `*`), i.e. the client's cached copy
+    /// is current and a `304 Not Modified` can be sent.
+    ///
+    /// Uses the weak comparison RFC 9110 prescribes for `If-None-Match`: `W/"v1"` matches `"v1"`.
+    /// `etag` may be given with or without quotes.
+    pub fn etag_matches(&self, etag: &str) -> bool {
+        let Some(header) = self.if_none_match.as_deref() else {
+            return false;
+        };
+        let (_, wanted) = split_etag(etag);
+        header.trim() == "*" || parse_etag_list(header).any(|(_, tag)| tag == wanted)
+    }
+
+    /// Indicates whether `If-Match` allows modifying a resource whose current tag is `etag`.
+    ///
+    /// Uses strong comparison, so weak tags on either side never match. `true` when the header
+    /// is absent or `*`.
+    pub fn if_match_allows(&self, etag: &str) -> bool {
+        let Some(header) = self.if_match.as_deref() else {
+            return true;
+        };
+        let (weak, wanted) = split_etag(etag);
+        header.trim() == "*"
+            || (!weak && parse_etag_list(header).any(|(weak, tag)| !weak && tag == wanted))
+    }
+
+    /// Indicates whether a resource last changed at `last_modified` is unchanged since the
+    /// client's `If-Modified-Since` date, i.e. a `304 Not Modified` can be sent.
+    ///
+    /// HTTP dates have one-second precision, so sub-second parts of `last_modified` are ignored.
+    /// Returns `false` when the header is absent or not a valid HTTP date. RFC 9110 says to ignore
+    /// `If-Modified-Since` when `If-None-Match` is present; check
+    /// [`RequestMetadata::etag_matches`] first in that case.
+    pub fn not_modified_since(&self, last_modified: SystemTime) -> bool {
+        let Some(since) = self
+            .if_modified_since
+            .as_deref()
+            .and_then(|value| httpdate::parse_http_date(value.trim()).ok())
+        else {
+            return false;
+        };
+        let truncate = |time: SystemTime| {
+            time.duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs())
+        };
+        truncate(last_modified) <= truncate(since)
+    }
+
+    /// Returns a copy with each of `fields` masked, e.g. before logging the metadata as JSON.
+    ///
+    /// Addresses that do not parse as IPs are cleared rather than truncated. Fields not listed
+    /// are left untouched.
+    pub fn redacted(&self, fields: &[MetadataField]) -> RequestMetadata {
+        let mut metadata = self.clone();
+        for field in fields {
+            match field {
+                MetadataField::ClientIp => {
+                    metadata.client_ip = metadata.client_ip.as_deref().and_then(truncate_ip);
+                }
+                MetadataField::ForwardedFor => {
+                    metadata.forwarded_for = metadata
+                        .forwarded_for
+                        .iter()
+                        .filter_map(|hop| truncate_ip(hop))
+                        .collect();
+                }
+                MetadataField::Forwarded => metadata.forwarded = None,
+                MetadataField::UserAgent => {
+                    metadata.user_agent = metadata.user_agent.as_deref().map(sha256_hex);
+                }
+                MetadataField::ClientHints => metadata.client_hints = None,
+                MetadataField::AcceptLanguage => metadata.accept_language = None,
+            }
+        }
+        metadata
+    }
+
+    /// Serializes the metadata back into an `x-containerflare-metadata` header value, the reverse
+    /// of what the runtime does with the Worker shim's header.
+    ///
+    /// Attach it to requests proxied to another containerflare service so that service sees the
+    /// same metadata instead of rebuilding it from its own (proxy-facing) headers. Non-ASCII text
+    /// is written as `\u` escapes, since header values must be ASCII to be read back. The
+    /// receiving runtime still applies its `max_metadata_header_len` limit.
+    ///
+    /// # Errors
+    /// Returns an error if the metadata cannot be serialized to JSON.
+    pub fn to_header_value(&self) -> Result<HeaderValue, serde_json::Error> {
+        let json = serde_json::to_string(self)?;
+        let mut ascii = String::with_capacity(json.len());
+        let mut units = [0; 2];
+        for ch in json.chars() {
+            if ch.is_ascii() && ch != '\x7f' {
+                ascii.push(ch);
+            } else {
+                // Only string contents can hold these, where `\u` escapes are valid JSON.
+                for unit in ch.encode_utf16(&mut units) {
+                    ascii.push_str(&format!("\\u{unit:04x}"));
+                }
+            }
+        }
+        Ok(HeaderValue::from_str(&ascii).expect("escaped JSON is visible ASCII"))
+    }
+
+    /// Returns [`RequestMetadata::path`] cleaned up for use as a lookup key (e.g. a file path).
+    ///
+    /// The query string is dropped, the path is percent-decoded, empty and `.` segments are
+    /// removed, `..` pops the previous segment, and any trailing slash is trimmed (`/a/../b/`
+    /// becomes `/b`). Input that tries to climb above the root, is not valid UTF-8, or contains
+    /// control characters or backslashes yields `/`. `path` itself stays untouched.
+    pub fn normalized_path(&self) -> String {
+        normalize_path(&self.path).unwrap_or_else(|| "/".to_owned())
+    }
+
     /// Builds metadata from either the shim header or fallbacks for local testing.
-    fn from_parts(parts: &Parts, platform: &RuntimePlatform) -> Self {
+    pub(crate) fn from_parts(parts: &Parts, platform: &RuntimePlatform) -> Self {
         let mut metadata = if let Some(metadata) = Self::from_metadata_header(parts) {
             metadata
         } else {
@@ -156,13 +842,82 @@ impl RequestMetadata {
         };
 
         metadata.apply_platform_defaults(parts, platform);
+        if metadata.trace_context.is_none()
+            && let Some(trace) =
+                TraceContext::from_headers(&parts.headers, metadata.platform_project_id())
+        {
+            metadata.trace_context = Some(trace);
+            if metadata.source == MetadataSource::ShimHeader {
+                metadata.source = MetadataSource::Merged;
+            }
+        }
+        if metadata.colo.is_none() {
+            metadata.colo = metadata.ray().and_then(|ray| ray.colo);
+        }
         metadata
     }
 
+    /// Deserializes the Worker shim's header, returning `None` (so the caller falls back to the
+    /// plain request headers) when it is oversized, malformed, or carries implausible values.
     fn from_metadata_header(parts: &Parts) -> Option<Self> {
         let header = parts.headers.get(METADATA_HEADER)?;
+        let max_len = parts
+            .extensions
+            .get::<ContextOptions>()
+            .map_or(DEFAULT_MAX_METADATA_HEADER_LEN, |options| {
+                options.max_metadata_header_len
+            });
+        if header.len() > max_len {
+            tracing::warn!(
+                len = header.len(),
+                max_len,
+                "ignoring oversized {METADATA_HEADER} header"
+            );
+            return None;
+        }
         let raw = header.to_str().ok()?;
-        serde_json::from_str(raw).ok()
+        let metadata: Self = match serde_json::from_str(raw) {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                tracing::warn!(error = %err, "ignoring malformed {METADATA_HEADER} header");
+                return None;
+            }
+        };
+        if let Err(reason) = metadata.check_shim_values() {
+            tracing::warn!(reason, "ignoring suspicious {METADATA_HEADER} header");
+            return None;
+        }
+        Some(metadata)
+    }
+
+    /// Rejects shim metadata that no well-behaved Worker would produce.
+    fn check_shim_values(&self) -> Result<(), &'static str> {
+        if Method::from_bytes(self.method.as_bytes()).is_err() {
+            return Err("invalid method");
+        }
+        if let Some(ip) = &self.client_ip
+            && ip.parse::<IpAddr>().is_err()
+        {
+            return Err("invalid client ip");
+        }
+        let text_fields = [
+            Some(self.path.as_str()),
+            self.raw_url.as_deref(),
+            self.request_id.as_deref(),
+            self.host.as_deref(),
+            self.scheme.as_deref(),
+            self.worker_name.as_deref(),
+            self.invoking_worker.as_deref(),
+            self.edge_via.as_deref(),
+        ];
+        if text_fields
+            .into_iter()
+            .flatten()
+            .any(|value| value.chars().any(char::is_control))
+        {
+            return Err("control characters in field");
+        }
+        Ok(())
     }
 
     fn from_headers(parts: &Parts) -> Self {
@@ -173,38 +928,40 @@ impl RequestMetadata {
         let region = header_to_string(headers, &HEADER_CF_REGION);
         let client_ip = header_to_string(headers, &HEADER_CF_CONNECTING_IP)
             .or_else(|| pick_client_ip_from_xff(headers));
-        let host = headers
-            .get(&HEADER_X_FORWARDED_HOST)
-            .or_else(|| headers.get(HOST))
-            .and_then(|value| value.to_str().ok())
-            .map(|value| value.to_owned());
+        let host = request_host(headers).map(|value| value.to_owned());
 
         let method = parts.method.to_string();
         let path_and_query = parts.uri.path_and_query().map(|pq| pq.as_str().to_owned());
-        let path = path_and_query
-            .clone()
-            .unwrap_or_else(|| parts.uri.path().to_owned());
+        // CONNECT targets are authority-form (`host:port`) and carry no path.
+        let path = path_and_query.clone().unwrap_or_else(|| {
+            match (&parts.method, parts.uri.authority()) {
+                (&Method::CONNECT, Some(authority)) => authority.as_str().to_owned(),
+                _ => parts.uri.path().to_owned(),
+            }
+        });
         let raw_url = Some(parts.uri.to_string()).filter(|value| !value.is_empty());
         let forwarded_proto = header_to_string(headers, &HEADER_X_FORWARDED_PROTO);
         let scheme = forwarded_proto
             .clone()
+            .or_else(|| scheme_from_cf_visitor(headers))
             .or_else(|| parts.uri.scheme_str().map(|value| value.to_owned()));
         let forwarded = header_to_string(headers, &FORWARDED);
-        let forwarded_for = header_to_string(headers, &HEADER_X_FORWARDED_FOR)
-            .map(|value| {
-                value
-                    .split(',')
-                    .map(|v| v.trim().to_owned())
-                    .filter(|v| !v.is_empty())
-                    .collect::<Vec<_>>()
-            })
-            .unwrap_or_default();
+        let forwarded_for = forwarded_for_chain(headers);
         let user_agent = header_to_string(headers, &USER_AGENT);
         let accept = header_to_string(headers, &ACCEPT);
         let accept_language = header_to_string(headers, &ACCEPT_LANGUAGE);
         let accept_encoding = header_to_string(headers, &ACCEPT_ENCODING);
         let sec_gpc = header_to_string(headers, &HEADER_SEC_GPC);
+        let invoking_worker = header_to_string(headers, &HEADER_CF_WORKER);
+        let edge_via = header_to_string(headers, &HEADER_CF_EW_VIA);
         let client_hints = ClientHints::from_headers(headers);
+        let tls = TlsInfo::from_headers(headers);
+        let upgrade = upgrade_protocol(parts);
+        let if_none_match = header_to_string(headers, &IF_NONE_MATCH);
+        let if_modified_since = header_to_string(headers, &IF_MODIFIED_SINCE);
+        let if_match = header_to_string(headers, &IF_MATCH);
+        let range = header_to_string(headers, &RANGE);
+        let content_type = header_to_string(headers, &CONTENT_TYPE);
 
         Self {
             request_id,
@@ -216,6 +973,8 @@ impl RequestMetadata {
             scheme,
             platform: None,
             worker_name: None,
+            invoking_worker,
+            edge_via,
             project_id: None,
             cloud_run_service: None,
             cloud_run_revision: None,
@@ -231,9 +990,17 @@ impl RequestMetadata {
             accept_encoding,
             sec_gpc,
             client_hints,
+            tls,
+            upgrade,
+            if_none_match,
+            if_modified_since,
+            if_match,
+            range,
+            content_type,
             method,
             path,
             raw_url,
+            source: MetadataSource::Headers,
         }
     }
 
@@ -312,20 +1079,18 @@ impl RequestMetadata {
             self.worker_name = self.cloud_run_service.clone();
         }
 
-        if let Some(value) = parts
-            .headers
-            .get(&HEADER_X_CLOUD_TRACE_CONTEXT)
-            .and_then(|header| header.to_str().ok())
-        {
-            let trace = TraceContext::from_cloud_trace_header(
-                value,
-                self.platform_project_id()
-                    .or(platform.project_id.as_deref()),
-            );
+        if let Some(trace) = TraceContext::from_headers(
+            &parts.headers,
+            self.platform_project_id()
+                .or(platform.project_id.as_deref()),
+        ) {
             if self.request_id.is_none() {
                 self.request_id = trace.trace_id.clone();
             }
             self.trace_context = Some(trace);
+            if self.source == MetadataSource::ShimHeader {
+                self.source = MetadataSource::Merged;
+            }
         }
 
         self.platform = match self.platform.take() {
@@ -373,6 +1138,10 @@ impl RequestMetadata {
 
     /// Attempts to rebuild the raw URL using scheme + host + path when only a path was available.
     fn rebuild_raw_url_if_needed(&mut self) {
+        // An authority-form CONNECT target is not a URL; keep it as received.
+        if self.method.eq_ignore_ascii_case(Method::CONNECT.as_str()) {
+            return;
+        }
         let needs_rebuild = self
             .raw_url
             .as_ref()
@@ -394,17 +1163,208 @@ impl RequestMetadata {
     }
 }
 
-/// Google Cloud Trace context parsed from `x-cloud-trace-context` headers.
+/// Builder type for [`RequestMetadata`].
+#[derive(Clone, Debug, Default)]
+pub struct RequestMetadataBuilder {
+    metadata: RequestMetadata,
+}
+
+impl RequestMetadataBuilder {
+    /// Sets the request identifier (normally the `cf-ray` header).
+    pub fn request_id(mut self, request_id: impl Into<String>) -> Self {
+        self.metadata.request_id = Some(request_id.into());
+        self
+    }
+
+    /// Sets the client IP address.
+    pub fn client_ip(mut self, client_ip: impl Into<String>) -> Self {
+        self.metadata.client_ip = Some(client_ip.into());
+        self
+    }
+
+    /// Sets the client's country code.
+    pub fn country(mut self, country: impl Into<String>) -> Self {
+        self.metadata.country = Some(country.into());
+        self
+    }
+
+    /// Sets the Cloudflare colo that handled the request.
+    pub fn colo(mut self, colo: impl Into<String>) -> Self {
+        self.metadata.colo = Some(colo.into());
+        self
+    }
+
+    /// Sets the request host.
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.metadata.host = Some(host.into());
+        self
+    }
+
+    /// Sets the HTTP method.
+    pub fn method(mut self, method: impl Into<String>) -> Self {
+        self.metadata.method = method.into();
+        self
+    }
+
+    /// Sets the path (including any query string).
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.metadata.path = path.into();
+        self
+    }
+
+    /// Sets the worker (or Cloud Run service) name.
+    pub fn worker_name(mut self, worker_name: impl Into<String>) -> Self {
+        self.metadata.worker_name = Some(worker_name.into());
+        self
+    }
+
+    /// Sets the raw `If-None-Match` header.
+    pub fn if_none_match(mut self, value: impl Into<String>) -> Self {
+        self.metadata.if_none_match = Some(value.into());
+        self
+    }
+
+    /// Sets the raw `If-Modified-Since` header.
+    pub fn if_modified_since(mut self, value: impl Into<String>) -> Self {
+        self.metadata.if_modified_since = Some(value.into());
+        self
+    }
+
+    /// Sets the parsed trace context.
+    pub fn trace_context(mut self, trace_context: TraceContext) -> Self {
+        self.metadata.trace_context = Some(trace_context);
+        self
+    }
+
+    /// Builds the final metadata.
+    pub fn build(self) -> RequestMetadata {
+        self.metadata
+    }
+}
+
+/// Length of a Cloudflare Ray ID without its `-<colo>` suffix.
+const RAY_HEX_LEN: usize = 16;
+
+/// Components of a Cloudflare Ray ID such as `7d3f0a1b2c3d4e5f-IAD`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RayId {
+    /// Hexadecimal request identifier.
+    pub hex: String,
+    /// IATA code of the data center that handled the request, when the ray carries one.
+    pub colo: Option<String>,
+}
+
+impl RayId {
+    /// Parses `<hex>-<colo>`, or a bare `<hex>` of exactly 16 characters, returning `None`
+    /// otherwise.
+    ///
+    /// A bare id of any other length is not taken for a ray, so a 32-character Cloud Trace id
+    /// does not parse.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let (hex, colo) = match value.rsplit_once('-') {
+            Some((hex, colo)) => (hex, Some(colo)),
+            None => (value, None),
+        };
+        let valid_hex = !hex.is_empty()
+            && hex.chars().all(|c| c.is_ascii_hexdigit())
+            && (colo.is_some() || hex.len() == RAY_HEX_LEN);
+        let valid_colo = colo
+            .is_none_or(|colo| !colo.is_empty() && colo.chars().all(|c| c.is_ascii_alphanumeric()));
+        if !valid_hex || !valid_colo {
+            return None;
+        }
+
+        Some(Self {
+            hex: hex.to_owned(),
+            colo: colo.map(str::to_owned),
+        })
+    }
+}
+
+/// Trace context parsed from Google Cloud Trace `x-cloud-trace-context` or W3C `traceparent`
+/// headers.
+///
+/// Cloud Trace is preferred when a request carries both. Ids are kept as sent: Cloud Trace span
+/// ids are decimal, W3C ones hexadecimal, as recorded in [`span_id_format`](Self::span_id_format).
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct TraceContext {
     pub trace_id: Option<String>,
     pub span_id: Option<String>,
+    /// How `span_id` is written, which decides how [`span_id_hex64`](Self::span_id_hex64) reads
+    /// it.
+    #[serde(default)]
+    pub span_id_format: SpanIdFormat,
     pub sampled: Option<bool>,
     pub project_id: Option<String>,
     pub raw: Option<String>,
 }
 
+/// How a [`TraceContext`] span id is written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpanIdFormat {
+    /// Decimal, as in `x-cloud-trace-context`.
+    #[default]
+    Decimal,
+    /// 16 hex characters, as in W3C `traceparent`.
+    Hex,
+}
+
 impl TraceContext {
+    /// Reads `x-cloud-trace-context`, falling back to a valid `traceparent`.
+    fn from_headers(headers: &HeaderMap, project_id: Option<&str>) -> Option<Self> {
+        if let Some(value) = headers
+            .get(&HEADER_X_CLOUD_TRACE_CONTEXT)
+            .and_then(|header| header.to_str().ok())
+        {
+            return Some(Self::from_cloud_trace_header(value, project_id));
+        }
+        headers
+            .get(&HEADER_TRACEPARENT)
+            .and_then(|header| header.to_str().ok())
+            .and_then(|value| Self::from_traceparent(value, project_id))
+    }
+
+    /// Parses a W3C `traceparent` (`version-traceid-parentid-flags`), returning `None` when it is
+    /// malformed or carries all-zero ids.
+    ///
+    /// Versions newer than `00` are accepted as long as they start with the four known fields.
+    fn from_traceparent(header: &str, project_id: Option<&str>) -> Option<Self> {
+        fn is_hex(value: &str, len: usize) -> bool {
+            value.len() == len
+                && value
+                    .bytes()
+                    .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+        }
+
+        let value = header.trim();
+        let mut fields = value.split('-');
+        let version = fields.next()?;
+        let trace_id = fields.next()?;
+        let span_id = fields.next()?;
+        let flags = fields.next()?;
+        if !is_hex(version, 2) || version == "ff" || (version == "00" && fields.next().is_some()) {
+            return None;
+        }
+        if !is_hex(trace_id, 32) || !is_hex(span_id, 16) || !is_hex(flags, 2) {
+            return None;
+        }
+        if trace_id.bytes().all(|b| b == b'0') || span_id.bytes().all(|b| b == b'0') {
+            return None;
+        }
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+
+        Some(Self {
+            trace_id: Some(trace_id.to_owned()),
+            span_id: Some(span_id.to_owned()),
+            span_id_format: SpanIdFormat::Hex,
+            sampled: Some(flags & 0x01 != 0),
+            project_id: project_id.map(|value| value.to_owned()),
+            raw: Some(header.to_owned()),
+        })
+    }
+
     fn from_cloud_trace_header(header: &str, project_id: Option<&str>) -> Self {
         let mut trace_id = None;
         let mut span_id = None;
@@ -441,38 +1401,237 @@ impl TraceContext {
         Self {
             trace_id,
             span_id,
+            span_id_format: SpanIdFormat::Decimal,
             sampled,
             project_id: project_id.map(|value| value.to_owned()),
             raw: Some(header.to_owned()),
         }
     }
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
-pub struct ClientHints {
-    pub ua: Option<String>,
-    pub ua_mobile: Option<String>,
-    pub ua_platform: Option<String>,
-    pub ua_arch: Option<String>,
-    pub ua_platform_version: Option<String>,
-    pub ua_model: Option<String>,
-    pub ua_bitness: Option<String>,
-    pub ua_wow64: Option<String>,
-    pub ua_full_version_list: Option<String>,
-}
 
-impl ClientHints {
-    fn from_headers(headers: &axum::http::HeaderMap) -> Option<Self> {
-        let ua = header_value_to_string(headers.get("sec-ch-ua"));
-        let ua_mobile = header_value_to_string(headers.get("sec-ch-ua-mobile"));
-        let ua_platform = header_value_to_string(headers.get("sec-ch-ua-platform"));
-        let ua_arch = header_value_to_string(headers.get("sec-ch-ua-arch"));
-        let ua_platform_version = header_value_to_string(headers.get("sec-ch-ua-platform-version"));
-        let ua_model = header_value_to_string(headers.get("sec-ch-ua-model"));
-        let ua_bitness = header_value_to_string(headers.get("sec-ch-ua-bitness"));
-        let ua_wow64 = header_value_to_string(headers.get("sec-ch-ua-wow64"));
-        let ua_full_version_list =
-            header_value_to_string(headers.get("sec-ch-ua-full-version-list"));
+    /// Returns the trace id as the 32 lowercase hex characters (128 bits) OpenTelemetry expects,
+    /// left-padding shorter ids with zeros.
+    ///
+    /// `None` when the id is missing, not hex, longer than 32 characters, or all zeros.
+    pub fn trace_id_hex128(&self) -> Option<String> {
+        let id = self.trace_id.as_deref()?.trim();
+        if id.is_empty() || id.len() > 32 || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        if id.chars().all(|c| c == '0') {
+            return None;
+        }
+        Some(format!("{:0>32}", id.to_ascii_lowercase()))
+    }
+
+    /// Returns the span id as 16 lowercase hex characters (64 bits).
+    ///
+    /// The id is read as [`span_id_format`](Self::span_id_format) says: decimal ids (Cloud
+    /// Trace) are converted, falling back to a 16-character hex id for hosts that send those;
+    /// hex ids (W3C `traceparent`) are never read as decimal, even when they are all digits.
+    /// `None` when the id is missing, malformed, or zero.
+    pub fn span_id_hex64(&self) -> Option<String> {
+        let raw = self.span_id.as_deref()?.trim();
+        let hex = || {
+            (raw.len() == 16)
+                .then(|| u64::from_str_radix(raw, 16).ok())
+                .flatten()
+        };
+        let id = match self.span_id_format {
+            SpanIdFormat::Decimal => raw.parse::<u64>().ok().or_else(hex),
+            SpanIdFormat::Hex => hex(),
+        }?;
+        (id != 0).then(|| format!("{id:016x}"))
+    }
+
+    /// Returns the upstream sampling decision: Cloud Trace's `o=1`/`o=0` or the sampled bit of
+    /// the W3C trace flags.
+    ///
+    /// `None` when the header did not say, e.g. a Cloud Trace header without an `o=` option.
+    pub fn is_sampled(&self) -> Option<bool> {
+        self.sampled
+    }
+
+    /// Formats the context as a version `00` W3C `traceparent` for outbound requests.
+    ///
+    /// Ids are normalized as by [`trace_id_hex128`](Self::trace_id_hex128) and
+    /// [`span_id_hex64`](Self::span_id_hex64), so Cloud Trace contexts convert too. An unknown
+    /// sampling decision is sent as not sampled. `None` when either id is unusable.
+    pub fn to_traceparent(&self) -> Option<String> {
+        let trace_id = self.trace_id_hex128()?;
+        let span_id = self.span_id_hex64()?;
+        let flags = if self.is_sampled() == Some(true) {
+            "01"
+        } else {
+            "00"
+        };
+        Some(format!("00-{trace_id}-{span_id}-{flags}"))
+    }
+
+    /// Formats the context as an `x-cloud-trace-context` value (`TRACE_ID/SPAN_ID;o=FLAG`), with
+    /// the span id in decimal and `;o=` omitted when the sampling decision is unknown.
+    ///
+    /// `None` when either id is unusable.
+    pub fn to_cloud_trace_header(&self) -> Option<String> {
+        let trace_id = self.trace_id_hex128()?;
+        let span_id = u64::from_str_radix(&self.span_id_hex64()?, 16).ok()?;
+        Some(match self.is_sampled() {
+            Some(sampled) => format!("{trace_id}/{span_id};o={}", u8::from(sampled)),
+            None => format!("{trace_id}/{span_id}"),
+        })
+    }
+
+    /// Writes `traceparent` and `x-cloud-trace-context` into `headers` so an outbound request
+    /// continues this trace, replacing any values already there.
+    ///
+    /// Works with any HTTP client that exposes an [`http::HeaderMap`](HeaderMap), e.g.
+    /// `reqwest::RequestBuilder::headers`. Nothing is written when the ids are unusable.
+    pub fn inject_into_headers(&self, headers: &mut HeaderMap) {
+        let values = [
+            (HEADER_TRACEPARENT, self.to_traceparent()),
+            (HEADER_X_CLOUD_TRACE_CONTEXT, self.to_cloud_trace_header()),
+        ];
+        for (name, value) in values {
+            // Both formats are plain ASCII, so the conversion cannot fail.
+            if let Some(value) = value.and_then(|value| HeaderValue::from_str(&value).ok()) {
+                headers.insert(name, value);
+            }
+        }
+    }
+}
+
+/// Extracts just the trace identifiers, for handlers and middleware that only correlate logs.
+///
+/// Parses the request headers the same way [`ContainerContext`] does, using the
+/// [`RuntimePlatform`] extension when present (for the Cloud Run project id). Never rejects: a
+/// request without trace headers yields `TraceContext::default()`.
+#[async_trait]
+impl<S> FromRequestParts<S> for TraceContext
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(TraceContext::from_parts(parts))
+    }
+}
+
+impl TraceContext {
+    /// Parses the trace headers of a request (the [`FromRequestParts`] impl, without the async).
+    pub(crate) fn from_parts(parts: &Parts) -> Self {
+        let platform = parts
+            .extensions
+            .get::<RuntimePlatform>()
+            .cloned()
+            .unwrap_or(RuntimePlatform::Generic);
+        RequestMetadata::from_parts(parts, &platform)
+            .trace_context
+            .unwrap_or_default()
+    }
+}
+
+/// TLS version and cipher reported by Cloudflare in `cf-tls-version` / `cf-tls-cipher`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct TlsInfo {
+    /// Protocol version, e.g. `TLSv1.3`.
+    pub version: Option<String>,
+    /// Cipher suite, e.g. `AEAD-AES128-GCM-SHA256`.
+    pub cipher: Option<String>,
+}
+
+impl TlsInfo {
+    fn from_headers(headers: &axum::http::HeaderMap) -> Option<Self> {
+        let version = header_to_string(headers, &HEADER_CF_TLS_VERSION);
+        let cipher = header_to_string(headers, &HEADER_CF_TLS_CIPHER);
+        if version.is_none() && cipher.is_none() {
+            None
+        } else {
+            Some(Self { version, cipher })
+        }
+    }
+}
+
+/// Detects HTTP/1.1 upgrades and HTTP/2 extended CONNECT (RFC 8441) requests.
+fn upgrade_protocol(parts: &Parts) -> Option<String> {
+    if let Some(protocol) = parts.extensions.get::<hyper::ext::Protocol>() {
+        return Some(protocol.as_str().to_owned());
+    }
+
+    let connection_upgrade = parts
+        .headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("upgrade"));
+    if !connection_upgrade {
+        return None;
+    }
+    header_to_string(&parts.headers, &UPGRADE).filter(|protocol| !protocol.is_empty())
+}
+
+/// Reads the scheme from Cloudflare's `cf-visitor` header (`{"scheme":"https"}`).
+fn scheme_from_cf_visitor(headers: &axum::http::HeaderMap) -> Option<String> {
+    #[derive(Deserialize)]
+    struct Visitor {
+        scheme: Option<String>,
+    }
+
+    let raw = headers.get(&HEADER_CF_VISITOR)?.to_str().ok()?;
+    serde_json::from_str::<Visitor>(raw)
+        .ok()?
+        .scheme
+        .filter(|scheme| !scheme.is_empty())
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct ClientHints {
+    pub ua: Option<String>,
+    pub ua_mobile: Option<String>,
+    pub ua_platform: Option<String>,
+    pub ua_arch: Option<String>,
+    pub ua_platform_version: Option<String>,
+    pub ua_model: Option<String>,
+    pub ua_bitness: Option<String>,
+    pub ua_wow64: Option<String>,
+    pub ua_full_version_list: Option<String>,
+}
+
+impl ClientHints {
+    /// Interprets `sec-ch-ua-mobile` (`?1` or `?0`); `None` when absent or malformed.
+    pub fn is_mobile(&self) -> Option<bool> {
+        parse_sf_boolean(self.ua_mobile.as_deref()?)
+    }
+
+    /// Interprets `sec-ch-ua-wow64` (`?1` or `?0`); `None` when absent or malformed.
+    pub fn is_wow64(&self) -> Option<bool> {
+        parse_sf_boolean(self.ua_wow64.as_deref()?)
+    }
+
+    /// Returns the `(brand, version)` pairs from `sec-ch-ua-full-version-list`, or from
+    /// `sec-ch-ua` (major versions only) when the full list was not sent.
+    ///
+    /// Entries are kept in header order, including Chrome's deliberately bogus "GREASE" brands
+    /// such as `Not_A Brand`. Malformed entries are skipped.
+    pub fn brands(&self) -> Vec<(String, String)> {
+        self.ua_full_version_list
+            .as_deref()
+            .or(self.ua.as_deref())
+            .map(parse_brand_list)
+            .unwrap_or_default()
+    }
+
+    fn from_headers(headers: &axum::http::HeaderMap) -> Option<Self> {
+        let ua = header_value_to_string(headers.get("sec-ch-ua"));
+        let ua_mobile = header_value_to_string(headers.get("sec-ch-ua-mobile"));
+        let ua_platform = header_value_to_string(headers.get("sec-ch-ua-platform"));
+        let ua_arch = header_value_to_string(headers.get("sec-ch-ua-arch"));
+        let ua_platform_version = header_value_to_string(headers.get("sec-ch-ua-platform-version"));
+        let ua_model = header_value_to_string(headers.get("sec-ch-ua-model"));
+        let ua_bitness = header_value_to_string(headers.get("sec-ch-ua-bitness"));
+        let ua_wow64 = header_value_to_string(headers.get("sec-ch-ua-wow64"));
+        let ua_full_version_list =
+            header_value_to_string(headers.get("sec-ch-ua-full-version-list"));
 
         if ua.is_none()
             && ua_mobile.is_none()
@@ -501,6 +1660,142 @@ impl ClientHints {
     }
 }
 
+/// Splits `W/"tag"` into its weakness flag and opaque tag, tolerating a missing pair of quotes.
+fn split_etag(etag: &str) -> (bool, &str) {
+    let etag = etag.trim();
+    let (weak, tag) = match etag.strip_prefix("W/") {
+        Some(tag) => (true, tag),
+        None => (false, etag),
+    };
+    let tag = tag
+        .strip_prefix('"')
+        .and_then(|tag| tag.strip_suffix('"'))
+        .unwrap_or(tag);
+    (weak, tag)
+}
+
+/// Iterates the entity tags of an `If-None-Match`/`If-Match` list, skipping malformed entries.
+///
+/// Tags may contain commas, so the list is split on quotes rather than on commas.
+fn parse_etag_list(header: &str) -> impl Iterator<Item = (bool, &str)> {
+    let mut rest = header;
+    std::iter::from_fn(move || {
+        loop {
+            rest = rest.trim_start_matches([' ', '\t', ',']);
+            if rest.is_empty() {
+                return None;
+            }
+            let (weak, after) = match rest.strip_prefix("W/") {
+                Some(after) => (true, after),
+                None => (false, rest),
+            };
+            let Some(quoted) = after.strip_prefix('"') else {
+                // Not a tag; skip to the next list member.
+                rest = rest.split_once(',').map_or("", |(_, next)| next);
+                continue;
+            };
+            let (tag, next) = quoted.split_once('"')?;
+            rest = next;
+            return Some((weak, tag));
+        }
+    })
+}
+
+/// Parses a structured-field boolean (`?1` / `?0`).
+fn parse_sf_boolean(value: &str) -> Option<bool> {
+    match value.trim() {
+        "?1" => Some(true),
+        "?0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parses a structured-field list of brands such as `"Chromium";v="120", "Not_A Brand";v="8"`.
+fn parse_brand_list(value: &str) -> Vec<(String, String)> {
+    split_sf_list(value)
+        .into_iter()
+        .filter_map(|member| {
+            let (brand, params) = parse_sf_string(member.trim())?;
+            let version = params.split(';').skip(1).find_map(|param| {
+                let (key, value) = param.trim().split_once('=')?;
+                (key == "v").then_some(value)
+            })?;
+            let version = match parse_sf_string(version) {
+                Some((version, _)) => version,
+                None => version.to_owned(),
+            };
+            Some((brand, version))
+        })
+        .collect()
+}
+
+/// Reads a quoted structured-field string, returning it and the unparsed remainder.
+fn parse_sf_string(value: &str) -> Option<(String, &str)> {
+    let mut chars = value.strip_prefix('"')?.char_indices();
+    let mut parsed = String::new();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => parsed.push(chars.next()?.1),
+            '"' => return Some((parsed, &value[index + 2..])),
+            c => parsed.push(c),
+        }
+    }
+    None
+}
+
+/// Splits a structured-field list on the commas that are not inside quoted strings.
+fn split_sf_list(value: &str) -> Vec<&str> {
+    let mut members = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                members.push(&value[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    members.push(&value[start..]);
+    members
+}
+
+/// Resolves `path` against the root, or `None` when it is suspicious.
+fn normalize_path(path: &str) -> Option<String> {
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+    let decoded = percent_encoding::percent_decode_str(path)
+        .decode_utf8()
+        .ok()?;
+    if decoded.chars().any(|c| c.is_control() || c == '\\') {
+        return None;
+    }
+
+    let mut segments = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            segment => segments.push(segment),
+        }
+    }
+    Some(format!("/{}", segments.join("/")))
+}
+
+/// The host a request was addressed to: `x-forwarded-host`, falling back to `Host`.
+pub(crate) fn request_host(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(&HEADER_X_FORWARDED_HOST)
+        .or_else(|| headers.get(HOST))
+        .and_then(|value| value.to_str().ok())
+}
+
 fn header_to_string(headers: &axum::http::HeaderMap, name: &HeaderName) -> Option<String> {
     headers
         .get(name)
@@ -512,43 +1807,98 @@ fn header_value_to_string(value: Option<&axum::http::HeaderValue>) -> Option<Str
     value.and_then(|v| v.to_str().ok().map(|s| s.to_owned()))
 }
 
+/// Collects the `x-forwarded-for` chain in order, whether proxies sent it comma-joined in one
+/// header or as several separate header lines.
+fn forwarded_for_chain(headers: &axum::http::HeaderMap) -> Vec<String> {
+    headers
+        .get_all(&HEADER_X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
 fn pick_client_ip_from_xff(headers: &axum::http::HeaderMap) -> Option<String> {
-    let xff = header_to_string(headers, &HEADER_X_FORWARDED_FOR)?;
-    let mut first = None;
-    for part in xff.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
-        if first.is_none() {
-            first = Some(part.to_owned());
-        }
-        if let Ok(ip) = part.parse::<IpAddr>()
-            && is_public_ip(&ip)
-        {
-            return Some(part.to_owned());
-        }
-    }
-    first
+    let chain = forwarded_for_chain(headers);
+    chain
+        .iter()
+        .find(|part| parse_forwarded_ip(part).is_some_and(|ip| is_public_ip(&ip)))
+        .or_else(|| chain.first())
+        .cloned()
 }
 
-fn is_public_ip(ip: &IpAddr) -> bool {
-    match ip {
+/// Parses an address from a forwarding header, ignoring an IPv6 zone (`fe80::1%eth0`).
+fn parse_forwarded_ip(value: &str) -> Option<IpAddr> {
+    let address = value.split_once('%').map_or(value, |(address, _)| address);
+    address.parse().ok()
+}
+
+/// Zeroes the host part of an address, keeping its /24 (IPv4) or /48 (IPv6) network.
+fn truncate_ip(value: &str) -> Option<String> {
+    let truncated = match parse_forwarded_ip(value.trim())? {
         IpAddr::V4(v4) => {
-            !(v4.is_private()
-                || v4.is_loopback()
-                || v4.is_link_local()
-                || v4.is_broadcast()
-                || v4.is_documentation()
-                || v4.is_unspecified()
-                || v4.is_multicast())
+            let [a, b, c, _] = v4.octets();
+            IpAddr::V4(Ipv4Addr::new(a, b, c, 0))
         }
         IpAddr::V6(v6) => {
-            !(v6.is_loopback()
-                || v6.is_multicast()
-                || v6.is_unspecified()
-                || v6.is_unique_local()
-                || v6.is_unicast_link_local())
+            let [a, b, c, ..] = v6.segments();
+            IpAddr::V6(Ipv6Addr::new(a, b, c, 0, 0, 0, 0, 0))
         }
+    };
+    Some(truncated.to_string())
+}
+
+fn sha256_hex(value: &str) -> String {
+    use sha2::{Digest, Sha256};
+    use std::fmt::Write;
+
+    Sha256::digest(value.as_bytes())
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+fn is_public_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => is_public_ipv4(v4),
+        // `::ffff:a.b.c.d` is an IPv4 client seen through a dual-stack socket.
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_public_ipv4(&v4),
+            None => {
+                let [first, second, third, ..] = v6.segments();
+                let documentation = first == 0x2001 && second == 0x0db8;
+                let benchmarking = first == 0x2001 && second == 0x0002 && third == 0;
+                !(v6.is_loopback()
+                    || v6.is_multicast()
+                    || v6.is_unspecified()
+                    || v6.is_unique_local()
+                    || v6.is_unicast_link_local()
+                    || documentation
+                    || benchmarking)
+            }
+        },
     }
 }
 
+fn is_public_ipv4(v4: &std::net::Ipv4Addr) -> bool {
+    let [first, second, ..] = v4.octets();
+    // 198.18.0.0/15 is reserved for benchmarking.
+    let benchmarking = first == 198 && second & 0xfe == 18;
+    !(v4.is_private()
+        || v4.is_loopback()
+        || v4.is_link_local()
+        || v4.is_broadcast()
+        || v4.is_documentation()
+        || v4.is_unspecified()
+        || v4.is_multicast()
+        || benchmarking)
+}
+
 fn extract_region_from_host(host: &str) -> Option<String> {
     // Cloud Run hosts look like:
     // - <service>-<hash>-<region>.a.run.app  (legacy)
@@ -597,6 +1947,32 @@ fn extract_project_from_host(host: &str) -> Option<String> {
 }
 
 /// Errors emitted when a handler requests [`ContainerContext`] but extensions were not set up.
+///
+/// With the `dev` feature enabled the extractor never rejects: outside `serve` it falls back to
+/// [`RuntimePlatform::Generic`] and an unavailable command client, which keeps hand-built routers
+/// usable during local development.
+///
+/// Used as an extractor on its own, a rejection becomes a plain-text 500. To respond differently,
+/// extract `Option<ContainerContext>` (`None` when the context is missing) or
+/// `Result<ContainerContext, ContainerContextRejection>` and build the response from
+/// [`status_code`](Self::status_code) and [`code`](Self::code):
+///
+/// ```
+/// use axum::Json;
+/// use axum::response::{IntoResponse, Response};
+/// use containerflare::ContainerContext;
+/// use containerflare::context::ContainerContextRejection;
+///
+/// async fn handler(context: Result<ContainerContext, ContainerContextRejection>) -> Response {
+///     match context {
+///         Ok(context) => context.metadata().path.clone().into_response(),
+///         Err(rejection) => {
+///             let body = serde_json::json!({ "error": rejection.code() });
+///             (rejection.status_code(), Json(body)).into_response()
+///         }
+///     }
+/// }
+/// ```
 #[derive(Debug, Error)]
 pub enum ContainerContextRejection {
     #[error("command client missing from request extensions")]
@@ -605,11 +1981,25 @@ pub enum ContainerContextRejection {
     MissingRuntimePlatform,
 }
 
+impl ContainerContextRejection {
+    /// Returns the status this rejection responds with: always `500`, since a missing context
+    /// means the router is not wired up by `serve`.
+    pub fn status_code(&self) -> StatusCode {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+
+    /// Returns a short, stable label for the variant, suitable for error bodies and metrics.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ContainerContextRejection::MissingCommandClient => "missing_command_client",
+            ContainerContextRejection::MissingRuntimePlatform => "missing_runtime_platform",
+        }
+    }
+}
+
 impl IntoResponse for ContainerContextRejection {
     fn into_response(self) -> Response {
-        let status = StatusCode::INTERNAL_SERVER_ERROR;
-        let message = self.to_string();
-        (status, message).into_response()
+        (self.status_code(), self.to_string()).into_response()
     }
 }
 
@@ -621,29 +2011,102 @@ where
     type Rejection = ContainerContextRejection;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        let command_client = parts
+        let command_client = match parts.extensions.get::<CommandClient>() {
+            Some(client) => client.clone(),
+            None if cfg!(feature = "dev") => CommandClient::unavailable(DEV_COMMAND_REASON),
+            None => return Err(ContainerContextRejection::MissingCommandClient),
+        };
+
+        let platform = match parts.extensions.get::<RuntimePlatform>() {
+            Some(platform) => platform.clone(),
+            None if cfg!(feature = "dev") => RuntimePlatform::Generic,
+            None => return Err(ContainerContextRejection::MissingRuntimePlatform),
+        };
+
+        let shutdown = parts
             .extensions
-            .get::<CommandClient>()
+            .get::<ShutdownState>()
             .cloned()
-            .ok_or(ContainerContextRejection::MissingCommandClient)?;
+            .unwrap_or_default();
 
-        let platform = parts
+        let options = parts
             .extensions
-            .get::<RuntimePlatform>()
+            .get::<ContextOptions>()
             .cloned()
-            .ok_or(ContainerContextRejection::MissingRuntimePlatform)?;
+            .unwrap_or_default();
+
+        let deadline = options
+            .deadline_header
+            .as_ref()
+            .and_then(|name| parts.headers.get(name))
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_deadline_budget)
+            .and_then(|budget| Instant::now().checked_add(budget));
 
         let mut metadata = RequestMetadata::from_parts(parts, &platform);
         metadata.rebuild_raw_url_if_needed();
+        if metadata.request_id.is_none()
+            && let Some(RequestId(id)) = parts.extensions.get::<RequestId>()
+        {
+            metadata.request_id = Some(id.clone());
+        }
 
         Ok(Self {
             metadata,
             command_client,
             platform,
+            shutdown,
+            options,
+            deadline,
+            byte_counts: parts
+                .extensions
+                .get::<ByteCounts>()
+                .cloned()
+                .unwrap_or_default(),
+            named_clients: parts
+                .extensions
+                .get::<NamedCommandClients>()
+                .cloned()
+                .unwrap_or_default(),
+            raw_headers: parts.extensions.get::<CapturedHeaders>().cloned(),
+            store: parts
+                .extensions
+                .get::<RequestStore>()
+                .cloned()
+                .unwrap_or_default(),
+            peer_addr: parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| *addr),
+            early_hints: parts.extensions.get::<EarlyHints>().cloned(),
         })
     }
 }
 
+/// Parses a relative time budget: gRPC's `grpc-timeout` syntax (`<digits><H|M|S|m|u|n>`, e.g.
+/// `250m` for 250 milliseconds) or a humantime duration such as `1.5s`.
+fn parse_deadline_budget(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let grpc = value
+        .split_at_checked(value.len().checked_sub(1)?)
+        .and_then(|(amount, unit)| {
+            if amount.is_empty() || amount.len() > 8 {
+                return None;
+            }
+            let amount = amount.parse::<u64>().ok()?;
+            match unit {
+                "H" => Some(Duration::from_secs(amount * 3600)),
+                "M" => Some(Duration::from_secs(amount * 60)),
+                "S" => Some(Duration::from_secs(amount)),
+                "m" => Some(Duration::from_millis(amount)),
+                "u" => Some(Duration::from_micros(amount)),
+                "n" => Some(Duration::from_nanos(amount)),
+                _ => None,
+            }
+        });
+    grpc.or_else(|| humantime::parse_duration(value).ok())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -678,84 +2141,899 @@ mod tests {
     }
 
     #[test]
-    fn metadata_header_overrides_values() {
-        let mut metadata = RequestMetadata::default();
-        metadata.request_id = Some("abc".into());
-        metadata.colo = Some("sfo".into());
-        metadata.region = Some("us-west".into());
-        metadata.country = Some("US".into());
-        metadata.client_ip = Some("203.0.113.9".into());
-        metadata.host = Some("example.com".into());
-        metadata.scheme = Some("https".into());
-        metadata.worker_name = Some("test-worker".into());
-        metadata.method = "POST".into();
-        metadata.path = "/foo?bar=baz".into();
-        metadata.raw_url = Some("https://example.com/foo?bar=baz".into());
-
-        let metadata_header = serde_json::to_string(&metadata).unwrap();
+    fn parses_invoking_worker_headers() {
         let request = Request::builder()
-            .method("POST")
-            .uri("https://placeholder.invalid/")
-            .header(METADATA_HEADER, metadata_header)
+            .uri("/")
+            .header("cf-worker", "upstream.example.com")
+            .header("cf-ew-via", "15")
             .body(())
             .unwrap();
+        let (parts, _) = request.into_parts();
+        let platform = RuntimePlatform::Cloudflare(CloudflarePlatform {
+            worker_name: Some("ours".into()),
+        });
+        let metadata = RequestMetadata::from_parts(&parts, &platform);
+        assert_eq!(
+            metadata.invoking_worker.as_deref(),
+            Some("upstream.example.com")
+        );
+        assert_eq!(metadata.edge_via.as_deref(), Some("15"));
+        assert_eq!(metadata.worker_name.as_deref(), Some("ours"));
+
+        // Shim headers from before these fields existed still deserialize.
+        let metadata: RequestMetadata = serde_json::from_str(r#"{"path":"/"}"#).unwrap();
+        assert!(metadata.invoking_worker.is_none() && metadata.edge_via.is_none());
+    }
 
+    #[test]
+    fn parses_tls_info_and_cf_visitor_scheme() {
+        let request = Request::builder()
+            .uri("/")
+            .header("cf-visitor", r#"{"scheme":"https"}"#)
+            .header("cf-tls-version", "TLSv1.3")
+            .header("cf-tls-cipher", "AEAD-AES128-GCM-SHA256")
+            .body(())
+            .unwrap();
         let (parts, _) = request.into_parts();
-        let parsed = RequestMetadata::from_parts(&parts, &RuntimePlatform::default());
+        let metadata = RequestMetadata::from_parts(&parts, &RuntimePlatform::default());
+        assert_eq!(metadata.scheme.as_deref(), Some("https"));
+        assert_eq!(
+            metadata.tls,
+            Some(TlsInfo {
+                version: Some("TLSv1.3".into()),
+                cipher: Some("AEAD-AES128-GCM-SHA256".into()),
+            })
+        );
 
-        assert_eq!(parsed.request_id, metadata.request_id);
-        assert_eq!(parsed.colo, metadata.colo);
-        assert_eq!(parsed.worker_name, metadata.worker_name);
-        assert_eq!(parsed.path, metadata.path);
-        assert_eq!(parsed.raw_url, metadata.raw_url);
-        assert!(matches!(
-            parsed.platform,
-            Some(RequestMetadataPlatform::Cloudflare { .. })
-        ));
+        let request = Request::builder()
+            .uri("/")
+            .header("cf-visitor", r#"{"scheme":"https"}"#)
+            .header("x-forwarded-proto", "http")
+            .body(())
+            .unwrap();
+        let (parts, _) = request.into_parts();
+        let metadata = RequestMetadata::from_parts(&parts, &RuntimePlatform::default());
+        assert_eq!(metadata.scheme.as_deref(), Some("http"));
+        assert!(metadata.tls.is_none());
     }
 
     #[test]
-    fn cloud_run_metadata_from_headers() {
-        let platform = RuntimePlatform::CloudRun(CloudRunPlatform {
-            service: Some("svc".into()),
-            revision: Some("rev".into()),
-            configuration: Some("cfg".into()),
-            project_id: Some("proj-123".into()),
-            region: Some("us-central1".into()),
-        });
+    fn detects_websocket_upgrades() {
+        let request = Request::builder()
+            .uri("/socket")
+            .header("connection", "keep-alive, Upgrade")
+            .header("upgrade", "websocket")
+            .body(())
+            .unwrap();
+        let (parts, _) = request.into_parts();
+        let metadata = RequestMetadata::from_parts(&parts, &RuntimePlatform::default());
+        assert!(metadata.is_upgrade());
+        assert_eq!(metadata.upgrade_protocol().as_deref(), Some("websocket"));
 
         let request = Request::builder()
-            .method("GET")
-            .uri("http://127.0.0.1/hello")
-            .header("x-forwarded-for", "198.51.100.1")
-            .header("x-forwarded-host", "example.run.app")
-            .header("x-forwarded-proto", "https")
-            .header(
-                "x-cloud-trace-context",
-                "105445aa7843bc8bf206b120001000/123;o=1",
-            )
-            .header("user-agent", "test-agent")
-            .header("accept-language", "en-US")
-            .header("sec-ch-ua", "\"Chromium\";v=\"1\"")
+            .uri("/socket")
+            .header("upgrade", "websocket")
             .body(())
             .unwrap();
+        let (parts, _) = request.into_parts();
+        let metadata = RequestMetadata::from_parts(&parts, &RuntimePlatform::default());
+        assert!(!metadata.is_upgrade());
+    }
 
+    #[test]
+    fn handles_authority_form_connect_targets() {
+        let request = Request::builder()
+            .method("CONNECT")
+            .uri("example.com:443")
+            .header("host", "example.com:443")
+            .header("x-forwarded-proto", "https")
+            .body(())
+            .unwrap();
         let (parts, _) = request.into_parts();
-        let metadata = RequestMetadata::from_parts(&parts, &platform);
+        let mut metadata = RequestMetadata::from_parts(&parts, &RuntimePlatform::default());
+        metadata.rebuild_raw_url_if_needed();
+        assert_eq!(metadata.method, "CONNECT");
+        assert_eq!(metadata.path, "example.com:443");
+        assert_eq!(metadata.raw_url.as_deref(), Some("example.com:443"));
+        assert!(!metadata.is_upgrade());
+    }
 
-        assert_eq!(metadata.cloud_run_service.as_deref(), Some("svc"));
-        assert_eq!(metadata.cloud_run_revision.as_deref(), Some("rev"));
-        assert_eq!(metadata.cloud_run_configuration.as_deref(), Some("cfg"));
-        assert_eq!(metadata.project_id.as_deref(), Some("proj-123"));
-        assert_eq!(metadata.cloud_run_region.as_deref(), Some("us-central1"));
-        assert_eq!(metadata.region.as_deref(), Some("us-central1"));
-        assert_eq!(metadata.scheme.as_deref(), Some("https"));
-        assert_eq!(metadata.host.as_deref(), Some("example.run.app"));
-        assert_eq!(metadata.client_ip.as_deref(), Some("198.51.100.1"));
-        assert_eq!(metadata.worker_name.as_deref(), Some("svc"));
-        assert_eq!(metadata.user_agent.as_deref(), Some("test-agent"));
-        assert_eq!(metadata.accept_language.as_deref(), Some("en-US"));
-        assert!(metadata.client_hints.is_some());
+    fn parts_with_metadata_header(value: &str, max_len: usize) -> Parts {
+        let request = Request::builder()
+            .uri("/fallback")
+            .header(HEADER_CF_RAY, "header-ray")
+            .header(METADATA_HEADER, value)
+            .extension(ContextOptions {
+                max_metadata_header_len: max_len,
+                ..ContextOptions::default()
+            })
+            .body(())
+            .unwrap();
+        request.into_parts().0
+    }
+
+    #[test]
+    fn oversized_metadata_header_falls_back_to_headers() {
+        let header = serde_json::json!({ "request_id": "shim-ray", "path": "/shim" }).to_string();
+
+        let parts = parts_with_metadata_header(&header, header.len());
+        let metadata = RequestMetadata::from_parts(&parts, &RuntimePlatform::default());
+        assert_eq!(metadata.request_id.as_deref(), Some("shim-ray"));
+
+        let parts = parts_with_metadata_header(&header, header.len() - 1);
+        let metadata = RequestMetadata::from_parts(&parts, &RuntimePlatform::default());
+        assert_eq!(metadata.request_id.as_deref(), Some("header-ray"));
+        assert_eq!(metadata.path, "/fallback");
+    }
+
+    #[test]
+    fn malformed_or_suspicious_metadata_header_falls_back_to_headers() {
+        let headers = [
+            "{not json".to_owned(),
+            serde_json::json!({ "path": "/shim", "request_id": 7 }).to_string(),
+            serde_json::json!({ "path": "/shim", "method": "GET /evil" }).to_string(),
+            serde_json::json!({ "path": "/shim", "client_ip": "not-an-ip" }).to_string(),
+            serde_json::json!({ "path": "/shim", "host": "example.com\r\nx-injected: 1" })
+                .to_string(),
+        ];
+        for header in headers {
+            let parts = parts_with_metadata_header(&header, DEFAULT_MAX_METADATA_HEADER_LEN);
+            let metadata = RequestMetadata::from_parts(&parts, &RuntimePlatform::default());
+            assert_eq!(
+                metadata.request_id.as_deref(),
+                Some("header-ray"),
+                "{header}"
+            );
+            assert_eq!(metadata.path, "/fallback", "{header}");
+        }
+    }
+
+    #[test]
+    fn unifies_sampling_across_trace_formats() {
+        let cases = [
+            ("4bf92f3577b34da6a3ce929d0e0e4736/1;o=1", "01", Some(true)),
+            ("4bf92f3577b34da6a3ce929d0e0e4736/1;o=0", "00", Some(false)),
+            ("4bf92f3577b34da6a3ce929d0e0e4736/1;o=0", "02", Some(false)),
+            ("4bf92f3577b34da6a3ce929d0e0e4736/1;o=1", "03", Some(true)),
+        ];
+        for (cloud, flags, sampled) in cases {
+            let cloud = TraceContext::from_cloud_trace_header(cloud, None);
+            let w3c = TraceContext::from_traceparent(
+                &format!("00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000001-{flags}"),
+                None,
+            )
+            .unwrap();
+            assert_eq!(cloud.is_sampled(), sampled, "{flags}");
+            assert_eq!(w3c.is_sampled(), sampled, "{flags}");
+            assert_eq!(cloud.to_traceparent(), w3c.to_traceparent(), "{flags}");
+        }
+
+        let trace =
+            TraceContext::from_cloud_trace_header("4bf92f3577b34da6a3ce929d0e0e4736/1", None);
+        assert_eq!(trace.is_sampled(), None);
+        assert_eq!(
+            trace.to_traceparent().as_deref(),
+            Some("00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000001-00")
+        );
+        assert_eq!(TraceContext::default().to_traceparent(), None);
+    }
+
+    #[test]
+    fn parses_traceparent_headers() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let trace = TraceContext::from_traceparent(header, Some("proj")).unwrap();
+        assert_eq!(
+            trace.trace_id.as_deref(),
+            Some("4bf92f3577b34da6a3ce929d0e0e4736")
+        );
+        assert_eq!(trace.span_id.as_deref(), Some("00f067aa0ba902b7"));
+        assert_eq!(trace.project_id.as_deref(), Some("proj"));
+        assert_eq!(trace.to_traceparent().as_deref(), Some(header));
+
+        // Future versions may append fields.
+        assert!(
+            TraceContext::from_traceparent(
+                "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+                None
+            )
+            .is_some()
+        );
+        for invalid in [
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6-00f067aa0ba902b7-01",
+        ] {
+            assert!(
+                TraceContext::from_traceparent(invalid, None).is_none(),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn trace_extractor_falls_back_to_traceparent() {
+        let (mut parts, _) = Request::builder()
+            .header(
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00",
+            )
+            .body(())
+            .unwrap()
+            .into_parts();
+        let trace = TraceContext::from_request_parts(&mut parts, &())
+            .await
+            .unwrap();
+        assert_eq!(trace.span_id.as_deref(), Some("00f067aa0ba902b7"));
+        assert_eq!(trace.is_sampled(), Some(false));
+
+        // Cloud Trace wins when both are present.
+        parts.headers.insert(
+            "x-cloud-trace-context",
+            HeaderValue::from_static("105445aa7843bc8bf206b12000100000/1;o=1"),
+        );
+        let trace = TraceContext::from_request_parts(&mut parts, &())
+            .await
+            .unwrap();
+        assert_eq!(trace.span_id.as_deref(), Some("1"));
+        assert_eq!(trace.is_sampled(), Some(true));
+    }
+
+    #[test]
+    fn injects_outbound_trace_headers() {
+        let trace = TraceContext::from_cloud_trace_header(
+            "4bf92f3577b34da6a3ce929d0e0e4736/17293822569102704642;o=1",
+            None,
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert("traceparent", HeaderValue::from_static("stale"));
+        trace.inject_into_headers(&mut headers);
+        assert_eq!(
+            headers["traceparent"],
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-f000000000000002-01"
+        );
+        assert_eq!(
+            headers["x-cloud-trace-context"],
+            "4bf92f3577b34da6a3ce929d0e0e4736/17293822569102704642;o=1"
+        );
+
+        let trace = TraceContext::from_traceparent(
+            "00-0af7651916cd43dd8448eb211c80319c-00f067aa0ba902b7-00",
+            None,
+        )
+        .unwrap();
+        let mut headers = HeaderMap::new();
+        trace.inject_into_headers(&mut headers);
+        assert_eq!(
+            headers["traceparent"],
+            "00-0af7651916cd43dd8448eb211c80319c-00f067aa0ba902b7-00"
+        );
+        assert_eq!(
+            headers["x-cloud-trace-context"],
+            "0af7651916cd43dd8448eb211c80319c/67667974448284343;o=0"
+        );
+
+        let trace = TraceContext::from_cloud_trace_header("abc/1", None);
+        assert_eq!(
+            trace.to_cloud_trace_header().as_deref(),
+            Some("00000000000000000000000000000abc/1")
+        );
+
+        let mut headers = HeaderMap::new();
+        TraceContext::default().inject_into_headers(&mut headers);
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn reads_all_digit_w3c_span_ids_as_hex() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000010-01";
+        let mut headers = HeaderMap::new();
+        headers.insert(HEADER_TRACEPARENT, HeaderValue::from_static(header));
+        let trace = TraceContext::from_headers(&headers, None).unwrap();
+        assert_eq!(trace.span_id_format, SpanIdFormat::Hex);
+        assert_eq!(trace.span_id_hex64().as_deref(), Some("0000000000000010"));
+        assert_eq!(trace.to_traceparent().as_deref(), Some(header));
+        assert_eq!(
+            trace.to_cloud_trace_header().as_deref(),
+            Some("4bf92f3577b34da6a3ce929d0e0e4736/16;o=1")
+        );
+
+        let mut outbound = HeaderMap::new();
+        trace.inject_into_headers(&mut outbound);
+        assert_eq!(outbound[&HEADER_TRACEPARENT], header);
+
+        let cloud = TraceContext::from_cloud_trace_header(
+            "4bf92f3577b34da6a3ce929d0e0e4736/0000000000000010",
+            None,
+        );
+        assert_eq!(cloud.span_id_format, SpanIdFormat::Decimal);
+        assert_eq!(cloud.span_id_hex64().as_deref(), Some("000000000000000a"));
+    }
+
+    #[test]
+    fn normalizes_trace_and_span_ids() {
+        let trace =
+            TraceContext::from_cloud_trace_header("105445aa7843bc8bf206b120001000/123;o=1", None);
+        assert_eq!(
+            trace.trace_id_hex128().as_deref(),
+            Some("00105445aa7843bc8bf206b120001000")
+        );
+        assert_eq!(trace.span_id_hex64().as_deref(), Some("000000000000007b"));
+        assert_eq!(
+            trace.trace_id.as_deref(),
+            Some("105445aa7843bc8bf206b120001000")
+        );
+        assert_eq!(trace.span_id.as_deref(), Some("123"));
+
+        let trace = TraceContext::from_cloud_trace_header(
+            "4BF92F3577B34DA6A3CE929D0E0E4736/18446744073709551615",
+            None,
+        );
+        assert_eq!(
+            trace.trace_id_hex128().as_deref(),
+            Some("4bf92f3577b34da6a3ce929d0e0e4736")
+        );
+        assert_eq!(trace.span_id_hex64().as_deref(), Some("ffffffffffffffff"));
+
+        let trace = TraceContext::from_cloud_trace_header("abc/00f067aa0ba902b7", None);
+        assert_eq!(trace.span_id_hex64().as_deref(), Some("00f067aa0ba902b7"));
+
+        for header in ["not-hex/abc", "0000/0", "/18446744073709551616"] {
+            let trace = TraceContext::from_cloud_trace_header(header, None);
+            assert_eq!(trace.trace_id_hex128(), None, "{header}");
+            assert_eq!(trace.span_id_hex64(), None, "{header}");
+        }
+    }
+
+    #[test]
+    fn redacts_listed_fields_only() {
+        let metadata = RequestMetadata {
+            client_ip: Some("203.0.113.57".into()),
+            forwarded_for: vec!["2001:db8:1234:5678::9".into(), "unknown".into()],
+            user_agent: Some("curl/8.5.0".into()),
+            accept_language: Some("en-GB".into()),
+            country: Some("GB".into()),
+            ..RequestMetadata::builder().path("/private").build()
+        };
+
+        let redacted = metadata.redacted(&[
+            MetadataField::ClientIp,
+            MetadataField::ForwardedFor,
+            MetadataField::UserAgent,
+        ]);
+        assert_eq!(redacted.client_ip.as_deref(), Some("203.0.113.0"));
+        assert_eq!(redacted.forwarded_for, vec!["2001:db8:1234::".to_owned()]);
+        let agent = redacted.user_agent.as_deref().unwrap();
+        assert_eq!(agent.len(), 64);
+        assert_ne!(agent, "curl/8.5.0");
+        assert_eq!(
+            metadata.redacted(&[MetadataField::UserAgent]).user_agent,
+            redacted.user_agent
+        );
+
+        assert_eq!(redacted.accept_language.as_deref(), Some("en-GB"));
+        assert_eq!(redacted.country.as_deref(), Some("GB"));
+        assert_eq!(redacted.path, "/private");
+        assert_eq!(metadata.client_ip.as_deref(), Some("203.0.113.57"));
+
+        let cleared = metadata.redacted(&[MetadataField::AcceptLanguage]);
+        assert_eq!(cleared.accept_language, None);
+        assert_eq!(cleared.client_ip, metadata.client_ip);
+        assert_eq!(cleared.user_agent, metadata.user_agent);
+    }
+
+    #[test]
+    fn negotiates_preferred_media_type() {
+        let offered = ["application/json", "text/html"];
+        let prefers = |accept: Option<&str>| {
+            RequestMetadata {
+                accept: accept.map(str::to_owned),
+                ..Default::default()
+            }
+            .preferred_media_type(&offered)
+        };
+
+        assert_eq!(prefers(None), Some("application/json"));
+        assert_eq!(prefers(Some("")), Some("application/json"));
+        assert_eq!(
+            prefers(Some("text/html,application/xhtml+xml,*/*;q=0.8")),
+            Some("text/html")
+        );
+        assert_eq!(
+            prefers(Some("application/json, text/html")),
+            Some("application/json")
+        );
+        assert_eq!(
+            prefers(Some("text/*;q=0.9, application/json;q=0.5")),
+            Some("text/html")
+        );
+        // The most specific range decides, even when a broader one scores higher.
+        assert_eq!(
+            prefers(Some("*/*, application/json;q=0")),
+            Some("text/html")
+        );
+        assert_eq!(prefers(Some("Text/HTML")), Some("text/html"));
+        assert_eq!(prefers(Some("image/png")), None);
+    }
+
+    #[test]
+    fn compares_etags_weakly_and_strongly() {
+        let request = Request::builder()
+            .uri("/asset")
+            .header("if-none-match", r#"W/"v1", "a,b""#)
+            .header("if-match", r#""v2", W/"v3""#)
+            .header("range", "bytes=0-99")
+            .body(())
+            .unwrap();
+        let (parts, _) = request.into_parts();
+        let metadata = RequestMetadata::from_parts(&parts, &RuntimePlatform::default());
+        assert_eq!(metadata.range.as_deref(), Some("bytes=0-99"));
+
+        assert!(metadata.etag_matches(r#""v1""#));
+        assert!(metadata.etag_matches(r#"W/"v1""#));
+        assert!(metadata.etag_matches("a,b"));
+        assert!(!metadata.etag_matches("v2"));
+
+        assert!(metadata.if_match_allows(r#""v2""#));
+        assert!(!metadata.if_match_allows(r#"W/"v2""#));
+        assert!(!metadata.if_match_allows("v3"));
+
+        let any = RequestMetadata::builder().if_none_match("*").build();
+        assert!(any.etag_matches("anything"));
+        let none = RequestMetadata::builder().build();
+        assert!(!none.etag_matches("v1"));
+        assert!(none.if_match_allows("v1"));
+    }
+
+    #[test]
+    fn checks_if_modified_since() {
+        let since = SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_777);
+        for date in [
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+        ] {
+            let metadata = RequestMetadata::builder().if_modified_since(date).build();
+            assert!(metadata.not_modified_since(since), "{date}");
+            assert!(
+                metadata.not_modified_since(since + Duration::from_millis(500)),
+                "{date}"
+            );
+            assert!(
+                !metadata.not_modified_since(since + Duration::from_secs(1)),
+                "{date}"
+            );
+        }
+
+        let metadata = RequestMetadata::builder()
+            .if_modified_since("yesterday")
+            .build();
+        assert!(!metadata.not_modified_since(since));
+    }
+
+    #[test]
+    fn parses_typed_client_hints() {
+        let request = Request::builder()
+            .uri("/")
+            .header(
+                "sec-ch-ua",
+                r#""Not_A Brand";v="8", "Chromium";v="120", "Google Chrome";v="120""#,
+            )
+            .header("sec-ch-ua-mobile", "?1")
+            .header("sec-ch-ua-wow64", "?0")
+            .body(())
+            .unwrap();
+        let (parts, _) = request.into_parts();
+        let hints = RequestMetadata::from_parts(&parts, &RuntimePlatform::default())
+            .client_hints
+            .unwrap();
+        assert_eq!(hints.is_mobile(), Some(true));
+        assert_eq!(hints.is_wow64(), Some(false));
+        assert_eq!(
+            hints.brands(),
+            vec![
+                ("Not_A Brand".to_owned(), "8".to_owned()),
+                ("Chromium".to_owned(), "120".to_owned()),
+                ("Google Chrome".to_owned(), "120".to_owned()),
+            ]
+        );
+
+        let hints = ClientHints {
+            ua: Some(r#""Chromium";v="120""#.into()),
+            ua_mobile: Some("yes".into()),
+            ua_full_version_list: Some(
+                r#""Not_A Brand";v="8.0.0.0", "Chro\"mium, Inc";v="120.0.6099.71", broken"#.into(),
+            ),
+            ..ClientHints::default()
+        };
+        assert_eq!(hints.is_mobile(), None);
+        assert_eq!(hints.is_wow64(), None);
+        assert_eq!(
+            hints.brands(),
+            vec![
+                ("Not_A Brand".to_owned(), "8.0.0.0".to_owned()),
+                ("Chro\"mium, Inc".to_owned(), "120.0.6099.71".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn metadata_header_round_trips() {
+        let header = serde_json::json!({
+            "request_id": "shim-ray",
+            "colo": "ZRH",
+            "region": "Zürich",
+            "path": "/orders?id=7",
+            "method": "POST",
+            "client_ip": "203.0.113.9",
+            "user_agent": "agent/1.0 \u{7f}",
+            "forwarded_for": ["203.0.113.9", "10.0.0.1"],
+            "tls": { "version": "TLSv1.3", "cipher": "AEAD-AES128-GCM-SHA256" },
+        })
+        .to_string()
+        // Header values must be ASCII, so non-ASCII text arrives escaped.
+        .replace('ü', "\\u00fc")
+        .replace('\u{7f}', "\\u007f");
+        let parts = parts_with_metadata_header(&header, DEFAULT_MAX_METADATA_HEADER_LEN);
+        let parsed = RequestMetadata::from_metadata_header(&parts).unwrap();
+
+        let value = parsed.to_header_value().unwrap();
+        assert!(value.to_str().unwrap().contains("Z\\u00fcrich"));
+        let parts = parts_with_metadata_header(value.to_str().unwrap(), usize::MAX);
+        let reparsed = RequestMetadata::from_metadata_header(&parts).unwrap();
+        assert_eq!(
+            serde_json::to_value(&reparsed).unwrap(),
+            serde_json::to_value(&parsed).unwrap()
+        );
+        assert_eq!(reparsed.region.as_deref(), Some("Zürich"));
+    }
+
+    #[test]
+    fn records_metadata_source() {
+        let header = serde_json::json!({ "request_id": "shim-ray", "path": "/shim" }).to_string();
+        let parts = parts_with_metadata_header(&header, DEFAULT_MAX_METADATA_HEADER_LEN);
+        let metadata = RequestMetadata::from_parts(&parts, &RuntimePlatform::default());
+        assert_eq!(metadata.source(), MetadataSource::ShimHeader);
+        let roundtrip: RequestMetadata =
+            serde_json::from_value(serde_json::to_value(&metadata).unwrap()).unwrap();
+        assert_eq!(roundtrip.source(), MetadataSource::ShimHeader);
+
+        let parts = parts_with_metadata_header("{not json", DEFAULT_MAX_METADATA_HEADER_LEN);
+        let metadata = RequestMetadata::from_parts(&parts, &RuntimePlatform::default());
+        assert_eq!(metadata.source(), MetadataSource::Headers);
+
+        let request = Request::builder()
+            .uri("/traced")
+            .header(METADATA_HEADER, header.as_str())
+            .header(
+                HEADER_X_CLOUD_TRACE_CONTEXT,
+                "105445aa7843bc8bf206b120001000/123;o=1",
+            )
+            .body(())
+            .unwrap();
+        let (parts, _) = request.into_parts();
+        let platform = RuntimePlatform::CloudRun(CloudRunPlatform::default());
+        let metadata = RequestMetadata::from_parts(&parts, &platform);
+        assert_eq!(metadata.source(), MetadataSource::Merged);
+        assert_eq!(metadata.request_id.as_deref(), Some("shim-ray"));
+
+        assert_eq!(
+            RequestMetadata::builder().build().source(),
+            MetadataSource::ShimHeader
+        );
+    }
+
+    #[cfg(feature = "colo-db")]
+    #[test]
+    fn resolves_colo_location() {
+        let metadata = RequestMetadata {
+            request_id: Some("8c5f2a1b3d4e5f60-NRT".into()),
+            ..Default::default()
+        };
+        assert_eq!(metadata.colo_location().unwrap().city, "Tokyo");
+
+        let metadata = RequestMetadata {
+            colo: Some("iad".into()),
+            ..Default::default()
+        };
+        assert_eq!(metadata.colo_location().unwrap().city, "Ashburn");
+        assert_eq!(
+            metadata.continent(),
+            Some(crate::colo::Continent::NorthAmerica)
+        );
+        assert_eq!(RequestMetadata::default().colo_location(), None);
+        assert_eq!(RequestMetadata::default().continent(), None);
+    }
+
+    #[test]
+    fn classifies_public_ips() {
+        let public = |ip: &str| is_public_ip(&ip.parse().unwrap());
+
+        assert!(public("203.0.114.7"));
+        assert!(public("::ffff:8.8.8.8"));
+        assert!(public("2606:4700::1111"));
+
+        assert!(!public("::ffff:10.0.0.1"));
+        assert!(!public("::ffff:127.0.0.1"));
+        assert!(!public("198.18.0.1"));
+        assert!(!public("198.19.255.255"));
+        assert!(!public("2001:db8::1"));
+        assert!(!public("2001:2::1"));
+        assert!(public("198.20.0.1"));
+    }
+
+    #[test]
+    fn skips_mapped_private_and_zoned_addresses_in_xff() {
+        let request = Request::builder()
+            .header(
+                "x-forwarded-for",
+                "fe80::1%eth0, ::ffff:10.0.0.1, 203.0.114.7",
+            )
+            .body(())
+            .unwrap();
+        let (parts, _) = request.into_parts();
+        assert_eq!(
+            pick_client_ip_from_xff(&parts.headers).as_deref(),
+            Some("203.0.114.7")
+        );
+        assert_eq!(
+            parse_forwarded_ip("fe80::1%eth0"),
+            Some("fe80::1".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn store_is_shared_between_extractions() {
+        use axum::Router;
+        use axum::body::Body;
+        use axum::extract::Extension;
+        use axum::middleware::{self, Next};
+        use axum::routing::get;
+        use tower::ServiceExt;
+
+        #[derive(Clone, Debug, PartialEq)]
+        struct User(String);
+
+        async fn authenticate(
+            ctx: ContainerContext,
+            request: Request<Body>,
+            next: Next,
+        ) -> Response {
+            ctx.insert(User("ada".into()));
+            next.run(request).await
+        }
+
+        let app = Router::new()
+            .route(
+                "/",
+                get(|ctx: ContainerContext| async move {
+                    assert_eq!(ctx.get::<User>(), Some(User("ada".into())));
+                    assert_eq!(ctx.get::<u32>(), None);
+                }),
+            )
+            .layer(middleware::from_fn(authenticate))
+            .layer(middleware::from_fn(install_request_store))
+            .layer(Extension(CommandClient::unavailable("tests")))
+            .layer(Extension(RuntimePlatform::Generic));
+
+        let request = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+    }
+
+    #[test]
+    fn normalizes_paths() {
+        let normalized = |path: &str| {
+            RequestMetadata {
+                path: path.to_owned(),
+                ..Default::default()
+            }
+            .normalized_path()
+        };
+
+        assert_eq!(normalized("/a/../b"), "/b");
+        assert_eq!(normalized("/a/./b//c"), "/a/b/c");
+        assert_eq!(normalized("/static/css/"), "/static/css");
+        assert_eq!(normalized("/"), "/");
+        assert_eq!(
+            normalized("/files/report%20v2.pdf?download=1"),
+            "/files/report v2.pdf"
+        );
+        assert_eq!(normalized("/a/%2e%2e/b"), "/b");
+
+        // Escapes above the root and other suspicious input collapse to `/`.
+        assert_eq!(normalized("/../etc/passwd"), "/");
+        assert_eq!(normalized("/a/%2e%2e/%2E%2E/etc/passwd"), "/");
+        assert_eq!(normalized("/a/..%2f..%2fetc"), "/");
+        assert_eq!(normalized("/a%00b"), "/");
+        assert_eq!(normalized("/a/..%5c..%5cetc"), "/");
+        assert_eq!(normalized("/%ff"), "/");
+    }
+
+    #[test]
+    fn metadata_header_overrides_values() {
+        let metadata = RequestMetadata {
+            request_id: Some("abc".into()),
+            colo: Some("sfo".into()),
+            region: Some("us-west".into()),
+            country: Some("US".into()),
+            client_ip: Some("203.0.113.9".into()),
+            host: Some("example.com".into()),
+            scheme: Some("https".into()),
+            worker_name: Some("test-worker".into()),
+            method: "POST".into(),
+            path: "/foo?bar=baz".into(),
+            raw_url: Some("https://example.com/foo?bar=baz".into()),
+            ..Default::default()
+        };
+
+        let metadata_header = serde_json::to_string(&metadata).unwrap();
+        let request = Request::builder()
+            .method("POST")
+            .uri("https://placeholder.invalid/")
+            .header(METADATA_HEADER, metadata_header)
+            .body(())
+            .unwrap();
+
+        let (parts, _) = request.into_parts();
+        let parsed = RequestMetadata::from_parts(&parts, &RuntimePlatform::default());
+
+        assert_eq!(parsed.request_id, metadata.request_id);
+        assert_eq!(parsed.colo, metadata.colo);
+        assert_eq!(parsed.worker_name, metadata.worker_name);
+        assert_eq!(parsed.path, metadata.path);
+        assert_eq!(parsed.raw_url, metadata.raw_url);
+        assert!(matches!(
+            parsed.platform,
+            Some(RequestMetadataPlatform::Cloudflare { .. })
+        ));
+    }
+
+    #[test]
+    fn builder_keeps_defaults_for_unset_fields() {
+        let metadata = RequestMetadata::builder()
+            .request_id("ray")
+            .client_ip("203.0.113.1")
+            .path("/items")
+            .build();
+
+        assert_eq!(metadata.request_id.as_deref(), Some("ray"));
+        assert_eq!(metadata.client_ip.as_deref(), Some("203.0.113.1"));
+        assert_eq!(metadata.path, "/items");
+        assert_eq!(metadata.method, "GET");
+        assert!(metadata.forwarded_for.is_empty());
+        assert!(metadata.platform.is_none());
+    }
+
+    #[tokio::test]
+    async fn looks_up_named_command_clients() {
+        let mut clients = HashMap::new();
+        clients.insert("gpu".to_string(), CommandClient::unavailable("gpu"));
+        let request = Request::builder()
+            .extension(CommandClient::unavailable("tests"))
+            .extension(RuntimePlatform::Generic)
+            .extension(NamedCommandClients(Arc::new(clients)))
+            .body(())
+            .unwrap();
+        let (mut parts, _) = request.into_parts();
+
+        let ctx = ContainerContext::from_request_parts(&mut parts, &())
+            .await
+            .unwrap();
+        assert!(ctx.command_named("gpu").is_some());
+        assert!(ctx.command_named("cpu").is_none());
+    }
+
+    fn context_with_trace(propagate_trace_context: bool) -> ContainerContext {
+        let metadata = RequestMetadata::builder()
+            .trace_context(TraceContext::from_cloud_trace_header("abc123/42;o=1", None))
+            .build();
+        ContainerContext {
+            metadata,
+            command_client: CommandClient::unavailable("tests"),
+            platform: RuntimePlatform::Generic,
+            shutdown: ShutdownState::default(),
+            options: ContextOptions {
+                propagate_trace_context,
+                ..ContextOptions::default()
+            },
+            deadline: None,
+            byte_counts: ByteCounts::default(),
+            named_clients: NamedCommandClients::default(),
+            raw_headers: None,
+            store: RequestStore::default(),
+            peer_addr: None,
+            early_hints: None,
+        }
+    }
+
+    #[test]
+    fn trace_headers_continue_the_request_trace() {
+        let headers = context_with_trace(false).trace_headers();
+        assert_eq!(
+            headers["traceparent"],
+            "00-00000000000000000000000000abc123-000000000000002a-01"
+        );
+        assert_eq!(
+            headers["x-cloud-trace-context"],
+            "00000000000000000000000000abc123/42;o=1"
+        );
+
+        let mut context = context_with_trace(false);
+        context.metadata.trace_context = None;
+        assert!(context.trace_headers().is_empty());
+    }
+
+    #[test]
+    fn invoke_propagates_trace_context() {
+        let request = context_with_trace(true).prepare_request(CommandRequest::empty("ping"));
+        assert_eq!(
+            request.trace,
+            Some(CommandTrace {
+                trace_id: Some("abc123".into()),
+                span_id: Some("42".into()),
+                sampled: Some(true),
+            })
+        );
+
+        let value = serde_json::to_value(&request).unwrap();
+        assert_eq!(value["_trace"]["trace_id"], "abc123");
+
+        let request = context_with_trace(false).prepare_request(CommandRequest::empty("ping"));
+        assert!(request.trace.is_none());
+        assert!(
+            serde_json::to_value(&request)
+                .unwrap()
+                .get("_trace")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn cloud_run_metadata_from_headers() {
+        let platform = RuntimePlatform::CloudRun(CloudRunPlatform {
+            service: Some("svc".into()),
+            revision: Some("rev".into()),
+            configuration: Some("cfg".into()),
+            project_id: Some("proj-123".into()),
+            region: Some("us-central1".into()),
+        });
+
+        let request = Request::builder()
+            .method("GET")
+            .uri("http://127.0.0.1/hello")
+            .header("x-forwarded-for", "198.51.100.1")
+            .header("x-forwarded-host", "example.run.app")
+            .header("x-forwarded-proto", "https")
+            .header(
+                "x-cloud-trace-context",
+                "105445aa7843bc8bf206b120001000/123;o=1",
+            )
+            .header("user-agent", "test-agent")
+            .header("accept-language", "en-US")
+            .header("sec-ch-ua", "\"Chromium\";v=\"1\"")
+            .body(())
+            .unwrap();
+
+        let (parts, _) = request.into_parts();
+        let metadata = RequestMetadata::from_parts(&parts, &platform);
+
+        assert_eq!(metadata.cloud_run_service.as_deref(), Some("svc"));
+        assert_eq!(metadata.cloud_run_revision.as_deref(), Some("rev"));
+        assert_eq!(metadata.cloud_run_configuration.as_deref(), Some("cfg"));
+        assert_eq!(metadata.project_id.as_deref(), Some("proj-123"));
+        assert_eq!(metadata.cloud_run_region.as_deref(), Some("us-central1"));
+        assert_eq!(metadata.region.as_deref(), Some("us-central1"));
+        assert_eq!(metadata.scheme.as_deref(), Some("https"));
+        assert_eq!(metadata.host.as_deref(), Some("example.run.app"));
+        assert_eq!(metadata.client_ip.as_deref(), Some("198.51.100.1"));
+        assert_eq!(metadata.worker_name.as_deref(), Some("svc"));
+        assert_eq!(metadata.user_agent.as_deref(), Some("test-agent"));
+        assert_eq!(metadata.accept_language.as_deref(), Some("en-US"));
+        assert!(metadata.client_hints.is_some());
         assert_eq!(
             metadata.request_id.as_deref(),
             Some("105445aa7843bc8bf206b120001000")
@@ -780,4 +3058,447 @@ mod tests {
                 && region.as_deref() == Some("us-central1")
         ));
     }
+
+    #[test]
+    fn joins_repeated_forwarded_for_headers() {
+        let request = Request::builder()
+            .uri("/")
+            .header("x-forwarded-for", "10.0.0.1, 192.168.1.7")
+            .header("x-forwarded-for", "93.184.216.34")
+            .body(())
+            .unwrap();
+        let (parts, _) = request.into_parts();
+        let metadata = RequestMetadata::from_parts(&parts, &RuntimePlatform::Generic);
+
+        assert_eq!(
+            metadata.forwarded_for,
+            vec!["10.0.0.1", "192.168.1.7", "93.184.216.34"]
+        );
+        assert_eq!(metadata.client_ip.as_deref(), Some("93.184.216.34"));
+    }
+
+    #[test]
+    fn parses_ray_ids() {
+        let metadata = RequestMetadata::builder()
+            .request_id("7d3f0a1b2c3d4e5f-IAD")
+            .build();
+        assert_eq!(
+            metadata.ray(),
+            Some(RayId {
+                hex: "7d3f0a1b2c3d4e5f".into(),
+                colo: Some("IAD".into()),
+            })
+        );
+
+        let metadata = RequestMetadata::builder()
+            .request_id("7d3f0a1b2c3d4e5f")
+            .build();
+        assert_eq!(metadata.ray().unwrap().colo, None);
+
+        assert_eq!(RayId::parse("not-a-ray!"), None);
+        assert_eq!(RayId::parse("7d3f0a1b2c3d4e"), None);
+        assert_eq!(RequestMetadata::default().ray(), None);
+    }
+
+    #[test]
+    fn cloud_run_trace_ids_are_not_rays() {
+        let platform = RuntimePlatform::CloudRun(CloudRunPlatform::default());
+        let request = Request::builder()
+            .uri("/")
+            .header(
+                "x-cloud-trace-context",
+                "4bf92f3577b34da6a3ce929d0e0e4736/1;o=1",
+            )
+            .body(())
+            .unwrap();
+        let (parts, _) = request.into_parts();
+        let metadata = RequestMetadata::from_parts(&parts, &platform);
+        assert_eq!(
+            metadata.request_id.as_deref(),
+            Some("4bf92f3577b34da6a3ce929d0e0e4736")
+        );
+        assert_eq!(metadata.ray(), None);
+        assert_eq!(metadata.colo, None);
+    }
+
+    #[test]
+    fn backfills_colo_from_ray() {
+        let request = Request::builder()
+            .uri("/")
+            .header("cf-ray", "7d3f0a1b2c3d4e5f-SJC")
+            .body(())
+            .unwrap();
+        let (parts, _) = request.into_parts();
+        let metadata = RequestMetadata::from_parts(&parts, &RuntimePlatform::Generic);
+        assert_eq!(metadata.colo.as_deref(), Some("SJC"));
+
+        let request = Request::builder()
+            .uri("/")
+            .header("cf-ray", "7d3f0a1b2c3d4e5f-SJC")
+            .header("cf-colo", "LAX")
+            .body(())
+            .unwrap();
+        let (parts, _) = request.into_parts();
+        let metadata = RequestMetadata::from_parts(&parts, &RuntimePlatform::Generic);
+        assert_eq!(metadata.colo.as_deref(), Some("LAX"));
+    }
+
+    #[tokio::test]
+    async fn extractor_outside_serve() {
+        let request = Request::builder().uri("/").body(()).unwrap();
+        let (mut parts, _) = request.into_parts();
+        let result = ContainerContext::from_request_parts(&mut parts, &()).await;
+
+        if cfg!(feature = "dev") {
+            let ctx = result.unwrap();
+            assert!(matches!(ctx.platform(), RuntimePlatform::Generic));
+            assert!(matches!(
+                ctx.invoke(CommandRequest::empty("ping")).await,
+                Err(CommandError::Unavailable(_))
+            ));
+        } else {
+            assert!(matches!(
+                result,
+                Err(ContainerContextRejection::MissingCommandClient)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn optional_and_fallible_extractors_expose_the_rejection() {
+        use axum::Router;
+        use axum::body::Body;
+        use axum::routing::get;
+        use http_body_util::BodyExt;
+        use tower::ServiceExt;
+
+        let router = Router::new()
+            .route(
+                "/option",
+                get(|context: Option<ContainerContext>| async move {
+                    if context.is_some() {
+                        "present"
+                    } else {
+                        "absent"
+                    }
+                }),
+            )
+            .route(
+                "/result",
+                get(
+                    |context: Result<ContainerContext, ContainerContextRejection>| async move {
+                        match context {
+                            Ok(_) => (StatusCode::OK, "present".to_owned()),
+                            Err(rejection) => (
+                                StatusCode::SERVICE_UNAVAILABLE,
+                                format!("{} ({})", rejection.code(), rejection.status_code()),
+                            ),
+                        }
+                    },
+                ),
+            );
+        let get = |path: &str| {
+            let request = Request::builder().uri(path).body(Body::empty()).unwrap();
+            let router = router.clone();
+            async move {
+                let response = router.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                (status, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+
+        if cfg!(feature = "dev") {
+            assert_eq!(get("/option").await, (StatusCode::OK, "present".into()));
+            assert_eq!(get("/result").await, (StatusCode::OK, "present".into()));
+        } else {
+            assert_eq!(get("/option").await, (StatusCode::OK, "absent".into()));
+            assert_eq!(
+                get("/result").await,
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "missing_command_client (500 Internal Server Error)".into()
+                )
+            );
+        }
+
+        let rejection = ContainerContextRejection::MissingRuntimePlatform;
+        assert_eq!(rejection.code(), "missing_runtime_platform");
+        assert_eq!(rejection.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            rejection.into_response().status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[test]
+    fn parses_deadline_budgets() {
+        assert_eq!(
+            parse_deadline_budget("250m"),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(parse_deadline_budget("2S"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_deadline_budget("1H"), Some(Duration::from_secs(3600)));
+        assert_eq!(
+            parse_deadline_budget("1500ms"),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(parse_deadline_budget("soon"), None);
+    }
+
+    #[tokio::test]
+    async fn expired_deadline_skips_the_transport() {
+        let mut ctx = context_with_trace(false);
+        ctx.deadline = Some(Instant::now());
+        // The unavailable client would answer `Unavailable` if the transport were touched.
+        let err = ctx.invoke(CommandRequest::empty("ping")).await.unwrap_err();
+        assert!(matches!(err, CommandError::Timeout(elapsed) if elapsed.is_zero()));
+    }
+
+    #[tokio::test]
+    async fn near_expired_deadline_caps_the_command_timeout() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        // Accept and hold the connection without ever answering.
+        tokio::spawn(async move {
+            let (_stream, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        });
+
+        let mut ctx = context_with_trace(false);
+        ctx.command_client =
+            CommandClient::connect(containerflare_command::CommandEndpoint::Tcp(addr))
+                .await
+                .unwrap();
+        ctx.deadline = Some(Instant::now() + Duration::from_millis(50));
+
+        let started = Instant::now();
+        let err = ctx.invoke(CommandRequest::empty("ping")).await.unwrap_err();
+        assert!(
+            matches!(err, CommandError::Timeout(budget) if budget <= Duration::from_millis(50))
+        );
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn deadline_timeouts_do_not_hand_late_replies_to_other_commands() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        // Echo each command name back on every connection, answering `slow` after 150ms.
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    let mut lines = BufReader::new(reader).lines();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let request: CommandRequest = serde_json::from_str(&line).unwrap();
+                        if request.command == "slow" {
+                            tokio::time::sleep(Duration::from_millis(150)).await;
+                        }
+                        let reply = serde_json::json!({"ok": true, "payload": request.command});
+                        let _ = writer.write_all(format!("{reply}\n").as_bytes()).await;
+                    }
+                });
+            }
+        });
+
+        let mut other = context_with_trace(false);
+        other.command_client =
+            CommandClient::connect(containerflare_command::CommandEndpoint::Tcp(addr))
+                .await
+                .unwrap();
+        let mut hurried = other.clone();
+        hurried.deadline = Some(Instant::now() + Duration::from_millis(50));
+
+        // `fast` queues behind `slow` on the shared channel and is sent once `slow` gives up.
+        let (slow, fast) = tokio::join!(hurried.invoke(CommandRequest::empty("slow")), async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            other.invoke(CommandRequest::empty("fast")).await
+        });
+        assert!(matches!(slow.unwrap_err(), CommandError::Timeout(_)));
+        assert_eq!(fast.unwrap().payload, "fast");
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let response = other.invoke(CommandRequest::empty("later")).await.unwrap();
+        assert_eq!(response.payload, "later");
+    }
+
+    #[tokio::test]
+    async fn invoke_dry_run_flags_the_request() {
+        use containerflare_command::{CommandResponse, CommandServer};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, writer) = stream.into_split();
+            let server = CommandServer::new(|request| CommandResponse {
+                ok: request.dry_run,
+                payload: serde_json::Value::Null,
+                diagnostic: None,
+            });
+            let _ = server.serve(reader, writer).await;
+        });
+
+        let mut ctx = context_with_trace(false);
+        ctx.command_client =
+            CommandClient::connect(containerflare_command::CommandEndpoint::Tcp(addr))
+                .await
+                .unwrap();
+        let response = ctx
+            .invoke_dry_run(CommandRequest::empty("kv_delete"))
+            .await
+            .unwrap();
+        assert!(response.ok);
+    }
+
+    #[tokio::test]
+    async fn invoke_with_retries_transient_failures() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        // Answer with the connection's number: the first two connections too late, so their
+        // requests time out and the client reconnects, and the third at once.
+        let host = tokio::spawn(async move {
+            let mut connections = 0;
+            while let Ok((stream, _)) = listener.accept().await {
+                connections += 1;
+                let delay = Duration::from_millis(if connections > 2 { 0 } else { 150 });
+                tokio::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    let mut lines = BufReader::new(reader).lines();
+                    while let Ok(Some(_)) = lines.next_line().await {
+                        tokio::time::sleep(delay).await;
+                        let reply = format!("{{\"ok\":true,\"payload\":{connections}}}\n");
+                        let _ = writer.write_all(reply.as_bytes()).await;
+                    }
+                });
+                if connections == 3 {
+                    return connections;
+                }
+            }
+            connections
+        });
+
+        let mut ctx = context_with_trace(false);
+        ctx.command_client =
+            CommandClient::connect(containerflare_command::CommandEndpoint::Tcp(addr))
+                .await
+                .unwrap();
+
+        let options = InvokeOptions::default()
+            .timeout(Duration::from_millis(50))
+            .retry_backoff(Duration::from_millis(1));
+        let err = ctx
+            .invoke_with(CommandRequest::empty("ping"), options.clone())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, CommandError::Timeout(timeout) if timeout == Duration::from_millis(50))
+        );
+
+        let response = ctx
+            .invoke_with(CommandRequest::empty("ping"), options.retries(1))
+            .await
+            .unwrap();
+        // The retry reads its own reply, not the late one owed to the attempt before it.
+        assert_eq!(response.payload, 3);
+
+        ctx.command_client.close().await.unwrap();
+        assert_eq!(host.await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn generic_platform_continues_the_request_trace() {
+        let request = Request::builder()
+            .uri("/")
+            .header(
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            )
+            .extension(CommandClient::unavailable("tests"))
+            .extension(RuntimePlatform::Generic)
+            .body(())
+            .unwrap();
+        let (mut parts, _) = request.into_parts();
+        let ctx = ContainerContext::from_request_parts(&mut parts, &())
+            .await
+            .unwrap();
+
+        let headers = ctx.trace_headers();
+        assert_eq!(
+            headers["traceparent"],
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+        assert_eq!(
+            headers["x-cloud-trace-context"],
+            "4bf92f3577b34da6a3ce929d0e0e4736/67667974448284343;o=1"
+        );
+        assert_eq!(ctx.metadata.request_id, None);
+    }
+
+    #[tokio::test]
+    async fn extractor_reads_deadline_header() {
+        let request = Request::builder()
+            .uri("/")
+            .header("grpc-timeout", "100m")
+            .extension(CommandClient::unavailable("tests"))
+            .extension(RuntimePlatform::Generic)
+            .body(())
+            .unwrap();
+        let (mut parts, _) = request.into_parts();
+        let ctx = ContainerContext::from_request_parts(&mut parts, &())
+            .await
+            .unwrap();
+        let remaining = ctx.deadline().unwrap() - Instant::now();
+        assert!(remaining <= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn trace_context_extractor_parses_cloud_trace_header() {
+        let request = Request::builder()
+            .uri("/")
+            .header(
+                "x-cloud-trace-context",
+                "105445aa7843bc8bf206b12000100000/1;o=1",
+            )
+            .body(())
+            .unwrap();
+        let (mut parts, _) = request.into_parts();
+        let trace = TraceContext::from_request_parts(&mut parts, &())
+            .await
+            .unwrap();
+        assert_eq!(
+            trace.trace_id.as_deref(),
+            Some("105445aa7843bc8bf206b12000100000")
+        );
+        assert_eq!(trace.span_id.as_deref(), Some("1"));
+        assert_eq!(trace.sampled, Some(true));
+
+        let (mut parts, _) = Request::builder().body(()).unwrap().into_parts();
+        let trace = TraceContext::from_request_parts(&mut parts, &())
+            .await
+            .unwrap();
+        assert!(trace.trace_id.is_none());
+    }
+
+    #[tokio::test]
+    async fn unavailable_channel_maps_to_retryable_503() {
+        use axum::http::header::RETRY_AFTER;
+
+        let err = context_with_trace(false)
+            .invoke(CommandRequest::empty("ping"))
+            .await
+            .unwrap_err();
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], "1");
+
+        let response = CommandError::Timeout(Duration::from_secs(5)).into_response();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(response.headers()[RETRY_AFTER], "2");
+    }
 }
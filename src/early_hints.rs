@@ -0,0 +1,228 @@
+use std::future::Future;
+use std::io::{self, IoSlice};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, ready};
+
+use axum::http::{HeaderValue, Request, Version};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tower_service::Service;
+
+/// Handle for sending a `103 Early Hints` interim response ahead of the final one.
+///
+/// `serve` adds one to the extensions of every HTTP/1.1 request; handlers normally reach it
+/// through [`ContainerContext::send_early_hints`](crate::ContainerContext::send_early_hints).
+///
+/// hyper has no API for interim responses, so the hints are written to the connection directly,
+/// and only when that cannot interleave with another response. HTTP/2 and HTTP/1.0 requests
+/// never get a handle (HTTP/1.0 clients do not understand `1xx` responses). Whether the hints
+/// reach the browser depends on the proxies in between, which may drop interim responses.
+/// Cloudflare's Early Hints feature derives hints from the `Link` headers of final responses
+/// instead, so set those as well when running behind it.
+#[derive(Clone, Debug)]
+pub struct EarlyHints {
+    connection: Arc<Mutex<Connection>>,
+    /// Cleared once the handler has produced its response, after which hyper writes the head.
+    open: Arc<AtomicBool>,
+}
+
+impl EarlyHints {
+    /// Writes a `103 Early Hints` response with one `Link` header per entry, e.g.
+    /// `</style.css>; rel=preload; as=style`.
+    ///
+    /// Returns `Ok(false)` without writing anything when `links` is empty, the final response
+    /// has already been produced, or the previous response on the connection is still being
+    /// flushed. Hints can be sent more than once per request.
+    ///
+    /// # Errors
+    /// Returns [`io::ErrorKind::InvalidInput`] when a link is not a valid header value, or the
+    /// socket error if writing fails.
+    pub fn send<I, L>(&self, links: I) -> io::Result<bool>
+    where
+        I: IntoIterator<Item = L>,
+        L: AsRef<str>,
+    {
+        let mut message = b"HTTP/1.1 103 Early Hints\r\n".to_vec();
+        let mut empty = true;
+        for link in links {
+            let link = link.as_ref();
+            if HeaderValue::from_str(link).is_err() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid Link header value: {link:?}"),
+                ));
+            }
+            message.extend_from_slice(b"link: ");
+            message.extend_from_slice(link.as_bytes());
+            message.extend_from_slice(b"\r\n");
+            empty = false;
+        }
+        if empty {
+            return Ok(false);
+        }
+        message.extend_from_slice(b"\r\n");
+
+        // Holding the lock keeps hyper from writing while the state is checked.
+        let mut connection = lock(&self.connection);
+        if !self.open.load(Ordering::SeqCst) || !connection.idle {
+            return Ok(false);
+        }
+        connection.pending.extend_from_slice(&message);
+        connection.try_write_pending()?;
+        Ok(true)
+    }
+}
+
+#[derive(Debug)]
+struct Connection {
+    stream: TcpStream,
+    /// hyper's last write was followed by a completed flush, so its own buffer is empty and
+    /// bytes written now land between two responses.
+    idle: bool,
+    /// Hint bytes the socket has not accepted yet; they go out ahead of hyper's next write.
+    pending: Vec<u8>,
+}
+
+impl Connection {
+    /// Writes as much of `pending` as the socket takes without blocking.
+    fn try_write_pending(&mut self) -> io::Result<()> {
+        while !self.pending.is_empty() {
+            match self.stream.try_write(&self.pending) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => drop(self.pending.drain(..written)),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    fn poll_write_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.pending.is_empty() {
+            let written = ready!(Pin::new(&mut self.stream).poll_write(cx, &self.pending))?;
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.pending.drain(..written);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn lock(connection: &Mutex<Connection>) -> MutexGuard<'_, Connection> {
+    connection.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// The accepted stream as hyper sees it, shared with the [`EarlyHints`] handles of its
+/// requests.
+pub(crate) struct HintedStream(Arc<Mutex<Connection>>);
+
+impl HintedStream {
+    pub(crate) fn new(stream: TcpStream) -> Self {
+        Self(Arc::new(Mutex::new(Connection {
+            stream,
+            idle: true,
+            pending: Vec::new(),
+        })))
+    }
+
+    /// Wraps the connection's service so each HTTP/1.1 request carries an [`EarlyHints`] handle.
+    pub(crate) fn service<S>(&self, inner: S) -> WithEarlyHints<S> {
+        WithEarlyHints {
+            inner,
+            connection: self.0.clone(),
+        }
+    }
+}
+
+impl AsyncRead for HintedStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut lock(&self.0).stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for HintedStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut connection = lock(&self.0);
+        ready!(connection.poll_write_pending(cx))?;
+        connection.idle = false;
+        Pin::new(&mut connection.stream).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let mut connection = lock(&self.0);
+        ready!(connection.poll_write_pending(cx))?;
+        connection.idle = false;
+        Pin::new(&mut connection.stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut connection = lock(&self.0);
+        ready!(connection.poll_write_pending(cx))?;
+        ready!(Pin::new(&mut connection.stream).poll_flush(cx))?;
+        // hyper only flushes the socket once its own buffer is drained.
+        connection.idle = true;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut connection = lock(&self.0);
+        ready!(connection.poll_write_pending(cx))?;
+        Pin::new(&mut connection.stream).poll_shutdown(cx)
+    }
+}
+
+/// Service wrapper installed by [`HintedStream::service`].
+#[derive(Clone)]
+pub(crate) struct WithEarlyHints<S> {
+    inner: S,
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl<S, B> Service<Request<B>> for WithEarlyHints<S>
+where
+    S: Service<Request<B>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        let open = Arc::new(AtomicBool::new(true));
+        if request.version() == Version::HTTP_11 {
+            request.extensions_mut().insert(EarlyHints {
+                connection: self.connection.clone(),
+                open: open.clone(),
+            });
+        }
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = response.await;
+            open.store(false, Ordering::SeqCst);
+            response
+        })
+    }
+}
@@ -1,3 +1,7 @@
+use std::net::SocketAddr;
+
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 use thiserror::Error;
 
 use crate::config::ConfigError;
@@ -13,6 +17,55 @@ pub enum ContainerflareError {
     Command(#[from] CommandError),
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
+    /// A listener could not be bound, e.g. because the address is already in use.
+    #[error("failed to bind {addr}: {source}")]
+    Bind {
+        addr: SocketAddr,
+        #[source]
+        source: std::io::Error,
+    },
     #[error("server error: {0}")]
     Hyper(#[from] hyper::Error),
+    #[cfg(feature = "metrics")]
+    #[error("metrics error: {0}")]
+    Metrics(String),
+}
+
+/// Lets handlers return [`Result`] directly.
+///
+/// Command errors keep the status mapping of [`CommandError`]'s own `IntoResponse` (502/503/504,
+/// with `Retry-After` where applicable); everything else is a `500 Internal Server Error`.
+impl IntoResponse for ContainerflareError {
+    fn into_response(self) -> Response {
+        match self {
+            ContainerflareError::Command(err) => err.into_response(),
+            err => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_errors_to_status_codes() {
+        let err = ContainerflareError::from(CommandError::TransportClosed);
+        assert_eq!(
+            err.into_response().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        let err = ContainerflareError::from(CommandError::CommandFailure {
+            diagnostic: "boom".into(),
+            payload: serde_json::Value::Null,
+        });
+        assert_eq!(err.into_response().status(), StatusCode::BAD_GATEWAY);
+
+        let err = ContainerflareError::from(std::io::Error::other("disk"));
+        assert_eq!(
+            err.into_response().status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
 }
@@ -0,0 +1,62 @@
+use std::net::SocketAddr;
+
+use tokio::sync::mpsc;
+
+use containerflare_command::CommandEndpoint;
+
+/// Lifecycle milestones reported by [`serve`](crate::serve) through
+/// [`RuntimeConfigBuilder::events`](crate::RuntimeConfigBuilder::events).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RuntimeEvent {
+    /// The listener is bound and about to accept connections.
+    ///
+    /// `addr` is read back from the socket, so with `PORT=0` it carries the ephemeral port the
+    /// OS picked rather than `:0`.
+    Bound { addr: SocketAddr },
+    /// The host command channel was established.
+    CommandChannelConnected { endpoint: CommandEndpoint },
+    /// The host command channel is disabled for this runtime.
+    CommandChannelDisabled { reason: String },
+    /// A shutdown signal arrived; new requests are rejected from here on.
+    ShutdownSignalReceived,
+    /// Every in-flight connection finished and the command channel was closed.
+    Drained,
+}
+
+/// Non-blocking wrapper around the optional event channel.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct EventSink {
+    sender: Option<mpsc::Sender<RuntimeEvent>>,
+}
+
+impl EventSink {
+    pub(crate) fn new(sender: Option<mpsc::Sender<RuntimeEvent>>) -> Self {
+        Self { sender }
+    }
+
+    /// Delivers `event` if there is room; a full or closed channel drops it so `serve` never waits
+    /// on a slow consumer.
+    pub(crate) fn emit(&self, event: RuntimeEvent) {
+        if let Some(sender) = &self.sender
+            && let Err(err) = sender.try_send(event)
+        {
+            tracing::debug!(error = %err, "dropped runtime event");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_events_when_channel_is_full() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let sink = EventSink::new(Some(tx));
+        sink.emit(RuntimeEvent::ShutdownSignalReceived);
+        sink.emit(RuntimeEvent::Drained);
+
+        assert_eq!(rx.try_recv(), Ok(RuntimeEvent::ShutdownSignalReceived));
+        assert!(rx.try_recv().is_err());
+    }
+}
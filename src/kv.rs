@@ -0,0 +1,213 @@
+use std::io;
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use containerflare_command::{CommandClient, CommandError, CommandRequest};
+
+const KV_GET: &str = "kv_get";
+const KV_PUT: &str = "kv_put";
+const KV_DELETE: &str = "kv_delete";
+/// Failure `code` the host reports when a key does not exist.
+const NOT_FOUND_CODE: &str = "not_found";
+
+/// Thin key/value wrapper over the host command channel.
+///
+/// # Wire format
+/// Values travel base64-encoded so arbitrary bytes survive the JSON framing:
+/// - `kv_get`: payload `{"key": "..."}`; the host replies with `{"value": "<base64>"}`, or with a
+///   `null` payload / `{"value": null}` when the key is missing.
+/// - `kv_put`: payload `{"key": "...", "value": "<base64>", "ttl_seconds": 60}` (`ttl_seconds`
+///   is omitted when no TTL is requested).
+/// - `kv_delete`: payload `{"key": "..."}`.
+///
+/// A failure response whose payload is `{"code": "not_found"}` is also treated as a missing key.
+#[derive(Clone, Debug)]
+pub struct KvClient {
+    client: CommandClient,
+}
+
+impl KvClient {
+    /// Wraps an existing command client.
+    pub fn new(client: CommandClient) -> Self {
+        Self { client }
+    }
+
+    /// Fetches the value stored under `key`, returning `Ok(None)` when it does not exist.
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, CommandError> {
+        let request = CommandRequest::new(KV_GET, key_payload(key)?);
+        match self.client.send(request).await {
+            Ok(response) => decode_value(response.payload),
+            Err(err) if is_not_found(&err) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Stores `value` under `key`, optionally expiring it after `ttl`.
+    ///
+    /// The host takes whole seconds, so a partial second is rounded up rather than sent as an
+    /// immediate expiry.
+    ///
+    /// # Errors
+    /// Fails with an [`io::ErrorKind::InvalidInput`] [`CommandError::Io`] when `ttl` is zero, and
+    /// as [`CommandClient::send`] does otherwise.
+    pub async fn put(
+        &self,
+        key: &str,
+        value: impl AsRef<[u8]>,
+        ttl: Option<Duration>,
+    ) -> Result<(), CommandError> {
+        let payload = serde_json::to_value(KvPut {
+            key,
+            value: BASE64.encode(value),
+            ttl_seconds: ttl.map(ttl_seconds).transpose()?,
+        })?;
+        self.client
+            .send(CommandRequest::new(KV_PUT, payload))
+            .await?;
+        Ok(())
+    }
+
+    /// Removes `key`; deleting a missing key is not an error.
+    pub async fn delete(&self, key: &str) -> Result<(), CommandError> {
+        let request = CommandRequest::new(KV_DELETE, key_payload(key)?);
+        match self.client.send(request).await {
+            Ok(_) => Ok(()),
+            Err(err) if is_not_found(&err) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct KvKey<'a> {
+    key: &'a str,
+}
+
+#[derive(Serialize)]
+struct KvPut<'a> {
+    key: &'a str,
+    value: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl_seconds: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct KvValue {
+    #[serde(default)]
+    value: Option<String>,
+}
+
+fn key_payload(key: &str) -> Result<Value, CommandError> {
+    Ok(serde_json::to_value(KvKey { key })?)
+}
+
+fn ttl_seconds(ttl: Duration) -> Result<u64, CommandError> {
+    if ttl.is_zero() {
+        return Err(CommandError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "kv ttl must be greater than zero",
+        )));
+    }
+    Ok(ttl
+        .as_secs()
+        .saturating_add(u64::from(ttl.subsec_nanos() > 0)))
+}
+
+fn decode_value(payload: Value) -> Result<Option<Vec<u8>>, CommandError> {
+    if payload.is_null() {
+        return Ok(None);
+    }
+
+    let KvValue { value } = serde_json::from_value(payload)
+        .map_err(|err| CommandError::MalformedResponse(format!("invalid {KV_GET} reply: {err}")))?;
+    value
+        .map(|encoded| {
+            BASE64.decode(encoded).map_err(|err| {
+                CommandError::MalformedResponse(format!("kv value is not valid base64: {err}"))
+            })
+        })
+        .transpose()
+}
+
+fn is_not_found(err: &CommandError) -> bool {
+    matches!(
+        err,
+        CommandError::CommandFailure { payload, .. }
+            if payload.get("code").and_then(Value::as_str) == Some(NOT_FOUND_CODE)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn encodes_put_payload() {
+        let payload = serde_json::to_value(KvPut {
+            key: "greeting",
+            value: BASE64.encode(b"hello"),
+            ttl_seconds: Some(60),
+        })
+        .unwrap();
+        assert_eq!(
+            payload,
+            json!({"key": "greeting", "value": "aGVsbG8=", "ttl_seconds": 60})
+        );
+
+        let payload = serde_json::to_value(KvPut {
+            key: "greeting",
+            value: BASE64.encode(b"hello"),
+            ttl_seconds: None,
+        })
+        .unwrap();
+        assert_eq!(payload, json!({"key": "greeting", "value": "aGVsbG8="}));
+    }
+
+    #[test]
+    fn decodes_values_and_missing_keys() {
+        assert_eq!(
+            decode_value(json!({"value": "aGVsbG8="})).unwrap(),
+            Some(b"hello".to_vec())
+        );
+        assert_eq!(decode_value(json!({"value": null})).unwrap(), None);
+        assert_eq!(decode_value(Value::Null).unwrap(), None);
+        assert!(matches!(
+            decode_value(json!({"value": "not base64!"})),
+            Err(CommandError::MalformedResponse(_))
+        ));
+        assert!(matches!(
+            decode_value(json!({"value": 7})),
+            Err(CommandError::MalformedResponse(_))
+        ));
+    }
+
+    #[test]
+    fn rounds_partial_second_ttls_up() {
+        assert_eq!(ttl_seconds(Duration::from_millis(1)).unwrap(), 1);
+        assert_eq!(ttl_seconds(Duration::from_millis(1500)).unwrap(), 2);
+        assert_eq!(ttl_seconds(Duration::from_secs(60)).unwrap(), 60);
+        assert!(matches!(
+            ttl_seconds(Duration::ZERO),
+            Err(CommandError::Io(err)) if err.kind() == io::ErrorKind::InvalidInput
+        ));
+    }
+
+    #[test]
+    fn recognizes_not_found_failures() {
+        let not_found = CommandError::CommandFailure {
+            diagnostic: "missing".into(),
+            payload: json!({"code": "not_found"}),
+        };
+        let other = CommandError::CommandFailure {
+            diagnostic: "boom".into(),
+            payload: Value::Null,
+        };
+        assert!(is_not_found(&not_found));
+        assert!(!is_not_found(&other));
+    }
+}
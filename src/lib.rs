@@ -4,19 +4,82 @@
 //! Containers platform, letting you write idiomatic Rust handlers that still have
 //! access to the surrounding worker container capabilities.
 
+pub mod access_log;
+pub mod accounting;
+pub mod body;
+#[cfg(feature = "colo-db")]
+pub mod colo;
 pub mod config;
 pub mod context;
+pub mod early_hints;
 pub mod error;
+pub mod events;
+pub mod kv;
+#[cfg(feature = "log-sink")]
+pub mod log_sink;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod not_found;
+#[cfg(feature = "otel")]
+pub mod otel;
 pub mod platform;
+pub mod queue;
+mod raw_headers;
+pub mod request_id;
+pub mod required;
 pub mod runtime;
+pub mod secrets;
+pub mod shutdown;
+pub mod state;
 
-pub use crate::config::{RuntimeConfig, RuntimeConfigBuilder};
+pub use crate::access_log::AccessLogFormat;
+pub use crate::accounting::ByteCounts;
+pub use crate::body::{ContainerBody, Streaming};
+#[cfg(feature = "colo-db")]
+pub use crate::colo::{ColoInfo, Continent, colo_to_location, continent_for_colo};
+pub use crate::config::{ConfigSummary, HttpVersion, RuntimeConfig, RuntimeConfigBuilder};
 pub use crate::context::{
-    ContainerContext, RequestMetadata, RequestMetadataPlatform, TraceContext,
+    ContainerContext, InvokeOptions, MetadataField, MetadataSource, RayId, RequestMetadata,
+    RequestMetadataBuilder, RequestMetadataPlatform, SpanIdFormat, TlsInfo, TraceContext,
 };
+pub use crate::early_hints::EarlyHints;
 pub use crate::error::{ContainerflareError, Result};
-pub use crate::platform::{CloudRunPlatform, CloudflarePlatform, RuntimePlatform};
-pub use crate::runtime::{ContainerflareRuntime, run, serve};
+pub use crate::events::RuntimeEvent;
+pub use crate::kv::KvClient;
+#[cfg(feature = "log-sink")]
+pub use crate::log_sink::LogSink;
+#[cfg(feature = "metrics")]
+pub use crate::metrics::MetricsObserver;
+pub use crate::not_found::NotFoundHandler;
+#[cfg(feature = "otel")]
+pub use crate::otel::OtelObserver;
+pub use crate::platform::{
+    CloudRunPlatform, CloudflarePlatform, DetectionReport, PlatformLimits, PlatformParseError,
+    RuntimePlatform,
+};
+pub use crate::queue::QueueClient;
+pub use crate::request_id::RequestId;
+pub use crate::required::Required;
+pub use crate::runtime::{
+    ContainerflareRuntime, run, serve, serve_reloadable, serve_with_listener,
+};
+pub use crate::secrets::{Secret, SecretClient};
+pub use crate::shutdown::ShutdownState;
+pub use crate::state::ContainerflareState;
 pub use containerflare_command::{
-    CommandClient, CommandEndpoint, CommandError, CommandRequest, CommandResponse,
+    AutoProbe, ClockOffset, CommandClient, CommandClientPool, CommandCompression,
+    CommandDescriptor, CommandEndpoint, CommandError, CommandEvent, CommandObserver,
+    CommandOptions, CommandRequest, CommandResponse, CommandStats, CommandTrace, ConnectGate,
+    ConnectionState, PooledClient,
 };
+
+#[cfg(test)]
+pub(crate) mod test_support {
+    use std::sync::{Mutex, OnceLock};
+
+    /// Serializes tests that mutate process environment variables.
+    pub(crate) fn env_lock() -> &'static Mutex<()> {
+        static ENV_LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+        ENV_LOCK.get_or_init(|| Mutex::new(()))
+    }
+}
@@ -0,0 +1,217 @@
+//! Streams log output to the host as `log_emit` commands (requires the `log-sink` feature).
+//!
+//! [`RuntimeConfigBuilder::stream_logs`](crate::RuntimeConfigBuilder::stream_logs) installs a
+//! `tracing_subscriber::fmt` subscriber writing to a [`LogSink`] once the command channel is up.
+//! Applications that set up their own subscriber can pass a [`LogSink`] to `with_writer`
+//! instead.
+
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use containerflare_command::{CommandClient, CommandRequest};
+use tokio::sync::mpsc;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Command each forwarded line is sent as, with a `{"line": "..."}` payload.
+pub const LOG_EMIT_COMMAND: &str = "log_emit";
+
+/// Lines buffered by the sink `stream_logs` installs.
+pub(crate) const DEFAULT_CAPACITY: usize = 1024;
+
+/// Writer that forwards each line written to it to the host as a `log_emit` notification.
+///
+/// Lines are queued in a bounded buffer drained by a background task, so writing never waits on
+/// the channel: when the buffer is full, or the channel has closed, the line is dropped and
+/// counted in [`LogSink::dropped`]. A partial line is sent when the writer is flushed or
+/// dropped. Clones share the buffer and the drop counter.
+#[derive(Debug)]
+pub struct LogSink {
+    shared: Arc<Shared>,
+    /// Bytes written since the last newline.
+    pending: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    sender: mpsc::Sender<String>,
+    dropped: AtomicU64,
+}
+
+impl LogSink {
+    /// Creates a sink that buffers up to `capacity` lines (at least one) for `client`.
+    ///
+    /// # Panics
+    /// Panics when called outside a Tokio runtime, which the forwarding task is spawned on.
+    pub fn new(client: CommandClient, capacity: usize) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<String>(capacity.max(1));
+        tokio::spawn(async move {
+            while let Some(line) = receiver.recv().await {
+                let request =
+                    CommandRequest::new(LOG_EMIT_COMMAND, serde_json::json!({ "line": line }));
+                // Failed TCP and Unix socket channels reconnect on a later line.
+                if client.notify(request).await.is_err() && client.is_closed() {
+                    break;
+                }
+            }
+        });
+        Self {
+            shared: Arc::new(Shared {
+                sender,
+                dropped: AtomicU64::new(0),
+            }),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Number of lines discarded because the buffer was full or the channel had closed.
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    fn forward(&self, line: &[u8]) {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let line = String::from_utf8_lossy(line).into_owned();
+        if self.shared.sender.try_send(line).is_err() {
+            self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Clone for LogSink {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl io::Write for LogSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        while let Some(end) = self.pending.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            self.forward(&line[..end]);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            let line = std::mem::take(&mut self.pending);
+            self.forward(&line);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for LogSink {
+    fn drop(&mut self) {
+        let _ = io::Write::flush(self);
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogSink {
+    type Writer = LogSink;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// Installs a global `fmt` subscriber writing to a [`LogSink`] for `client`.
+pub(crate) fn install(client: CommandClient) {
+    let sink = LogSink::new(client, DEFAULT_CAPACITY);
+    if tracing_subscriber::fmt()
+        .with_writer(sink)
+        .try_init()
+        .is_err()
+    {
+        tracing::warn!("a tracing subscriber is already installed; not streaming logs to the host");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::time::Duration;
+
+    use containerflare_command::{CommandEndpoint, CommandResponse, CommandServer};
+    use tokio::net::TcpListener;
+
+    async fn recording_client() -> (CommandClient, mpsc::UnboundedReceiver<CommandRequest>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, writer) = stream.into_split();
+            let server = CommandServer::new(move |request| {
+                let _ = sender.send(request);
+                CommandResponse::ok()
+            });
+            let _ = server.serve(reader, writer).await;
+        });
+        let client = CommandClient::connect(CommandEndpoint::Tcp(addr))
+            .await
+            .unwrap();
+        (client, receiver)
+    }
+
+    async fn next_line(receiver: &mut mpsc::UnboundedReceiver<CommandRequest>) -> String {
+        let request = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.command, LOG_EMIT_COMMAND);
+        assert!(request.notify);
+        request.payload["line"].as_str().unwrap().to_owned()
+    }
+
+    #[tokio::test]
+    async fn forwards_each_line_as_a_log_emit_command() {
+        let (client, mut receiver) = recording_client().await;
+        let mut sink = LogSink::new(client, 16);
+
+        sink.write_all(b"first line\nsecond ").unwrap();
+        sink.write_all(b"line\r\nthird").unwrap();
+        assert_eq!(next_line(&mut receiver).await, "first line");
+        assert_eq!(next_line(&mut receiver).await, "second line");
+
+        drop(sink);
+        assert_eq!(next_line(&mut receiver).await, "third");
+    }
+
+    #[tokio::test]
+    async fn forwards_tracing_events_through_make_writer() {
+        let (client, mut receiver) = recording_client().await;
+        let sink = LogSink::new(client, 16);
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(sink.clone())
+            .without_time()
+            .finish();
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(user = 7, "signed in");
+        });
+
+        let line = next_line(&mut receiver).await;
+        assert!(line.contains("INFO"), "{line}");
+        assert!(line.ends_with("signed in user=7"), "{line}");
+        assert_eq!(sink.dropped(), 0);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn drops_lines_instead_of_blocking_when_the_buffer_is_full() {
+        let (client, mut receiver) = recording_client().await;
+        let mut sink = LogSink::new(client, 2);
+
+        // The forwarding task cannot run until this test yields, so only two lines fit.
+        sink.write_all(b"one\ntwo\nthree\nfour\n").unwrap();
+        assert_eq!(sink.dropped(), 2);
+        assert_eq!(next_line(&mut receiver).await, "one");
+        assert_eq!(next_line(&mut receiver).await, "two");
+    }
+}
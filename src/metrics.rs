@@ -0,0 +1,136 @@
+//! Prometheus metrics for HTTP traffic and host commands (requires the `metrics` feature).
+
+use std::sync::Mutex;
+use std::time::Instant;
+
+use axum::extract::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+
+use containerflare_command::{CommandEvent, CommandObserver};
+
+use crate::error::ContainerflareError;
+
+const HTTP_REQUESTS_TOTAL: &str = "containerflare_http_requests_total";
+const HTTP_REQUEST_DURATION: &str = "containerflare_http_request_duration_seconds";
+const COMMANDS_TOTAL: &str = "containerflare_commands_total";
+const COMMAND_DURATION: &str = "containerflare_command_duration_seconds";
+const COMMAND_FAILURES_TOTAL: &str = "containerflare_command_failures_total";
+
+/// [`CommandObserver`] that records command counts, latency, and failures by error kind.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MetricsObserver;
+
+impl CommandObserver for MetricsObserver {
+    fn on_command(&self, event: &CommandEvent<'_>) {
+        let command = event.command.to_owned();
+        ::metrics::counter!(COMMANDS_TOTAL, "command" => command.clone()).increment(1);
+        ::metrics::histogram!(COMMAND_DURATION, "command" => command.clone())
+            .record(event.elapsed.as_secs_f64());
+
+        if let Err(err) = event.outcome {
+            ::metrics::counter!(
+                COMMAND_FAILURES_TOTAL,
+                "command" => command,
+                "kind" => err.kind()
+            )
+            .increment(1);
+        }
+    }
+}
+
+/// Installs the process-wide Prometheus recorder, reusing it when `serve` runs more than once.
+pub(crate) fn install_recorder() -> Result<PrometheusHandle, ContainerflareError> {
+    static HANDLE: Mutex<Option<PrometheusHandle>> = Mutex::new(None);
+
+    let mut installed = HANDLE.lock().unwrap_or_else(|err| err.into_inner());
+    if let Some(handle) = installed.as_ref() {
+        return Ok(handle.clone());
+    }
+
+    let recorder = PrometheusBuilder::new().build_recorder();
+    let handle = recorder.handle();
+    ::metrics::set_global_recorder(recorder)
+        .map_err(|err| ContainerflareError::Metrics(err.to_string()))?;
+    *installed = Some(handle.clone());
+    Ok(handle)
+}
+
+/// Middleware recording request counts and durations labelled by method and status.
+pub(crate) async fn track_http(request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let started = Instant::now();
+    let response = next.run(request).await;
+    let status = response.status().as_u16().to_string();
+
+    ::metrics::counter!(
+        HTTP_REQUESTS_TOTAL,
+        "method" => method.clone(),
+        "status" => status.clone()
+    )
+    .increment(1);
+    ::metrics::histogram!(HTTP_REQUEST_DURATION, "method" => method, "status" => status)
+        .record(started.elapsed().as_secs_f64());
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use axum::Router;
+    use axum::body::Body;
+    use axum::middleware;
+    use axum::routing::get;
+    use containerflare_command::{CommandError, CommandResponse};
+    use tower::ServiceExt;
+
+    #[test]
+    fn observer_records_command_metrics() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+
+        ::metrics::with_local_recorder(&recorder, || {
+            let response = CommandResponse::ok();
+            MetricsObserver.on_command(&CommandEvent {
+                command: "kv_get",
+                elapsed: Duration::from_millis(3),
+                outcome: Ok(&response),
+            });
+            let err = CommandError::Timeout(Duration::from_secs(1));
+            MetricsObserver.on_command(&CommandEvent {
+                command: "kv_get",
+                elapsed: Duration::from_secs(1),
+                outcome: Err(&err),
+            });
+        });
+
+        let rendered = handle.render();
+        assert!(rendered.contains(r#"containerflare_commands_total{command="kv_get"} 2"#));
+        assert!(rendered.contains(
+            r#"containerflare_command_failures_total{command="kv_get",kind="timeout"} 1"#
+        ));
+        assert!(rendered.contains(COMMAND_DURATION));
+    }
+
+    #[tokio::test]
+    async fn middleware_records_http_metrics() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        let _guard = ::metrics::set_default_local_recorder(&recorder);
+
+        let router = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(middleware::from_fn(track_http));
+        let request = Request::builder().uri("/").body(Body::empty()).unwrap();
+        router.oneshot(request).await.unwrap();
+
+        let rendered = handle.render();
+        assert!(
+            rendered.contains(r#"containerflare_http_requests_total{method="GET",status="200"} 1"#)
+        );
+    }
+}
@@ -0,0 +1,95 @@
+//! Responses for requests that match no route, installed with
+//! [`RuntimeConfigBuilder::not_found_handler`](crate::RuntimeConfigBuilder::not_found_handler).
+
+use std::fmt;
+use std::sync::Arc;
+
+use axum::Json;
+use axum::http::StatusCode;
+use axum::http::header::VARY;
+use axum::response::{Html, IntoResponse, Response};
+
+use crate::context::RequestMetadata;
+
+type NotFoundFn = dyn Fn(&RequestMetadata) -> Response + Send + Sync;
+
+/// Builds the response `serve` sends when no route matches, from the request's parsed metadata.
+///
+/// Two handlers compare equal only when they are the same instance.
+#[derive(Clone)]
+pub struct NotFoundHandler(Arc<NotFoundFn>);
+
+impl NotFoundHandler {
+    /// Wraps `handler`; see [`negotiated`] for a ready-made one.
+    pub fn new<F>(handler: F) -> Self
+    where
+        F: Fn(&RequestMetadata) -> Response + Send + Sync + 'static,
+    {
+        Self(Arc::new(handler))
+    }
+
+    pub(crate) fn respond(&self, metadata: &RequestMetadata) -> Response {
+        (self.0)(metadata)
+    }
+}
+
+impl fmt::Debug for NotFoundHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("NotFoundHandler(..)")
+    }
+}
+
+impl PartialEq for NotFoundHandler {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for NotFoundHandler {}
+
+/// `404 Not Found` in whichever of JSON, HTML, or plain text the client's `Accept` header
+/// prefers (JSON when it does not say), naming the path and request id.
+///
+/// JSON bodies look like `{"error":"not_found","path":"/missing","request_id":"…"}`. The
+/// response carries `Vary: accept` so caches keep the variants apart.
+pub fn negotiated(metadata: &RequestMetadata) -> Response {
+    let path = metadata.path.as_str();
+    let request_id = metadata.request_id.as_deref();
+    let body = match metadata.preferred_media_type(&["application/json", "text/html", "text/plain"])
+    {
+        Some("application/json") => Json(serde_json::json!({
+            "error": "not_found",
+            "path": path,
+            "request_id": request_id,
+        }))
+        .into_response(),
+        Some("text/html") => {
+            let request_id = request_id
+                .map(|id| format!("<p>Request id: <code>{}</code></p>", escape_html(id)))
+                .unwrap_or_default();
+            Html(format!(
+                "<!doctype html>\n<title>404 Not Found</title>\n<h1>Not Found</h1>\n\
+                 <p>No route matches <code>{}</code>.</p>\n{request_id}\n",
+                escape_html(path)
+            ))
+            .into_response()
+        }
+        _ => format!("not found: {path}\n").into_response(),
+    };
+    (StatusCode::NOT_FOUND, [(VARY, "accept")], body).into_response()
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,135 @@
+//! OpenTelemetry span parenting for requests and host commands (requires the `otel` feature).
+//!
+//! `serve` wraps every request in a `request` span whose parent is the caller's trace context,
+//! and [`ContainerContext::invoke`](crate::ContainerContext::invoke) runs each command in a
+//! `command` span beneath it. The spans only reach a collector once the application installs a
+//! `tracing_opentelemetry` layer with an exporter; see the README.
+
+use axum::extract::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+use tracing::field::Empty;
+use tracing::{Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use containerflare_command::{CommandEvent, CommandObserver};
+
+use crate::context::TraceContext;
+
+/// [`CommandObserver`] that marks the enclosing `command` span as succeeded or failed.
+///
+/// Observers run inline on the task issuing the command, so the current span is the one
+/// [`command_span`] opened around it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OtelObserver;
+
+impl CommandObserver for OtelObserver {
+    fn on_command(&self, event: &CommandEvent<'_>) {
+        let span = Span::current();
+        match event.outcome {
+            Ok(_) => {
+                span.record("otel.status_code", "OK");
+            }
+            Err(err) => {
+                span.record("otel.status_code", "ERROR");
+                span.record("error.kind", err.kind());
+            }
+        }
+    }
+}
+
+/// Middleware running each request inside a `request` span parented to the incoming trace.
+pub(crate) async fn request_span(request: Request, next: Next) -> Response {
+    let (parts, body) = request.into_parts();
+    let span = tracing::info_span!(
+        "request",
+        otel.name = %format_args!("{} {}", parts.method, parts.uri.path()),
+        otel.kind = "server",
+        http.request.method = %parts.method,
+        url.path = %parts.uri.path(),
+        http.response.status_code = Empty,
+    );
+    if let Some(parent) = remote_context(&TraceContext::from_parts(&parts)) {
+        // Fails only when no OpenTelemetry layer is installed, in which case there is nothing
+        // to parent.
+        let _ = span.set_parent(parent);
+    }
+
+    let response = next
+        .run(Request::from_parts(parts, body))
+        .instrument(span.clone())
+        .await;
+    span.record("http.response.status_code", response.status().as_u16());
+    response
+}
+
+/// Opens the span a host command runs in; its parent is the current (request) span.
+pub(crate) fn command_span(command: &str) -> Span {
+    tracing::info_span!(
+        "command",
+        otel.name = %command,
+        otel.kind = "client",
+        command = %command,
+        otel.status_code = Empty,
+        error.kind = Empty,
+    )
+}
+
+/// Converts the request's trace identifiers into a remote OpenTelemetry parent context.
+///
+/// The span id is read as [`TraceContext::span_id_hex64`] reads it.
+fn remote_context(trace: &TraceContext) -> Option<opentelemetry::Context> {
+    let trace_id = TraceId::from_hex(trace.trace_id.as_deref()?).ok()?;
+    let span_id = SpanId::from_hex(&trace.span_id_hex64()?).ok()?;
+    if trace_id == TraceId::INVALID || span_id == SpanId::INVALID {
+        return None;
+    }
+    let flags = if trace.sampled == Some(true) {
+        TraceFlags::SAMPLED
+    } else {
+        TraceFlags::default()
+    };
+    let span_context = SpanContext::new(trace_id, span_id, flags, true, TraceState::default());
+    Some(opentelemetry::Context::new().with_remote_span_context(span_context))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::SpanIdFormat;
+
+    fn trace(trace_id: &str, span_id: &str, sampled: Option<bool>) -> TraceContext {
+        TraceContext {
+            trace_id: Some(trace_id.into()),
+            span_id: Some(span_id.into()),
+            sampled,
+            ..TraceContext::default()
+        }
+    }
+
+    #[test]
+    fn builds_remote_parent_from_cloud_trace_ids() {
+        let context =
+            remote_context(&trace("105445aa7843bc8bf206b12000100000", "1", Some(true))).unwrap();
+        let parent = context.span().span_context().clone();
+        assert_eq!(
+            parent.trace_id(),
+            TraceId::from_hex("105445aa7843bc8bf206b12000100000").unwrap()
+        );
+        assert_eq!(parent.span_id(), SpanId::from(1));
+        assert!(parent.is_remote());
+        assert!(parent.is_sampled());
+
+        assert!(remote_context(&trace("not-hex", "1", None)).is_none());
+        assert!(remote_context(&trace("00000000000000000000000000000000", "1", None)).is_none());
+        assert!(remote_context(&TraceContext::default()).is_none());
+
+        let trace = TraceContext {
+            span_id_format: SpanIdFormat::Hex,
+            ..trace("105445aa7843bc8bf206b12000100000", "0000000000000010", None)
+        };
+        let context = remote_context(&trace).unwrap();
+        assert_eq!(context.span().span_context().span_id(), SpanId::from(16));
+    }
+}
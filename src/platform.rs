@@ -1,4 +1,36 @@
+use std::collections::BTreeMap;
 use std::env;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::Serialize;
+use thiserror::Error;
+
+const PLATFORM_OVERRIDE_ENV: &str = "CONTAINERFLARE_PLATFORM";
+const CLOUDFLARE_WORKER_ENV: &str = "CONTAINERFLARE_WORKER";
+/// Variables only Cloud Run (Knative) injects; these outrank the generic `CF_*` variables.
+const KNATIVE_SIGNALS: &[&str] = &["K_SERVICE", "K_REVISION"];
+
+/// Variables whose presence marks a Cloudflare Containers deployment.
+const CLOUDFLARE_SIGNALS: &[&str] = &[
+    "CONTAINERFLARE_WORKER",
+    "CF_CONTAINER_PORT",
+    "CF_CONTAINER_ADDR",
+    "CF_CMD_ENDPOINT",
+];
+/// Variables Cloud Run sets that plenty of other hosts set too, so they never select it alone.
+const WEAK_CLOUD_RUN_HINTS: &[&str] = &["PORT", "GOOGLE_CLOUD_PROJECT", "GCLOUD_PROJECT"];
+/// Cloud Run rejects HTTP/1 request bodies above 32 MiB.
+const CLOUD_RUN_MAX_BODY_BYTES: usize = 32 * 1024 * 1024;
+/// Cloudflare's smallest plan-level request body limit (100 MB on Free and Pro).
+const CLOUDFLARE_MAX_BODY_BYTES: usize = 100 * 1000 * 1000;
+/// Additional variables captured in [`DetectionReport::env`] for context.
+const INFORMATIONAL_VARS: &[&str] = &[
+    PLATFORM_OVERRIDE_ENV,
+    "K_CONFIGURATION",
+    "GOOGLE_CLOUD_REGION",
+    "REGION",
+];
 
 /// Describes the runtime platform the container is executing inside.
 #[derive(Clone, Debug)]
@@ -17,16 +49,142 @@ impl Default for RuntimePlatform {
 impl RuntimePlatform {
     /// Attempts to infer the current platform from environment variables that Cloudflare or
     /// Google Cloud Run automatically inject.
+    ///
+    /// `CONTAINERFLARE_PLATFORM` (`cloudflare`, `cloud_run`, or `generic`) short-circuits the
+    /// heuristics; an unrecognized value is logged and ignored. Otherwise signals are checked from
+    /// most to least specific:
+    /// 1. `CONTAINERFLARE_WORKER` selects Cloudflare.
+    /// 2. `K_SERVICE`/`K_REVISION` (only injected by Cloud Run) select Cloud Run, even when `CF_*`
+    ///    transport variables were set by hand (e.g. `CF_CMD_ENDPOINT=disabled` while testing).
+    /// 3. Any other `CF_*` variable selects Cloudflare.
+    /// 4. Anything else is [`RuntimePlatform::Generic`]. `PORT` and the Google Cloud project
+    ///    variables are set by most container hosts, so they do not select Cloud Run on their own.
+    ///
+    /// When `CF_*`/`CONTAINERFLARE_WORKER` and `K_SERVICE`/`K_REVISION` are set together, a
+    /// warning names both sets and the platform that won; set `CONTAINERFLARE_PLATFORM` to pick
+    /// one explicitly.
     pub fn detect() -> Self {
-        if let Some(platform) = CloudflarePlatform::from_env() {
-            return Self::Cloudflare(platform);
+        if let Some(platform) = Self::from_override() {
+            return platform;
         }
 
-        if let Some(platform) = CloudRunPlatform::from_env() {
-            return Self::CloudRun(platform);
+        let signals = PlatformSignals::from_env();
+        let platform = if signals.cloudflare.contains(&CLOUDFLARE_WORKER_ENV) {
+            Self::Cloudflare(CloudflarePlatform::load())
+        } else if !signals.knative.is_empty() {
+            Self::CloudRun(CloudRunPlatform::load())
+        } else if !signals.cloudflare.is_empty() {
+            Self::Cloudflare(CloudflarePlatform::load())
+        } else {
+            Self::Generic
+        };
+
+        if let Some(conflict) = signals.conflict() {
+            tracing::warn!(
+                cloudflare_vars = %conflict.cloudflare.join(", "),
+                cloud_run_vars = %conflict.knative.join(", "),
+                selected = platform.name(),
+                "both Cloudflare and Cloud Run variables are set; \
+                 set {PLATFORM_OVERRIDE_ENV} to choose a platform explicitly"
+            );
+        }
+        platform
+    }
+
+    /// Returns the platform forced via `CONTAINERFLARE_PLATFORM`, if it holds a valid value.
+    fn from_override() -> Option<Self> {
+        let value = env::var(PLATFORM_OVERRIDE_ENV).ok()?;
+        match value.parse() {
+            Ok(platform) => Some(platform),
+            Err(err) => {
+                tracing::warn!(
+                    error = %err,
+                    "ignoring {PLATFORM_OVERRIDE_ENV}, falling back to platform detection"
+                );
+                None
+            }
+        }
+    }
+
+    /// Returns the canonical name of the platform (`cloudflare`, `cloud_run`, or `generic`).
+    pub fn name(&self) -> &'static str {
+        match self {
+            RuntimePlatform::Cloudflare(_) => "cloudflare",
+            RuntimePlatform::CloudRun(_) => "cloud_run",
+            RuntimePlatform::Generic => "generic",
         }
+    }
+
+    /// Runs [`RuntimePlatform::detect`] and explains the outcome.
+    ///
+    /// This snapshots the platform-related environment variables, so prefer plain `detect` on hot
+    /// paths and reserve this for diagnostics.
+    pub fn detect_with_report() -> (Self, DetectionReport) {
+        let platform = Self::detect();
+        let env = CLOUDFLARE_SIGNALS
+            .iter()
+            .chain(KNATIVE_SIGNALS)
+            .chain(WEAK_CLOUD_RUN_HINTS)
+            .chain(INFORMATIONAL_VARS)
+            .filter_map(|name| env::var(name).ok().map(|value| (name.to_string(), value)))
+            .collect::<BTreeMap<_, _>>();
 
-        Self::Generic
+        let overridden = env
+            .get(PLATFORM_OVERRIDE_ENV)
+            .is_some_and(|value| value.parse::<RuntimePlatform>().is_ok());
+        let name = platform.name();
+        let signals = match &platform {
+            _ if overridden => &[PLATFORM_OVERRIDE_ENV][..],
+            RuntimePlatform::Cloudflare(_) => CLOUDFLARE_SIGNALS,
+            RuntimePlatform::CloudRun(_) => KNATIVE_SIGNALS,
+            RuntimePlatform::Generic => &[][..],
+        };
+        let matched_vars = signals
+            .iter()
+            .filter(|var| env.contains_key(**var))
+            .map(|var| var.to_string())
+            .collect::<Vec<_>>();
+        let hints = WEAK_CLOUD_RUN_HINTS
+            .iter()
+            .filter(|var| env.contains_key(**var))
+            .copied()
+            .collect::<Vec<_>>();
+        let rule = if !matched_vars.is_empty() {
+            format!("{name} selected because {} is set", matched_vars.join(", "))
+        } else if !hints.is_empty() {
+            format!(
+                "{name} selected because only {} is set; Cloud Run also requires K_SERVICE or \
+                 K_REVISION",
+                hints.join(", ")
+            )
+        } else {
+            "no Cloudflare or Cloud Run variables are set".to_owned()
+        };
+
+        let report = DetectionReport {
+            platform: name,
+            rule,
+            matched_vars,
+            env,
+        };
+        (platform, report)
+    }
+
+    /// Returns the request limits `serve` applies on this platform unless configured otherwise.
+    pub fn recommended_limits(&self) -> PlatformLimits {
+        match self {
+            RuntimePlatform::Cloudflare(_) => PlatformLimits {
+                max_body_bytes: Some(CLOUDFLARE_MAX_BODY_BYTES),
+                // Cloudflare keeps proxying as long as the client stays connected.
+                request_timeout: None,
+            },
+            RuntimePlatform::CloudRun(_) => PlatformLimits {
+                max_body_bytes: Some(CLOUD_RUN_MAX_BODY_BYTES),
+                // The timeout is set per service (up to 3600s), so there is no safe default.
+                request_timeout: None,
+            },
+            RuntimePlatform::Generic => PlatformLimits::default(),
+        }
     }
 
     /// Returns the Cloudflare platform details when active.
@@ -56,6 +214,88 @@ impl RuntimePlatform {
     }
 }
 
+impl FromStr for RuntimePlatform {
+    type Err = PlatformParseError;
+
+    /// Parses a platform name (`cloudflare`, `cloud_run`, or `generic`, case-insensitive; `-` is
+    /// accepted in place of `_`). Platform details are still loaded from their usual environment
+    /// variables.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = s.trim().to_ascii_lowercase().replace('-', "_");
+        match value.as_str() {
+            "cloudflare" => Ok(Self::Cloudflare(CloudflarePlatform::load())),
+            "cloud_run" | "cloudrun" => Ok(Self::CloudRun(CloudRunPlatform::load())),
+            "generic" => Ok(Self::Generic),
+            _ => Err(PlatformParseError::UnknownPlatform(s.to_owned())),
+        }
+    }
+}
+
+/// Errors encountered while parsing a [`RuntimePlatform`] name.
+#[derive(Debug, Error, Clone)]
+pub enum PlatformParseError {
+    #[error("unknown platform: {0}")]
+    UnknownPlatform(String),
+}
+
+/// Strong platform signals present in the environment, gathered up front so detection can tell
+/// when more than one platform claims the container.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct PlatformSignals {
+    /// Set entries of [`CLOUDFLARE_SIGNALS`].
+    cloudflare: Vec<&'static str>,
+    /// Set entries of [`KNATIVE_SIGNALS`].
+    knative: Vec<&'static str>,
+}
+
+impl PlatformSignals {
+    fn from_env() -> Self {
+        let set = |names: &[&'static str]| {
+            names
+                .iter()
+                .copied()
+                .filter(|name| env::var(name).is_ok())
+                .collect()
+        };
+        Self {
+            cloudflare: set(CLOUDFLARE_SIGNALS),
+            knative: set(KNATIVE_SIGNALS),
+        }
+    }
+
+    /// Returns the signals when both platforms are claimed, i.e. detection had to break a tie.
+    ///
+    /// Weak Cloud Run hints such as `PORT` do not count; they are commonly set everywhere.
+    fn conflict(&self) -> Option<&Self> {
+        (!self.cloudflare.is_empty() && !self.knative.is_empty()).then_some(self)
+    }
+}
+
+/// Explanation of how [`RuntimePlatform::detect_with_report`] picked a platform.
+#[derive(Clone, Debug, Serialize)]
+pub struct DetectionReport {
+    /// Detected platform (`cloudflare`, `cloud_run`, or `generic`).
+    pub platform: &'static str,
+    /// Human-readable description of the rule that fired.
+    pub rule: String,
+    /// Variables that triggered the selected platform.
+    pub matched_vars: Vec<String>,
+    /// Every platform-related variable that was set, with its value.
+    pub env: BTreeMap<String, String>,
+}
+
+/// Request limits a platform enforces in front of the container; `None` means no known limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PlatformLimits {
+    /// Largest request body the platform forwards.
+    pub max_body_bytes: Option<usize>,
+    /// How long a handler may run before the platform gives up on the request, less a margin.
+    ///
+    /// `None` on Cloud Run too: its timeout is configured per service, so set
+    /// [`RuntimeConfig::request_timeout`](crate::RuntimeConfig::request_timeout) to match yours.
+    pub request_timeout: Option<Duration>,
+}
+
 /// Cloudflare-specific platform configuration gleaned from environment variables.
 #[derive(Clone, Debug, Default)]
 pub struct CloudflarePlatform {
@@ -63,17 +303,9 @@ pub struct CloudflarePlatform {
 }
 
 impl CloudflarePlatform {
-    fn from_env() -> Option<Self> {
-        let worker_name = env::var("CONTAINERFLARE_WORKER").ok();
-        let has_cf_env = worker_name.is_some()
-            || env::var("CF_CONTAINER_PORT").is_ok()
-            || env::var("CF_CONTAINER_ADDR").is_ok()
-            || env::var("CF_CMD_ENDPOINT").is_ok();
-
-        if has_cf_env {
-            Some(Self { worker_name })
-        } else {
-            None
+    fn load() -> Self {
+        Self {
+            worker_name: env::var(CLOUDFLARE_WORKER_ENV).ok(),
         }
     }
 }
@@ -89,32 +321,227 @@ pub struct CloudRunPlatform {
 }
 
 impl CloudRunPlatform {
-    fn from_env() -> Option<Self> {
-        let service = env::var("K_SERVICE").ok();
-        let revision = env::var("K_REVISION").ok();
-        let configuration = env::var("K_CONFIGURATION").ok();
-        let project_id = env::var("GOOGLE_CLOUD_PROJECT")
-            .ok()
-            .or_else(|| env::var("GCLOUD_PROJECT").ok());
-        let region = env::var("GOOGLE_CLOUD_REGION")
-            .ok()
-            .or_else(|| env::var("REGION").ok());
-
-        let has_run_env = service.is_some()
-            || revision.is_some()
-            || env::var("PORT").is_ok()
-            || project_id.is_some();
-
-        if has_run_env {
-            Some(Self {
-                service,
-                revision,
-                configuration,
-                project_id,
-                region,
+    fn load() -> Self {
+        Self {
+            service: env::var("K_SERVICE").ok(),
+            revision: env::var("K_REVISION").ok(),
+            configuration: env::var("K_CONFIGURATION").ok(),
+            project_id: env::var("GOOGLE_CLOUD_PROJECT")
+                .ok()
+                .or_else(|| env::var("GCLOUD_PROJECT").ok()),
+            region: env::var("GOOGLE_CLOUD_REGION")
+                .ok()
+                .or_else(|| env::var("REGION").ok()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::env_lock;
+
+    #[test]
+    fn parses_platform_names() {
+        assert!(
+            "cloudflare"
+                .parse::<RuntimePlatform>()
+                .unwrap()
+                .is_cloudflare()
+        );
+        assert!(
+            "Cloud-Run"
+                .parse::<RuntimePlatform>()
+                .unwrap()
+                .is_cloud_run()
+        );
+        assert!(
+            "cloud_run"
+                .parse::<RuntimePlatform>()
+                .unwrap()
+                .is_cloud_run()
+        );
+        assert!(matches!(
+            "generic".parse::<RuntimePlatform>(),
+            Ok(RuntimePlatform::Generic)
+        ));
+        assert!("lambda".parse::<RuntimePlatform>().is_err());
+    }
+
+    #[test]
+    fn override_short_circuits_detection() {
+        let _guard = env_lock().lock().unwrap();
+        unsafe {
+            std::env::set_var("CF_CMD_ENDPOINT", "tcp://127.0.0.1:9");
+            std::env::set_var("K_REVISION", "rev-1");
+        }
+
+        for (value, expected) in [
+            ("cloudflare", "cloudflare"),
+            ("cloud_run", "cloud_run"),
+            ("generic", "generic"),
+        ] {
+            unsafe {
+                std::env::set_var("CONTAINERFLARE_PLATFORM", value);
+            }
+            assert_eq!(RuntimePlatform::detect().name(), expected);
+        }
+
+        unsafe {
+            std::env::set_var("CONTAINERFLARE_PLATFORM", "cloud_run");
+        }
+        let (platform, report) = RuntimePlatform::detect_with_report();
+        assert_eq!(
+            platform.as_cloud_run().unwrap().revision.as_deref(),
+            Some("rev-1")
+        );
+        assert_eq!(
+            report.matched_vars,
+            vec!["CONTAINERFLARE_PLATFORM".to_owned()]
+        );
+
+        unsafe {
+            std::env::set_var("CONTAINERFLARE_PLATFORM", "bogus");
+        }
+        assert!(RuntimePlatform::detect().is_cloud_run());
+
+        unsafe {
+            std::env::remove_var("CONTAINERFLARE_PLATFORM");
+            std::env::remove_var("CF_CMD_ENDPOINT");
+            std::env::remove_var("K_REVISION");
+        }
+    }
+
+    #[test]
+    fn k_service_outranks_stray_cf_variables() {
+        let _guard = env_lock().lock().unwrap();
+        unsafe {
+            std::env::set_var("K_SERVICE", "svc");
+            std::env::set_var("CF_CMD_ENDPOINT", "disabled");
+        }
+
+        let platform = RuntimePlatform::detect();
+        assert!(platform.is_cloud_run());
+        assert_eq!(
+            platform.as_cloud_run().unwrap().service.as_deref(),
+            Some("svc")
+        );
+        let signals = PlatformSignals::from_env();
+        assert_eq!(
+            signals.conflict(),
+            Some(&PlatformSignals {
+                cloudflare: vec!["CF_CMD_ENDPOINT"],
+                knative: vec!["K_SERVICE"],
             })
-        } else {
-            None
+        );
+
+        unsafe {
+            std::env::set_var("CONTAINERFLARE_WORKER", "worker");
+        }
+        let platform = RuntimePlatform::detect();
+        assert_eq!(
+            platform.as_cloudflare().unwrap().worker_name.as_deref(),
+            Some("worker")
+        );
+
+        unsafe {
+            std::env::remove_var("K_SERVICE");
+            std::env::remove_var("CF_CMD_ENDPOINT");
+            std::env::remove_var("CONTAINERFLARE_WORKER");
+        }
+    }
+
+    #[test]
+    fn single_platform_signals_do_not_conflict() {
+        let _guard = env_lock().lock().unwrap();
+        unsafe {
+            std::env::set_var("CF_CMD_ENDPOINT", "tcp://127.0.0.1:9");
+            std::env::set_var("PORT", "8080");
+            std::env::set_var("GOOGLE_CLOUD_PROJECT", "proj");
+        }
+        let signals = PlatformSignals::from_env();
+        assert_eq!(signals.cloudflare, vec!["CF_CMD_ENDPOINT"]);
+        assert_eq!(signals.conflict(), None);
+        assert!(RuntimePlatform::detect().is_cloudflare());
+
+        unsafe {
+            std::env::remove_var("CF_CMD_ENDPOINT");
+            std::env::set_var("K_REVISION", "rev-1");
+        }
+        assert_eq!(PlatformSignals::from_env().conflict(), None);
+        assert!(RuntimePlatform::detect().is_cloud_run());
+
+        unsafe {
+            std::env::remove_var("PORT");
+            std::env::remove_var("GOOGLE_CLOUD_PROJECT");
+            std::env::remove_var("K_REVISION");
+        }
+    }
+
+    #[test]
+    fn report_explains_detection() {
+        let _guard = env_lock().lock().unwrap();
+        unsafe {
+            std::env::set_var("CF_CMD_ENDPOINT", "tcp://127.0.0.1:9");
+            std::env::set_var("K_CONFIGURATION", "cfg");
+        }
+
+        let (platform, report) = RuntimePlatform::detect_with_report();
+        assert!(platform.is_cloudflare());
+        assert_eq!(report.platform, "cloudflare");
+        assert_eq!(report.matched_vars, vec!["CF_CMD_ENDPOINT".to_owned()]);
+        assert!(report.rule.contains("CF_CMD_ENDPOINT"));
+        assert_eq!(
+            report.env.get("K_CONFIGURATION").map(String::as_str),
+            Some("cfg")
+        );
+
+        unsafe {
+            std::env::remove_var("CF_CMD_ENDPOINT");
+            std::env::remove_var("K_CONFIGURATION");
+        }
+
+        let (platform, report) = RuntimePlatform::detect_with_report();
+        assert!(matches!(platform, RuntimePlatform::Generic));
+        assert_eq!(report.platform, "generic");
+        assert!(report.matched_vars.is_empty());
+    }
+
+    #[test]
+    fn port_alone_falls_through_to_generic() {
+        let _guard = env_lock().lock().unwrap();
+        unsafe {
+            std::env::set_var("PORT", "8080");
+            std::env::set_var("GOOGLE_CLOUD_PROJECT", "proj");
+        }
+
+        let (platform, report) = RuntimePlatform::detect_with_report();
+        assert!(matches!(platform, RuntimePlatform::Generic));
+        assert!(report.matched_vars.is_empty());
+        assert!(report.rule.contains("K_SERVICE"), "{}", report.rule);
+        assert_eq!(report.env.get("PORT").map(String::as_str), Some("8080"));
+
+        unsafe {
+            std::env::remove_var("GOOGLE_CLOUD_PROJECT");
+        }
+        assert!(matches!(
+            RuntimePlatform::detect(),
+            RuntimePlatform::Generic
+        ));
+
+        unsafe {
+            std::env::set_var("K_SERVICE", "svc");
+        }
+        let (platform, report) = RuntimePlatform::detect_with_report();
+        assert_eq!(
+            platform.as_cloud_run().unwrap().service.as_deref(),
+            Some("svc")
+        );
+        assert_eq!(report.matched_vars, vec!["K_SERVICE".to_owned()]);
+
+        unsafe {
+            std::env::remove_var("PORT");
+            std::env::remove_var("K_SERVICE");
         }
     }
 }
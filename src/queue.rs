@@ -0,0 +1,104 @@
+use serde::Serialize;
+use serde_json::Value;
+
+use containerflare_command::{CommandClient, CommandError, CommandRequest};
+
+const QUEUE_SEND: &str = "queue_send";
+const QUEUE_SEND_BATCH: &str = "queue_send_batch";
+
+/// Publishes messages to Cloudflare Queues through the host command channel.
+///
+/// # Wire format
+/// - `queue_send`: payload `{"queue": "...", "body": <json>}`.
+/// - `queue_send_batch`: payload `{"queue": "...", "messages": [{"body": <json>}, ...]}`.
+///
+/// The host answers with `ok: true` once the message(s) are accepted. A rejection is reported as
+/// `ok: false` with a `diagnostic`, which surfaces as [`CommandError::CommandFailure`].
+#[derive(Clone, Debug)]
+pub struct QueueClient {
+    client: CommandClient,
+}
+
+impl QueueClient {
+    /// Wraps an existing command client.
+    pub fn new(client: CommandClient) -> Self {
+        Self { client }
+    }
+
+    /// Enqueues a single message on `queue`.
+    pub async fn send(&self, queue: &str, body: impl Serialize) -> Result<(), CommandError> {
+        let payload = serde_json::to_value(QueueSend {
+            queue,
+            body: serde_json::to_value(body)?,
+        })?;
+        self.client
+            .send(CommandRequest::new(QUEUE_SEND, payload))
+            .await?;
+        Ok(())
+    }
+
+    /// Enqueues every message in `bodies` on `queue` with a single command.
+    pub async fn send_batch<T>(&self, queue: &str, bodies: Vec<T>) -> Result<(), CommandError>
+    where
+        T: Serialize,
+    {
+        let messages = bodies
+            .into_iter()
+            .map(|body| serde_json::to_value(body).map(|body| QueueMessage { body }))
+            .collect::<Result<Vec<_>, _>>()?;
+        let payload = serde_json::to_value(QueueSendBatch { queue, messages })?;
+        self.client
+            .send(CommandRequest::new(QUEUE_SEND_BATCH, payload))
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct QueueSend<'a> {
+    queue: &'a str,
+    body: Value,
+}
+
+#[derive(Serialize)]
+struct QueueMessage {
+    body: Value,
+}
+
+#[derive(Serialize)]
+struct QueueSendBatch<'a> {
+    queue: &'a str,
+    messages: Vec<QueueMessage>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn encodes_batch_payload() {
+        let payload = serde_json::to_value(QueueSendBatch {
+            queue: "jobs",
+            messages: vec![
+                QueueMessage { body: json!(1) },
+                QueueMessage {
+                    body: json!({"id": 2}),
+                },
+            ],
+        })
+        .unwrap();
+
+        assert_eq!(
+            payload,
+            json!({"queue": "jobs", "messages": [{"body": 1}, {"body": {"id": 2}}]})
+        );
+    }
+
+    #[tokio::test]
+    async fn surfaces_unavailable_channel() {
+        let queue = QueueClient::new(CommandClient::unavailable("no host"));
+        let err = queue.send("jobs", json!({"id": 1})).await.unwrap_err();
+        assert!(matches!(err, CommandError::Unavailable(reason) if reason == "no host"));
+    }
+}
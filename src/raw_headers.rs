@@ -0,0 +1,73 @@
+use std::sync::Arc;
+
+use axum::extract::{Request, State};
+use axum::http::HeaderMap;
+use axum::http::header::HeaderName;
+use axum::middleware::Next;
+use axum::response::Response;
+
+/// Headers left out of the snapshot unless the denylist is replaced.
+pub(crate) const DEFAULT_CAPTURE_DENYLIST: &[&str] =
+    &["authorization", "proxy-authorization", "cookie"];
+
+/// Copy of the headers a request arrived with, minus the denylisted ones.
+#[derive(Clone, Debug)]
+pub(crate) struct CapturedHeaders(pub(crate) Arc<HeaderMap>);
+
+/// Middleware that snapshots the incoming headers for
+/// [`ContainerContext::raw_headers`](crate::ContainerContext::raw_headers).
+pub(crate) async fn capture_headers(
+    State(denylist): State<Arc<[HeaderName]>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let mut headers = request.headers().clone();
+    for name in denylist.iter() {
+        headers.remove(name);
+    }
+    request
+        .extensions_mut()
+        .insert(CapturedHeaders(Arc::new(headers)));
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::extract::Extension;
+    use axum::middleware;
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn strips_denylisted_headers() {
+        let denylist: Arc<[HeaderName]> = DEFAULT_CAPTURE_DENYLIST
+            .iter()
+            .map(|name| HeaderName::from_static(name))
+            .collect();
+        let app = Router::new()
+            .route(
+                "/",
+                get(
+                    |Extension(CapturedHeaders(headers)): Extension<CapturedHeaders>| async move {
+                        assert_eq!(headers["x-debug"], "1");
+                        assert!(!headers.contains_key("authorization"));
+                        assert!(!headers.contains_key("cookie"));
+                    },
+                ),
+            )
+            .layer(middleware::from_fn_with_state(denylist, capture_headers));
+
+        let request = Request::builder()
+            .uri("/")
+            .header("x-debug", "1")
+            .header("authorization", "Bearer secret")
+            .header("cookie", "session=abc")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+    }
+}
@@ -0,0 +1,96 @@
+use axum::extract::{Request, State};
+use axum::http::HeaderValue;
+use axum::http::header::HeaderName;
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::context::RequestMetadata;
+use crate::platform::RuntimePlatform;
+
+/// Response header carrying the request identifier.
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Identifier echoed back in the `x-request-id` response header.
+///
+/// This is the request's `cf-ray` (or shim/trace-derived) id when one is present, otherwise a
+/// freshly generated UUID. `serve` stores it in the request extensions, so handlers can read it
+/// with `Extension<RequestId>`; [`ContainerContext`](crate::ContainerContext) also falls back to
+/// it for [`RequestMetadata::request_id`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// Returns the identifier as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Middleware that resolves (or generates) the request id, exposes it to handlers, and stamps it
+/// on the response unless the handler already set `x-request-id` itself.
+pub(crate) async fn echo_request_id(
+    State(platform): State<RuntimePlatform>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = request.into_parts();
+    let id = RequestMetadata::from_parts(&parts, &platform)
+        .request_id
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    parts.extensions.insert(RequestId(id.clone()));
+
+    let mut response = next.run(Request::from_parts(parts, body)).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response
+            .headers_mut()
+            .entry(REQUEST_ID_HEADER)
+            .or_insert(value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::extract::Extension;
+    use axum::middleware;
+    use axum::routing::get;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    fn router() -> Router {
+        Router::new()
+            .route(
+                "/",
+                get(|Extension(id): Extension<RequestId>| async move { id.0 }),
+            )
+            .layer(middleware::from_fn_with_state(
+                RuntimePlatform::Generic,
+                echo_request_id,
+            ))
+    }
+
+    #[tokio::test]
+    async fn echoes_cf_ray() {
+        let request = Request::builder()
+            .uri("/")
+            .header("cf-ray", "ray123")
+            .body(Body::empty())
+            .unwrap();
+        let response = router().oneshot(request).await.unwrap();
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "ray123");
+    }
+
+    #[tokio::test]
+    async fn generates_missing_ids_for_handlers() {
+        let request = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let response = router().oneshot(request).await.unwrap();
+        let header = response.headers()[REQUEST_ID_HEADER].clone();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        assert!(uuid::Uuid::parse_str(header.to_str().unwrap()).is_ok());
+        assert_eq!(&body[..], header.as_bytes());
+    }
+}
@@ -0,0 +1,265 @@
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+use async_trait::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::StatusCode;
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Response};
+use thiserror::Error;
+
+use crate::context::{ContainerContext, ContainerContextRejection, RequestMetadata, TraceContext};
+
+/// A [`RequestMetadata`] field that [`Required`] can demand.
+///
+/// Implement it on a marker type to require fields beyond the ones provided here.
+pub trait MetadataRequirement: Send + Sync + 'static {
+    /// What the extractor yields when the field is present.
+    type Value: Send;
+    /// Field name used in the rejection message.
+    const NAME: &'static str;
+
+    /// Reads the field, returning `None` when the request does not carry it.
+    fn extract(metadata: &RequestMetadata) -> Option<Self::Value>;
+}
+
+/// Extractor that yields a [`RequestMetadata`] field, rejecting the request with `400 Bad Request`
+/// when the field is absent.
+///
+/// The field is named by a marker type such as [`ClientIp`] or [`Trace`]; the value is reached
+/// through `Deref` or [`Required::into_inner`].
+///
+/// ```
+/// use containerflare::Required;
+/// use containerflare::required::ClientIp;
+///
+/// async fn handler(ip: Required<ClientIp>) -> String {
+///     format!("hello {}", *ip)
+/// }
+/// ```
+pub struct Required<F: MetadataRequirement> {
+    value: F::Value,
+    field: PhantomData<fn() -> F>,
+}
+
+impl<F: MetadataRequirement> Required<F> {
+    /// Returns the extracted value.
+    pub fn into_inner(self) -> F::Value {
+        self.value
+    }
+}
+
+impl<F: MetadataRequirement> Deref for Required<F> {
+    type Target = F::Value;
+
+    fn deref(&self) -> &F::Value {
+        &self.value
+    }
+}
+
+impl<F> std::fmt::Debug for Required<F>
+where
+    F: MetadataRequirement,
+    F::Value: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Required").field(&self.value).finish()
+    }
+}
+
+/// Errors emitted by the [`Required`] extractor.
+#[derive(Debug, Error)]
+pub enum RequiredRejection {
+    /// The [`ContainerContext`] itself could not be extracted.
+    #[error(transparent)]
+    Context(#[from] ContainerContextRejection),
+    /// The request does not carry the named field.
+    #[error("request is missing required metadata: {0}")]
+    Missing(&'static str),
+}
+
+impl RequiredRejection {
+    /// Returns the status this rejection responds with: `400` for a missing field, otherwise that
+    /// of the [`ContainerContextRejection`].
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            RequiredRejection::Context(rejection) => rejection.status_code(),
+            RequiredRejection::Missing(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// Returns a short, stable label for the rejection, suitable for error bodies and metrics.
+    pub fn code(&self) -> &'static str {
+        match self {
+            RequiredRejection::Context(rejection) => rejection.code(),
+            RequiredRejection::Missing(_) => "missing_metadata",
+        }
+    }
+}
+
+impl IntoResponse for RequiredRejection {
+    fn into_response(self) -> Response {
+        (self.status_code(), self.to_string()).into_response()
+    }
+}
+
+#[async_trait]
+impl<S, F> FromRequestParts<S> for Required<F>
+where
+    S: Send + Sync,
+    F: MetadataRequirement,
+{
+    type Rejection = RequiredRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let context = ContainerContext::from_request_parts(parts, state).await?;
+        let value = F::extract(context.metadata()).ok_or(RequiredRejection::Missing(F::NAME))?;
+        Ok(Self {
+            value,
+            field: PhantomData,
+        })
+    }
+}
+
+/// [`RequestMetadata::client_ip`].
+#[derive(Debug)]
+pub struct ClientIp;
+
+impl MetadataRequirement for ClientIp {
+    type Value = String;
+    const NAME: &'static str = "client_ip";
+
+    fn extract(metadata: &RequestMetadata) -> Option<String> {
+        metadata.client_ip.clone()
+    }
+}
+
+/// [`RequestMetadata::country`].
+#[derive(Debug)]
+pub struct Country;
+
+impl MetadataRequirement for Country {
+    type Value = String;
+    const NAME: &'static str = "country";
+
+    fn extract(metadata: &RequestMetadata) -> Option<String> {
+        metadata.country.clone()
+    }
+}
+
+/// [`RequestMetadata::colo`].
+#[derive(Debug)]
+pub struct Colo;
+
+impl MetadataRequirement for Colo {
+    type Value = String;
+    const NAME: &'static str = "colo";
+
+    fn extract(metadata: &RequestMetadata) -> Option<String> {
+        metadata.colo.clone()
+    }
+}
+
+/// [`RequestMetadata::host`].
+#[derive(Debug)]
+pub struct Host;
+
+impl MetadataRequirement for Host {
+    type Value = String;
+    const NAME: &'static str = "host";
+
+    fn extract(metadata: &RequestMetadata) -> Option<String> {
+        metadata.host.clone()
+    }
+}
+
+/// [`RequestMetadata::user_agent`].
+#[derive(Debug)]
+pub struct UserAgent;
+
+impl MetadataRequirement for UserAgent {
+    type Value = String;
+    const NAME: &'static str = "user_agent";
+
+    fn extract(metadata: &RequestMetadata) -> Option<String> {
+        metadata.user_agent.clone()
+    }
+}
+
+/// [`RequestMetadata::trace_context`].
+#[derive(Debug)]
+pub struct Trace;
+
+impl MetadataRequirement for Trace {
+    type Value = TraceContext;
+    const NAME: &'static str = "trace_context";
+
+    fn extract(metadata: &RequestMetadata) -> Option<TraceContext> {
+        metadata.trace_context.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+    use containerflare_command::CommandClient;
+
+    use crate::platform::{CloudRunPlatform, RuntimePlatform};
+
+    fn parts(request: axum::http::request::Builder, platform: RuntimePlatform) -> Parts {
+        request
+            .extension(CommandClient::unavailable("tests"))
+            .extension(platform)
+            .body(())
+            .unwrap()
+            .into_parts()
+            .0
+    }
+
+    #[tokio::test]
+    async fn extracts_present_fields() {
+        let mut parts = parts(
+            Request::builder()
+                .uri("/")
+                .header("cf-connecting-ip", "203.0.113.9")
+                .header(
+                    "x-cloud-trace-context",
+                    "105445aa7843bc8bf206b12000100000/1;o=1",
+                ),
+            RuntimePlatform::CloudRun(CloudRunPlatform::default()),
+        );
+        let ip = Required::<ClientIp>::from_request_parts(&mut parts, &())
+            .await
+            .unwrap();
+        assert_eq!(*ip, "203.0.113.9");
+        let trace = Required::<Trace>::from_request_parts(&mut parts, &())
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            trace.trace_id.as_deref(),
+            Some("105445aa7843bc8bf206b12000100000")
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_absent_fields_with_400() {
+        let mut parts = parts(Request::builder().uri("/"), RuntimePlatform::Generic);
+        let rejection = Required::<ClientIp>::from_request_parts(&mut parts, &())
+            .await
+            .unwrap_err();
+        assert!(matches!(rejection, RequiredRejection::Missing("client_ip")));
+        assert_eq!(rejection.code(), "missing_metadata");
+        let response = rejection.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let rejection = Required::<Trace>::from_request_parts(&mut parts, &())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            rejection.to_string(),
+            "request is missing required metadata: trace_context"
+        );
+    }
+}
@@ -1,10 +1,43 @@
-use axum::Router;
-use axum::extract::Extension;
-use tokio::net::TcpListener;
+use std::convert::Infallible;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
 
-use crate::config::RuntimeConfig;
-use crate::error::Result;
-use containerflare_command::CommandClient;
+use axum::extract::{DefaultBodyLimit, Extension, Request, State};
+use axum::http::header::ALLOW;
+use axum::http::request::Parts;
+use axum::http::{HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use hyper::server::conn::{http1, http2};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::service::TowerToHyperService;
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tower_service::Service;
+
+use crate::access_log::{AccessLog, log_access};
+use crate::accounting::count_bytes;
+use crate::config::{ConfigError, HttpVersion, RuntimeConfig};
+use crate::context::{ContextOptions, NamedCommandClients, RequestMetadata, install_request_store};
+use crate::early_hints::HintedStream;
+use crate::error::{ContainerflareError, Result};
+use crate::events::{EventSink, RuntimeEvent};
+use crate::not_found::NotFoundHandler;
+use crate::platform::RuntimePlatform;
+use crate::shutdown::{ShutdownGuard, ShutdownState, reject_while_shutting_down};
+use containerflare_command::{CommandClient, CommandError, CommandEvent, CommandObserver};
+
+/// Back-off applied when `accept` fails (e.g. the process ran out of file descriptors).
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+const DEBUG_PLATFORM_PATH: &str = "/debug/platform";
 
 /// High-level runtime that wires an Axum router into Cloudflare Containers (and adapts to Cloud Run when detected).
 pub struct ContainerflareRuntime {
@@ -21,60 +54,1764 @@ impl ContainerflareRuntime {
     pub async fn serve(self, router: Router) -> Result<()> {
         serve(router, self.config).await
     }
+
+    /// Consumes the runtime and serves the router on a listener bound by the caller.
+    ///
+    /// See [`serve_with_listener`].
+    pub async fn serve_with_listener(self, router: Router, listener: TcpListener) -> Result<()> {
+        serve_with_listener(router, self.config, listener).await
+    }
+
+    /// Consumes the runtime and serves `initial_router` until a new router is published on
+    /// `reload`.
+    ///
+    /// See [`serve_reloadable`].
+    pub async fn serve_reloadable(
+        self,
+        initial_router: Router,
+        reload: watch::Receiver<Router>,
+    ) -> Result<()> {
+        serve_reloadable(initial_router, self.config, reload).await
+    }
 }
 
 /// Serves the router with the provided configuration.
+///
+/// Returns once a shutdown signal (Ctrl+C or `SIGTERM` unless
+/// [`RuntimeConfig::shutdown_signals`] says otherwise) has been received, in-flight connections
+/// have drained, and the command channel has been closed.
 pub async fn serve(router: Router, config: RuntimeConfig) -> Result<()> {
+    #[cfg(unix)]
+    let signal = shutdown_signal(&config.shutdown_signals)?;
+    #[cfg(not(unix))]
+    let signal = shutdown_signal();
+    serve_until(router, config, None, signal).await
+}
+
+/// [`serve`] on a listener that is already bound, e.g. one handed over by systemd socket
+/// activation or bound to `127.0.0.1:0` by a test.
+///
+/// [`RuntimeConfig::bind_addr`], [`RuntimeConfig::additional_bind_addrs`], and
+/// [`RuntimeConfig::listen_backlog`] are ignored; everything else (command channel, middleware,
+/// graceful shutdown) behaves as in [`serve`]. A `std::net::TcpListener` can be converted with
+/// [`TcpListener::from_std`] after calling `set_nonblocking(true)` on it.
+pub async fn serve_with_listener(
+    router: Router,
+    config: RuntimeConfig,
+    listener: TcpListener,
+) -> Result<()> {
+    #[cfg(unix)]
+    let signal = shutdown_signal(&config.shutdown_signals)?;
+    #[cfg(not(unix))]
+    let signal = shutdown_signal();
+    serve_until(router, config, Some(listener), signal).await
+}
+
+/// [`serve`] with a router that can be swapped without restarting, e.g. by a dev server that
+/// rebuilds its routes when files change.
+///
+/// Requests use `initial_router` until a router is sent on `reload`, and the latest one after
+/// that. Each request keeps the router it started on, so in-flight requests finish on the old
+/// routes while new ones pick up the replacement. The runtime middleware, command clients, and
+/// extensions wrap the swappable router and persist across reloads.
+pub async fn serve_reloadable(
+    initial_router: Router,
+    mut config: RuntimeConfig,
+    reload: watch::Receiver<Router>,
+) -> Result<()> {
+    // The outer router's fallback is the reload dispatcher, so 404s are answered per router.
+    let not_found = config.not_found_handler.take();
+    serve(reloadable(initial_router, reload, not_found), config).await
+}
+
+/// [`serve`] with a caller-supplied shutdown signal, binding the configured addresses unless
+/// `listener` is given.
+async fn serve_until<S>(
+    router: Router,
+    config: RuntimeConfig,
+    listener: Option<TcpListener>,
+    shutdown: S,
+) -> Result<()>
+where
+    S: Future<Output = ()>,
+{
+    let summary = config.summary();
+    tracing::info!(
+        platform = summary.platform,
+        command_endpoint = summary.command_endpoint,
+        command_disabled_reason = summary.command_disabled_reason.as_deref(),
+        http_version = summary.http_version,
+        config = %serde_json::to_string(&summary).unwrap_or_default(),
+        "containerflare starting"
+    );
     let RuntimeConfig {
         bind_addr,
+        additional_bind_addrs,
         platform,
         command_endpoint,
         command_disabled_reason,
+        command_client,
+        named_command_clients,
+        command_timeout,
+        require_command_channel,
+        http_version,
+        shutdown_grace_period,
+        health_check_paths,
+        propagate_trace_context,
+        tcp_nodelay,
+        listen_backlog,
+        debug_platform_route,
+        #[cfg(feature = "metrics")]
+        metrics_endpoint,
+        #[cfg(feature = "log-sink")]
+        stream_logs,
+        deadline_header,
+        max_metadata_header_len,
+        max_body_bytes,
+        unlimited_body_size,
+        request_timeout,
+        allowed_methods,
+        reject_malformed_paths,
+        allowed_hosts,
+        access_log,
+        echo_request_id,
+        capture_headers,
+        capture_headers_denylist,
+        #[cfg(feature = "compression")]
+        compression,
+        events,
+        not_found_handler,
+        #[cfg(unix)]
+        shutdown_signals,
     } = config;
+    // `serve` installed these handlers before handing us the config.
+    #[cfg(unix)]
+    let _ = shutdown_signals;
+    let events = EventSink::new(events);
+    let deadline_header = deadline_header
+        .map(|name| {
+            HeaderName::try_from(name.as_str()).map_err(|err| {
+                ConfigError::Validation(format!("invalid deadline header {name:?}: {err}"))
+            })
+        })
+        .transpose()?;
+
+    let listeners = match listener {
+        Some(listener) => vec![listener],
+        None => std::iter::once(bind_addr)
+            .chain(additional_bind_addrs)
+            .map(|addr| {
+                bind_listener(addr, listen_backlog)
+                    .map_err(|source| ContainerflareError::Bind { addr, source })
+            })
+            .collect::<Result<Vec<_>>>()?,
+    };
+    for listener in &listeners {
+        let local_addr = listener.local_addr()?;
+        tracing::info!(
+            addr = %local_addr,
+            platform = ?platform,
+            http_version = ?http_version,
+            "containerflare listening"
+        );
+        events.emit(RuntimeEvent::Bound { addr: local_addr });
+    }
+
+    let command_client = match (command_client, command_endpoint) {
+        (Some(client), _) => {
+            events.emit(RuntimeEvent::CommandChannelConnected {
+                endpoint: client.endpoint().clone(),
+            });
+            client
+        }
+        (None, Some(endpoint)) => {
+            let client = match command_timeout {
+                Some(timeout) => {
+                    CommandClient::connect_with_timeout(endpoint.clone(), timeout).await?
+                }
+                None => CommandClient::connect(endpoint.clone()).await?,
+            };
+            match client.unavailable_reason() {
+                Some(reason) => {
+                    tracing::warn!(reason, "command channel unavailable");
+                    events.emit(RuntimeEvent::CommandChannelDisabled {
+                        reason: reason.to_owned(),
+                    });
+                }
+                None => events.emit(RuntimeEvent::CommandChannelConnected { endpoint }),
+            }
+            client
+        }
+        (None, None) => {
+            let reason =
+                command_disabled_reason.unwrap_or_else(|| "command channel disabled".to_owned());
+            events.emit(RuntimeEvent::CommandChannelDisabled {
+                reason: reason.clone(),
+            });
+            CommandClient::unavailable(reason)
+        }
+    };
+    if require_command_channel {
+        if let Some(reason) = command_client.unavailable_reason() {
+            return Err(CommandError::Unavailable(reason.to_owned()).into());
+        }
+        command_client.probe(command_client.timeout()).await?;
+    }
+    #[cfg(feature = "log-sink")]
+    if stream_logs && command_client.unavailable_reason().is_none() {
+        crate::log_sink::install(command_client.clone());
+    }
+
+    let router = match not_found_handler {
+        Some(handler) => with_not_found(router, handler),
+        None => router,
+    };
+
+    let router = if debug_platform_route {
+        let (_, report) = RuntimePlatform::detect_with_report();
+        router.route(
+            DEBUG_PLATFORM_PATH,
+            get(move || std::future::ready(Json(report))),
+        )
+    } else {
+        router
+    };
+
+    #[allow(unused_mut)]
+    let mut observers: Vec<Arc<dyn CommandObserver>> = Vec::new();
+
+    #[cfg(feature = "metrics")]
+    let router = match metrics_endpoint {
+        Some(path) => {
+            let handle = crate::metrics::install_recorder()?;
+            observers.push(Arc::new(crate::metrics::MetricsObserver));
+            router
+                .route(&path, get(move || std::future::ready(handle.render())))
+                .layer(middleware::from_fn(crate::metrics::track_http))
+        }
+        None => router,
+    };
+
+    #[cfg(feature = "otel")]
+    observers.push(Arc::new(crate::otel::OtelObserver));
+
+    let observer: Option<Arc<dyn CommandObserver>> = match observers.len() {
+        0 => None,
+        1 => Some(observers.remove(0)),
+        _ => Some(Arc::new(ObserverSet(observers))),
+    };
+    let with_observer = |client: CommandClient| match &observer {
+        Some(observer) => client.with_observer(observer.clone()),
+        None => client,
+    };
+    let command_client = with_observer(command_client);
+    let named_clients = NamedCommandClients(Arc::new(
+        named_command_clients
+            .into_iter()
+            .map(|(name, client)| (name, with_observer(client)))
+            .collect(),
+    ));
+
+    #[cfg(feature = "compression")]
+    let router = if compression {
+        router.layer(compression_layer())
+    } else {
+        router
+    };
+
+    let router = with_body_limit(router, max_body_bytes, unlimited_body_size);
 
-    let listener = TcpListener::bind(bind_addr).await?;
-    tracing::info!(addr = %bind_addr, platform = ?platform, "containerflare listening");
+    let router = match request_timeout {
+        Some(timeout) => router.layer(middleware::from_fn_with_state(
+            timeout,
+            enforce_request_timeout,
+        )),
+        None => router,
+    };
+
+    let allowed_hosts = allowed_hosts
+        .map(|hosts| {
+            hosts
+                .iter()
+                .map(|host| HostPattern::parse(host))
+                .collect::<std::result::Result<Arc<[HostPattern]>, _>>()
+        })
+        .transpose()?;
+    let router = if allowed_methods.is_some() || reject_malformed_paths || allowed_hosts.is_some() {
+        router.layer(middleware::from_fn_with_state(
+            RequestGuard {
+                allowed_methods: allowed_methods.map(Into::into),
+                reject_malformed_paths,
+                allowed_hosts,
+            },
+            guard_request,
+        ))
+    } else {
+        router
+    };
+
+    let shutdown_state = ShutdownState::new();
+    let shutdown_guard = ShutdownGuard {
+        state: shutdown_state.clone(),
+        health_check_paths: health_check_paths.into(),
+    };
+
+    let router = match access_log {
+        Some(format) => router.layer(middleware::from_fn_with_state(
+            AccessLog {
+                format,
+                platform: platform.clone(),
+            },
+            log_access,
+        )),
+        None => router,
+    };
+
+    let router = if echo_request_id {
+        router.layer(middleware::from_fn_with_state(
+            platform.clone(),
+            crate::request_id::echo_request_id,
+        ))
+    } else {
+        router
+    };
 
-    let command_client = match command_endpoint {
-        Some(endpoint) => CommandClient::connect(endpoint).await?,
-        None => CommandClient::unavailable(
-            command_disabled_reason.unwrap_or_else(|| "command channel disabled".to_owned()),
-        ),
+    let router = if capture_headers {
+        let denylist = capture_headers_denylist
+            .iter()
+            .map(|name| {
+                HeaderName::try_from(name.as_str()).map_err(|err| {
+                    ConfigError::Validation(format!("invalid denylisted header {name:?}: {err}"))
+                })
+            })
+            .collect::<std::result::Result<Arc<[HeaderName]>, _>>()?;
+        router.layer(middleware::from_fn_with_state(
+            denylist,
+            crate::raw_headers::capture_headers,
+        ))
+    } else {
+        router
     };
 
+    #[cfg(feature = "otel")]
+    let router = router.layer(middleware::from_fn(crate::otel::request_span));
+
     let router = router
-        .layer(Extension(command_client))
-        .layer(Extension(platform));
-    let service = router.into_make_service();
+        .layer(middleware::from_fn(count_bytes))
+        .layer(middleware::from_fn(install_request_store))
+        .layer(Extension(command_client.clone()))
+        .layer(Extension(named_clients.clone()))
+        .layer(Extension(platform))
+        .layer(Extension(shutdown_state.clone()))
+        .layer(Extension(ContextOptions {
+            propagate_trace_context,
+            deadline_header,
+            max_metadata_header_len,
+        }))
+        .layer(middleware::from_fn_with_state(
+            shutdown_guard,
+            reject_while_shutting_down,
+        ));
 
-    axum::serve(listener, service)
-        .with_graceful_shutdown(shutdown_signal())
-        .into_future()
-        .await?;
+    let signal_events = events.clone();
+    let signal = async move {
+        shutdown.await;
+        shutdown_state.begin();
+        signal_events.emit(RuntimeEvent::ShutdownSignalReceived);
+        if !shutdown_grace_period.is_zero() {
+            tracing::info!(
+                grace_period = ?shutdown_grace_period,
+                "shutdown signal received, rejecting new requests"
+            );
+            tokio::time::sleep(shutdown_grace_period).await;
+        }
+    };
+
+    let options = ConnectionOptions {
+        http_version,
+        tcp_nodelay,
+    };
+    serve_connections(listeners, router, options, signal).await?;
+
+    // Every connection has drained, so no handler can issue another command.
+    if let Err(err) = command_client.close().await {
+        tracing::warn!(error = %err, "failed to close command channel");
+    }
+    for (name, client) in named_clients.0.iter() {
+        if let Err(err) = client.close().await {
+            tracing::warn!(name = %name, error = %err, "failed to close named command channel");
+        }
+    }
+    events.emit(RuntimeEvent::Drained);
 
     Ok(())
 }
 
+/// Answers requests that match no route in `router` with `handler`.
+fn with_not_found(router: Router, handler: NotFoundHandler) -> Router {
+    router.fallback(move |parts: Parts| {
+        let platform = parts
+            .extensions
+            .get::<RuntimePlatform>()
+            .cloned()
+            .unwrap_or(RuntimePlatform::Generic);
+        let metadata = RequestMetadata::from_parts(&parts, &platform);
+        std::future::ready(handler.respond(&metadata))
+    })
+}
+
+/// Wraps `initial` in a router that dispatches every request to the latest router on `reload`.
+fn reloadable(
+    initial: Router,
+    reload: watch::Receiver<Router>,
+    not_found: Option<NotFoundHandler>,
+) -> Router {
+    let initial = match &not_found {
+        Some(handler) => with_not_found(initial, handler.clone()),
+        None => initial,
+    };
+    Router::new().fallback_service(ReloadableRouter {
+        current: Arc::new(std::sync::Mutex::new(CurrentRouter {
+            router: initial,
+            reload,
+        })),
+        not_found,
+    })
+}
+
+/// Routes each request to the latest router sent on `reload`, or `initial` until one is.
+#[derive(Clone)]
+struct ReloadableRouter {
+    /// Shared by every clone, so each reloaded router is prepared once.
+    current: Arc<std::sync::Mutex<CurrentRouter>>,
+    /// Installed on each reloaded router when it is first used.
+    not_found: Option<NotFoundHandler>,
+}
+
+struct CurrentRouter {
+    /// The router requests go to, already carrying `not_found`.
+    router: Router,
+    /// Marked as seen once its latest router has replaced `router`.
+    reload: watch::Receiver<Router>,
+}
+
+impl Service<Request> for ReloadableRouter {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = <Router as Service<Request>>::Future;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<std::result::Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let mut router = {
+            let mut guard = self.current.lock().unwrap_or_else(|err| err.into_inner());
+            let current = &mut *guard;
+            let latest = current.reload.borrow_and_update();
+            if latest.has_changed() {
+                let router = latest.clone();
+                drop(latest);
+                current.router = match &self.not_found {
+                    Some(handler) => with_not_found(router, handler.clone()),
+                    None => router,
+                };
+            }
+            current.router.clone()
+        };
+        router.call(request)
+    }
+}
+
 /// Loads [`RuntimeConfig`] from the environment and starts serving the router.
 pub async fn run(router: Router) -> Result<()> {
     let config = RuntimeConfig::from_env()?;
     serve(router, config).await
 }
 
-async fn shutdown_signal() {
-    #[cfg(unix)]
-    {
-        let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
-            .expect("failed to install SIGTERM handler");
+/// Response compression that skips payloads which are already compressed and responses marked
+/// [`Streaming`](crate::Streaming).
+#[cfg(feature = "compression")]
+fn compression_layer()
+-> tower_http::compression::CompressionLayer<impl tower_http::compression::Predicate> {
+    use tower_http::compression::predicate::{DefaultPredicate, NotForContentType, Predicate};
+
+    tower_http::compression::CompressionLayer::new().compress_when(
+        DefaultPredicate::new()
+            .and(NotForContentType::const_new("application/gzip"))
+            .and(NotForContentType::const_new("application/zip"))
+            .and(NotForContentType::const_new("application/zstd"))
+            .and(
+                |_: StatusCode,
+                 _: axum::http::Version,
+                 _: &axum::http::HeaderMap,
+                 extensions: &axum::http::Extensions| {
+                    extensions.get::<crate::body::Streaming>().is_none()
+                },
+            ),
+    )
+}
+
+/// Per-connection settings applied by [`serve_connections`].
+#[derive(Clone, Copy, Debug)]
+struct ConnectionOptions {
+    http_version: HttpVersion,
+    tcp_nodelay: bool,
+}
+
+/// Binds a listener with an explicit accept backlog (`TcpListener::bind` hardcodes 1024).
+fn bind_listener(addr: SocketAddr, backlog: u32) -> io::Result<TcpListener> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    #[cfg(not(windows))]
+    socket.set_reuseaddr(true)?;
+    socket.bind(addr)?;
+    socket.listen(backlog)
+}
+
+/// Accepts connections on every listener until `signal` resolves, then drains in-flight
+/// connections.
+///
+/// Each listener runs its own accept loop feeding a shared queue, so a single shutdown signal
+/// stops all of them. With [`HttpVersion::Auto`] each connection is handed to hyper's auto builder, which detects
+/// HTTP/1.1 vs. HTTP/2 prior knowledge from the connection preface; pinned versions use the
+/// dedicated hyper connection builders instead. Every stream is wrapped in a [`HintedStream`] so
+/// HTTP/1.1 handlers can send early hints.
+async fn serve_connections<F>(
+    listeners: Vec<TcpListener>,
+    router: Router,
+    options: ConnectionOptions,
+    signal: F,
+) -> io::Result<()>
+where
+    F: Future<Output = ()>,
+{
+    let (drain_tx, drain_rx) = watch::channel(());
+    let mut signal = std::pin::pin!(signal);
+    let (accepted_tx, mut accepted_rx) = mpsc::channel(listeners.len().max(1));
+    let acceptors = listeners
+        .into_iter()
+        .map(|listener| tokio::spawn(accept_loop(listener, accepted_tx.clone())))
+        .collect::<Vec<_>>();
+    drop(accepted_tx);
+    let mut make_service = router.into_make_service_with_connect_info::<SocketAddr>();
+
+    loop {
+        let (stream, remote_addr) = tokio::select! {
+            accepted = accepted_rx.recv() => match accepted {
+                Some(accepted) => accepted,
+                None => break,
+            },
+            _ = &mut signal => {
+                tracing::info!("no longer accepting connections, draining");
+                break;
+            }
+        };
 
-        tokio::select! {
-            _ = tokio::signal::ctrl_c() => {},
-            _ = sigterm.recv() => {},
+        if let Err(err) = stream.set_nodelay(options.tcp_nodelay) {
+            tracing::debug!(peer = %remote_addr, error = %err, "failed to set TCP_NODELAY");
         }
+
+        let stream = HintedStream::new(stream);
+        // Adds `ConnectInfo<SocketAddr>` to every request on this connection.
+        let Ok(service) = make_service.call(remote_addr).await;
+        let service = TowerToHyperService::new(stream.service(service));
+        let io = TokioIo::new(stream);
+        let drain = drain_rx.clone();
+
+        tokio::spawn(async move {
+            let result = match options.http_version {
+                HttpVersion::Auto => {
+                    let connection = auto::Builder::new(TokioExecutor::new())
+                        .serve_connection_with_upgrades(io, service)
+                        .into_owned();
+                    drive_connection(connection, drain, |conn| conn.graceful_shutdown()).await
+                }
+                HttpVersion::Http1 => {
+                    let connection = http1::Builder::new()
+                        .serve_connection(io, service)
+                        .with_upgrades();
+                    drive_connection(connection, drain, |conn| conn.graceful_shutdown())
+                        .await
+                        .map_err(Into::into)
+                }
+                HttpVersion::Http2 => {
+                    let connection =
+                        http2::Builder::new(TokioExecutor::new()).serve_connection(io, service);
+                    drive_connection(connection, drain, |conn| conn.graceful_shutdown())
+                        .await
+                        .map_err(Into::into)
+                }
+            };
+
+            if let Err(err) = result {
+                tracing::debug!(peer = %remote_addr, error = %err, "connection closed with error");
+            }
+        });
     }
 
-    #[cfg(not(unix))]
+    // Aborting the accept loops drops (and closes) their listeners.
+    for acceptor in acceptors {
+        acceptor.abort();
+    }
+    drop(drain_rx);
+    let _ = drain_tx.send(());
+    drain_tx.closed().await;
+    Ok(())
+}
+
+/// Forwards accepted connections from `listener` until the receiving side goes away.
+async fn accept_loop(listener: TcpListener, accepted: mpsc::Sender<(TcpStream, SocketAddr)>) {
+    loop {
+        match listener.accept().await {
+            Ok(connection) => {
+                if accepted.send(connection).await.is_err() {
+                    return;
+                }
+            }
+            Err(err) => {
+                tracing::warn!(error = %err, "failed to accept connection");
+                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+            }
+        }
+    }
+}
+
+/// Drives a connection to completion, switching it into graceful shutdown once `drain` fires.
+///
+/// The receiver is held until the connection finishes so the accept loop can wait for every
+/// in-flight connection by awaiting `Sender::closed`.
+async fn drive_connection<C, E>(
+    connection: C,
+    mut drain: watch::Receiver<()>,
+    start_shutdown: fn(Pin<&mut C>),
+) -> std::result::Result<(), E>
+where
+    C: Future<Output = std::result::Result<(), E>>,
+{
+    let mut connection = std::pin::pin!(connection);
+    tokio::select! {
+        result = connection.as_mut() => return result,
+        _ = drain.changed() => start_shutdown(connection.as_mut()),
+    }
+    connection.await
+}
+
+/// Fans command events out to every observer the enabled features installed.
+struct ObserverSet(Vec<Arc<dyn CommandObserver>>);
+
+impl CommandObserver for ObserverSet {
+    fn on_command(&self, event: &CommandEvent<'_>) {
+        for observer in &self.0 {
+            observer.on_command(event);
+        }
+    }
+}
+
+/// Applies the configured body limit. Without one, axum's 2 MB default stays in place; only an
+/// explicit [`RuntimeConfigBuilder::unlimited_body_size`](crate::RuntimeConfigBuilder::unlimited_body_size)
+/// lifts it.
+fn with_body_limit(router: Router, max_body_bytes: Option<usize>, unlimited: bool) -> Router {
+    match (unlimited, max_body_bytes) {
+        (true, _) => router.layer(DefaultBodyLimit::disable()),
+        (false, Some(limit)) => router.layer(DefaultBodyLimit::max(limit)),
+        (false, None) => router,
+    }
+}
+
+/// Middleware that answers `504` when the rest of the stack takes longer than `timeout`.
+async fn enforce_request_timeout(
+    State(timeout): State<Duration>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match tokio::time::timeout(timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            tracing::warn!(?timeout, "request timed out");
+            (StatusCode::GATEWAY_TIMEOUT, "request timed out").into_response()
+        }
+    }
+}
+
+/// Global method, path, and host checks applied before routing.
+#[derive(Clone, Debug)]
+struct RequestGuard {
+    allowed_methods: Option<Arc<[Method]>>,
+    reject_malformed_paths: bool,
+    allowed_hosts: Option<Arc<[HostPattern]>>,
+}
+
+/// One entry of [`RuntimeConfig::allowed_hosts`], lowercased.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum HostPattern {
+    Exact(String),
+    /// `*.example.com`, stored as `.example.com`.
+    Subdomains(String),
+}
+
+impl HostPattern {
+    fn parse(pattern: &str) -> std::result::Result<Self, ConfigError> {
+        let pattern = pattern.trim().to_ascii_lowercase();
+        let name = pattern.strip_prefix("*.").unwrap_or(&pattern);
+        if name.is_empty() || name.contains('*') {
+            return Err(ConfigError::Validation(format!(
+                "invalid allowed host {pattern:?}: use an exact name or a leading `*.` wildcard"
+            )));
+        }
+        Ok(if name.len() == pattern.len() {
+            HostPattern::Exact(pattern)
+        } else {
+            HostPattern::Subdomains(format!(".{name}"))
+        })
+    }
+
+    /// `host` must already be lowercased and stripped of its port.
+    fn matches(&self, host: &str) -> bool {
+        match self {
+            HostPattern::Exact(exact) => host == exact,
+            HostPattern::Subdomains(suffix) => {
+                host.len() > suffix.len() && host.ends_with(suffix.as_str())
+            }
+        }
+    }
+}
+
+/// Lowercases `host` and drops any port, keeping IPv6 literals bracketed.
+fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+    let host = match host.strip_prefix('[') {
+        Some(rest) => rest
+            .split_once(']')
+            .map_or(host, |(literal, _)| &host[..literal.len() + 2]),
+        None => host.split_once(':').map_or(host, |(name, _)| name),
+    };
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Middleware that turns away disallowed methods (`405`), malformed paths (`400`), and
+/// unexpected hosts (`421`).
+async fn guard_request(
+    State(guard): State<RequestGuard>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(allowed) = &guard.allowed_methods
+        && !allowed.contains(request.method())
     {
-        let _ = tokio::signal::ctrl_c().await;
+        let allow = allowed
+            .iter()
+            .map(Method::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut response = (StatusCode::METHOD_NOT_ALLOWED, "method not allowed").into_response();
+        if let Ok(allow) = HeaderValue::from_str(&allow) {
+            response.headers_mut().insert(ALLOW, allow);
+        }
+        return response;
+    }
+    if guard.reject_malformed_paths && is_malformed_path(request.uri().path()) {
+        tracing::warn!(
+            path = request.uri().path(),
+            "rejecting malformed request path"
+        );
+        return (StatusCode::BAD_REQUEST, "malformed request path").into_response();
+    }
+    if let Some(allowed) = &guard.allowed_hosts {
+        let host = crate::context::request_host(request.headers())
+            .or_else(|| request.uri().host())
+            .map(normalize_host);
+        if !host
+            .as_deref()
+            .is_some_and(|host| allowed.iter().any(|pattern| pattern.matches(host)))
+        {
+            tracing::warn!(host = ?host, "rejecting request for unexpected host");
+            return (StatusCode::MISDIRECTED_REQUEST, "misdirected request").into_response();
+        }
+    }
+    next.run(request).await
+}
+
+/// Whether `path` contains a control character once percent-decoded.
+fn is_malformed_path(path: &str) -> bool {
+    percent_encoding::percent_decode_str(path).any(|byte| byte.is_ascii_control())
+}
+
+/// Installs handlers for `kinds` and returns a future that resolves on the first one received.
+///
+/// Handlers are installed eagerly so a failure surfaces from [`serve`] instead of panicking later.
+/// An empty set never resolves.
+#[cfg(unix)]
+fn shutdown_signal(
+    kinds: &[tokio::signal::unix::SignalKind],
+) -> std::io::Result<impl Future<Output = ()> + use<>> {
+    let mut signals = kinds
+        .iter()
+        .map(|kind| tokio::signal::unix::signal(*kind))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    Ok(async move {
+        std::future::poll_fn(|cx| {
+            if signals
+                .iter_mut()
+                .any(|signal| signal.poll_recv(cx).is_ready())
+            {
+                std::task::Poll::Ready(())
+            } else {
+                std::task::Poll::Pending
+            }
+        })
+        .await
+    })
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode, Version};
+    use http_body_util::BodyExt;
+    use tokio::sync::oneshot;
+
+    async fn spawn_server(http_version: HttpVersion) -> (SocketAddr, oneshot::Sender<()>) {
+        let (addrs, tx) = spawn_server_on(&["127.0.0.1:0"], http_version).await;
+        (addrs[0], tx)
+    }
+
+    async fn spawn_server_on(
+        addrs: &[&str],
+        http_version: HttpVersion,
+    ) -> (Vec<SocketAddr>, oneshot::Sender<()>) {
+        let listeners = addrs
+            .iter()
+            .map(|addr| bind_listener(addr.parse().unwrap(), 16).unwrap())
+            .collect::<Vec<_>>();
+        let addrs = listeners
+            .iter()
+            .map(|listener| listener.local_addr().unwrap())
+            .collect();
+        let router = Router::new().route("/", get(|| async { "ok" }));
+        let (tx, rx) = oneshot::channel::<()>();
+        let options = ConnectionOptions {
+            http_version,
+            tcp_nodelay: true,
+        };
+        tokio::spawn(serve_connections(listeners, router, options, async {
+            let _ = rx.await;
+        }));
+        (addrs, tx)
+    }
+
+    /// Sends `request` over a fresh HTTP/1.1 connection on `stream`.
+    async fn send(stream: TcpStream, request: Request<Body>) -> Response<hyper::body::Incoming> {
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+        sender.send_request(request).await.unwrap()
+    }
+
+    fn get_request(addr: SocketAddr, path: &str) -> Request<Body> {
+        Request::builder()
+            .uri(path)
+            .header("host", addr.to_string())
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    async fn fetch(addr: SocketAddr, path: &str) -> (StatusCode, String) {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let response = send(stream, get_request(addr, path)).await;
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    async fn get_status(addr: SocketAddr) -> StatusCode {
+        fetch(addr, "/").await.0
+    }
+
+    #[tokio::test]
+    async fn serves_on_prebound_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (events_tx, mut events_rx) = tokio::sync::mpsc::channel(8);
+        let config = RuntimeConfig::builder()
+            // Binding this again would fail if the listener were not used as-is.
+            .bind_addr(addr)
+            .disable_command_channel("tests")
+            .events(events_tx)
+            .build();
+        let router = Router::new().route("/", get(|| async { "ok" }));
+        let (tx, rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve_until(router, config, Some(listener), async {
+            let _ = rx.await;
+        }));
+
+        assert_eq!(
+            events_rx.recv().await.unwrap(),
+            RuntimeEvent::Bound { addr }
+        );
+        assert_eq!(get_status(addr).await, StatusCode::OK);
+
+        let _ = tx.send(());
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn reloads_router_without_dropping_in_flight_requests() {
+        use tokio::sync::Mutex;
+
+        let (started_tx, started_rx) = oneshot::channel::<()>();
+        let (release_tx, release_rx) = oneshot::channel::<()>();
+        let slow = Arc::new(Mutex::new(Some((started_tx, release_rx))));
+        let initial = Router::new().route(
+            "/slow",
+            get(move || async move {
+                let (started, release) = slow.lock().await.take().unwrap();
+                let _ = started.send(());
+                let _ = release.await;
+                "v1"
+            }),
+        );
+        let (reload_tx, reload_rx) = watch::channel(Router::new());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = RuntimeConfig::builder()
+            .bind_addr(addr)
+            .disable_command_channel("tests")
+            .build();
+        let (tx, rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve_until(
+            reloadable(initial, reload_rx, None),
+            config,
+            Some(listener),
+            async {
+                let _ = rx.await;
+            },
+        ));
+
+        let in_flight = tokio::spawn(fetch(addr, "/slow"));
+        started_rx.await.unwrap();
+        assert_eq!(fetch(addr, "/new").await.0, StatusCode::NOT_FOUND);
+
+        reload_tx
+            .send(Router::new().route("/new", get(|| async { "v2" })))
+            .unwrap();
+        assert_eq!(fetch(addr, "/new").await, (StatusCode::OK, "v2".to_owned()));
+        assert_eq!(fetch(addr, "/slow").await.0, StatusCode::NOT_FOUND);
+
+        let _ = release_tx.send(());
+        assert_eq!(in_flight.await.unwrap(), (StatusCode::OK, "v1".to_owned()));
+
+        let _ = tx.send(());
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn streams_response_bodies_through_every_layer() {
+        use crate::{AccessLogFormat, Streaming};
+        use axum::http::header::ACCEPT_ENCODING;
+        use hyper::body::{Body as HttpBody, Bytes, Frame};
+        use std::task::{Context, Poll};
+
+        /// Yields chunks as the test releases them.
+        struct ChannelBody(mpsc::Receiver<Bytes>);
+
+        impl HttpBody for ChannelBody {
+            type Data = Bytes;
+            type Error = Infallible;
+
+            fn poll_frame(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+            ) -> Poll<Option<std::result::Result<Frame<Bytes>, Infallible>>> {
+                self.0
+                    .poll_recv(cx)
+                    .map(|chunk| chunk.map(|chunk| Ok(Frame::data(chunk))))
+            }
+        }
+
+        let (chunks_tx, chunks_rx) = mpsc::channel::<Bytes>(1);
+        let chunks_rx = Arc::new(tokio::sync::Mutex::new(Some(chunks_rx)));
+        let router = Router::new().route(
+            "/stream",
+            get(move || async move {
+                let chunks = chunks_rx.lock().await.take().unwrap();
+                (Streaming, Body::new(ChannelBody(chunks)))
+            }),
+        );
+        let builder = RuntimeConfig::builder()
+            .request_timeout(Duration::from_secs(30))
+            .allowed_methods(vec![Method::GET])
+            .access_log(AccessLogFormat::Json)
+            .echo_request_id(true)
+            .capture_headers(true);
+        #[cfg(feature = "compression")]
+        let builder = builder.compression(true);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = builder
+            .bind_addr(addr)
+            .disable_command_channel("tests")
+            .build();
+        let (tx, rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve_until(router, config, Some(listener), async {
+            let _ = rx.await;
+        }));
+
+        let mut request = get_request(addr, "/stream");
+        request
+            .headers_mut()
+            .insert(ACCEPT_ENCODING, "gzip".parse().unwrap());
+        let response = send(TcpStream::connect(addr).await.unwrap(), request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get("content-encoding").is_none());
+        let mut body = response.into_body();
+
+        // Each chunk must arrive while the handler's body is still open; a layer that buffered
+        // the response would hold it back until the stream ends and time out here.
+        for i in 0..50 {
+            let chunk = Bytes::from(format!("chunk {i}\n"));
+            chunks_tx.send(chunk.clone()).await.unwrap();
+            let frame = tokio::time::timeout(Duration::from_secs(2), body.frame())
+                .await
+                .expect("chunk was buffered")
+                .unwrap()
+                .unwrap();
+            assert_eq!(frame.into_data().unwrap(), chunk);
+        }
+        drop(chunks_tx);
+        assert!(body.frame().await.is_none());
+
+        let _ = tx.send(());
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn exposes_peer_addr_to_handlers() {
+        use crate::ContainerContext;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = Router::new().route(
+            "/",
+            get(|ctx: ContainerContext| async move { format!("{:?}", ctx.peer_addr()) }),
+        );
+        let config = RuntimeConfig::builder()
+            .bind_addr(addr)
+            .disable_command_channel("tests")
+            .build();
+        let (tx, rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve_until(router, config, Some(listener), async {
+            let _ = rx.await;
+        }));
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let client_addr = stream.local_addr().unwrap();
+        let response = send(stream, get_request(addr, "/")).await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, format!("{:?}", Some(client_addr)));
+
+        let _ = tx.send(());
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn sends_early_hints_before_the_final_response() {
+        use crate::ContainerContext;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = Router::new().route(
+            "/",
+            get(|ctx: ContainerContext| async move {
+                let sent = ctx
+                    .send_early_hints(vec!["</app.css>; rel=preload; as=style".into()])
+                    .unwrap();
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                sent.to_string()
+            }),
+        );
+        let config = RuntimeConfig::builder()
+            .bind_addr(addr)
+            .disable_command_channel("tests")
+            .build();
+        let (tx, rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve_until(router, config, Some(listener), async {
+            let _ = rx.await;
+        }));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let read_response = async |stream: &mut TcpStream, request: &[u8]| {
+            stream.write_all(request).await.unwrap();
+            let mut received = Vec::new();
+            let mut buf = [0; 1024];
+            while !received.ends_with(b"true") && !received.ends_with(b"false") {
+                let read = stream.read(&mut buf).await.unwrap();
+                assert_ne!(read, 0, "{}", String::from_utf8_lossy(&received));
+                received.extend_from_slice(&buf[..read]);
+            }
+            String::from_utf8(received).unwrap()
+        };
+
+        let request = b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n";
+        // The second request checks the connection is still in sync after the interim response.
+        for _ in 0..2 {
+            let response = read_response(&mut stream, request).await;
+            let (hints, fin) = response.split_once("\r\n\r\n").unwrap();
+            assert_eq!(
+                hints,
+                "HTTP/1.1 103 Early Hints\r\nlink: </app.css>; rel=preload; as=style"
+            );
+            assert!(fin.starts_with("HTTP/1.1 200 OK\r\n"), "{fin}");
+            assert!(fin.ends_with("\r\n\r\ntrue"), "{fin}");
+        }
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let response = read_response(&mut stream, b"GET / HTTP/1.0\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"), "{response}");
+        assert!(response.ends_with("false"));
+
+        let _ = tx.send(());
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn serves_h2c_prior_knowledge() {
+        let (addr, shutdown) = spawn_server(HttpVersion::Auto).await;
+
+        let stream = TokioIo::new(TcpStream::connect(addr).await.unwrap());
+        let (mut sender, connection) =
+            hyper::client::conn::http2::handshake(TokioExecutor::new(), stream)
+                .await
+                .unwrap();
+        tokio::spawn(connection);
+
+        let request = Request::builder()
+            .uri(format!("http://{addr}/"))
+            .body(Body::empty())
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.version(), Version::HTTP_2);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"ok");
+
+        let _ = shutdown.send(());
+    }
+
+    #[tokio::test]
+    async fn serves_http1_with_auto_detection() {
+        let (addr, shutdown) = spawn_server(HttpVersion::Auto).await;
+        assert_eq!(get_status(addr).await, StatusCode::OK);
+
+        let _ = shutdown.send(());
+    }
+
+    #[tokio::test]
+    async fn binds_listeners_with_the_configured_backlog() {
+        /// Opens connections nobody accepts until the listener's queue stops taking them.
+        async fn fill_queue(backlog: u32) -> usize {
+            let listener = bind_listener("127.0.0.1:0".parse().unwrap(), backlog).unwrap();
+            let addr = listener.local_addr().unwrap();
+            let mut queued = Vec::new();
+            while let Ok(Ok(stream)) =
+                tokio::time::timeout(Duration::from_millis(100), TcpStream::connect(addr)).await
+            {
+                queued.push(stream);
+                assert!(queued.len() <= 64, "backlog {backlog} was not applied");
+            }
+            queued.len()
+        }
+
+        let short = fill_queue(2).await;
+        let long = fill_queue(8).await;
+        assert!(short < 8, "{short}");
+        assert!(long >= 8, "{long}");
+    }
+
+    #[tokio::test]
+    async fn closes_command_channel_once_after_draining() {
+        use containerflare_command::{CommandEndpoint, CommandResponse, CommandServer};
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let host = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host_addr = host.local_addr().unwrap();
+        let closes = Arc::new(AtomicUsize::new(0));
+        let host_task = tokio::spawn({
+            let closes = closes.clone();
+            async move {
+                let (stream, _) = host.accept().await.unwrap();
+                let (reader, writer) = stream.into_split();
+                CommandServer::new(|_| CommandResponse::ok())
+                    .serve(reader, writer)
+                    .await
+                    .unwrap();
+                closes.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+        let config = RuntimeConfig::builder()
+            .bind_addr("127.0.0.1:0".parse().unwrap())
+            .command_endpoint(CommandEndpoint::Tcp(host_addr.to_string()))
+            .build();
+        let (tx, rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve_until(Router::new(), config, None, async {
+            let _ = rx.await;
+        }));
+
+        let _ = tx.send(());
+        server.await.unwrap().unwrap();
+        host_task.await.unwrap();
+        assert_eq!(closes.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn required_command_channel_fails_boot_when_missing() {
+        use containerflare_command::{CommandEndpoint, CommandResponse, CommandServer};
+
+        let config = RuntimeConfig::builder()
+            .bind_addr("127.0.0.1:0".parse().unwrap())
+            .disable_command_channel("no sidecar")
+            .require_command_channel(true)
+            .build();
+        let err = serve_until(Router::new(), config, None, async {})
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            crate::ContainerflareError::Command(CommandError::Unavailable(ref reason))
+                if reason == "no sidecar"
+        ));
+
+        let host = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = CommandEndpoint::Tcp(host.local_addr().unwrap().to_string());
+        tokio::spawn(async move {
+            let (stream, _) = host.accept().await.unwrap();
+            let (reader, writer) = stream.into_split();
+            let _ = CommandServer::new(|_| CommandResponse::ok())
+                .serve(reader, writer)
+                .await;
+        });
+        let config = RuntimeConfig::builder()
+            .bind_addr("127.0.0.1:0".parse().unwrap())
+            .command_endpoint(endpoint)
+            .require_command_channel(true)
+            .build();
+        serve_until(Router::new(), config, None, async {})
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn uses_injected_command_client() {
+        use containerflare_command::{CommandEndpoint, CommandResponse, CommandServer};
+
+        let host = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = CommandEndpoint::Tcp(host.local_addr().unwrap().to_string());
+        tokio::spawn(async move {
+            let (stream, _) = host.accept().await.unwrap();
+            let (reader, writer) = stream.into_split();
+            let _ = CommandServer::new(|_| CommandResponse::ok())
+                .serve(reader, writer)
+                .await;
+        });
+        let client = CommandClient::connect(endpoint.clone()).await.unwrap();
+
+        let (events_tx, mut events_rx) = tokio::sync::mpsc::channel(8);
+        let config = RuntimeConfig::builder()
+            .bind_addr("127.0.0.1:0".parse().unwrap())
+            // Nothing listens here; dialing it would fail `serve`.
+            .command_endpoint(CommandEndpoint::Tcp("127.0.0.1:1".into()))
+            .command_client(client.clone())
+            .events(events_tx)
+            .build();
+        serve_until(Router::new(), config, None, async {})
+            .await
+            .unwrap();
+
+        let _bound = events_rx.recv().await.unwrap();
+        assert_eq!(
+            events_rx.recv().await.unwrap(),
+            RuntimeEvent::CommandChannelConnected { endpoint }
+        );
+        assert!(client.is_closed());
+    }
+
+    #[tokio::test]
+    async fn closes_named_command_clients() {
+        use containerflare_command::{CommandEndpoint, CommandResponse, CommandServer};
+
+        let host = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = CommandEndpoint::Tcp(host.local_addr().unwrap().to_string());
+        tokio::spawn(async move {
+            let (stream, _) = host.accept().await.unwrap();
+            let (reader, writer) = stream.into_split();
+            let _ = CommandServer::new(|_| CommandResponse::ok())
+                .serve(reader, writer)
+                .await;
+        });
+        let gpu = CommandClient::connect(endpoint).await.unwrap();
+
+        let config = RuntimeConfig::builder()
+            .bind_addr("127.0.0.1:0".parse().unwrap())
+            .disable_command_channel("tests")
+            .command_client_named("gpu", gpu.clone())
+            .build();
+        serve_until(Router::new(), config, None, async {})
+            .await
+            .unwrap();
+
+        assert!(gpu.is_closed());
+    }
+
+    #[tokio::test]
+    async fn emits_lifecycle_events() {
+        let (events_tx, mut events_rx) = tokio::sync::mpsc::channel(8);
+        let config = RuntimeConfig::builder()
+            .bind_addr("127.0.0.1:0".parse().unwrap())
+            .disable_command_channel("tests")
+            .events(events_tx)
+            .build();
+
+        serve_until(Router::new(), config, None, async {})
+            .await
+            .unwrap();
+
+        let mut events = Vec::new();
+        while let Ok(event) = events_rx.try_recv() {
+            events.push(event);
+        }
+        assert!(matches!(events[0], RuntimeEvent::Bound { addr } if addr.port() != 0));
+        assert_eq!(
+            events[1..],
+            [
+                RuntimeEvent::CommandChannelDisabled {
+                    reason: "tests".into()
+                },
+                RuntimeEvent::ShutdownSignalReceived,
+                RuntimeEvent::Drained,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_the_ephemeral_port_bound_for_port_zero() {
+        let mut config = {
+            let _guard = crate::test_support::env_lock().lock().unwrap();
+            unsafe {
+                std::env::set_var("PORT", "0");
+                std::env::set_var("CF_CONTAINER_ADDR", "127.0.0.1");
+            }
+            let config = RuntimeConfig::from_env_without_dotenv();
+            unsafe {
+                std::env::remove_var("PORT");
+                std::env::remove_var("CF_CONTAINER_ADDR");
+            }
+            config.unwrap()
+        };
+        assert_eq!(config.bind_addr.port(), 0);
+        let (events_tx, mut events_rx) = tokio::sync::mpsc::channel(8);
+        config.events = Some(events_tx);
+        config.command_endpoint = None;
+        config.command_disabled_reason = Some("tests".into());
+
+        serve_until(Router::new(), config, None, async {})
+            .await
+            .unwrap();
+
+        let Ok(RuntimeEvent::Bound { addr }) = events_rx.try_recv() else {
+            panic!("expected a Bound event first");
+        };
+        assert!(addr.ip().is_loopback());
+        assert_ne!(addr.port(), 0);
+    }
+
+    #[tokio::test]
+    async fn reports_the_address_that_failed_to_bind() {
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = taken.local_addr().unwrap();
+        let config = RuntimeConfig::builder()
+            .bind_addr(addr)
+            .disable_command_channel("tests")
+            .build();
+
+        let err = serve_until(Router::new(), config, None, async {})
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, ContainerflareError::Bind { addr: failed, source }
+                if *failed == addr && source.kind() == io::ErrorKind::AddrInUse),
+            "{err:?}"
+        );
+        assert!(err.to_string().contains(&addr.to_string()));
+    }
+
+    #[tokio::test]
+    async fn serves_every_bind_address() {
+        let (addrs, shutdown) =
+            spawn_server_on(&["127.0.0.1:0", "[::1]:0"], HttpVersion::Auto).await;
+
+        for addr in addrs {
+            assert_eq!(get_status(addr).await, StatusCode::OK);
+        }
+
+        let _ = shutdown.send(());
+    }
+
+    #[cfg(feature = "compression")]
+    #[tokio::test]
+    async fn compresses_negotiated_responses() {
+        use axum::http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE};
+        use tower::ServiceExt;
+
+        let body = "containerflare ".repeat(16);
+        let router = Router::new()
+            .route("/text", get(move || std::future::ready(body.clone())))
+            .route(
+                "/archive",
+                get(|| async { ([(CONTENT_TYPE, "application/gzip")], vec![0u8; 256]) }),
+            )
+            .layer(compression_layer());
+
+        let request = |path: &str| {
+            Request::builder()
+                .uri(path)
+                .header(ACCEPT_ENCODING, "gzip")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = router.clone().oneshot(request("/text")).await.unwrap();
+        assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+
+        let response = router.oneshot(request("/archive")).await.unwrap();
+        assert!(response.headers().get(CONTENT_ENCODING).is_none());
+    }
+
+    #[tokio::test]
+    async fn answers_unmatched_routes_with_a_negotiated_404() {
+        use axum::http::header::{ACCEPT, CONTENT_TYPE};
+        use tower::ServiceExt;
+
+        async fn fetch(router: Router, accept: Option<&str>) -> (StatusCode, String, String) {
+            let mut request = Request::builder()
+                .uri("/missing")
+                .header("cf-ray", "8c5f2a1b3d4e5f60-IAD");
+            if let Some(accept) = accept {
+                request = request.header(ACCEPT, accept);
+            }
+            let response = router
+                .oneshot(request.body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let status = response.status();
+            let content_type = response.headers()[CONTENT_TYPE]
+                .to_str()
+                .unwrap()
+                .to_owned();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (
+                status,
+                content_type,
+                String::from_utf8(body.to_vec()).unwrap(),
+            )
+        }
+
+        let handler = NotFoundHandler::new(crate::not_found::negotiated);
+        let router = with_not_found(
+            Router::new().route("/", get(|| async { "ok" })),
+            handler.clone(),
+        );
+
+        let (status, content_type, body) = fetch(router.clone(), Some("application/json")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(content_type, "application/json");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({
+                "error": "not_found",
+                "path": "/missing",
+                "request_id": "8c5f2a1b3d4e5f60-IAD",
+            })
+        );
+
+        let (status, content_type, body) = fetch(router.clone(), Some("text/html,*/*;q=0.8")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(content_type.starts_with("text/html"), "{content_type}");
+        assert!(body.contains("<code>/missing</code>"), "{body}");
+
+        let (_, content_type, _) = fetch(router.clone(), None).await;
+        assert_eq!(content_type, "application/json");
+
+        let response = router
+            .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Reloaded routers get the handler too.
+        let (reload_tx, reload_rx) = watch::channel(Router::new());
+        let router = reloadable(Router::new(), reload_rx, Some(handler.clone()));
+        let (_, content_type, _) = fetch(router.clone(), Some("text/html")).await;
+        assert!(content_type.starts_with("text/html"), "{content_type}");
+        reload_tx
+            .send(Router::new().route("/", get(|| async { "v2" })))
+            .unwrap();
+        let (status, content_type, _) = fetch(router.clone(), Some("text/html")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(content_type.starts_with("text/html"), "{content_type}");
+
+        // Each reload is prepared by the first request after it and reused by the rest.
+        let (reload_tx, reload_rx) = watch::channel(Router::new());
+        let current = Arc::new(std::sync::Mutex::new(CurrentRouter {
+            router: Router::new(),
+            reload: reload_rx,
+        }));
+        let router = Router::new().fallback_service(ReloadableRouter {
+            current: current.clone(),
+            not_found: Some(handler),
+        });
+        let unprepared = || current.lock().unwrap().reload.has_changed().unwrap();
+        reload_tx
+            .send(Router::new().route("/", get(|| async { "v3" })))
+            .unwrap();
+        assert!(unprepared());
+        for _ in 0..2 {
+            let (status, content_type, _) = fetch(router.clone(), Some("text/html")).await;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert!(content_type.starts_with("text/html"), "{content_type}");
+            assert!(!unprepared());
+        }
+    }
+
+    #[tokio::test]
+    async fn generic_platform_keeps_axums_default_body_limit() {
+        use axum::body::Bytes;
+        use axum::routing::post;
+        use tower::ServiceExt;
+
+        let router = Router::new().route(
+            "/",
+            post(|body: Bytes| async move { body.len().to_string() }),
+        );
+        let upload = |len: usize| {
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .body(Body::from(vec![0u8; len]))
+                .unwrap()
+        };
+        let over_default = 2 * 1024 * 1024 + 1;
+
+        let config = RuntimeConfig::builder()
+            .platform(RuntimePlatform::Generic)
+            .build();
+        let limited = with_body_limit(
+            router.clone(),
+            config.max_body_bytes,
+            config.unlimited_body_size,
+        );
+        let response = limited.clone().oneshot(upload(1024)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = limited.oneshot(upload(over_default)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let config = RuntimeConfig::builder()
+            .platform(RuntimePlatform::Generic)
+            .unlimited_body_size()
+            .build();
+        let unlimited = with_body_limit(router, config.max_body_bytes, config.unlimited_body_size);
+        let response = unlimited.oneshot(upload(over_default)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn times_out_slow_handlers() {
+        use tower::ServiceExt;
+
+        let router = Router::new()
+            .route("/fast", get(|| async { "ok" }))
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    "late"
+                }),
+            )
+            .layer(middleware::from_fn_with_state(
+                Duration::from_millis(20),
+                enforce_request_timeout,
+            ));
+
+        let request = |path: &str| Request::builder().uri(path).body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request("/fast")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = router.oneshot(request("/slow")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn guard_rejects_disallowed_methods_and_malformed_paths() {
+        use axum::routing::any;
+        use tower::ServiceExt;
+
+        let router = Router::new().route("/*path", any(|| async { "ok" })).layer(
+            middleware::from_fn_with_state(
+                RequestGuard {
+                    allowed_methods: Some(vec![Method::GET, Method::POST].into()),
+                    reject_malformed_paths: true,
+                    allowed_hosts: None,
+                },
+                guard_request,
+            ),
+        );
+
+        let request = |method: Method, path: &str| {
+            Request::builder()
+                .method(method)
+                .uri(path)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = router
+            .clone()
+            .oneshot(request(Method::GET, "/items/1"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = router
+            .clone()
+            .oneshot(request(Method::TRACE, "/items/1"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[ALLOW], "GET, POST");
+
+        let response = router
+            .oneshot(request(Method::GET, "/items/%00/x"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn guard_rejects_unexpected_hosts() {
+        use tower::ServiceExt;
+
+        let allowed_hosts = ["api.example.com", "*.Example.org"]
+            .into_iter()
+            .map(HostPattern::parse)
+            .collect::<std::result::Result<Arc<[_]>, _>>()
+            .unwrap();
+        let router =
+            Router::new()
+                .route("/", get(|| async { "ok" }))
+                .layer(middleware::from_fn_with_state(
+                    RequestGuard {
+                        allowed_methods: None,
+                        reject_malformed_paths: false,
+                        allowed_hosts: Some(allowed_hosts),
+                    },
+                    guard_request,
+                ));
+        let status = |headers: &[(&str, &str)]| {
+            let mut request = Request::builder().uri("/");
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            let router = router.clone();
+            async move {
+                let request = request.body(Body::empty()).unwrap();
+                router.oneshot(request).await.unwrap().status()
+            }
+        };
+
+        // Exact, case-insensitive, with and without a port.
+        assert_eq!(status(&[("host", "api.example.com")]).await, StatusCode::OK);
+        assert_eq!(
+            status(&[("host", "API.Example.com:8787")]).await,
+            StatusCode::OK
+        );
+        // Wildcards cover subdomains at any depth, but not the bare domain.
+        assert_eq!(status(&[("host", "a.example.org")]).await, StatusCode::OK);
+        assert_eq!(status(&[("host", "a.b.example.org")]).await, StatusCode::OK);
+        assert_eq!(
+            status(&[("host", "example.org")]).await,
+            StatusCode::MISDIRECTED_REQUEST
+        );
+        assert_eq!(
+            status(&[("host", "evilexample.org")]).await,
+            StatusCode::MISDIRECTED_REQUEST
+        );
+        // `x-forwarded-host` wins over `Host`, as in `RequestMetadata::host`.
+        assert_eq!(
+            status(&[
+                ("host", "api.example.com"),
+                ("x-forwarded-host", "evil.test")
+            ])
+            .await,
+            StatusCode::MISDIRECTED_REQUEST
+        );
+        assert_eq!(
+            status(&[
+                ("host", "evil.test"),
+                ("x-forwarded-host", "api.example.com")
+            ])
+            .await,
+            StatusCode::OK
+        );
+        assert_eq!(status(&[]).await, StatusCode::MISDIRECTED_REQUEST);
+
+        assert!(HostPattern::parse("api.*.example.com").is_err());
+        assert!(HostPattern::parse("*example.com").is_err());
+        assert!(HostPattern::parse("*.").is_err());
+        assert_eq!(normalize_host("[::1]:8787"), "[::1]");
+    }
+
+    #[tokio::test]
+    async fn http1_only_rejects_h2c() {
+        let (addr, shutdown) = spawn_server(HttpVersion::Http1).await;
+
+        let stream = TokioIo::new(TcpStream::connect(addr).await.unwrap());
+        let (mut sender, connection) =
+            hyper::client::conn::http2::handshake(TokioExecutor::new(), stream)
+                .await
+                .unwrap();
+        tokio::spawn(connection);
+
+        let request = Request::builder()
+            .uri(format!("http://{addr}/"))
+            .body(Body::empty())
+            .unwrap();
+        assert!(sender.send_request(request).await.is_err());
+
+        let _ = shutdown.send(());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn configured_signal_completes_shutdown_future() {
+        use tokio::signal::unix::SignalKind;
+
+        let signal = shutdown_signal(&[SignalKind::hangup()]).unwrap();
+        let status = std::process::Command::new("kill")
+            .args(["-HUP", &std::process::id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        tokio::time::timeout(std::time::Duration::from_secs(5), signal)
+            .await
+            .expect("SIGHUP should complete the shutdown future");
     }
 }
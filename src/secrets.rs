@@ -0,0 +1,113 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use containerflare_command::{CommandClient, CommandError, CommandRequest};
+
+const SECRET_GET: &str = "secret_get";
+const REDACTED: &str = "[redacted]";
+
+/// Read-only access to secrets the sidecar exposes over the host command channel.
+///
+/// # Wire format
+/// - `secret_get`: payload `{"name": "..."}`; the host replies with `{"value": "..."}`.
+///
+/// A reply without a string `value` is reported as [`CommandError::CommandFailure`].
+#[derive(Clone, Debug)]
+pub struct SecretClient {
+    client: CommandClient,
+}
+
+impl SecretClient {
+    /// Wraps an existing command client.
+    pub fn new(client: CommandClient) -> Self {
+        Self { client }
+    }
+
+    /// Fetches the secret called `name`.
+    pub async fn get(&self, name: &str) -> Result<Secret, CommandError> {
+        let payload = serde_json::to_value(SecretName { name })?;
+        let response = self
+            .client
+            .send(CommandRequest::new(SECRET_GET, payload))
+            .await?;
+        decode_secret(name, response.payload)
+    }
+}
+
+/// A secret value whose `Debug` and `Display` output is always `[redacted]`.
+///
+/// Call [`Secret::expose`] at the point the plaintext is actually needed, so logging the secret by
+/// accident (`tracing::info!(?secret)`) cannot leak it.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    /// Wraps a plaintext value.
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    /// Returns the plaintext value.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Secret")
+            .field(&format_args!("{REDACTED}"))
+            .finish()
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+#[derive(Serialize)]
+struct SecretName<'a> {
+    name: &'a str,
+}
+
+#[derive(Deserialize)]
+struct SecretValue {
+    value: Option<String>,
+}
+
+fn decode_secret(name: &str, payload: Value) -> Result<Secret, CommandError> {
+    serde_json::from_value::<SecretValue>(payload)
+        .ok()
+        .and_then(|reply| reply.value)
+        .map(Secret)
+        .ok_or_else(|| CommandError::CommandFailure {
+            diagnostic: format!("secret_get returned no value for {name:?}"),
+            payload: Value::Null,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn redacts_formatting() {
+        let secret = Secret::new("hunter2");
+        assert_eq!(format!("{secret}"), "[redacted]");
+        assert_eq!(format!("{secret:?}"), "Secret([redacted])");
+        assert_eq!(secret.expose(), "hunter2");
+    }
+
+    #[test]
+    fn decodes_secret_payloads() {
+        let secret = decode_secret("db", json!({"value": "s3cret"})).unwrap();
+        assert_eq!(secret.expose(), "s3cret");
+        assert!(decode_secret("db", json!({"value": null})).is_err());
+        assert!(decode_secret("db", Value::Null).is_err());
+    }
+}
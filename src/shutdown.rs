@@ -0,0 +1,102 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+/// Shared flag flipped by the runtime once a shutdown signal arrives.
+///
+/// The runtime installs a clone in every request's extensions so handlers (typically readiness
+/// probes) can observe it through [`ContainerContext::is_shutting_down`](crate::ContainerContext::is_shutting_down).
+#[derive(Clone, Debug, Default)]
+pub struct ShutdownState {
+    shutting_down: Arc<AtomicBool>,
+}
+
+impl ShutdownState {
+    /// Creates a flag that reports the server as running.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Indicates whether the runtime has received a shutdown signal.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Acquire)
+    }
+
+    /// Marks the runtime as shutting down; new requests are rejected from this point on.
+    pub(crate) fn begin(&self) {
+        self.shutting_down.store(true, Ordering::Release);
+    }
+}
+
+/// State consumed by [`reject_while_shutting_down`].
+#[derive(Clone, Debug)]
+pub(crate) struct ShutdownGuard {
+    pub(crate) state: ShutdownState,
+    pub(crate) health_check_paths: Arc<[String]>,
+}
+
+/// Middleware that answers `503 Service Unavailable` once shutdown has begun, except for the
+/// configured health-check paths.
+pub(crate) async fn reject_while_shutting_down(
+    State(guard): State<ShutdownGuard>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if guard.state.is_shutting_down()
+        && !guard
+            .health_check_paths
+            .iter()
+            .any(|path| path == request.uri().path())
+    {
+        return (StatusCode::SERVICE_UNAVAILABLE, "server is shutting down").into_response();
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::middleware;
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    fn router(state: ShutdownState) -> Router {
+        let guard = ShutdownGuard {
+            state,
+            health_check_paths: vec!["/healthz".to_owned()].into(),
+        };
+        Router::new()
+            .route("/", get(|| async { "ok" }))
+            .route("/healthz", get(|| async { "alive" }))
+            .layer(middleware::from_fn_with_state(
+                guard,
+                reject_while_shutting_down,
+            ))
+    }
+
+    async fn status(router: Router, path: &str) -> StatusCode {
+        let request = Request::builder().uri(path).body(Body::empty()).unwrap();
+        router.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn rejects_new_requests_once_shutting_down() {
+        let state = ShutdownState::new();
+        assert_eq!(status(router(state.clone()), "/").await, StatusCode::OK);
+
+        state.begin();
+        assert!(state.is_shutting_down());
+        assert_eq!(
+            status(router(state.clone()), "/").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(status(router(state), "/healthz").await, StatusCode::OK);
+    }
+}
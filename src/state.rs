@@ -0,0 +1,172 @@
+use axum::extract::FromRef;
+
+use containerflare_command::CommandClient;
+
+use crate::config::RuntimeConfig;
+use crate::error::Result;
+use crate::platform::RuntimePlatform;
+
+/// Runtime handles that can live in axum `State`, for code that is not tied to a request.
+///
+/// [`ContainerContext`](crate::ContainerContext) only exists while a request is being handled,
+/// so background tasks (cache refreshers, queue consumers) cannot reach the command channel
+/// through it. Build this state before serving, hand clones to those tasks, and install it with
+/// `Router::with_state`; its [`FromRef`] impls make `State<CommandClient>` and
+/// `State<RuntimePlatform>` work in handlers. An application state struct can embed it and
+/// forward the same impls.
+///
+/// ```no_run
+/// use axum::extract::State;
+/// use axum::{Router, routing::get};
+/// use containerflare::{CommandClient, ContainerflareState, RuntimeConfig};
+///
+/// # async fn demo() -> containerflare::Result<()> {
+/// let mut config = RuntimeConfig::from_env()?;
+/// let state = ContainerflareState::connect(&mut config).await?;
+/// let router = Router::new()
+///     .route("/", get(|State(client): State<CommandClient>| async move {
+///         format!("{:?}", client.endpoint())
+///     }))
+///     .with_state(state);
+/// containerflare::serve(router, config).await
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ContainerflareState {
+    command_client: CommandClient,
+    platform: RuntimePlatform,
+}
+
+impl ContainerflareState {
+    /// Bundles an existing client and platform.
+    ///
+    /// Pass the same client to [`RuntimeConfigBuilder::command_client`] so requests and
+    /// background tasks share one connection.
+    ///
+    /// [`RuntimeConfigBuilder::command_client`]: crate::RuntimeConfigBuilder::command_client
+    pub fn new(command_client: CommandClient, platform: RuntimePlatform) -> Self {
+        Self {
+            command_client,
+            platform,
+        }
+    }
+
+    /// Connects the command channel `config` describes, the way `serve` would, and records the
+    /// client in [`RuntimeConfig::command_client`] so `serve` reuses the same connection.
+    ///
+    /// A client already set on `config` is reused as-is. Without an endpoint the state holds an
+    /// [`unavailable`](CommandClient::unavailable) client and `config` is left untouched.
+    ///
+    /// # Errors
+    /// Returns an error when the configured endpoint cannot be reached.
+    pub async fn connect(config: &mut RuntimeConfig) -> Result<Self> {
+        let command_client = match (&config.command_client, &config.command_endpoint) {
+            (Some(client), _) => client.clone(),
+            (None, Some(endpoint)) => {
+                let client = match config.command_timeout {
+                    Some(timeout) => {
+                        CommandClient::connect_with_timeout(endpoint.clone(), timeout).await?
+                    }
+                    None => CommandClient::connect(endpoint.clone()).await?,
+                };
+                config.command_client = Some(client.clone());
+                client
+            }
+            (None, None) => CommandClient::unavailable(
+                config
+                    .command_disabled_reason
+                    .clone()
+                    .unwrap_or_else(|| "command channel disabled".to_owned()),
+            ),
+        };
+        Ok(Self::new(command_client, config.platform.clone()))
+    }
+
+    /// Returns the shared command client.
+    pub fn command_client(&self) -> &CommandClient {
+        &self.command_client
+    }
+
+    /// Returns the platform the runtime is configured for.
+    pub fn platform(&self) -> &RuntimePlatform {
+        &self.platform
+    }
+}
+
+impl FromRef<ContainerflareState> for CommandClient {
+    fn from_ref(state: &ContainerflareState) -> Self {
+        state.command_client.clone()
+    }
+}
+
+impl FromRef<ContainerflareState> for RuntimePlatform {
+    fn from_ref(state: &ContainerflareState) -> Self {
+        state.platform.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::extract::State;
+    use axum::http::Request;
+    use axum::routing::get;
+    use containerflare_command::{CommandEndpoint, CommandResponse, CommandServer};
+    use http_body_util::BodyExt;
+    use tokio::net::TcpListener;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn shares_the_configured_client_through_state() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, writer) = stream.into_split();
+            let server = CommandServer::new(|_| CommandResponse::ok());
+            let _ = server.serve(reader, writer).await;
+        });
+
+        let mut config = RuntimeConfig::builder()
+            .command_endpoint(CommandEndpoint::Tcp(addr))
+            .build();
+        let state = ContainerflareState::connect(&mut config).await.unwrap();
+        let injected = config.command_client.as_ref().unwrap();
+        assert_eq!(injected.endpoint(), state.command_client().endpoint());
+
+        // A background task can use its own clone while handlers read it from `State`.
+        let background = state.command_client().clone();
+        let router = Router::new()
+            .route(
+                "/",
+                get(|State(client): State<CommandClient>| async move {
+                    client
+                        .send(containerflare_command::CommandRequest::empty("ping"))
+                        .await
+                        .map(|response| response.ok.to_string())
+                        .unwrap_or_else(|err| err.to_string())
+                }),
+            )
+            .with_state(state);
+        let request = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "true");
+        assert_eq!(background.stats().sent, 1);
+    }
+
+    #[tokio::test]
+    async fn leaves_config_alone_without_an_endpoint() {
+        let mut config = RuntimeConfig::builder()
+            .disable_command_channel("not needed")
+            .build();
+        let state = ContainerflareState::connect(&mut config).await.unwrap();
+        assert_eq!(
+            state.command_client().unavailable_reason(),
+            Some("not needed")
+        );
+        assert!(config.command_client.is_none());
+    }
+}